@@ -1,20 +1,24 @@
-use anyhow::Error;
 use fancy_regex::Regex as FancyRegex;
-use ignore::WalkState;
+use globset::Glob;
 use itertools::Itertools;
+use log::warn;
 use parking_lot::{
     MappedRwLockReadGuard, MappedRwLockWriteGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
 };
 use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    cmp::{max, min},
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
     fs::{self, File},
-    io::{BufRead, BufReader, BufWriter, Write},
-    mem,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    iter, mem,
+    ops::{Range, RangeInclusive},
     path::{Path, PathBuf},
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
     sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
@@ -22,41 +26,423 @@ use tokio::{
 };
 
 use crate::{
-    event::{AppEvent, BackgroundProcessingEvent, ReplaceResult, SearchResult},
+    error::ScooterError,
+    event::{AppEvent, BackgroundProcessingEvent, ReplaceResult, ReplaceWarning, SearchResult},
+    export::{unified_patch, ResultRecord},
     fields::{CheckboxField, Field, FieldError, TextField},
-    parsed_fields::{ParsedFields, SearchType},
-    utils::relative_path_from,
+    git,
+    logging::{
+        append_audit_records, default_log_file, default_review_file, read_log_tail, AuditRecord,
+    },
+    parsed_fields::{FuzzyPathMatcher, ParsedFields, PathMatcher, SearchType, DEFAULT_PRUNED_DIRS},
+    replace::{
+        display_counter_placeholders, expand_env_vars, insert_counter_placeholders, Counter,
+        CounterConfig, ReplacementMap,
+    },
+    ui::{plain_text_diff, ContentWidth},
+    utils::{
+        copy_to_clipboard, hard_link_id, is_large_search_root, line_ending_len,
+        open_containing_directory, read_lines_around, relative_path_from_roots,
+    },
     EventHandlingResult,
 };
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct SearchState {
     pub results: Vec<SearchResult>,
-    pub selected: usize, // TODO: allow for selection of ranges
+    pub selected: usize,
+    /// Hide results whose replacement is identical to the original line. Hidden results are
+    /// still replaced if included.
+    pub hide_unchanged: bool,
+    /// If set, the results between this and `selected` (inclusive) form a visual range, and only
+    /// those results are replaced
+    pub visual_anchor: Option<usize>,
+    /// If set, only the first this many results in each file are shown, other than in the files
+    /// in `expanded_files`. Hidden results are still replaced if included. While searching, this
+    /// applies to each run of results in a file separately, until they are sorted.
+    #[serde(skip)]
+    pub max_per_file: Option<usize>,
+    #[serde(skip)]
+    pub expanded_files: HashSet<PathBuf>,
 }
 
 impl SearchState {
+    /// Writes the results, along with which are included and which is selected, to `path` as
+    /// JSON, so that the review can be resumed later with `load`
+    pub fn save(&self, path: &Path) -> Result<(), ScooterError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let writer = BufWriter::new(File::create(path).map_err(ScooterError::DiskWrite)?);
+        serde_json::to_writer(writer, self)
+            .map_err(|e| ScooterError::InvalidReviewFile(path.to_owned(), e.to_string()))
+    }
+
+    /// Reads results saved with `save`. These may be out of date, so should be checked against
+    /// the files with `revalidate`.
+    pub fn load(path: &Path) -> Result<Self, ScooterError> {
+        let reader = BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader)
+            .map_err(|e| ScooterError::InvalidReviewFile(path.to_owned(), e.to_string()))
+    }
+
+    /// Removes results whose line has changed since they were found, e.g. because the file was
+    /// edited after the review was saved, returning the number removed
+    pub fn revalidate(&mut self) -> usize {
+        let mut line_numbers: HashMap<&Path, HashSet<usize>> = HashMap::new();
+        for res in &self.results {
+            line_numbers
+                .entry(&res.path)
+                .or_default()
+                .insert(res.line_number);
+        }
+        let mut current_lines: HashMap<PathBuf, HashMap<usize, String>> = HashMap::new();
+        for (path, line_numbers) in line_numbers {
+            let Ok(file) = File::open(path) else {
+                continue;
+            };
+            let lines = current_lines.entry(path.to_owned()).or_default();
+            for (idx, line) in BufReader::new(file).split(b'\n').enumerate() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if !line_numbers.contains(&(idx + 1)) {
+                    continue;
+                }
+                // Lines that aren't valid UTF-8 are left out, as they can't be replaced anyway
+                let Ok(mut line) = String::from_utf8(line) else {
+                    continue;
+                };
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+                lines.insert(idx + 1, line);
+            }
+        }
+
+        let num_results = self.results.len();
+        let selected = self.results.get(self.selected).map(result_key);
+        self.results.retain(|res| {
+            current_lines
+                .get(&res.path)
+                .and_then(|lines| lines.get(&res.line_number))
+                == Some(&res.line)
+        });
+        self.selected = selected
+            .and_then(|key| self.results.iter().position(|res| result_key(res) == key))
+            .unwrap_or(0);
+        self.visual_anchor = None;
+        self.clamp_selection();
+        num_results - self.results.len()
+    }
+
+    /// Keeps the selected result, and the visual anchor if there is one, within the results. This
+    /// should be called whenever results are removed, so that the selection always points at a
+    /// result, or is 0 if there are none.
+    pub fn clamp_selection(&mut self) {
+        let last = self.results.len().saturating_sub(1);
+        self.selected = min(self.selected, last);
+        if self.results.is_empty() {
+            self.visual_anchor = None;
+        } else if let Some(anchor) = &mut self.visual_anchor {
+            *anchor = min(*anchor, last);
+        }
+    }
+
+    fn is_shown(&self, result: &SearchResult) -> bool {
+        !self.hide_unchanged || result.replacement != result.line
+    }
+
+    /// Each result not hidden by `hide_unchanged`, along with the number of such results before it
+    /// in the same file. Results in the same file are only contiguous once sorted with
+    /// `sort_by_file`, so until then each run of results in a file is counted separately.
+    fn positions_in_file(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let mut prev_path: Option<&Path> = None;
+        let mut position = 0;
+        self.results
+            .iter()
+            .enumerate()
+            .filter(|(_, res)| self.is_shown(res))
+            .map(move |(idx, res)| {
+                position = if prev_path == Some(&res.path) {
+                    position + 1
+                } else {
+                    0
+                };
+                prev_path = Some(&res.path);
+                (idx, position)
+            })
+    }
+
+    /// Whether the result at `idx`, which has `position` results before it in its file, is hidden
+    /// by `max_per_file`
+    fn is_collapsed(&self, idx: usize, position: usize) -> bool {
+        self.max_per_file
+            .is_some_and(|max_per_file| position >= max_per_file)
+            && !self.expanded_files.contains(&self.results[idx].path)
+    }
+
+    /// Indices of the results that are shown, i.e. not hidden by `hide_unchanged` or
+    /// `max_per_file`
+    pub fn shown_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.positions_in_file()
+            .filter(|&(idx, position)| !self.is_collapsed(idx, position))
+            .map(|(idx, _)| idx)
+    }
+
+    /// Each shown result, along with whether any results after it in its file are hidden by
+    /// `max_per_file`
+    fn shown_with_hidden_after(&self) -> impl Iterator<Item = (usize, bool)> + '_ {
+        let mut positions = self.positions_in_file().peekable();
+        iter::from_fn(move || loop {
+            let (idx, position) = positions.next()?;
+            if self.is_collapsed(idx, position) {
+                continue;
+            }
+            let hidden_after = positions.peek().is_some_and(|&(next, next_position)| {
+                next_position > 0 && self.is_collapsed(next, next_position)
+            });
+            return Some((idx, hidden_after));
+        })
+    }
+
+    fn is_index_shown(&self, idx: usize) -> bool {
+        self.shown_indices().any(|shown| shown == idx)
+    }
+
+    /// For each file with results hidden by `max_per_file`, maps the index of the last result
+    /// shown in that file to the number of results hidden after it
+    pub fn num_hidden_per_file(&self) -> HashMap<usize, usize> {
+        let mut num_hidden = HashMap::new();
+        if self.max_per_file.is_none() {
+            return num_hidden;
+        }
+        let mut last_shown = None;
+        for (idx, position) in self.positions_in_file() {
+            if !self.is_collapsed(idx, position) {
+                last_shown = Some(idx);
+            } else if let Some(last_shown) = last_shown {
+                *num_hidden.entry(last_shown).or_default() += 1;
+            }
+        }
+        num_hidden
+    }
+
+    /// Shows all results in the selected result's file, or collapses them back to
+    /// `max_per_file`, in which case the selection moves to the last result still shown in the
+    /// file if the selected result is hidden
+    pub fn toggle_selected_file_expanded(&mut self) {
+        if self.max_per_file.is_none() {
+            return;
+        }
+        let Some(path) = self.results.get(self.selected).map(|res| res.path.clone()) else {
+            return;
+        };
+        if !self.expanded_files.remove(&path) {
+            self.expanded_files.insert(path);
+            return;
+        }
+        if !self.is_index_shown(self.selected) {
+            if let Some(idx) = self
+                .shown_indices()
+                .take_while(|&idx| idx < self.selected)
+                .filter(|&idx| self.results[idx].path == path)
+                .last()
+            {
+                self.selected = idx;
+            }
+        }
+    }
+
+    /// Position of the selected result among the shown results
+    fn selected_position(&self) -> usize {
+        self.shown_indices()
+            .take_while(|&idx| idx < self.selected)
+            .count()
+    }
+
     pub fn move_selected_up(&mut self) {
-        if self.selected == 0 {
-            self.selected = self.results.len();
+        self.clamp_selection();
+        let prev = self
+            .shown_indices()
+            .take_while(|&idx| idx < self.selected)
+            .last();
+        if let Some(idx) = prev.or_else(|| self.shown_indices().last()) {
+            self.selected = idx;
         }
-        self.selected = self.selected.saturating_sub(1);
     }
 
     pub fn move_selected_down(&mut self) {
-        if self.selected >= self.results.len().saturating_sub(1) {
-            self.selected = 0;
-        } else {
-            self.selected += 1;
+        self.clamp_selection();
+        let next = self.shown_indices().find(|&idx| idx > self.selected);
+        if let Some(idx) = next.or_else(|| self.shown_indices().next()) {
+            self.selected = idx;
+        }
+    }
+
+    /// Sorts the results by path and then line number, keeping the same results selected and
+    /// anchoring the visual range. Files are searched in parallel, so results from different files
+    /// otherwise arrive interleaved.
+    pub fn sort_by_file(&mut self) {
+        let mut results = mem::take(&mut self.results)
+            .into_iter()
+            .enumerate()
+            .collect::<Vec<_>>();
+        results.sort_by(|(_, a), (_, b)| (&a.path, a.line_number).cmp(&(&b.path, b.line_number)));
+        let new_position = |idx| results.iter().position(|&(old_idx, _)| old_idx == idx);
+        self.selected = new_position(self.selected).unwrap_or(self.selected);
+        self.visual_anchor = self.visual_anchor.and_then(new_position);
+        self.results = results.into_iter().map(|(_, res)| res).collect();
+    }
+
+    /// Indices of the shown results at which each run of results in the same file starts.
+    /// Results in the same file are only contiguous once sorted with `sort_by_file`.
+    fn file_starts(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut prev_path: Option<&Path> = None;
+        self.shown_indices().filter(move |&idx| {
+            let path = &self.results[idx].path;
+            let is_start = prev_path != Some(path);
+            prev_path = Some(path);
+            is_start
+        })
+    }
+
+    /// Selects the first result in the next file, wrapping around to the first file
+    pub fn move_to_next_file(&mut self) {
+        let next = self.file_starts().find(|&start| start > self.selected);
+        if let Some(idx) = next.or_else(|| self.shown_indices().next()) {
+            self.selected = idx;
+        }
+    }
+
+    /// Selects the first result in the previous file, wrapping around to the last file
+    pub fn move_to_prev_file(&mut self) {
+        // The start of the file containing the selected result
+        let current = self
+            .file_starts()
+            .take_while(|&start| start <= self.selected)
+            .last();
+        let prev = current.and_then(|current| {
+            self.file_starts()
+                .take_while(|&start| start < current)
+                .last()
+        });
+        if let Some(idx) = prev.or_else(|| self.file_starts().last()) {
+            self.selected = idx;
+        }
+    }
+
+    /// Shows or hides results whose replacement is identical to the original line, moving the
+    /// selection to the next shown result if the selected result is hidden. If every result is
+    /// hidden, the selection is kept within the results.
+    pub fn toggle_hide_unchanged(&mut self) {
+        self.hide_unchanged = !self.hide_unchanged;
+        self.clamp_selection();
+        if !self.is_index_shown(self.selected) {
+            let next = self.shown_indices().find(|&idx| idx > self.selected);
+            if let Some(idx) = next.or_else(|| self.shown_indices().next()) {
+                self.selected = idx;
+            }
+        }
+    }
+
+    /// Starts a visual range at the selected result, or ends the current one
+    pub fn toggle_visual_mode(&mut self) {
+        self.visual_anchor = match self.visual_anchor {
+            Some(_) => None,
+            None => Some(self.selected),
+        };
+    }
+
+    /// The results between the visual anchor and the selected result, if a visual range is active
+    pub fn visual_range(&self) -> Option<RangeInclusive<usize>> {
+        self.visual_anchor
+            .map(|anchor| min(anchor, self.selected)..=max(anchor, self.selected))
+    }
+
+    /// The included results, along with their indices, restricted to the visual range if one is
+    /// active. These are the results that would be replaced.
+    pub fn results_to_replace(&self) -> impl Iterator<Item = (usize, &SearchResult)> {
+        let range = self.visual_range();
+        self.results.iter().enumerate().filter(move |(idx, res)| {
+            res.included && range.as_ref().is_none_or(|range| range.contains(idx))
+        })
+    }
+
+    /// Excludes all results outside of the visual range, if one is active, so that only the
+    /// included results within it are replaced
+    fn exclude_outside_visual_range(&mut self) {
+        if let Some(range) = self.visual_range() {
+            self.results
+                .iter_mut()
+                .enumerate()
+                .filter(|(idx, _)| !range.contains(idx))
+                .for_each(|(_, res)| res.included = false);
+            self.visual_anchor = None;
         }
     }
 
     pub fn toggle_selected_inclusion(&mut self) {
-        if self.selected < self.results.len() {
-            let selected_result = &mut self.results[self.selected];
+        self.clamp_selection();
+        if let Some(selected_result) = self.results.get_mut(self.selected) {
             selected_result.included = !selected_result.included;
-        } else {
-            self.selected = self.results.len().saturating_sub(1);
+        }
+    }
+
+    /// Includes only the `occurrence`th match (counting from 1, in order of path and line number),
+    /// either across all results or within each file if `per_file` is set, excluding every other
+    /// result. Where a line contains other matches, its replacement is changed so that only that
+    /// match is replaced. Returns false if there are fewer than `occurrence` matches (in every
+    /// file, if `per_file` is set), in which case nothing is included.
+    pub fn include_only_occurrence(&mut self, occurrence: usize, per_file: bool) -> bool {
+        let order = (0..self.results.len())
+            .sorted_by(|&a, &b| {
+                let (a, b) = (&self.results[a], &self.results[b]);
+                (&a.path, a.line_number).cmp(&(&b.path, b.line_number))
+            })
+            .collect::<Vec<_>>();
+        let groups = order
+            .into_iter()
+            .chunk_by(|&idx| per_file.then(|| self.results[idx].path.clone()));
+
+        let mut targets = vec![];
+        for (_, indices) in &groups {
+            let mut num_before = 0;
+            for idx in indices {
+                let num_matches = self.results[idx].num_matches;
+                if occurrence > num_before && occurrence <= num_before + num_matches {
+                    targets.push((idx, occurrence - num_before - 1));
+                    break;
+                }
+                num_before += num_matches;
+            }
+        }
+
+        self.results.iter_mut().for_each(|res| res.included = false);
+        let found = !targets.is_empty();
+        for (idx, match_idx) in targets {
+            let result = &mut self.results[idx];
+            if replace_only_match(result, match_idx) {
+                result.included = true;
+            } else {
+                warn!(
+                    "Couldn't replace only match {} in {:?}:{}, so it was excluded",
+                    match_idx + 1,
+                    result.path,
+                    result.line_number
+                );
+            }
+        }
+        found
+    }
+
+    /// Replaces the selected line with `replacement` rather than the text generated from the
+    /// search and replace fields
+    pub fn set_selected_replacement(&mut self, replacement: String) {
+        if let Some(result) = self.results.get_mut(self.selected) {
+            result.replacement = replacement;
+            // The replaced text can no longer be told apart from the rest of the line
+            result.replacement_spans.clear();
         }
     }
 
@@ -66,6 +452,120 @@ impl SearchState {
             .iter_mut()
             .for_each(|res| res.included = !all_included);
     }
+
+    /// Toggles inclusion of all results whose file has the given extension: if all such results
+    /// are included then they are all excluded, otherwise they are all included
+    pub fn toggle_extension(&mut self, extension: Option<&OsStr>) {
+        let matches_extension = |res: &SearchResult| res.path.extension() == extension;
+        let all_included = self
+            .results
+            .iter()
+            .filter(|res| matches_extension(res))
+            .all(|res| res.included);
+        self.results
+            .iter_mut()
+            .filter(|res| matches_extension(res))
+            .for_each(|res| res.included = !all_included);
+    }
+
+    /// Toggles inclusion of the results in `range`: if all of them are included then they are all
+    /// excluded, otherwise they are all included
+    fn toggle_range(&mut self, range: Range<usize>) {
+        let Some(results) = self.results.get_mut(range) else {
+            return;
+        };
+        let all_included = results.iter().all(|res| res.included);
+        results
+            .iter_mut()
+            .for_each(|res| res.included = !all_included);
+    }
+
+    /// Toggles inclusion of the selected result and all results after it
+    pub fn toggle_to_end(&mut self) {
+        self.toggle_range(self.selected..self.results.len());
+    }
+
+    /// Toggles inclusion of the selected result and all results before it
+    pub fn toggle_to_start(&mut self) {
+        self.toggle_range(0..min(self.selected + 1, self.results.len()));
+    }
+
+    pub fn toggle_selected_extension(&mut self) {
+        if let Some(selected_result) = self.results.get(self.selected) {
+            let extension = selected_result.path.extension().map(OsStr::to_owned);
+            self.toggle_extension(extension.as_deref());
+        }
+    }
+
+    pub fn num_files(&self) -> usize {
+        self.results.iter().map(|res| &res.path).unique().count()
+    }
+
+    /// Positions, among the shown results, of the results to show in `height` rows, keeping the
+    /// selected result in the middle where possible. Each result takes `item_height` rows, plus one
+    /// for the row counting the results hidden after it by `max_per_file`, if any.
+    pub fn visible_range(&self, height: usize, item_height: usize) -> Range<usize> {
+        let heights = || {
+            self.shown_with_hidden_after()
+                .map(move |(_, hidden_after)| item_height + usize::from(hidden_after))
+        };
+        // Number of rows taken by the results before each position, up to and including the end
+        let rows_before = || {
+            iter::once(0).chain(heights().scan(0, |rows, height| {
+                *rows += height;
+                Some(*rows)
+            }))
+        };
+        let first_position_with_rows_before = |rows: usize| {
+            rows_before()
+                .position(|rows_before| rows_before >= rows)
+                .unwrap_or(0)
+        };
+
+        let num_shown = heights().count();
+        let position = min(self.selected_position(), num_shown.saturating_sub(1));
+        let rows_above_selected = rows_before().nth(position).unwrap_or(0);
+        let total_rows = rows_before().last().unwrap_or(0);
+
+        let start = min(
+            first_position_with_rows_before(rows_above_selected.saturating_sub(height / 2)),
+            // Don't scroll further than is needed to show the last result
+            first_position_with_rows_before(total_rows.saturating_sub(height)),
+        );
+        let num_fitting = heights()
+            .skip(start)
+            .scan(0, |rows, item_rows| {
+                *rows += item_rows;
+                Some(*rows)
+            })
+            .take_while(|&rows| rows <= height)
+            .count();
+        start..start + num_fitting
+    }
+
+    /// Number of results that will be replaced
+    pub fn num_included(&self) -> usize {
+        self.results.iter().filter(|res| res.included).count()
+    }
+
+    /// Change in size, in bytes, that replacing the included results in the file at `path` would
+    /// make to that file
+    pub fn byte_size_delta(&self, path: &Path) -> isize {
+        self.results
+            .iter()
+            .filter(|res| res.included && res.path == path)
+            .map(|res| res.replacement.len() as isize - res.line.len() as isize)
+            .sum()
+    }
+
+    /// Number of results in files matched by a `.gitignore`, which are only found when ignored
+    /// files are included
+    pub fn num_gitignored(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|res| res.warnings.contains(&ReplaceWarning::Gitignored))
+            .count()
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -74,23 +574,70 @@ pub struct ReplaceState {
     pub num_ignored: usize,
     pub errors: Vec<SearchResult>,
     pub replacement_errors_pos: usize,
+    pub successes: Vec<SearchResult>,
+    pub successes_pos: usize,
+    /// Whether the list of successful replacements is shown rather than the list of errors
+    pub show_successes: bool,
+    /// Number of matches for the search that remain in the replaced files, other than those
+    /// that weren't included. Only set if verification is enabled.
+    pub num_remaining_matches: Option<usize>,
+    /// Number of lines of context shown around each successful replacement, by index, in
+    /// addition to those shown for every result
+    pub expanded_context: HashMap<usize, usize>,
+    /// Lines around each successful replacement, by index, as they were when the replacement
+    /// completed. Empty if no context is shown.
+    pub success_context: HashMap<usize, Vec<(usize, String)>>,
 }
 
+/// Size above which a patch copied to the clipboard is flagged, as it may be too large to paste
+const LARGE_PATCH_BYTES: usize = 1024 * 1024;
+
+/// Number of lines either side of a result included when its diff is copied to the clipboard
+const COPIED_DIFF_CONTEXT: usize = 3;
+
+/// Number of lines of context added either side of a successful replacement each time its context
+/// is expanded
+pub const CONTEXT_EXPANSION: usize = 5;
+
 impl ReplaceState {
-    fn handle_key_results(&mut self, key: &KeyEvent) -> bool {
+    fn handle_key_results(&mut self, key: &KeyEvent, context: usize) -> bool {
         let mut exit = false;
         match (key.code, key.modifiers) {
             (KeyCode::Char('j') | KeyCode::Down, _)
             | (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
-                self.scroll_replacement_errors_down();
+                if self.showing_successes() {
+                    self.scroll_successes_down();
+                } else {
+                    self.scroll_replacement_errors_down();
+                }
             }
             (KeyCode::Char('k') | KeyCode::Up, _) | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
-                self.scroll_replacement_errors_up();
+                if self.showing_successes() {
+                    self.scroll_successes_up();
+                } else {
+                    self.scroll_replacement_errors_up();
+                }
+            }
+            (KeyCode::Char('s'), KeyModifiers::NONE) => {
+                self.toggle_show_successes();
+            }
+            (KeyCode::Char('+'), _) => {
+                self.expand_selected_context(context);
             }
             (KeyCode::Char('d'), KeyModifiers::CONTROL) => {} // TODO
             (KeyCode::PageDown, _) => {}                      // TODO
             (KeyCode::Char('u'), KeyModifiers::CONTROL) => {} // TODO
             (KeyCode::PageUp, _) => {}                        // TODO
+            (KeyCode::Char('o'), KeyModifiers::NONE) => {
+                let selected = if self.showing_successes() {
+                    self.successes.get(self.successes_pos)
+                } else {
+                    self.errors.get(self.replacement_errors_pos)
+                };
+                if let Some(result) = selected {
+                    open_directory_of(&result.path);
+                }
+            }
             (KeyCode::Enter | KeyCode::Char('q'), _) => {
                 exit = true;
             }
@@ -99,6 +646,53 @@ impl ReplaceState {
         exit
     }
 
+    /// Whether the list of successful replacements is shown, which is always the case when there
+    /// are no errors to show instead
+    pub fn showing_successes(&self) -> bool {
+        self.show_successes || self.errors.is_empty()
+    }
+
+    /// Reads the lines around each successful replacement, given that `context` lines are shown
+    /// either side of every result
+    pub fn read_success_context(&mut self, context: usize) {
+        if context == 0 {
+            return;
+        }
+        for idx in 0..self.successes.len() {
+            self.read_context_of(idx, context);
+        }
+    }
+
+    fn read_context_of(&mut self, idx: usize, context: usize) {
+        let result = &self.successes[idx];
+        // Read the file again, as other replacements may have changed nearby lines
+        match read_lines_around(
+            &result.path,
+            result.line_number,
+            self.context_for(idx, context),
+        ) {
+            Ok(lines) => {
+                self.success_context.insert(idx, lines);
+            }
+            Err(e) => warn!("Couldn't read context from {:?}: {e}", result.path),
+        }
+    }
+
+    /// Shows more lines around the successful replacement at the top of the list, re-reading them
+    /// from the file
+    pub fn expand_selected_context(&mut self, context: usize) {
+        if self.showing_successes() && self.successes_pos < self.successes.len() {
+            *self.expanded_context.entry(self.successes_pos).or_default() += CONTEXT_EXPANSION;
+            self.read_context_of(self.successes_pos, context);
+        }
+    }
+
+    /// Number of lines of context to show either side of the successful replacement at `idx`,
+    /// given that `context` lines are shown for every result
+    pub fn context_for(&self, idx: usize, context: usize) -> usize {
+        context + self.expanded_context.get(&idx).copied().unwrap_or(0)
+    }
+
     pub fn scroll_replacement_errors_up(&mut self) {
         if self.replacement_errors_pos == 0 {
             self.replacement_errors_pos = self.errors.len();
@@ -113,12 +707,78 @@ impl ReplaceState {
             self.replacement_errors_pos += 1;
         }
     }
+
+    pub fn scroll_successes_up(&mut self) {
+        if self.successes_pos == 0 {
+            self.successes_pos = self.successes.len();
+        }
+        self.successes_pos = self.successes_pos.saturating_sub(1);
+    }
+
+    pub fn scroll_successes_down(&mut self) {
+        if self.successes_pos >= self.successes.len().saturating_sub(1) {
+            self.successes_pos = 0;
+        } else {
+            self.successes_pos += 1;
+        }
+    }
+
+    /// The files that were successfully modified, in the order they were first replaced in
+    pub fn changed_files(&self) -> Vec<PathBuf> {
+        let mut seen = HashSet::new();
+        self.successes
+            .iter()
+            .filter(|result| seen.insert(&result.path))
+            .map(|result| result.path.clone())
+            .collect()
+    }
+
+    /// Switches between the lists of successful replacements and errors. Only possible when
+    /// there are both, as otherwise there is no list of errors to switch from.
+    pub fn toggle_show_successes(&mut self) {
+        if !self.errors.is_empty() && !self.successes.is_empty() {
+            self.show_successes = !self.show_successes;
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct LogViewerState {
+    pub path: PathBuf,
+    pub lines: Vec<String>,
+    pub scroll: usize,
+}
+
+const NUM_LOG_LINES: usize = 500;
+
+impl LogViewerState {
+    pub fn open(path: PathBuf) -> Self {
+        let lines = read_log_tail(&path, NUM_LOG_LINES)
+            .unwrap_or_else(|e| vec![format!("Couldn't read log file: {e}")]);
+        // Start at the most recent logs
+        let scroll = lines.len().saturating_sub(1);
+        Self {
+            path,
+            lines,
+            scroll,
+        }
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = min(self.scroll + 1, self.lines.len().saturating_sub(1));
+    }
 }
 
 #[derive(Debug)]
 pub struct SearchInProgressState {
     pub search_state: SearchState,
     pub last_render: Instant,
+    /// Whether to keep the newest result selected as results stream in
+    pub follow: bool,
     handle: JoinHandle<()>,
     processing_sender: UnboundedSender<BackgroundProcessingEvent>,
     processing_receiver: UnboundedReceiver<BackgroundProcessingEvent>,
@@ -134,17 +794,168 @@ impl SearchInProgressState {
             search_state: SearchState {
                 results: vec![],
                 selected: 0,
+                hide_unchanged: false,
+                visual_anchor: None,
+                max_per_file: None,
+                expanded_files: HashSet::new(),
             },
             last_render: Instant::now(),
+            follow: false,
             handle,
             processing_sender,
             processing_receiver,
         }
     }
+
+    pub fn add_result(&mut self, result: SearchResult) {
+        self.search_state.results.push(result);
+        if self.follow {
+            self.search_state.selected = self.search_state.results.len() - 1;
+        }
+    }
+
+    pub fn toggle_follow(&mut self) {
+        self.follow = !self.follow;
+        if self.follow {
+            self.search_state.selected = self.search_state.results.len().saturating_sub(1);
+        }
+    }
+}
+
+/// Steps through the files containing included results, so that replacements can be confirmed
+/// or declined a whole file at a time
+#[derive(Debug)]
+pub struct FileConfirmationState {
+    pub search_state: SearchState,
+    /// Indices into `search_state.results` of the included results, grouped by file
+    pub files: Vec<(PathBuf, Vec<usize>)>,
+    /// Index into `files` of the file currently being confirmed
+    pub current: usize,
+    /// Index into the current file's results of the first one shown
+    pub scroll_pos: usize,
+}
+
+impl FileConfirmationState {
+    pub fn new(search_state: SearchState) -> Self {
+        let mut files: Vec<(PathBuf, Vec<usize>)> = vec![];
+        let mut file_indices: HashMap<PathBuf, usize> = HashMap::new();
+        for (idx, result) in search_state.results_to_replace() {
+            match file_indices.get(&result.path) {
+                Some(&file_idx) => files[file_idx].1.push(idx),
+                None => {
+                    file_indices.insert(result.path.clone(), files.len());
+                    files.push((result.path.clone(), vec![idx]));
+                }
+            }
+        }
+        Self {
+            search_state,
+            files,
+            current: 0,
+            scroll_pos: 0,
+        }
+    }
+
+    pub fn current_file(&self) -> Option<&(PathBuf, Vec<usize>)> {
+        self.files.get(self.current)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current >= self.files.len()
+    }
+
+    /// Keeps the current file's results included and moves on to the next file
+    pub fn accept_current(&mut self) {
+        self.current = min(self.current + 1, self.files.len());
+        self.scroll_pos = 0;
+    }
+
+    /// Excludes all of the current file's results and moves on to the next file
+    pub fn decline_current(&mut self) {
+        if let Some((_, indices)) = self.files.get(self.current) {
+            for &idx in indices {
+                self.search_state.results[idx].included = false;
+            }
+        }
+        self.accept_current();
+    }
+
+    pub fn scroll_down(&mut self) {
+        let num_results = self.current_file().map_or(0, |(_, indices)| indices.len());
+        self.scroll_pos = min(self.scroll_pos + 1, num_results.saturating_sub(1));
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll_pos = self.scroll_pos.saturating_sub(1);
+    }
+}
+
+/// What a replacement would change, shown for confirmation before anything is written
+#[derive(Debug)]
+pub struct ReplacementSummary {
+    pub search_state: SearchState,
+    pub num_files: usize,
+    pub num_lines: usize,
+    pub num_substitutions: usize,
+    /// Number of included results with each warning, in the order the warnings first appear,
+    /// other than `ReplaceWarning::LargeFile` which is counted by `num_large_files`
+    pub warnings: Vec<(ReplaceWarning, usize)>,
+    /// Number of files with included results that are larger than `LARGE_FILE_BYTES`
+    pub num_large_files: usize,
+    /// Number of files with lines that matched but were skipped because they look binary, and so
+    /// won't be replaced in
+    pub num_binary_files: usize,
+}
+
+impl ReplacementSummary {
+    pub fn new(search_state: SearchState, num_binary_files: usize) -> Self {
+        let to_replace = search_state
+            .results_to_replace()
+            .map(|(_, res)| res)
+            .collect::<Vec<_>>();
+        let files = to_replace
+            .iter()
+            .map(|res| res.path.as_path())
+            .collect::<HashSet<_>>();
+        let mut warnings: Vec<(ReplaceWarning, usize)> = vec![];
+        let mut large_files = HashSet::new();
+        for res in &to_replace {
+            for &warning in &res.warnings {
+                if warning == ReplaceWarning::LargeFile {
+                    large_files.insert(res.path.as_path());
+                    continue;
+                }
+                match warnings.iter_mut().find(|(w, _)| *w == warning) {
+                    Some((_, count)) => *count += 1,
+                    None => warnings.push((warning, 1)),
+                }
+            }
+        }
+        Self {
+            num_files: files.len(),
+            num_lines: to_replace.len(),
+            num_substitutions: to_replace.iter().map(|res| res.num_matches).sum(),
+            warnings,
+            num_large_files: large_files.len(),
+            num_binary_files,
+            search_state,
+        }
+    }
+}
+
+/// How far through the replacement is, for display while it's in progress
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplacementProgress {
+    /// The file currently being replaced in
+    pub current_file: PathBuf,
+    pub num_files_done: usize,
+    pub num_files: usize,
 }
 
 #[derive(Debug)]
 pub struct PerformingReplacementState {
+    pub progress: Option<ReplacementProgress>,
+    pub last_render: Instant,
     handle: Option<JoinHandle<()>>,
     #[allow(dead_code)]
     processing_sender: UnboundedSender<BackgroundProcessingEvent>,
@@ -158,6 +969,8 @@ impl PerformingReplacementState {
         processing_receiver: UnboundedReceiver<BackgroundProcessingEvent>,
     ) -> Self {
         Self {
+            progress: None,
+            last_render: Instant::now(),
             handle,
             processing_sender,
             processing_receiver,
@@ -174,6 +987,8 @@ pub enum Screen {
     SearchFields,
     SearchProgressing(SearchInProgressState),
     SearchComplete(SearchState),
+    ConfirmingFiles(FileConfirmationState),
+    ReviewingReplacement(ReplacementSummary),
     PerformingReplacement(PerformingReplacementState),
     Results(ReplaceState),
 }
@@ -197,6 +1012,7 @@ pub enum FieldName {
     Replace,
     FixedStrings,
     PathPattern,
+    PathGlob,
 }
 
 pub struct SearchField {
@@ -204,15 +1020,20 @@ pub struct SearchField {
     pub field: Arc<RwLock<Field>>,
 }
 
-pub const NUM_SEARCH_FIELDS: usize = 4;
+pub const NUM_SEARCH_FIELDS: usize = 5;
 
 pub struct SearchFields {
     pub fields: [SearchField; NUM_SEARCH_FIELDS],
     pub highlighted: usize,
     pub show_error_popup: bool,
+    /// Index of the first line shown in the error popup, before the lines are wrapped
+    pub error_popup_scroll: usize,
     advanced_regex: bool,
+    path_fuzzy: bool,
 }
 
+pub const DEFAULT_MIN_FIXED_LEN: usize = 1;
+
 macro_rules! define_field_accessor {
     ($method_name:ident, $field_name:expr, $field_variant:ident, $return_type:ty) => {
         pub fn $method_name(&self) -> MappedRwLockReadGuard<'_, $return_type> {
@@ -262,9 +1083,11 @@ impl SearchFields {
         CheckboxField
     );
     define_field_accessor!(path_pattern, FieldName::PathPattern, Text, TextField);
+    define_field_accessor!(path_glob, FieldName::PathGlob, Checkbox, CheckboxField);
 
     define_field_accessor_mut!(search_mut, FieldName::Search, Text, TextField);
     define_field_accessor_mut!(path_pattern_mut, FieldName::PathPattern, Text, TextField);
+    define_field_accessor_mut!(path_glob_mut, FieldName::PathGlob, Checkbox, CheckboxField);
 
     pub fn with_values(
         search: impl Into<String>,
@@ -276,11 +1099,16 @@ impl SearchFields {
             fields: [
                 SearchField {
                     name: FieldName::Search,
-                    field: Arc::new(RwLock::new(Field::text(search.into()))),
+                    field: Arc::new(RwLock::new(
+                        Field::text(search).with_placeholder("e.g. foo.*bar"),
+                    )),
                 },
                 SearchField {
                     name: FieldName::Replace,
-                    field: Arc::new(RwLock::new(Field::text(replace.into()))),
+                    field: Arc::new(RwLock::new(
+                        Field::text(replace)
+                            .with_placeholder("e.g. baz, or $1 to use a capture group"),
+                    )),
                 },
                 SearchField {
                     name: FieldName::FixedStrings,
@@ -288,12 +1116,20 @@ impl SearchFields {
                 },
                 SearchField {
                     name: FieldName::PathPattern,
-                    field: Arc::new(RwLock::new(Field::text(filename_pattern.into()))),
+                    field: Arc::new(RwLock::new(
+                        Field::text(filename_pattern).with_placeholder("e.g. src/"),
+                    )),
+                },
+                SearchField {
+                    name: FieldName::PathGlob,
+                    field: Arc::new(RwLock::new(Field::checkbox(false))),
                 },
             ],
             highlighted: 0,
             show_error_popup: false,
+            error_popup_scroll: 0,
             advanced_regex: false,
+            path_fuzzy: false,
         }
     }
 
@@ -302,28 +1138,70 @@ impl SearchFields {
         self
     }
 
-    fn highlighted_field_impl(&self) -> &SearchField {
-        &self.fields[self.highlighted]
+    /// Interpret the path pattern as a glob, e.g. `*.rs`, rather than as a regex
+    pub fn with_path_glob(self, path_glob: bool) -> Self {
+        self.path_glob_mut().checked = path_glob;
+        self
     }
 
-    pub fn highlighted_field(&self) -> &Arc<RwLock<Field>> {
-        &self.highlighted_field_impl().field
+    pub fn advanced_regex(&self) -> bool {
+        self.advanced_regex
     }
 
-    pub fn highlighted_field_name(&self) -> &FieldName {
-        &self.highlighted_field_impl().name
+    /// Switches between the `regex` and `fancy_regex` engines, revalidating the patterns so that
+    /// errors reflect the newly selected engine
+    pub fn toggle_advanced_regex(&mut self) {
+        self.advanced_regex = !self.advanced_regex;
+        self.validate_patterns();
     }
 
-    pub fn focus_next(&mut self) {
-        self.highlighted = (self.highlighted + 1) % self.fields.len();
+    /// Whether no text has been entered into any of the fields
+    pub fn is_empty(&self) -> bool {
+        self.search().text.is_empty()
+            && self.replace().text.is_empty()
+            && self.path_pattern().text.is_empty()
     }
 
-    pub fn focus_prev(&mut self) {
-        self.highlighted =
-            (self.highlighted + self.fields.len().saturating_sub(1)) % self.fields.len();
+    /// Match the path pattern fuzzily, e.g. `srcmain` matches `src/main.rs`, rather than as a
+    /// regex or glob
+    pub fn with_path_fuzzy(mut self, path_fuzzy: bool) -> Self {
+        self.path_fuzzy = path_fuzzy;
+        self
     }
 
-    pub fn errors(&self) -> Vec<(&str, FieldError)> {
+    pub fn path_fuzzy(&self) -> bool {
+        self.path_fuzzy
+    }
+
+    /// Switches between fuzzy matching of the path pattern and the mode selected by the other
+    /// fields, revalidating the path pattern as fuzzy queries can't be invalid
+    pub fn toggle_path_fuzzy(&mut self) {
+        self.path_fuzzy = !self.path_fuzzy;
+        self.validate_patterns();
+    }
+
+    fn highlighted_field_impl(&self) -> &SearchField {
+        &self.fields[self.highlighted]
+    }
+
+    pub fn highlighted_field(&self) -> &Arc<RwLock<Field>> {
+        &self.highlighted_field_impl().field
+    }
+
+    pub fn highlighted_field_name(&self) -> &FieldName {
+        &self.highlighted_field_impl().name
+    }
+
+    pub fn focus_next(&mut self) {
+        self.highlighted = (self.highlighted + 1) % self.fields.len();
+    }
+
+    pub fn focus_prev(&mut self) {
+        self.highlighted =
+            (self.highlighted + self.fields.len().saturating_sub(1)) % self.fields.len();
+    }
+
+    pub fn errors(&self) -> Vec<(&str, FieldError)> {
         self.fields
             .iter()
             .filter_map(|field| {
@@ -336,36 +1214,195 @@ impl SearchFields {
             .collect::<Vec<_>>()
     }
 
-    pub fn search_type(&self) -> anyhow::Result<SearchType> {
+    /// Lines shown in the error popup, paired with whether the line is a field title
+    pub fn error_popup_lines(&self) -> Vec<(String, bool)> {
+        let can_search_literally = self.can_search_literally();
+        self.errors()
+            .into_iter()
+            .flat_map(|(name, error)| {
+                let hint = (can_search_literally && name == FieldName::Search.title())
+                    .then_some(["", "To search for the text literally instead, press <l>"]);
+                iter::once((name.to_owned(), true))
+                    .chain(error.long.lines().map(|line| (line.to_owned(), false)))
+                    .chain(
+                        hint.into_iter()
+                            .flatten()
+                            .map(|line| (line.to_owned(), false)),
+                    )
+                    .chain(iter::once((String::new(), false)))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    pub fn scroll_error_popup_down(&mut self) {
+        let num_lines = self.error_popup_lines().len();
+        self.error_popup_scroll = min(self.error_popup_scroll + 1, num_lines.saturating_sub(1));
+    }
+
+    pub fn scroll_error_popup_up(&mut self) {
+        self.error_popup_scroll = self.error_popup_scroll.saturating_sub(1);
+    }
+
+    pub fn close_error_popup(&mut self) {
+        self.show_error_popup = false;
+        self.error_popup_scroll = 0;
+    }
+
+    pub fn search_type(&self) -> Result<SearchType, ScooterError> {
         let search = self.search();
         let search_text = search.text();
-        let result = if self.fixed_strings().checked {
+        let result = if self.fixed_strings().checked && search_text.contains('\n') {
+            // Lines never contain newlines, so search for each line of the text separately
+            SearchType::multi_fixed(search_text.lines())
+                .map_err(|e| ScooterError::InvalidFixedStrings(e.to_string()))?
+        } else if self.fixed_strings().checked {
             SearchType::Fixed(search_text)
         } else if self.advanced_regex {
-            SearchType::PatternAdvanced(FancyRegex::new(&search_text)?)
+            SearchType::PatternAdvanced(
+                FancyRegex::new(&search_text)
+                    .map_err(|e| ScooterError::InvalidSearchRegex(e.into()))?,
+            )
         } else {
-            SearchType::Pattern(Regex::new(&search_text)?)
+            SearchType::Pattern(
+                Regex::new(&search_text).map_err(|e| ScooterError::InvalidSearchRegex(e.into()))?,
+            )
         };
         Ok(result)
     }
 
-    pub fn path_pattern_parsed(&self) -> anyhow::Result<Option<SearchType>> {
-        let path_patt_text = &self.path_pattern().text;
-        let result = if path_patt_text.is_empty() {
-            None
+    /// Sets or clears the errors on the search and path pattern fields, without showing the
+    /// error popup, so that invalid patterns can be flagged while the user is still typing
+    pub fn validate_patterns(&self) {
+        match self.search_type() {
+            Err(e) => {
+                let (short, long) = self.search_error_messages(&e);
+                self.search_mut().set_error(short, long);
+            }
+            Ok(_) => self.search_mut().clear_error(),
+        }
+        match self.path_pattern_parsed() {
+            Err(e) => {
+                let (short, long) = self.path_pattern_error_messages(&e);
+                self.path_pattern_mut().set_error(short, long);
+            }
+            Ok(_) => self.path_pattern_mut().clear_error(),
+        }
+    }
+
+    /// Whether the path pattern starts with `!`, meaning that files whose paths match the rest of
+    /// the pattern are excluded rather than included
+    pub fn path_pattern_negated(&self) -> bool {
+        self.path_pattern()
+            .text
+            .starts_with(NEGATED_PATH_PATTERN_PREFIX)
+    }
+
+    fn path_pattern_text(&self) -> String {
+        let path_pattern = self.path_pattern();
+        path_pattern
+            .text
+            .strip_prefix(NEGATED_PATH_PATTERN_PREFIX)
+            .unwrap_or(&path_pattern.text)
+            .to_owned()
+    }
+
+    /// The path pattern, without any leading `!`, parsed according to the fuzzy, glob, fixed
+    /// strings and advanced regex options in that order of precedence
+    pub fn path_pattern_parsed(&self) -> Result<Option<PathMatcher>, ScooterError> {
+        let path_patt_text = self.path_pattern_text();
+        if path_patt_text.is_empty() {
+            return Ok(None);
+        }
+        let path_matcher = if self.path_fuzzy {
+            PathMatcher::Fuzzy(FuzzyPathMatcher::new(path_patt_text))
+        } else if self.path_glob().checked {
+            let glob = Glob::new(&path_patt_text).map_err(ScooterError::InvalidPathGlob)?;
+            PathMatcher::Glob(glob.compile_matcher())
+        } else if self.fixed_strings().checked {
+            PathMatcher::Fixed(path_patt_text)
+        } else if self.advanced_regex {
+            PathMatcher::RegexAdvanced(
+                FancyRegex::new(&path_patt_text)
+                    .map_err(|e| ScooterError::InvalidPathRegex(e.into()))?,
+            )
         } else {
-            Some({
-                if self.advanced_regex {
-                    SearchType::PatternAdvanced(FancyRegex::new(path_patt_text)?)
-                } else {
-                    SearchType::Pattern(Regex::new(path_patt_text)?)
-                }
-            })
+            PathMatcher::Regex(
+                Regex::new(&path_patt_text)
+                    .map_err(|e| ScooterError::InvalidPathRegex(e.into()))?,
+            )
         };
-        Ok(result)
+        Ok(Some(path_matcher))
+    }
+
+    /// Short and long descriptions of an error parsing the search pattern
+    pub fn search_error_messages(&self, error: &ScooterError) -> (String, String) {
+        if let ScooterError::InvalidFixedStrings(_) = error {
+            return ("Couldn't search for strings".to_owned(), error.to_string());
+        }
+        if let ScooterError::SearchTooShort(_) = error {
+            return (
+                "Search is too short".to_owned(),
+                format!(
+                    "{error}, as shorter strings match almost every line.\n\nThe minimum can be \
+                    changed with --min-fixed-length"
+                ),
+            );
+        }
+        self.regex_error_messages(&self.search().text(), error)
+    }
+
+    /// Whether the search is an invalid regex, in which case it can be searched for literally
+    /// with `search_literally`
+    pub fn can_search_literally(&self) -> bool {
+        matches!(self.search_type(), Err(ScooterError::InvalidSearchRegex(_)))
+    }
+
+    /// Escapes the search text so that it matches literally, e.g. `foo(` becomes `foo\(`, and
+    /// closes the error popup
+    pub fn search_literally(&mut self) {
+        {
+            let mut search = self.search_mut();
+            search.text = if self.advanced_regex {
+                fancy_regex::escape(&search.text).into_owned()
+            } else {
+                regex::escape(&search.text)
+            };
+            search.move_cursor_end();
+        }
+        self.close_error_popup();
+        self.validate_patterns();
+    }
+
+    /// Short and long descriptions of an error parsing the path pattern
+    pub fn path_pattern_error_messages(&self, error: &ScooterError) -> (String, String) {
+        if self.path_glob().checked {
+            ("Couldn't parse glob".to_owned(), error.to_string())
+        } else {
+            self.regex_error_messages(&self.path_pattern_text(), error)
+        }
+    }
+
+    /// Short and long descriptions of an error parsing `pattern` as a regex. If the pattern is only
+    /// invalid because it uses features that need advanced regex, such as lookaround or
+    /// backreferences, then advanced regex is suggested.
+    fn regex_error_messages(&self, pattern: &str, error: &ScooterError) -> (String, String) {
+        if !self.advanced_regex && FancyRegex::new(pattern).is_ok() {
+            (
+                "Couldn't parse regex, try advanced regex".to_owned(),
+                format!(
+                    "{error}\n\nThis pattern is supported by advanced regex: toggle it with <A-r>, \
+                    or pass --advanced-regex"
+                ),
+            )
+        } else {
+            ("Couldn't parse regex".to_owned(), error.to_string())
+        }
     }
 }
 
+const NEGATED_PATH_PATTERN_PREFIX: char = '!';
+
 enum ValidatedField<T> {
     Parsed(T),
     Error,
@@ -374,24 +1411,182 @@ enum ValidatedField<T> {
 pub struct App {
     pub current_screen: Screen,
     pub search_fields: SearchFields,
-    directory: PathBuf,
+    pub log_viewer: Option<LogViewerState>,
+    /// Set while the replacement for the selected result is being edited by hand
+    pub editing_replacement: Option<TextField>,
+    /// Set when a search was requested in a very large directory, until the user confirms it
+    pub confirming_large_search: bool,
+    directories: Vec<PathBuf>,
     include_hidden: bool,
+    include_ignored: bool,
+    /// The values of `include_hidden` and `include_ignored` from before they were toggled with
+    /// `toggle_hidden_and_search`, so that they can be restored when toggling back
+    untoggled_hidden_and_ignored: Option<(bool, bool)>,
+    counter: CounterConfig,
+    git_modified_only: bool,
+    lossy: bool,
+    repeat: bool,
+    replacement_map: Option<Arc<ReplacementMap>>,
+    max_open_files: Option<usize>,
+    changed_within: Option<Duration>,
+    changed_before: Option<Duration>,
+    follow_symlinks: bool,
+    expand_env: bool,
+    compact: bool,
+    results_context: usize,
+    content_width: ContentWidth,
+    verify: bool,
+    retry_failed: bool,
+    large_search_confirmed: bool,
+    /// If set, searches stop after finding this many results, until resumed
+    preview_limit: Option<usize>,
+    /// Whether the last search stopped early due to `preview_limit`
+    search_paused: bool,
+    /// Lines that matched the last search but were skipped because they look binary, and the
+    /// number of files they were in
+    binary_lines_skipped: usize,
+    binary_files_skipped: usize,
+    large_dirs: Vec<PathBuf>,
+    pruned_dirs: Vec<String>,
+    search_git: bool,
+    /// Fixed search strings shorter than this many characters are refused, as they match
+    /// almost every line
+    min_fixed_len: usize,
+    /// If true, matches are found without computing their replacements, as nothing will be
+    /// replaced
+    search_only: bool,
+    /// If set, only this match (counting from 1) is replaced, either across the whole search or
+    /// within each file if `occurrence_per_file` is set
+    occurrence: Option<usize>,
+    occurrence_per_file: bool,
+    /// If set, only lines that also match this are replaced
+    line_filter: Option<Regex>,
+    pending_validation: Option<JoinHandle<()>>,
+    /// Whether each result from previous searches was included, so that the user's choices can be
+    /// reapplied to the same results when searching again
+    inclusion_decisions: HashMap<ResultKey, bool>,
+    /// The search fields from before the last reset, so that they can be restored
+    last_search_fields: Option<SearchFields>,
+    /// Where the search results are saved to, so that the review can be resumed later
+    review_file: PathBuf,
+    /// If set, each file modified by a replacement is recorded here
+    audit_file: Option<PathBuf>,
+    /// If set, only this many results are shown for each file until the file is expanded
+    max_results_per_file: Option<usize>,
+    /// If true, runs of whitespace in fixed search strings match any run of whitespace
+    normalize_whitespace: bool,
+    /// If true, runs of whitespace in replaced lines are collapsed into a single space
+    collapse_whitespace: bool,
+    /// Files that the user has asked to open in their editor, which can only be done once the
+    /// terminal has been handed over
+    editor_request: Option<Vec<PathBuf>>,
+    /// A message about the outcome of the last action, e.g. copying to the clipboard, shown with
+    /// the search results until the next key press
+    notice: Option<String>,
 
     app_event_sender: UnboundedSender<AppEvent>,
 }
 
-const BINARY_EXTENSIONS: &[&str] = &["png", "gif", "jpg", "jpeg", "ico", "svg", "pdf"];
+/// The settings that a search and replacement will run with, once the command-line flags have been
+/// applied over the defaults, as printed by `--print-config`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ResolvedConfig {
+    pub directories: Vec<PathBuf>,
+    pub include_hidden: bool,
+    pub include_ignored: bool,
+    pub fixed_strings: bool,
+    pub advanced_regex: bool,
+    pub path_pattern: String,
+    pub path_glob: bool,
+    pub path_fuzzy: bool,
+    pub min_fixed_len: usize,
+    pub git_modified_only: bool,
+    pub lossy: bool,
+    pub repeat: bool,
+    pub replacement_map: bool,
+    pub max_open_files: Option<usize>,
+    /// In seconds
+    pub changed_within: Option<u64>,
+    /// In seconds
+    pub changed_before: Option<u64>,
+    pub follow_symlinks: bool,
+    pub pruned_dirs: Vec<String>,
+    pub search_git: bool,
+    pub occurrence: Option<usize>,
+    pub occurrence_per_file: bool,
+    pub line_filter: Option<String>,
+    pub expand_env: bool,
+    pub counter: CounterConfig,
+    pub compact: bool,
+    pub results_context: usize,
+    pub content_width: String,
+    pub verify: bool,
+    pub retry_failed: bool,
+    pub large_search_confirmed: bool,
+    pub preview_limit: Option<usize>,
+    pub review_file: PathBuf,
+    pub audit_file: Option<PathBuf>,
+    pub max_results_per_file: Option<usize>,
+    pub normalize_whitespace: bool,
+    pub collapse_whitespace: bool,
+}
+
+/// Changes `result` so that only the match at `match_idx` in its line is replaced, returning false
+/// if this isn't possible because the positions of the matches aren't known, e.g. when replacing
+/// repeatedly
+fn replace_only_match(result: &mut SearchResult, match_idx: usize) -> bool {
+    if result.num_matches == 1 && match_idx == 0 {
+        return true;
+    }
+    let (Some(span), Some(replacement_span)) = (
+        result.match_spans.get(match_idx).cloned(),
+        result.replacement_spans.get(match_idx).cloned(),
+    ) else {
+        return false;
+    };
+    let replaced = result.replacement[replacement_span].to_owned();
+    let start = span.start;
+    result.replacement = format!(
+        "{}{replaced}{}",
+        &result.line[..start],
+        &result.line[span.end..]
+    );
+    result.match_spans = vec![span];
+    let replacement_span = start..start + replaced.len();
+    result.replacement_spans = vec![replacement_span];
+    result.num_matches = 1;
+    true
+}
+
+/// Identifies a search result across searches: the path, line number and line
+type ResultKey = (PathBuf, usize, String);
+
+fn result_key(result: &SearchResult) -> ResultKey {
+    (result.path.clone(), result.line_number, result.line.clone())
+}
+
+/// Opens the directory containing `path` in the system file manager, logging any failure as
+/// there's nowhere else to report it
+fn open_directory_of(path: &Path) {
+    if let Err(e) = open_containing_directory(path) {
+        warn!("Couldn't open the directory containing {:?}: {e}", path);
+    }
+}
+
+const RESULT_NOT_FOUND_ERROR: &str = "Failed to find search result in file";
+const VALIDATION_DEBOUNCE: Duration = Duration::from_millis(300);
 
 impl App {
     pub fn new(
-        directory: Option<PathBuf>,
+        directories: Vec<PathBuf>,
         include_hidden: bool,
         advanced_regex: bool,
         app_event_sender: UnboundedSender<AppEvent>,
     ) -> Self {
-        let directory = match directory {
-            Some(d) => d,
-            None => std::env::current_dir().unwrap(),
+        let directories = if directories.is_empty() {
+            vec![std::env::current_dir().unwrap()]
+        } else {
+            directories
         };
         let search_fields =
             SearchFields::with_values("", "", false, "").with_advanced_regex(advanced_regex);
@@ -399,14 +1594,305 @@ impl App {
         Self {
             current_screen: Screen::SearchFields,
             search_fields,
-            directory,
+            log_viewer: None,
+            editing_replacement: None,
+            confirming_large_search: false,
+            directories,
             include_hidden,
+            include_ignored: false,
+            untoggled_hidden_and_ignored: None,
+            counter: CounterConfig::default(),
+            git_modified_only: false,
+            lossy: false,
+            repeat: false,
+            replacement_map: None,
+            max_open_files: None,
+            changed_within: None,
+            changed_before: None,
+            follow_symlinks: false,
+            expand_env: false,
+            compact: false,
+            results_context: 0,
+            content_width: ContentWidth::default(),
+            verify: false,
+            retry_failed: false,
+            large_search_confirmed: false,
+            preview_limit: None,
+            search_paused: false,
+            binary_lines_skipped: 0,
+            binary_files_skipped: 0,
+            large_dirs: dirs::home_dir().into_iter().collect(),
+            pruned_dirs: DEFAULT_PRUNED_DIRS.iter().map(|&d| d.to_owned()).collect(),
+            search_git: false,
+            min_fixed_len: DEFAULT_MIN_FIXED_LEN,
+            search_only: false,
+            occurrence: None,
+            occurrence_per_file: false,
+            line_filter: None,
+            pending_validation: None,
+            inclusion_decisions: HashMap::new(),
+            last_search_fields: None,
+            review_file: default_review_file(),
+            audit_file: None,
+            max_results_per_file: None,
+            normalize_whitespace: false,
+            collapse_whitespace: false,
+            editor_request: None,
+            notice: None,
 
             app_event_sender,
         }
     }
 
+    /// Search files that would otherwise be skipped due to `.gitignore`, `.ignore` etc.
+    pub fn with_include_ignored(mut self, include_ignored: bool) -> Self {
+        self.include_ignored = include_ignored;
+        self
+    }
+
+    /// Configure how the `${n}` token in the replacement text is numbered
+    pub fn with_counter(mut self, counter: CounterConfig) -> Self {
+        self.counter = counter;
+        self
+    }
+
+    /// Only search files that git reports as modified relative to HEAD, or untracked
+    pub fn with_git_modified_only(mut self, git_modified_only: bool) -> Self {
+        self.git_modified_only = git_modified_only;
+        self
+    }
+
+    /// Search lines that aren't valid UTF-8, by replacing invalid sequences with U+FFFD. Such
+    /// lines are never written back to, to avoid corrupting them.
+    pub fn with_lossy(mut self, lossy: bool) -> Self {
+        self.lossy = lossy;
+        self
+    }
+
+    /// Apply the replacement to each line repeatedly until it stops changing, e.g. so that
+    /// replacing `aa` with `a` in `aaaa` gives `a`, up to `MAX_REPEAT_ITERATIONS` times
+    pub fn with_repeat(mut self, repeat: bool) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    /// Replace each match with its entry in `replacement_map` rather than with the replace text.
+    /// The search text should match the strings in the map, e.g. using
+    /// `ReplacementMap::search_pattern`.
+    pub fn with_replacement_map(mut self, replacement_map: Option<ReplacementMap>) -> Self {
+        self.replacement_map = replacement_map.map(Arc::new);
+        self
+    }
+
+    /// Keep at most this many files open at once while searching, to avoid running out of file
+    /// descriptors
+    pub fn with_max_open_files(mut self, max_open_files: Option<usize>) -> Self {
+        self.max_open_files = max_open_files;
+        self
+    }
+
+    /// Only search files modified within this long before each search
+    pub fn with_changed_within(mut self, changed_within: Option<Duration>) -> Self {
+        self.changed_within = changed_within;
+        self
+    }
+
+    /// Only search files last modified at least this long before each search
+    pub fn with_changed_before(mut self, changed_before: Option<Duration>) -> Self {
+        self.changed_before = changed_before;
+        self
+    }
+
+    /// Search symlinked files and directories. Replacing in a symlinked file writes to the file
+    /// it links to, leaving the symlink in place.
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Expand references to environment variables, such as `$HOME` or `${USER}`, in the replacement
+    /// text. Capture groups take precedence over environment variables with the same name.
+    pub fn with_expand_env(mut self, expand_env: bool) -> Self {
+        self.expand_env = expand_env;
+        self
+    }
+
+    /// Show each result on the confirmation screen as a single inline diff, with no separator
+    pub fn with_compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    /// Show this many lines either side of each successful replacement on the results screen.
+    /// These are read from the file once replacing is complete, so reflect any other
+    /// replacements made nearby.
+    pub fn with_results_context(mut self, results_context: usize) -> Self {
+        self.results_context = results_context;
+        self
+    }
+
+    /// Refuse fixed search strings shorter than this many characters
+    pub fn with_min_fixed_len(mut self, min_fixed_len: usize) -> Self {
+        self.min_fixed_len = min_fixed_len;
+        self
+    }
+
+    /// Ignore the replace field, e.g. when only counting or exporting matches, so that each
+    /// result's replacement is the unchanged line
+    pub fn with_search_only(mut self, search_only: bool) -> Self {
+        self.search_only = search_only;
+        self
+    }
+
+    /// Set the width of the main content, which defaults to 80% of the terminal
+    pub fn with_content_width(mut self, content_width: ContentWidth) -> Self {
+        self.content_width = content_width;
+        self
+    }
+
+    /// After replacing, search the replaced files again and count any matches that remain, e.g.
+    /// because the replacement text itself matches the search
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// If any replacements fail, go back to the confirmation list with only the failed results
+    /// selected, so that they can be retried, rather than showing the errors on their own
+    pub fn with_retry_failed(mut self, retry_failed: bool) -> Self {
+        self.retry_failed = retry_failed;
+        self
+    }
+
+    /// Search without asking for confirmation, even if a directory is very large, such as the home
+    /// directory or a filesystem root
+    pub fn with_large_search_confirmed(mut self, large_search_confirmed: bool) -> Self {
+        self.large_search_confirmed = large_search_confirmed;
+        self
+    }
+
+    /// Pause each search once this many results have been found, so that the pattern can be
+    /// checked before searching everything. The search can then be resumed with `resume_search`.
+    pub fn with_preview_limit(mut self, preview_limit: Option<usize>) -> Self {
+        self.preview_limit = preview_limit;
+        self
+    }
+
+    /// Names of directories that are never searched, replacing `DEFAULT_PRUNED_DIRS`
+    pub fn with_pruned_dirs(mut self, pruned_dirs: Vec<String>) -> Self {
+        self.pruned_dirs = pruned_dirs;
+        self
+    }
+
+    /// Search inside `.git` directories, which are otherwise never searched
+    pub fn with_search_git(mut self, search_git: bool) -> Self {
+        self.search_git = search_git;
+        self
+    }
+
+    /// The settings that searches and replacements will use
+    pub fn resolved_config(&self) -> ResolvedConfig {
+        ResolvedConfig {
+            directories: self.directories.clone(),
+            include_hidden: self.include_hidden,
+            include_ignored: self.include_ignored,
+            fixed_strings: self.search_fields.fixed_strings().checked,
+            advanced_regex: self.search_fields.advanced_regex(),
+            path_pattern: self.search_fields.path_pattern().text.clone(),
+            path_glob: self.search_fields.path_glob().checked,
+            path_fuzzy: self.search_fields.path_fuzzy(),
+            min_fixed_len: self.min_fixed_len,
+            git_modified_only: self.git_modified_only,
+            lossy: self.lossy,
+            repeat: self.repeat,
+            replacement_map: self.replacement_map.is_some(),
+            max_open_files: self.max_open_files,
+            changed_within: self.changed_within.map(|d| d.as_secs()),
+            changed_before: self.changed_before.map(|d| d.as_secs()),
+            follow_symlinks: self.follow_symlinks,
+            pruned_dirs: self.pruned_dirs.clone(),
+            search_git: self.search_git,
+            occurrence: self.occurrence,
+            occurrence_per_file: self.occurrence_per_file,
+            line_filter: self.line_filter.as_ref().map(|re| re.as_str().to_owned()),
+            expand_env: self.expand_env,
+            counter: self.counter.clone(),
+            compact: self.compact,
+            results_context: self.results_context,
+            content_width: self.content_width.to_string(),
+            verify: self.verify,
+            retry_failed: self.retry_failed,
+            large_search_confirmed: self.large_search_confirmed,
+            preview_limit: self.preview_limit,
+            review_file: self.review_file.clone(),
+            audit_file: self.audit_file.clone(),
+            max_results_per_file: self.max_results_per_file,
+            normalize_whitespace: self.normalize_whitespace,
+            collapse_whitespace: self.collapse_whitespace,
+        }
+    }
+
+    /// Append a record of each file modified by a replacement to this file, as JSON lines
+    pub fn with_audit_file(mut self, audit_file: Option<PathBuf>) -> Self {
+        self.audit_file = audit_file;
+        self
+    }
+
+    /// Show only the first `max_results_per_file` results in each file, until the file is expanded
+    /// with `m`
+    pub fn with_max_results_per_file(mut self, max_results_per_file: Option<usize>) -> Self {
+        self.max_results_per_file = max_results_per_file;
+        self
+    }
+
+    /// When searching with fixed strings, match each run of whitespace in the search text against
+    /// any run of whitespace, so that `foo bar` also matches `foo   bar`
+    pub fn with_normalize_whitespace(mut self, normalize_whitespace: bool) -> Self {
+        self.normalize_whitespace = normalize_whitespace;
+        self
+    }
+
+    /// Collapse runs of whitespace in the lines that are replaced into a single space, rather than
+    /// keeping the original whitespace
+    pub fn with_collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.collapse_whitespace = collapse_whitespace;
+        self
+    }
+
+    /// Replace only the match with this number, counting from 1 in order of path and line number,
+    /// leaving every other match as it is
+    pub fn with_occurrence(mut self, occurrence: Option<usize>) -> Self {
+        self.occurrence = occurrence;
+        self
+    }
+
+    /// Count the matches for `with_occurrence` within each file, rather than across the whole
+    /// search, so that one match is replaced in each file
+    pub fn with_occurrence_per_file(mut self, occurrence_per_file: bool) -> Self {
+        self.occurrence_per_file = occurrence_per_file;
+        self
+    }
+
+    /// Only replace in lines that also match `line_filter`, in addition to the search pattern
+    pub fn with_line_filter(mut self, line_filter: Option<Regex>) -> Self {
+        self.line_filter = line_filter;
+        self
+    }
+
+    pub(crate) fn compact(&self) -> bool {
+        self.compact
+    }
+
+    pub(crate) fn results_context(&self) -> usize {
+        self.results_context
+    }
+
+    pub(crate) fn content_width(&self) -> ContentWidth {
+        self.content_width
+    }
+
     pub fn cancel_search(&mut self) {
+        self.remember_inclusion_decisions();
         if let Screen::SearchProgressing(SearchInProgressState { handle, .. }) =
             &mut self.current_screen
         {
@@ -415,14 +1901,65 @@ impl App {
         self.current_screen = Screen::SearchFields;
     }
 
+    fn remember_inclusion_decisions(&mut self) {
+        let search_state = match &self.current_screen {
+            Screen::SearchProgressing(SearchInProgressState { search_state, .. })
+            | Screen::SearchComplete(search_state) => search_state,
+            _ => return,
+        };
+        self.inclusion_decisions.extend(
+            search_state
+                .results
+                .iter()
+                .map(|res| (result_key(res), res.included)),
+        );
+    }
+
+    /// Clears the search fields and returns to them, ready for a new search. This resets only the
+    /// state of the current search in place, rather than rebuilding the app with `App::new`, as
+    /// most configuration (e.g. `--lossy` or `--changed-within`) is only set through the `with_*`
+    /// builders and so would otherwise be lost.
     pub fn reset(&mut self) {
         self.cancel_search();
-        *self = Self::new(
-            Some(self.directory.clone()),
-            self.include_hidden,
-            self.search_fields.advanced_regex,
-            self.app_event_sender.clone(),
-        );
+        if let Some(handle) = self.pending_validation.take() {
+            handle.abort();
+        }
+        self.current_screen = Screen::SearchFields;
+        self.inclusion_decisions.clear();
+        let empty_fields = match &self.replacement_map {
+            Some(replacement_map) => {
+                SearchFields::with_values(replacement_map.search_text(), "", true, "")
+            }
+            None => SearchFields::with_values("", "", false, ""),
+        }
+        .with_advanced_regex(self.search_fields.advanced_regex)
+        .with_path_fuzzy(self.search_fields.path_fuzzy);
+        let search_fields = mem::replace(&mut self.search_fields, empty_fields);
+        // Resetting twice shouldn't lose the previous search
+        if !search_fields.is_empty() {
+            self.last_search_fields = Some(search_fields);
+        }
+    }
+
+    /// Whether `field` can't be edited, as it was set from the `--map` file
+    pub fn is_read_only(&self, field: &FieldName) -> bool {
+        self.replacement_map.is_some()
+            && matches!(
+                field,
+                FieldName::Search | FieldName::Replace | FieldName::FixedStrings
+            )
+    }
+
+    pub fn can_restore_last_search(&self) -> bool {
+        self.last_search_fields.is_some()
+    }
+
+    /// Repopulates the search fields with their values from before the last reset
+    pub fn restore_last_search(&mut self) {
+        if let Some(mut search_fields) = self.last_search_fields.take() {
+            search_fields.close_error_popup();
+            self.search_fields = search_fields;
+        }
     }
 
     pub async fn background_processing_recv(&mut self) -> Option<BackgroundProcessingEvent> {
@@ -439,7 +1976,6 @@ impl App {
         }
     }
 
-    #[allow(dead_code)]
     pub fn background_processing_sender(
         &mut self,
     ) -> Option<&mut UnboundedSender<BackgroundProcessingEvent>> {
@@ -460,30 +1996,149 @@ impl App {
                 rerender: true,
             },
             AppEvent::PerformSearch => self.perform_search_if_valid(),
+            AppEvent::ShowNotice(notice) => {
+                self.notice = Some(notice);
+                EventHandlingResult {
+                    exit: false,
+                    rerender: true,
+                }
+            }
+            AppEvent::ValidateFields => {
+                self.pending_validation = None;
+                let rerender = matches!(self.current_screen, Screen::SearchFields);
+                if rerender {
+                    self.search_fields.validate_patterns();
+                }
+                EventHandlingResult {
+                    exit: false,
+                    rerender,
+                }
+            }
         }
     }
 
+    fn schedule_validation(&mut self) {
+        if let Some(handle) = self.pending_validation.take() {
+            handle.abort();
+        }
+        let app_event_sender = self.app_event_sender.clone();
+        self.pending_validation = Some(tokio::spawn(async move {
+            tokio::time::sleep(VALIDATION_DEBOUNCE).await;
+            // Ignore error: the app may have exited
+            let _ = app_event_sender.send(AppEvent::ValidateFields);
+        }));
+    }
+
+    /// Directories being searched that are likely to take a long time to search
+    pub fn large_search_roots(&self) -> Vec<&Path> {
+        self.directories
+            .iter()
+            .filter(|dir| is_large_search_root(dir, &self.large_dirs))
+            .map(PathBuf::as_path)
+            .collect()
+    }
+
     pub fn perform_search_if_valid(&mut self) -> EventHandlingResult {
+        self.perform_search_up_to(self.preview_limit)
+    }
+
+    /// The directories that are searched. There is always at least one, as the current directory
+    /// is used if none are given.
+    pub fn directories(&self) -> &[PathBuf] {
+        &self.directories
+    }
+
+    /// The first of the directories that are searched
+    pub fn directory(&self) -> &Path {
+        &self.directories[0]
+    }
+
+    /// Whether hidden files, such as those whose name starts with a `.`, are searched
+    pub fn include_hidden(&self) -> bool {
+        self.include_hidden
+    }
+
+    /// Whether files skipped due to `.gitignore`, `.ignore` etc. are searched
+    pub fn include_ignored(&self) -> bool {
+        self.include_ignored
+    }
+
+    pub fn advanced_regex(&self) -> bool {
+        self.search_fields.advanced_regex()
+    }
+
+    /// Whether the last search was paused after finding the preview number of results, so may
+    /// not have found everything
+    pub fn search_paused(&self) -> bool {
+        self.search_paused
+    }
+
+    /// The number of lines that matched the last search but were skipped because they look binary
+    pub fn binary_lines_skipped(&self) -> usize {
+        self.binary_lines_skipped
+    }
+
+    /// The number of files containing lines counted by `binary_lines_skipped`
+    pub fn binary_files_skipped(&self) -> usize {
+        self.binary_files_skipped
+    }
+
+    /// A message about the outcome of the last action, if there is one to show
+    pub fn notice(&self) -> Option<&str> {
+        self.notice.as_deref()
+    }
+
+    /// Takes the files that the user has asked to open in their editor, if any
+    pub fn take_editor_request(&mut self) -> Option<Vec<PathBuf>> {
+        self.editor_request.take()
+    }
+
+    /// Searches again without stopping at the preview limit, if the last search was paused.
+    /// Results from the preview keep their inclusion state.
+    pub fn resume_search(&mut self) -> EventHandlingResult {
+        if !(self.search_paused && matches!(self.current_screen, Screen::SearchComplete(_))) {
+            return EventHandlingResult {
+                exit: false,
+                rerender: false,
+            };
+        }
+        self.perform_search_up_to(None)
+    }
+
+    fn perform_search_up_to(&mut self, limit: Option<usize>) -> EventHandlingResult {
+        self.search_paused = false;
+        self.binary_lines_skipped = 0;
+        self.binary_files_skipped = 0;
+        if !self.large_search_confirmed && !self.large_search_roots().is_empty() {
+            self.confirming_large_search = true;
+            self.current_screen = Screen::SearchFields;
+            return EventHandlingResult {
+                exit: false,
+                rerender: true,
+            };
+        }
+
+        self.remember_inclusion_decisions();
         let (background_processing_sender, background_processing_receiver) =
             mpsc::unbounded_channel();
 
-        match self
-            .validate_fields(background_processing_sender.clone())
-            .unwrap()
-        {
+        match self.validate_fields(background_processing_sender.clone()) {
             None => {
                 self.current_screen = Screen::SearchFields;
             }
             Some(parsed_fields) => {
                 let handle = Self::update_search_results(
                     parsed_fields,
+                    limit,
                     background_processing_sender.clone(),
                 );
-                self.current_screen = Screen::SearchProgressing(SearchInProgressState::new(
+                let mut search_in_progress_state = SearchInProgressState::new(
                     handle,
                     background_processing_sender,
                     background_processing_receiver,
-                ));
+                );
+                search_in_progress_state.search_state.max_per_file = self.max_results_per_file;
+                self.current_screen = Screen::SearchProgressing(search_in_progress_state);
             }
         };
 
@@ -505,8 +2160,24 @@ impl App {
                 background_processing_receiver,
             )),
         ) {
-            Screen::SearchComplete(search_state) => {
-                let handle = Self::perform_replacement(search_state, background_processing_sender);
+            Screen::SearchComplete(mut search_state) => {
+                search_state.exclude_outside_visual_range();
+                let verification = if self.verify {
+                    let (verification_sender, verification_receiver) = mpsc::unbounded_channel();
+                    self.validate_fields(verification_sender)
+                        .map(|parsed_fields| (parsed_fields, verification_receiver))
+                } else {
+                    None
+                };
+                let handle = Self::perform_replacement(
+                    search_state,
+                    self.counter.clone(),
+                    self.follow_symlinks,
+                    verification,
+                    self.audit_file.clone(),
+                    self.results_context,
+                    background_processing_sender,
+                );
                 if let Screen::PerformingReplacement(ref mut state) = &mut self.current_screen {
                     state.set_handle(handle);
                 } else {
@@ -523,28 +2194,98 @@ impl App {
     }
     pub fn perform_replacement(
         mut search_state: SearchState,
+        counter: CounterConfig,
+        follow_symlinks: bool,
+        verification: Option<(ParsedFields, UnboundedReceiver<BackgroundProcessingEvent>)>,
+        audit_file: Option<PathBuf>,
+        results_context: usize,
         background_processing_sender: UnboundedSender<BackgroundProcessingEvent>,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            for (path, results) in &search_state
+            let mut counter = Counter::new(counter);
+            search_state
                 .results
                 .iter_mut()
                 .filter(|res| res.included)
-                .chunk_by(|res| res.path.clone())
-            {
-                let mut results = results.collect::<Vec<_>>();
-                if let Err(file_err) = Self::replace_in_file(path, &mut results) {
+                .for_each(|res| res.replacement = counter.apply(&res.path, &res.replacement));
+
+            // Paths that are hard links to the same file are replaced in together, as replacing in
+            // each separately would break the link
+            let hard_links = Self::hard_link_aliases(
+                search_state
+                    .results
+                    .iter()
+                    .filter(|res| res.included)
+                    .map(|res| res.path.as_path()),
+            );
+            let mut files: Vec<(PathBuf, Vec<&mut SearchResult>)> = vec![];
+            let mut file_positions = HashMap::new();
+            for res in search_state.results.iter_mut().filter(|res| res.included) {
+                let path = hard_links.get(&res.path).unwrap_or(&res.path).clone();
+                let position = *file_positions.entry(path.clone()).or_insert_with(|| {
+                    files.push((path, vec![]));
+                    files.len() - 1
+                });
+                files[position].1.push(res);
+            }
+
+            let num_files = files.len();
+            let mut audit_records = vec![];
+            for (num_files_done, (path, mut results)) in files.into_iter().enumerate() {
+                // Ignore error: we may have gone back to the previous screen
+                let _ = background_processing_sender.send(
+                    BackgroundProcessingEvent::ReplacementProgress(ReplacementProgress {
+                        current_file: path.clone(),
+                        num_files_done,
+                        num_files,
+                    }),
+                );
+                if let Err(file_err) =
+                    Self::replace_in_file(path.clone(), &mut results, follow_symlinks)
+                {
                     results.iter_mut().for_each(|res| {
                         res.replace_result = Some(ReplaceResult::Error(file_err.to_string()))
                     });
                 }
+                if audit_file.is_some() {
+                    let replacements = results
+                        .iter()
+                        .filter(|res| res.replace_result == Some(ReplaceResult::Success))
+                        // Hard links to this file each have a result for the same line, which
+                        // was only replaced once
+                        .unique_by(|res| res.line_number)
+                        .map(|res| res.num_matches)
+                        .sum();
+                    if replacements > 0 {
+                        audit_records.push(AuditRecord {
+                            timestamp: SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .map_or(0, |d| d.as_secs()),
+                            path,
+                            replacements,
+                        });
+                    }
+                }
+            }
+            if let Some(audit_file) = audit_file {
+                if let Err(e) = append_audit_records(&audit_file, &audit_records) {
+                    warn!("Couldn't write to audit log {:?}: {e}", audit_file);
+                }
             }
 
-            let replace_state = Self::calculate_statistics(&search_state.results);
+            let mut replace_state = Self::calculate_statistics(&search_state.results);
+            replace_state.read_success_context(results_context);
+            if let Some((parsed_fields, receiver)) = verification {
+                replace_state.num_remaining_matches = Some(Self::count_remaining_matches(
+                    &search_state.results,
+                    parsed_fields,
+                    receiver,
+                ));
+            }
 
             // Ignore error: we may have gone back to the previous screen
             let _ = background_processing_sender.send(
-                BackgroundProcessingEvent::ReplacementCompleted(replace_state),
+                BackgroundProcessingEvent::ReplacementCompleted(replace_state, search_state),
             );
         })
     }
@@ -554,12 +2295,15 @@ impl App {
         event: BackgroundProcessingEvent,
     ) -> EventHandlingResult {
         match event {
-            BackgroundProcessingEvent::AddSearchResult(result) => {
+            BackgroundProcessingEvent::AddSearchResult(mut result) => {
+                if let Some(&included) = self.inclusion_decisions.get(&result_key(&result)) {
+                    result.included = included;
+                }
                 let mut rerender = false;
                 if let Screen::SearchProgressing(search_in_progress_state) =
                     &mut self.current_screen
                 {
-                    search_in_progress_state.search_state.results.push(result);
+                    search_in_progress_state.add_result(result);
 
                     if search_in_progress_state.last_render.elapsed() >= Duration::from_millis(100)
                     {
@@ -572,10 +2316,25 @@ impl App {
                     rerender,
                 }
             }
-            BackgroundProcessingEvent::SearchCompleted => {
-                if let Screen::SearchProgressing(SearchInProgressState { search_state, .. }) =
-                    mem::replace(&mut self.current_screen, Screen::SearchFields)
+            BackgroundProcessingEvent::SearchCompleted
+            | BackgroundProcessingEvent::SearchPaused => {
+                self.search_paused = matches!(event, BackgroundProcessingEvent::SearchPaused);
+                if let Screen::SearchProgressing(SearchInProgressState {
+                    mut search_state, ..
+                }) = mem::replace(&mut self.current_screen, Screen::SearchFields)
                 {
+                    search_state.sort_by_file();
+                    if let (Some(occurrence), false) = (self.occurrence, self.search_paused) {
+                        if !search_state
+                            .include_only_occurrence(occurrence, self.occurrence_per_file)
+                        {
+                            self.show_occurrence_not_found(occurrence, &search_state);
+                            return EventHandlingResult {
+                                exit: false,
+                                rerender: true,
+                            };
+                        }
+                    }
                     self.current_screen = Screen::SearchComplete(search_state);
                 }
                 EventHandlingResult {
@@ -583,8 +2342,34 @@ impl App {
                     rerender: true,
                 }
             }
-            BackgroundProcessingEvent::ReplacementCompleted(replace_state) => {
-                self.current_screen = Screen::Results(replace_state);
+            BackgroundProcessingEvent::BinaryLinesSkipped(num_lines) => {
+                self.binary_lines_skipped += num_lines;
+                self.binary_files_skipped += 1;
+                EventHandlingResult {
+                    exit: false,
+                    rerender: false,
+                }
+            }
+            BackgroundProcessingEvent::ReplacementProgress(progress) => {
+                let mut rerender = false;
+                if let Screen::PerformingReplacement(state) = &mut self.current_screen {
+                    state.progress = Some(progress);
+                    if state.last_render.elapsed() >= Duration::from_millis(100) {
+                        rerender = true;
+                        state.last_render = Instant::now();
+                    }
+                }
+                EventHandlingResult {
+                    exit: false,
+                    rerender,
+                }
+            }
+            BackgroundProcessingEvent::ReplacementCompleted(replace_state, search_state) => {
+                self.current_screen = if self.retry_failed && !replace_state.errors.is_empty() {
+                    Screen::SearchComplete(Self::failed_replacements(search_state))
+                } else {
+                    Screen::Results(replace_state)
+                };
                 EventHandlingResult {
                     exit: false,
                     rerender: true,
@@ -593,12 +2378,56 @@ impl App {
         }
     }
 
+    /// Goes back to the search fields, showing an error as there is no match `occurrence` to
+    /// replace
+    fn show_occurrence_not_found(&mut self, occurrence: usize, search_state: &SearchState) {
+        let num_matches = search_state
+            .results
+            .iter()
+            .map(|res| res.num_matches)
+            .sum::<usize>();
+        let long = if self.occurrence_per_file {
+            format!("No file has {occurrence} matches, so there is nothing to replace")
+        } else {
+            format!(
+                "Only {num_matches} {} found, so there is no match {occurrence} to replace",
+                if num_matches == 1 {
+                    "match was"
+                } else {
+                    "matches were"
+                }
+            )
+        };
+        self.search_fields
+            .search_mut()
+            .set_error("Occurrence not found".to_owned(), long);
+        self.search_fields.show_error_popup = true;
+        self.current_screen = Screen::SearchFields;
+    }
+
     fn handle_key_searching(&mut self, key: &KeyEvent) -> bool {
         if self.search_fields.show_error_popup {
-            self.search_fields.show_error_popup = false;
+            match (key.code, key.modifiers) {
+                (KeyCode::Char('j') | KeyCode::Down, _)
+                | (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
+                    self.search_fields.scroll_error_popup_down();
+                }
+                (KeyCode::Char('k') | KeyCode::Up, _)
+                | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
+                    self.search_fields.scroll_error_popup_up();
+                }
+                (KeyCode::Char('l'), KeyModifiers::NONE)
+                    if self.search_fields.can_search_literally() =>
+                {
+                    self.search_fields.search_literally();
+                    self.app_event_sender.send(AppEvent::PerformSearch).unwrap();
+                }
+                _ => self.search_fields.close_error_popup(),
+            }
         } else {
             match (key.code, key.modifiers) {
-                (KeyCode::Enter, _) => {
+                // Alt-enter inserts a newline into the focused field
+                (KeyCode::Enter, modifiers) if !modifiers.contains(KeyModifiers::ALT) => {
                     self.app_event_sender.send(AppEvent::PerformSearch).unwrap();
                 }
                 (KeyCode::BackTab, _) | (KeyCode::Tab, KeyModifiers::ALT) => {
@@ -607,15 +2436,30 @@ impl App {
                 (KeyCode::Tab, _) => {
                     self.search_fields.focus_next();
                 }
+                (KeyCode::Char('r'), KeyModifiers::ALT) => {
+                    self.search_fields.toggle_advanced_regex();
+                }
+                (KeyCode::Char('f'), KeyModifiers::ALT) => {
+                    self.search_fields.toggle_path_fuzzy();
+                }
+                (KeyCode::Char('z'), KeyModifiers::CONTROL) => {
+                    self.restore_last_search();
+                }
+                _ if self.is_read_only(self.search_fields.highlighted_field_name()) => {}
                 (code, modifiers) => {
-                    if let FieldName::FixedStrings = self.search_fields.highlighted_field_name() {
-                        // TODO: ideally this should only happen when the field is checked, but for now this will do
-                        self.search_fields.search_mut().clear_error();
-                    };
                     self.search_fields
                         .highlighted_field()
                         .write()
                         .handle_keys(code, modifiers);
+                    match self.search_fields.highlighted_field_name() {
+                        FieldName::Replace => {}
+                        // Toggling fixed strings changes how both patterns are parsed, so errors
+                        // are cleared (or shown again, when switching back to regex) immediately
+                        FieldName::FixedStrings | FieldName::PathGlob => {
+                            self.search_fields.validate_patterns()
+                        }
+                        FieldName::Search | FieldName::PathPattern => self.schedule_validation(),
+                    }
                 }
             }
         };
@@ -631,9 +2475,21 @@ impl App {
                     .move_selected_down();
             }
             (KeyCode::Char('k') | KeyCode::Up, _) | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
+                if let Screen::SearchProgressing(search_in_progress_state) =
+                    &mut self.current_screen
+                {
+                    search_in_progress_state.follow = false;
+                }
                 // TODO: need to fix issue where screen gets out of sync with state
                 self.current_screen.search_results_mut().move_selected_up();
             }
+            (KeyCode::Char('f'), _) => {
+                if let Screen::SearchProgressing(search_in_progress_state) =
+                    &mut self.current_screen
+                {
+                    search_in_progress_state.toggle_follow();
+                }
+            }
             (KeyCode::Char(' '), _) => {
                 self.current_screen
                     .search_results_mut()
@@ -644,8 +2500,87 @@ impl App {
                     .search_results_mut()
                     .toggle_all_selected();
             }
+            (KeyCode::Char('e'), _) => {
+                self.current_screen
+                    .search_results_mut()
+                    .toggle_selected_extension();
+            }
+            (KeyCode::Char('}'), _) => {
+                self.current_screen.search_results_mut().move_to_next_file();
+            }
+            (KeyCode::Char('{'), _) => {
+                self.current_screen.search_results_mut().move_to_prev_file();
+            }
+            (KeyCode::Char('J'), _) => {
+                self.current_screen.search_results_mut().toggle_to_end();
+            }
+            (KeyCode::Char('K'), _) => {
+                self.current_screen.search_results_mut().toggle_to_start();
+            }
+            (KeyCode::Char('u'), _) => {
+                self.current_screen
+                    .search_results_mut()
+                    .toggle_hide_unchanged();
+            }
+            (KeyCode::Char('m'), KeyModifiers::NONE) => {
+                self.current_screen
+                    .search_results_mut()
+                    .toggle_selected_file_expanded();
+            }
+            (KeyCode::Char('v'), _) => {
+                self.current_screen
+                    .search_results_mut()
+                    .toggle_visual_mode();
+            }
+            (KeyCode::Char('i'), KeyModifiers::NONE) => {
+                self.edit_selected_replacement();
+            }
             (KeyCode::Enter, _) => {
-                self.trigger_replacement();
+                self.review_replacement();
+            }
+            (KeyCode::Char('c'), KeyModifiers::NONE) => {
+                self.confirm_each_file();
+            }
+            (KeyCode::Char('h'), KeyModifiers::NONE) => {
+                self.toggle_hidden_and_search();
+            }
+            (KeyCode::Char('r'), KeyModifiers::NONE) => {
+                self.resume_search();
+            }
+            (KeyCode::Char('s'), KeyModifiers::NONE) => {
+                self.save_review();
+            }
+            (KeyCode::Char('y'), KeyModifiers::NONE) => {
+                let search_state = self.current_screen.search_results_mut();
+                if let Some(result) = search_state.results.get(search_state.selected).cloned() {
+                    let context =
+                        read_lines_around(&result.path, result.line_number, COPIED_DIFF_CONTEXT)
+                            .unwrap_or_else(|e| {
+                                warn!("Couldn't read context from {:?}: {e}", result.path);
+                                vec![]
+                            });
+                    copy_to_clipboard(
+                        plain_text_diff(&result, &self.relative_path(&result.path), &context),
+                        self.app_event_sender.clone(),
+                    );
+                }
+            }
+            (KeyCode::Char('Y'), _) => {
+                if let Some(patch) = self.changeset_patch() {
+                    if patch.len() > LARGE_PATCH_BYTES {
+                        self.notice = Some(format!(
+                            "Copied a patch of {} bytes, which may be too large to paste",
+                            patch.len()
+                        ));
+                    }
+                    copy_to_clipboard(patch, self.app_event_sender.clone());
+                }
+            }
+            (KeyCode::Char('o'), KeyModifiers::NONE) => {
+                let search_state = self.current_screen.search_results_mut();
+                if let Some(result) = search_state.results.get(search_state.selected) {
+                    open_directory_of(&result.path);
+                }
             }
             (KeyCode::Char('o'), KeyModifiers::CONTROL) => {
                 self.cancel_search();
@@ -657,15 +2592,278 @@ impl App {
         false
     }
 
-    pub fn handle_key_events(&mut self, key: &KeyEvent) -> anyhow::Result<EventHandlingResult> {
+    fn handle_key_file_confirmation(&mut self, key: &KeyEvent) -> bool {
+        let Screen::ConfirmingFiles(state) = &mut self.current_screen else {
+            return false;
+        };
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('y'), KeyModifiers::NONE) => {
+                state.accept_current();
+            }
+            (KeyCode::Char('n'), KeyModifiers::NONE) => {
+                state.decline_current();
+            }
+            (KeyCode::Char('j') | KeyCode::Down, _)
+            | (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
+                state.scroll_down();
+            }
+            (KeyCode::Char('k') | KeyCode::Up, _) | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
+                state.scroll_up();
+            }
+            (KeyCode::Char('o'), KeyModifiers::NONE) => {
+                if let Some((path, _)) = state.current_file() {
+                    open_directory_of(path);
+                }
+            }
+            (KeyCode::Char('o'), KeyModifiers::CONTROL) => {
+                if let Screen::ConfirmingFiles(state) =
+                    mem::replace(&mut self.current_screen, Screen::SearchFields)
+                {
+                    self.current_screen = Screen::SearchComplete(state.search_state);
+                }
+                return false;
+            }
+            _ => {}
+        }
+        if state.is_finished() {
+            self.finish_file_confirmation();
+        }
+        false
+    }
+
+    /// Moves from the search results to confirming the replacements one file at a time
+    pub fn confirm_each_file(&mut self) {
+        // If the search is still in progress we can't yet know which files to confirm
+        if !matches!(self.current_screen, Screen::SearchComplete(_)) {
+            return;
+        }
+        if let Screen::SearchComplete(search_state) =
+            mem::replace(&mut self.current_screen, Screen::SearchFields)
+        {
+            let state = FileConfirmationState::new(search_state);
+            let is_finished = state.is_finished();
+            self.current_screen = Screen::ConfirmingFiles(state);
+            if is_finished {
+                self.finish_file_confirmation();
+            }
+        }
+    }
+
+    /// A unified diff of the results found so far that would be replaced, grouped by file, as
+    /// copied with `<Y>`. Counter tokens are numbered as they would be when replacing. Returns
+    /// `None` if no search results are being shown.
+    pub fn changeset_patch(&self) -> Option<String> {
+        let search_state = match &self.current_screen {
+            Screen::SearchProgressing(SearchInProgressState { search_state, .. })
+            | Screen::SearchComplete(search_state) => search_state,
+            _ => return None,
+        };
+        let mut counter = Counter::new(self.counter.clone());
+        Some(unified_patch(search_state.results_to_replace().map(
+            |(_, res)| {
+                let result = SearchResult {
+                    replacement: counter.apply(&res.path, &res.replacement),
+                    ..res.clone()
+                };
+                ResultRecord::new(&result, self.relative_path(&res.path))
+            },
+        )))
+    }
+
+    /// Saves the search results, along with which are included and which is selected, so that
+    /// the review can be picked up again later with `resume_review`
+    pub fn save_review(&mut self) {
+        // Results can still arrive while the search is in progress, so only complete searches
+        // are saved
+        let Screen::SearchComplete(ref search_state) = self.current_screen else {
+            return;
+        };
+        self.notice = Some(match search_state.save(&self.review_file) {
+            Ok(()) => format!("Saved review to {}", self.review_file.display()),
+            Err(e) => e.to_string(),
+        });
+    }
+
+    /// Starts editing the replacement for the selected result by hand, once the search has
+    /// completed
+    pub fn edit_selected_replacement(&mut self) {
+        let Screen::SearchComplete(search_state) = &self.current_screen else {
+            return;
+        };
+        if let Some(result) = search_state.results.get(search_state.selected) {
+            let mut field = TextField::new(display_counter_placeholders(&result.replacement));
+            field.move_cursor_end();
+            self.editing_replacement = Some(field);
+        }
+    }
+
+    /// Uses the edited text as the replacement for the selected result
+    pub fn finish_editing_replacement(&mut self) {
+        let Some(field) = self.editing_replacement.take() else {
+            return;
+        };
+        if let Screen::SearchComplete(search_state) = &mut self.current_screen {
+            search_state.set_selected_replacement(insert_counter_placeholders(&field.text));
+        }
+    }
+
+    /// Loads results saved with `save_review` from `path` and shows them as the results of a
+    /// completed search, leaving out any that are out of date. Saving again writes back to `path`.
+    pub fn resume_review(&mut self, path: &Path) -> Result<(), ScooterError> {
+        let mut search_state = SearchState::load(path)?;
+        let num_removed = search_state.revalidate();
+        if num_removed > 0 {
+            warn!("{num_removed} saved results were removed as their files have changed");
+        }
+        search_state.max_per_file = self.max_results_per_file;
+        self.current_screen = Screen::SearchComplete(search_state);
+        self.review_file = path.to_owned();
+        Ok(())
+    }
+
+    /// Searches again with the same fields once the search has completed, but with hidden and
+    /// ignored files included if hidden files weren't before, or excluded if they were. Toggling
+    /// again restores both settings as they were.
+    pub fn toggle_hidden_and_search(&mut self) -> EventHandlingResult {
+        if !matches!(self.current_screen, Screen::SearchComplete(_)) {
+            return EventHandlingResult {
+                exit: false,
+                rerender: false,
+            };
+        }
+        (self.include_hidden, self.include_ignored) = match self.untoggled_hidden_and_ignored.take()
+        {
+            Some(untoggled) => untoggled,
+            None => {
+                self.untoggled_hidden_and_ignored =
+                    Some((self.include_hidden, self.include_ignored));
+                (!self.include_hidden, !self.include_hidden)
+            }
+        };
+        self.perform_search_if_valid()
+    }
+
+    fn finish_file_confirmation(&mut self) {
+        if let Screen::ConfirmingFiles(state) =
+            mem::replace(&mut self.current_screen, Screen::SearchFields)
+        {
+            self.current_screen = Screen::SearchComplete(state.search_state);
+            self.review_replacement();
+        }
+    }
+
+    /// Moves from the search results to a summary of what replacing would change, from which
+    /// the replacement can be performed or abandoned
+    pub fn review_replacement(&mut self) {
+        // The summary would be incomplete while the search is still in progress
+        if !matches!(self.current_screen, Screen::SearchComplete(_)) {
+            return;
+        }
+        if let Screen::SearchComplete(search_state) =
+            mem::replace(&mut self.current_screen, Screen::SearchFields)
+        {
+            self.current_screen = Screen::ReviewingReplacement(ReplacementSummary::new(
+                search_state,
+                self.binary_files_skipped,
+            ));
+        }
+    }
+
+    /// Returns from the replacement summary to the search results, without replacing anything
+    pub fn cancel_review(&mut self) {
+        if let Screen::ReviewingReplacement(summary) =
+            mem::replace(&mut self.current_screen, Screen::SearchFields)
+        {
+            self.current_screen = Screen::SearchComplete(summary.search_state);
+        }
+    }
+
+    fn handle_key_replacement_review(&mut self, key: &KeyEvent) -> bool {
+        match (key.code, key.modifiers) {
+            (KeyCode::Enter, _) => {
+                self.cancel_review();
+                self.trigger_replacement();
+            }
+            (KeyCode::Char('o'), KeyModifiers::CONTROL) => self.cancel_review(),
+            _ => {}
+        }
+        false
+    }
+
+    pub fn handle_key_events(
+        &mut self,
+        key: &KeyEvent,
+    ) -> Result<EventHandlingResult, ScooterError> {
         if key.kind == KeyEventKind::Release {
             return Ok(EventHandlingResult {
                 exit: false,
                 rerender: true,
             });
         }
+        self.notice = None;
+
+        if (key.code, key.modifiers) == (KeyCode::Char('l'), KeyModifiers::CONTROL) {
+            self.log_viewer = match self.log_viewer {
+                Some(_) => None,
+                None => Some(LogViewerState::open(default_log_file())),
+            };
+            return Ok(EventHandlingResult {
+                exit: false,
+                rerender: true,
+            });
+        }
+        if self.confirming_large_search {
+            self.confirming_large_search = false;
+            if let KeyCode::Char('y') | KeyCode::Enter = key.code {
+                self.large_search_confirmed = true;
+                return Ok(self.perform_search_if_valid());
+            }
+            return Ok(EventHandlingResult {
+                exit: false,
+                rerender: true,
+            });
+        }
+        if let Some(field) = &mut self.editing_replacement {
+            match (key.code, key.modifiers) {
+                (KeyCode::Enter, KeyModifiers::NONE) => self.finish_editing_replacement(),
+                (KeyCode::Esc, _) => self.editing_replacement = None,
+                (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
+                    return Ok(EventHandlingResult {
+                        exit: true,
+                        rerender: true,
+                    });
+                }
+                (code, modifiers) => field.handle_keys(code, modifiers),
+            }
+            return Ok(EventHandlingResult {
+                exit: false,
+                rerender: true,
+            });
+        }
+        if let Some(log_viewer) = &mut self.log_viewer {
+            match (key.code, key.modifiers) {
+                (KeyCode::Char('j') | KeyCode::Down, _)
+                | (KeyCode::Char('n'), KeyModifiers::CONTROL) => log_viewer.scroll_down(),
+                (KeyCode::Char('k') | KeyCode::Up, _)
+                | (KeyCode::Char('p'), KeyModifiers::CONTROL) => log_viewer.scroll_up(),
+                (KeyCode::Esc | KeyCode::Char('q'), _) => self.log_viewer = None,
+                _ => {}
+            }
+            return Ok(EventHandlingResult {
+                exit: false,
+                rerender: true,
+            });
+        }
 
         match (key.code, key.modifiers) {
+            // Backing out of the replacement summary is more likely intended than quitting
+            (KeyCode::Esc, _) if matches!(self.current_screen, Screen::ReviewingReplacement(_)) => {
+                self.cancel_review();
+                return Ok(EventHandlingResult {
+                    exit: false,
+                    rerender: true,
+                });
+            }
             (KeyCode::Esc, _) | (KeyCode::Char('c'), KeyModifiers::CONTROL)
                 if !self.search_fields.show_error_popup =>
             {
@@ -689,8 +2887,20 @@ impl App {
             Screen::SearchProgressing(_) | Screen::SearchComplete(_) => {
                 self.handle_key_confirmation(key)
             }
+            Screen::ConfirmingFiles(_) => self.handle_key_file_confirmation(key),
+            Screen::ReviewingReplacement(_) => self.handle_key_replacement_review(key),
             Screen::PerformingReplacement(_) => false, // TODO: handle keys here
-            Screen::Results(replace_state) => replace_state.handle_key_results(key),
+            Screen::Results(replace_state) => {
+                if (key.code, key.modifiers) == (KeyCode::Char('e'), KeyModifiers::NONE) {
+                    let changed_files = replace_state.changed_files();
+                    if !changed_files.is_empty() {
+                        self.editor_request = Some(changed_files);
+                    }
+                    false
+                } else {
+                    replace_state.handle_key_results(key, self.results_context)
+                }
+            }
         };
         Ok(EventHandlingResult {
             exit,
@@ -698,107 +2908,212 @@ impl App {
         })
     }
 
-    fn is_regex_error(e: &Error) -> bool {
-        e.downcast_ref::<regex::Error>().is_some()
-            || e.downcast_ref::<fancy_regex::Error>().is_some()
+    /// Parses the search fields without searching, setting the field errors if they're invalid
+    pub fn parsed_fields(&mut self) -> Option<ParsedFields> {
+        let (background_processing_sender, _) = mpsc::unbounded_channel();
+        self.validate_fields(background_processing_sender)
+    }
+
+    pub fn counter(&self) -> CounterConfig {
+        self.counter.clone()
+    }
+
+    /// Parses the search pattern, refusing fixed search strings shorter than `min_fixed_len`
+    fn search_type(&self) -> Result<SearchType, ScooterError> {
+        if self.search_fields.fixed_strings().checked {
+            // Empty lines aren't searched for, so only the other lines are checked, unless there
+            // are none
+            let shortest = self
+                .search_fields
+                .search()
+                .text()
+                .lines()
+                .filter(|s| !s.is_empty())
+                .map(|s| s.chars().count())
+                .min()
+                .unwrap_or(0);
+            if shortest < self.min_fixed_len {
+                return Err(ScooterError::SearchTooShort(self.min_fixed_len));
+            }
+        }
+        self.search_fields.search_type()
     }
 
     fn validate_fields(
         &mut self,
         background_processing_sender: UnboundedSender<BackgroundProcessingEvent>,
-    ) -> anyhow::Result<Option<ParsedFields>> {
-        let search_pattern = match self.search_fields.search_type() {
+    ) -> Option<ParsedFields> {
+        let search_pattern = match self.search_type() {
             Err(e) => {
-                if Self::is_regex_error(&e) {
-                    self.search_fields
-                        .search_mut()
-                        .set_error("Couldn't parse regex".to_owned(), e.to_string());
-                    ValidatedField::Error
-                } else {
-                    return Err(e);
+                let (short, long) = self.search_fields.search_error_messages(&e);
+                self.search_fields.search_mut().set_error(short, long);
+                ValidatedField::Error
+            }
+            Ok(SearchType::Fixed(_) | SearchType::MultiFixed(_))
+                if self.normalize_whitespace && self.replacement_map.is_none() =>
+            {
+                match SearchType::fixed_any_whitespace(self.search_fields.search().text().lines()) {
+                    Ok(p) => ValidatedField::Parsed(p),
+                    Err(e) => {
+                        let e = ScooterError::InvalidFixedStrings(e.to_string());
+                        let (short, long) = self.search_fields.search_error_messages(&e);
+                        self.search_fields.search_mut().set_error(short, long);
+                        ValidatedField::Error
+                    }
                 }
             }
             Ok(p) => ValidatedField::Parsed(p),
         };
 
-        let path_pattern = match self.search_fields.path_pattern_parsed() {
+        let path_matcher = match self.search_fields.path_pattern_parsed() {
             Err(e) => {
-                self.search_fields
-                    .path_pattern_mut()
-                    .set_error("Couldn't parse regex".to_owned(), e.to_string());
+                let (short, long) = self.search_fields.path_pattern_error_messages(&e);
+                self.search_fields.path_pattern_mut().set_error(short, long);
                 ValidatedField::Error
             }
             Ok(r) => ValidatedField::Parsed(r),
         };
 
-        let (search_pattern, path_pattern) = match (search_pattern, path_pattern) {
+        let (search_pattern, path_matcher) = match (search_pattern, path_matcher) {
             (ValidatedField::Parsed(s), ValidatedField::Parsed(p)) => (s, p),
             _ => {
                 self.search_fields.show_error_popup = true;
-                return Ok(None);
+                return None;
             }
         };
 
-        Ok(Some(ParsedFields::new(
+        // Whitespace is only normalised when searching with fixed strings
+        let collapse_whitespace =
+            self.collapse_whitespace && matches!(search_pattern, SearchType::FixedAnyWhitespace(_));
+        let replace_string = (!self.search_only).then(|| {
+            let replace_string = self.search_fields.replace().text();
+            if self.expand_env {
+                expand_env_vars(&replace_string, &search_pattern)
+            } else {
+                replace_string
+            }
+        });
+        let parsed_fields = ParsedFields::new(
             search_pattern,
-            self.search_fields.replace().text(),
-            path_pattern,
-            self.directory.clone(),
+            replace_string,
+            path_matcher,
+            self.directories.clone(),
             self.include_hidden,
+            self.include_ignored,
             background_processing_sender.clone(),
-        )))
+        )
+        .with_path_pattern_negated(self.search_fields.path_pattern_negated())
+        .with_lossy(self.lossy)
+        .with_repeat(self.repeat)
+        .with_collapse_whitespace(collapse_whitespace)
+        .with_replacement_map(self.replacement_map.clone())
+        .with_max_open_files(self.max_open_files)
+        .with_changed_within(self.changed_within)
+        .with_changed_before(self.changed_before)
+        .with_follow_symlinks(self.follow_symlinks)
+        .with_pruned_dirs(&self.pruned_dirs)
+        .with_search_git(self.search_git)
+        .with_line_filter(self.line_filter.clone());
+        if !self.git_modified_only {
+            return Some(parsed_fields);
+        }
+        let mut files = HashSet::new();
+        for dir in &self.directories {
+            match git::modified_files(dir) {
+                Ok(modified) => files.extend(modified),
+                Err(e) => {
+                    // Searching every file instead could replace in files the user meant to leave
+                    // alone, so report the error as for an invalid path pattern
+                    self.search_fields.path_pattern_mut().set_error(
+                        "Couldn't find modified files".to_owned(),
+                        format!("Couldn't find the files modified in {dir:?}: {e}"),
+                    );
+                    self.search_fields.show_error_popup = true;
+                    return None;
+                }
+            }
+        }
+        Some(parsed_fields.with_file_filter(files))
     }
 
     pub fn update_search_results(
         parsed_fields: ParsedFields,
+        limit: Option<usize>,
         background_processing_sender: UnboundedSender<BackgroundProcessingEvent>,
     ) -> JoinHandle<()> {
-        let walker = parsed_fields.build_walker();
-
         tokio::spawn(async move {
-            walker.run(|| {
-                let parsed_fields = parsed_fields.clone();
-
-                Box::new(move |entry| {
-                    let entry = match entry {
-                        Ok(entry) => entry,
-                        Err(_) => return WalkState::Continue,
-                    };
-
-                    if !entry.file_type().is_some_and(|ft| ft.is_file()) {
-                        return WalkState::Continue;
-                    };
-
-                    if Self::ignore_file(entry.path()) {
-                        return WalkState::Continue;
-                    }
-
-                    parsed_fields.handle_path(entry.path());
-
-                    WalkState::Continue
-                })
-            });
+            let paused = match limit {
+                Some(limit) => parsed_fields.search_up_to(limit),
+                None => {
+                    parsed_fields.search();
+                    false
+                }
+            };
 
             // Ignore error: we may have gone back to the previous screen
-            let _ = background_processing_sender.send(BackgroundProcessingEvent::SearchCompleted);
+            let _ = background_processing_sender.send(if paused {
+                BackgroundProcessingEvent::SearchPaused
+            } else {
+                BackgroundProcessingEvent::SearchCompleted
+            });
         })
     }
 
-    fn ignore_file(path: &Path) -> bool {
-        if let Some(ext) = path.extension() {
-            if let Some(ext_str) = ext.to_str() {
-                if BINARY_EXTENSIONS.contains(&ext_str.to_lowercase().as_str()) {
-                    return true;
+    /// Searches the files that were replaced in again, returning the number of matches other
+    /// than those on lines that weren't included in the replacement
+    fn count_remaining_matches(
+        results: &[SearchResult],
+        parsed_fields: ParsedFields,
+        mut receiver: UnboundedReceiver<BackgroundProcessingEvent>,
+    ) -> usize {
+        let replaced_files = results
+            .iter()
+            .filter(|res| res.replace_result == Some(ReplaceResult::Success))
+            .map(|res| res.path.clone())
+            .collect::<HashSet<_>>();
+        if replaced_files.is_empty() {
+            return 0;
+        }
+        let excluded_lines = results
+            .iter()
+            .filter(|res| !res.included)
+            .map(|res| (&res.path, res.line_number))
+            .collect::<HashSet<_>>();
+
+        parsed_fields.with_file_filter(replaced_files).search();
+
+        let mut num_remaining = 0;
+        while let Ok(event) = receiver.try_recv() {
+            if let BackgroundProcessingEvent::AddSearchResult(res) = event {
+                if !excluded_lines.contains(&(&res.path, res.line_number)) {
+                    num_remaining += 1;
                 }
             }
         }
-        false
+        num_remaining
+    }
+
+    /// Flags every result that was included but not replaced as an error, and selects only those
+    /// results, so that replacing again retries them
+    fn failed_replacements(mut search_state: SearchState) -> SearchState {
+        for res in &mut search_state.results {
+            if res.included && res.replace_result.is_none() {
+                res.replace_result = Some(ReplaceResult::Error(RESULT_NOT_FOUND_ERROR.to_owned()));
+            }
+            res.included = matches!(res.replace_result, Some(ReplaceResult::Error(_)));
+        }
+        search_state.selected = search_state
+            .results
+            .iter()
+            .position(|res| res.included)
+            .unwrap_or(0);
+        search_state
     }
 
     fn calculate_statistics(results: &[SearchResult]) -> ReplaceState {
-        let mut num_successes = 0;
         let mut num_ignored = 0;
         let mut errors = vec![];
+        let mut successes = vec![];
 
         results
             .iter()
@@ -807,13 +3122,12 @@ impl App {
                     num_ignored += 1;
                 }
                 (_, Some(ReplaceResult::Success)) => {
-                    num_successes += 1;
+                    successes.push(res.clone());
                 }
                 (_, None) => {
                     let mut res = res.clone();
-                    res.replace_result = Some(ReplaceResult::Error(
-                        "Failed to find search result in file".to_owned(),
-                    ));
+                    res.replace_result =
+                        Some(ReplaceResult::Error(RESULT_NOT_FOUND_ERROR.to_owned()));
                     errors.push(res);
                 }
                 (_, Some(ReplaceResult::Error(_))) => {
@@ -822,58 +3136,222 @@ impl App {
             });
 
         ReplaceState {
-            num_successes,
+            num_successes: successes.len(),
             num_ignored,
             errors,
             replacement_errors_pos: 0,
+            successes,
+            successes_pos: 0,
+            show_successes: false,
+            num_remaining_matches: None,
+            expanded_context: HashMap::new(),
+            success_context: HashMap::new(),
+        }
+    }
+
+    /// Maps each path that is a hard link to the same file as an earlier path to that earlier
+    /// path, so that the file is only written once
+    fn hard_link_aliases<'a>(paths: impl Iterator<Item = &'a Path>) -> HashMap<PathBuf, PathBuf> {
+        let mut first_paths = HashMap::new();
+        let mut aliases = HashMap::new();
+        for path in paths.unique() {
+            let Some(id) = hard_link_id(path) else {
+                continue;
+            };
+            let first_path = first_paths.entry(id).or_insert_with(|| path.to_owned());
+            if first_path != path {
+                aliases.insert(path.to_owned(), first_path.clone());
+            }
         }
+        aliases
     }
 
     fn replace_in_file(
         file_path: PathBuf,
         results: &mut [&mut SearchResult],
-    ) -> anyhow::Result<()> {
-        let mut line_map: HashMap<_, _> =
-            HashMap::from_iter(results.iter_mut().map(|res| (res.line_number, res)));
-
+        follow_symlinks: bool,
+    ) -> Result<(), ScooterError> {
+        // Write to the file that a symlink points to, as renaming the temp file over the symlink
+        // would replace the symlink itself with a regular file
+        let file_path = if follow_symlinks && file_path.is_symlink() {
+            fs::canonicalize(&file_path)?
+        } else {
+            file_path
+        };
         let input = File::open(file_path.clone())?;
-        let buffered = BufReader::new(input);
+        let reader = BufReader::new(input);
 
         let temp_file_path = file_path.with_extension("tmp");
-        let output = File::create(temp_file_path.clone())?;
+        let output = File::create(temp_file_path.clone()).map_err(ScooterError::DiskWrite)?;
         let mut writer = BufWriter::new(output);
 
-        for (index, line) in buffered.lines().enumerate() {
-            let mut line = line?;
-            if let Some(res) = line_map.get_mut(&(index + 1)) {
-                if line == res.line {
-                    line.clone_from(&res.replacement);
-                    res.replace_result = Some(ReplaceResult::Success);
-                } else {
-                    res.replace_result = Some(ReplaceResult::Error(
-                        "File changed since last search".to_owned(),
-                    ));
+        // Remove the temp file if anything goes wrong, e.g. the disk is full, so that the
+        // original file is left as it was
+        Self::write_replaced_lines(reader, &mut writer, results)
+            .and_then(|()| writer.get_ref().sync_all().map_err(ScooterError::DiskWrite))
+            .inspect_err(|_| Self::remove_temp_file(&temp_file_path))?;
+        drop(writer);
+
+        // Renaming the temp file over a file with other hard links would leave them pointing at
+        // the original contents, so copy the contents over the file instead
+        let hard_linked = hard_link_id(&file_path).is_some();
+        Self::move_temp_file(&temp_file_path, &file_path, hard_linked, |from, to| {
+            fs::copy(from, to)
+        })
+    }
+
+    /// Moves the replaced contents in `temp_file_path` to `file_path`. Files with other hard links
+    /// are overwritten with `copy` instead, which isn't atomic, so if that fails the temp file is
+    /// kept as the only complete copy of the replaced contents.
+    fn move_temp_file(
+        temp_file_path: &Path,
+        file_path: &Path,
+        hard_linked: bool,
+        copy: impl FnOnce(&Path, &Path) -> io::Result<u64>,
+    ) -> Result<(), ScooterError> {
+        if !hard_linked {
+            return fs::rename(temp_file_path, file_path).map_err(|e| {
+                Self::remove_temp_file(temp_file_path);
+                e.into()
+            });
+        }
+        copy(temp_file_path, file_path)
+            .map_err(|e| ScooterError::IncompleteWrite(temp_file_path.to_owned(), e))?;
+        Self::remove_temp_file(temp_file_path);
+        Ok(())
+    }
+
+    fn remove_temp_file(temp_file_path: &Path) {
+        if let Err(e) = fs::remove_file(temp_file_path) {
+            warn!("Couldn't remove temp file {:?}: {e}", temp_file_path);
+        }
+    }
+
+    fn write_replaced_lines(
+        mut reader: impl BufRead,
+        mut writer: impl Write,
+        results: &mut [&mut SearchResult],
+    ) -> Result<(), ScooterError> {
+        // Hard links to the same file, or several searches, can give more than one result for each
+        // line
+        let mut line_map: HashMap<usize, Vec<&mut &mut SearchResult>> = HashMap::new();
+        for res in results.iter_mut() {
+            line_map.entry(res.line_number).or_default().push(res);
+        }
+        let mut write = |bytes: &[u8]| writer.write_all(bytes).map_err(ScooterError::DiskWrite);
+
+        let mut line_number = 0;
+        let mut line_bytes = vec![];
+        while !line_map.is_empty() {
+            line_bytes.clear();
+            if reader.read_until(b'\n', &mut line_bytes)? == 0 {
+                break;
+            }
+            line_number += 1;
+
+            let Some(line_results) = line_map.remove(&line_number) else {
+                write(&line_bytes)?;
+                continue;
+            };
+            let line_ending_len = line_ending_len(&line_bytes);
+            let (line, line_ending) = line_bytes.split_at(line_bytes.len() - line_ending_len);
+            let replace_result = match std::str::from_utf8(line) {
+                Ok(line) if line_results.iter().all(|res| line == res.line) => {
+                    write(Self::merged_replacement(line, &line_results).as_bytes())?;
+                    write(line_ending)?;
+                    ReplaceResult::Success
+                }
+                Ok(_) => {
+                    write(&line_bytes)?;
+                    ReplaceResult::Error("File changed since last search".to_owned())
                 }
+                Err(_) => {
+                    // Write back invalid UTF-8 untouched rather than risk corrupting it
+                    write(&line_bytes)?;
+                    ReplaceResult::Error("Line is not valid UTF-8, so can't be replaced".to_owned())
+                }
+            };
+            for res in line_results {
+                res.replace_result = Some(replace_result.clone());
+            }
+        }
+        // There is nothing left to replace, so copy the rest of the file as-is
+        loop {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                break;
             }
-            writeln!(writer, "{}", line)?;
+            write(buf)?;
+            let len = buf.len();
+            reader.consume(len);
         }
 
-        writer.flush()?;
-        fs::rename(temp_file_path, file_path)?;
-        Ok(())
+        writer.flush().map_err(ScooterError::DiskWrite)
+    }
+
+    /// Applies the replacements of all of `results`, which are for the same `line`. If they agree,
+    /// e.g. because they're for hard links to the same file, that replacement is used as-is.
+    /// Otherwise the matches of all results are replaced from left to right, skipping any match
+    /// that overlaps one to its left.
+    fn merged_replacement(line: &str, results: &[&mut &mut SearchResult]) -> String {
+        let first = &results[0].replacement;
+        if results.iter().all(|res| res.replacement == *first) {
+            return first.clone();
+        }
+
+        let mut edits = results
+            .iter()
+            .flat_map(|res| replacement_edits(res))
+            .collect::<Vec<_>>();
+        edits.sort_by_key(|(span, _)| (span.start, span.end));
+        edits.dedup();
+
+        let mut merged = String::with_capacity(line.len());
+        let mut pos = 0;
+        for (span, replacement) in edits {
+            if span.start < pos {
+                continue;
+            }
+            merged.push_str(&line[pos..span.start]);
+            merged.push_str(replacement);
+            pos = span.end;
+        }
+        merged.push_str(&line[pos..]);
+        merged
     }
 
     pub fn relative_path(&self, path: &Path) -> String {
-        relative_path_from(&self.directory, path)
+        relative_path_from_roots(&self.directories, path)
     }
 }
 
+/// Each match in `result.line` paired with the text it's replaced with. If these aren't known,
+/// e.g. because the replacement was edited by hand, the whole line is treated as one match.
+fn replacement_edits(result: &SearchResult) -> Vec<(Range<usize>, &str)> {
+    let edits = (result.match_spans.len() == result.replacement_spans.len())
+        .then(|| {
+            result
+                .match_spans
+                .iter()
+                .zip(&result.replacement_spans)
+                .map(|(match_span, replacement_span)| {
+                    let replacement = result.replacement.get(replacement_span.clone())?;
+                    result.line.get(match_span.clone())?;
+                    Some((match_span.clone(), replacement))
+                })
+                .collect::<Option<Vec<_>>>()
+        })
+        .flatten()
+        .filter(|edits| !edits.is_empty());
+    edits.unwrap_or_else(|| vec![(0..result.line.len(), result.replacement.as_str())])
+}
+
 #[cfg(test)]
 mod tests {
     use rand::Rng;
 
     use super::*;
-    use crate::EventHandler;
+    use crate::{replace::LineReplacer, EventHandler};
 
     fn random_num() -> usize {
         let mut rng = rand::thread_rng();
@@ -886,8 +3364,9 @@ mod tests {
             line_number: random_num(),
             line: "foo".to_owned(),
             replacement: "bar".to_owned(),
+            num_matches: 1,
             included,
-            replace_result: None,
+            ..Default::default()
         }
     }
 
@@ -899,7 +3378,7 @@ mod tests {
                 search_result(true),
                 search_result(true),
             ],
-            selected: 0,
+            ..Default::default()
         };
         search_state.toggle_all_selected();
         assert_eq!(
@@ -920,7 +3399,7 @@ mod tests {
                 search_result(false),
                 search_result(false),
             ],
-            selected: 0,
+            ..Default::default()
         };
         search_state.toggle_all_selected();
         assert_eq!(
@@ -941,7 +3420,7 @@ mod tests {
                 search_result(false),
                 search_result(true),
             ],
-            selected: 0,
+            ..Default::default()
         };
         search_state.toggle_all_selected();
         assert_eq!(
@@ -958,7 +3437,7 @@ mod tests {
     fn test_toggle_all_selected_when_no_results() {
         let mut search_state = SearchState {
             results: vec![],
-            selected: 0,
+            ..Default::default()
         };
         search_state.toggle_all_selected();
         assert_eq!(
@@ -971,14 +3450,83 @@ mod tests {
         );
     }
 
+    fn search_result_with_path(path: &str) -> SearchResult {
+        SearchResult {
+            path: Path::new(path).to_path_buf(),
+            ..search_result(true)
+        }
+    }
+
+    #[test]
+    fn test_toggle_extension() {
+        let mut search_state = SearchState {
+            results: vec![
+                search_result_with_path("README.md"),
+                search_result_with_path("src/main.rs"),
+                search_result_with_path("docs/guide.md"),
+                search_result_with_path("Makefile"),
+                search_result_with_path("notes.markdown"),
+            ],
+            ..Default::default()
+        };
+        let included = |search_state: &SearchState| {
+            search_state
+                .results
+                .iter()
+                .map(|res| res.included)
+                .collect::<Vec<_>>()
+        };
+
+        search_state.toggle_extension(Some(OsStr::new("md")));
+        assert_eq!(
+            included(&search_state),
+            vec![false, true, false, true, true]
+        );
+
+        search_state.toggle_extension(Some(OsStr::new("md")));
+        assert_eq!(included(&search_state), vec![true, true, true, true, true]);
+
+        search_state.toggle_extension(None);
+        assert_eq!(included(&search_state), vec![true, true, true, false, true]);
+    }
+
+    #[test]
+    fn test_toggle_selected_extension_includes_all_if_some_excluded() {
+        let mut search_state = SearchState {
+            results: vec![
+                search_result_with_path("a.md"),
+                search_result_with_path("b.rs"),
+                search_result_with_path("c.md"),
+            ],
+            selected: 2,
+            ..Default::default()
+        };
+        search_state.results[0].included = false;
+
+        search_state.toggle_selected_extension();
+        assert!(search_state.results.iter().all(|res| res.included));
+
+        search_state.toggle_selected_extension();
+        assert_eq!(
+            search_state
+                .results
+                .iter()
+                .map(|res| res.included)
+                .collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
+    }
+
     fn success_result() -> SearchResult {
         SearchResult {
             path: Path::new("random/file").to_path_buf(),
             line_number: random_num(),
             line: "foo".to_owned(),
             replacement: "bar".to_owned(),
+            num_matches: 1,
             included: true,
             replace_result: Some(ReplaceResult::Success),
+            ..Default::default()
         }
     }
 
@@ -988,8 +3536,9 @@ mod tests {
             line_number: random_num(),
             line: "foo".to_owned(),
             replacement: "bar".to_owned(),
+            num_matches: 1,
             included: false,
-            replace_result: None,
+            ..Default::default()
         }
     }
 
@@ -999,24 +3548,441 @@ mod tests {
             line_number: random_num(),
             line: "foo".to_owned(),
             replacement: "bar".to_owned(),
+            num_matches: 1,
             included: true,
             replace_result: Some(ReplaceResult::Error("error".to_owned())),
+            ..Default::default()
         }
     }
 
+    #[tokio::test]
+    async fn test_follow_keeps_newest_result_selected() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut state = SearchInProgressState::new(tokio::spawn(async {}), sender, receiver);
+
+        state.add_result(search_result(true));
+        state.add_result(search_result(true));
+        assert_eq!(state.search_state.selected, 0);
+
+        state.toggle_follow();
+        assert!(state.follow);
+        assert_eq!(state.search_state.selected, 1);
+        state.add_result(search_result(true));
+        assert_eq!(state.search_state.selected, 2);
+        state.add_result(search_result(true));
+        assert_eq!(state.search_state.selected, 3);
+
+        state.toggle_follow();
+        assert!(!state.follow);
+        state.add_result(search_result(true));
+        assert_eq!(state.search_state.selected, 3);
+    }
+
+    #[tokio::test]
+    async fn test_scrolling_up_pauses_follow() {
+        let mut app = build_test_app(vec![]);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut state = SearchInProgressState::new(tokio::spawn(async {}), sender, receiver);
+        state.toggle_follow();
+        state.add_result(search_result(true));
+        state.add_result(search_result(true));
+        app.current_screen = Screen::SearchProgressing(state);
+
+        app.handle_key_events(&KeyEvent::new(KeyCode::Char('k'), KeyModifiers::NONE))
+            .unwrap();
+
+        let Screen::SearchProgressing(state) = &mut app.current_screen else {
+            panic!("Expected SearchProgressing");
+        };
+        assert!(!state.follow);
+        assert_eq!(state.search_state.selected, 0);
+        state.add_result(search_result(true));
+        assert_eq!(state.search_state.selected, 0);
+    }
+
+    #[test]
+    fn test_log_viewer_scroll() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("test.log");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+
+        let mut log_viewer = LogViewerState::open(path);
+        assert_eq!(log_viewer.lines, vec!["one", "two", "three"]);
+        assert_eq!(log_viewer.scroll, 2);
+        log_viewer.scroll_down();
+        assert_eq!(log_viewer.scroll, 2);
+        log_viewer.scroll_up();
+        log_viewer.scroll_up();
+        log_viewer.scroll_up();
+        assert_eq!(log_viewer.scroll, 0);
+    }
+
+    #[test]
+    fn test_log_viewer_missing_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let log_viewer = LogViewerState::open(temp_dir.path().join("missing.log"));
+        assert_eq!(log_viewer.lines.len(), 1);
+        assert!(log_viewer.lines[0].starts_with("Couldn't read log file"));
+    }
+
+    #[tokio::test]
+    async fn test_toggle_log_viewer() {
+        let mut app = build_test_app(vec![]);
+        let ctrl_l = KeyEvent::new(KeyCode::Char('l'), KeyModifiers::CONTROL);
+
+        app.handle_key_events(&ctrl_l).unwrap();
+        assert_eq!(app.log_viewer.as_ref().unwrap().path, default_log_file());
+
+        // Keys go to the log viewer rather than the screen underneath
+        let res = app
+            .handle_key_events(&KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE))
+            .unwrap();
+        assert!(!res.exit);
+        assert!(app.log_viewer.is_none());
+
+        app.handle_key_events(&ctrl_l).unwrap();
+        app.handle_key_events(&ctrl_l).unwrap();
+        assert!(app.log_viewer.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_large_search_requires_confirmation() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut app = build_test_app(vec![]);
+        app.directories = vec![temp_dir.path().to_path_buf()];
+        app.large_dirs = vec![temp_dir.path().to_path_buf()];
+
+        app.perform_search_if_valid();
+        assert!(app.confirming_large_search);
+        assert!(matches!(app.current_screen, Screen::SearchFields));
+
+        // Any key other than y or enter cancels
+        app.handle_key_events(&KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE))
+            .unwrap();
+        assert!(!app.confirming_large_search);
+        assert!(matches!(app.current_screen, Screen::SearchFields));
+
+        app.perform_search_if_valid();
+        assert!(app.confirming_large_search);
+        app.handle_key_events(&KeyEvent::new(KeyCode::Char('y'), KeyModifiers::NONE))
+            .unwrap();
+        assert!(!app.confirming_large_search);
+        assert!(matches!(app.current_screen, Screen::SearchProgressing(_)));
+    }
+
+    #[tokio::test]
+    async fn test_large_search_confirmed_up_front() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut app = build_test_app(vec![]).with_large_search_confirmed(true);
+        app.directories = vec![temp_dir.path().to_path_buf()];
+        app.large_dirs = vec![temp_dir.path().to_path_buf()];
+
+        app.perform_search_if_valid();
+        assert!(!app.confirming_large_search);
+        assert!(matches!(app.current_screen, Screen::SearchProgressing(_)));
+    }
+
+    #[test]
+    fn test_replace_in_large_file_with_early_match() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.txt");
+        let tail = (0..100_000)
+            .map(|i| format!("line {i}\r\n"))
+            .collect::<String>()
+            + "no trailing newline";
+        fs::write(&path, format!("first\nfoo\n{tail}")).unwrap();
+
+        let mut result = SearchResult {
+            path: path.clone(),
+            line_number: 2,
+            line: "foo".to_owned(),
+            replacement: "bar".to_owned(),
+            num_matches: 1,
+            included: true,
+            ..Default::default()
+        };
+        App::replace_in_file(path.clone(), &mut [&mut result], false).unwrap();
+
+        assert_eq!(result.replace_result, Some(ReplaceResult::Success));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("first\nbar\n{tail}")
+        );
+    }
+
+    #[test]
+    fn test_replace_in_file_preserves_line_endings() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.txt");
+        fs::write(&path, "foo\r\nbaz\nfoo").unwrap();
+
+        let mut results = [1, 3].map(|line_number| SearchResult {
+            path: path.clone(),
+            line_number,
+            line: "foo".to_owned(),
+            replacement: "bar".to_owned(),
+            num_matches: 1,
+            included: true,
+            ..Default::default()
+        });
+        let [first, second] = &mut results;
+        App::replace_in_file(path.clone(), &mut [first, second], false).unwrap();
+
+        assert!(results
+            .iter()
+            .all(|res| res.replace_result == Some(ReplaceResult::Success)));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "bar\r\nbaz\nbar");
+    }
+
+    #[test]
+    fn test_replace_in_file_merges_results_for_the_same_line() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.txt");
+        fs::write(&path, "foo baz foo\nbaz\n").unwrap();
+
+        let result = |search: &str, replace: &str, line_number: usize, line: &str| {
+            let replacer = LineReplacer::new(
+                SearchType::Fixed(search.to_owned()),
+                Some(replace.to_owned()),
+            );
+            let replaced = replacer.replace_line(line).unwrap();
+            SearchResult {
+                path: path.clone(),
+                line_number,
+                line: line.to_owned(),
+                replacement: replaced.replacement,
+                num_matches: replaced.num_matches,
+                included: true,
+                match_spans: replaced.match_spans,
+                replacement_spans: replaced.replacement_spans,
+                ..Default::default()
+            }
+        };
+        let mut baz = result("baz", "quux", 1, "foo baz foo");
+        let mut foo = result("foo", "bar", 1, "foo baz foo");
+        let mut only_baz = result("baz", "quux", 2, "baz");
+        App::replace_in_file(
+            path.clone(),
+            &mut [&mut baz, &mut foo, &mut only_baz],
+            false,
+        )
+        .unwrap();
+
+        assert!([baz, foo, only_baz]
+            .iter()
+            .all(|res| res.replace_result == Some(ReplaceResult::Success)));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "bar quux bar\nquux\n");
+    }
+
+    #[test]
+    fn test_merged_replacement_skips_overlapping_matches() {
+        let result = |replacement: &str, match_span: Range<usize>, replacement_span| SearchResult {
+            path: PathBuf::from("file.txt"),
+            line_number: 1,
+            line: "abcd".to_owned(),
+            replacement: replacement.to_owned(),
+            num_matches: 1,
+            included: true,
+            match_spans: vec![match_span],
+            replacement_spans: vec![replacement_span],
+            ..Default::default()
+        };
+        let mut first = result("Xcd", 0..2, 0..1);
+        let mut second = result("aYd", 1..3, 1..2);
+        let mut third = result("abcZ", 3..4, 3..4);
+        let (mut first, mut second, mut third) = (&mut first, &mut second, &mut third);
+        assert_eq!(
+            App::merged_replacement("abcd", &[&mut third, &mut second, &mut first]),
+            "XcZ"
+        );
+    }
+
+    #[test]
+    fn test_replace_in_missing_file_returns_io_error() {
+        let mut result = search_result(true);
+        let res = App::replace_in_file(
+            PathBuf::from("/path/that/definitely/does/not/exist/12345.txt"),
+            &mut [&mut result],
+            false,
+        );
+        assert!(matches!(res, Err(ScooterError::Io(_))));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_replace_with_failed_disk_write() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.txt");
+        let contents = "foo\nbar\n".repeat(10_000);
+        fs::write(&path, &contents).unwrap();
+        // Writes to /dev/full fail as if the disk were full
+        let temp_file_path = path.with_extension("tmp");
+        std::os::unix::fs::symlink("/dev/full", &temp_file_path).unwrap();
+
+        let mut result = SearchResult {
+            path: path.clone(),
+            line_number: 1,
+            ..search_result(true)
+        };
+        let res = App::replace_in_file(path.clone(), &mut [&mut result], false);
+        let Err(err @ ScooterError::DiskWrite(_)) = res else {
+            panic!("Expected DiskWrite error, found {:?}", res);
+        };
+        assert!(err.to_string().starts_with("Disk write failed"));
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+        assert!(fs::symlink_metadata(&temp_file_path).is_err());
+    }
+
+    #[test]
+    fn test_failed_copy_over_hard_linked_file_keeps_temp_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.txt");
+        fs::write(&path, "foo\nbar\n").unwrap();
+        let temp_file_path = path.with_extension("tmp");
+        fs::write(&temp_file_path, "baz\nbar\n").unwrap();
+
+        // Fail part way through, after truncating the original
+        let res = App::move_temp_file(&temp_file_path, &path, true, |_, to| {
+            File::create(to)?.write_all(b"baz")?;
+            Err(io::Error::other("injected failure"))
+        });
+        let Err(err @ ScooterError::IncompleteWrite(_, _)) = res else {
+            panic!("Expected IncompleteWrite error, found {:?}", res);
+        };
+        assert!(err.to_string().contains(&format!("{:?}", temp_file_path)));
+
+        assert_eq!(fs::read_to_string(&temp_file_path).unwrap(), "baz\nbar\n");
+    }
+
+    #[test]
+    fn test_copy_over_hard_linked_file_removes_temp_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.txt");
+        let link_path = temp_dir.path().join("link.txt");
+        fs::write(&path, "foo\nbar\n").unwrap();
+        fs::hard_link(&path, &link_path).unwrap();
+
+        let mut result = SearchResult {
+            path: path.clone(),
+            line_number: 1,
+            ..search_result(true)
+        };
+        App::replace_in_file(path.clone(), &mut [&mut result], false).unwrap();
+
+        assert_eq!(fs::read_to_string(&link_path).unwrap(), "bar\nbar\n");
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_replace_in_file_with_long_path() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = (0..20).fold(temp_dir.path().to_path_buf(), |dir, i| {
+            dir.join(format!("nested_directory_{i}"))
+        });
+        let path = dir.join("file.txt");
+        // Longer than `MAX_PATH`, which the standard library handles by adding the `\\?\` prefix
+        assert!(path.as_os_str().len() > 260);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "foo\nbaz\n").unwrap();
+
+        let mut result = SearchResult {
+            path: path.clone(),
+            line_number: 1,
+            ..search_result(true)
+        };
+        App::replace_in_file(path.clone(), &mut [&mut result], false).unwrap();
+
+        assert_eq!(result.replace_result, Some(ReplaceResult::Success));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "bar\nbaz\n");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    #[ignore = "needs the administrative share for the temp directory's drive, e.g. \\\\localhost\\C$"]
+    fn test_replace_in_file_with_unc_path() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.txt");
+        fs::write(&path, "foo\nbaz\n").unwrap();
+        // Refer to the file through the administrative share for its drive, e.g. C$
+        let (drive, rest) = path.to_str().unwrap().split_once(":\\").unwrap();
+        let unc_path = PathBuf::from(format!(r"\\localhost\{drive}$\{rest}"));
+
+        let mut result = SearchResult {
+            path: unc_path.clone(),
+            line_number: 1,
+            ..search_result(true)
+        };
+        App::replace_in_file(unc_path, &mut [&mut result], false).unwrap();
+
+        assert_eq!(result.replace_result, Some(ReplaceResult::Success));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "bar\nbaz\n");
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replace_via_symlink_edits_target() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let target_path = temp_dir.path().join("target.txt");
+        let link_path = temp_dir.path().join("link.txt");
+        fs::write(&target_path, "foo\nbaz\n").unwrap();
+        std::os::unix::fs::symlink(&target_path, &link_path).unwrap();
+
+        let mut result = SearchResult {
+            path: link_path.clone(),
+            line_number: 1,
+            ..search_result(true)
+        };
+        App::replace_in_file(link_path.clone(), &mut [&mut result], true).unwrap();
+
+        assert_eq!(result.replace_result, Some(ReplaceResult::Success));
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "bar\nbaz\n");
+        assert!(fs::symlink_metadata(&link_path)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_link(&link_path).unwrap(), target_path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replace_via_symlink_without_following_replaces_link() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let target_path = temp_dir.path().join("target.txt");
+        let link_path = temp_dir.path().join("link.txt");
+        fs::write(&target_path, "foo\nbaz\n").unwrap();
+        std::os::unix::fs::symlink(&target_path, &link_path).unwrap();
+
+        let mut result = SearchResult {
+            path: link_path.clone(),
+            line_number: 1,
+            ..search_result(true)
+        };
+        App::replace_in_file(link_path.clone(), &mut [&mut result], false).unwrap();
+
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "foo\nbaz\n");
+        assert!(!fs::symlink_metadata(&link_path)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+    }
+
     fn build_test_app(results: Vec<SearchResult>) -> App {
         let event_handler = EventHandler::new();
-        let mut app = App::new(None, false, false, event_handler.app_event_sender);
+        let mut app = App::new(vec![], false, false, event_handler.app_event_sender);
         app.current_screen = Screen::SearchComplete(SearchState {
             results,
-            selected: 0,
+            ..Default::default()
         });
         app
     }
 
     #[tokio::test]
     async fn test_calculate_statistics_all_success() {
-        let app = build_test_app(vec![success_result(), success_result(), success_result()]);
+        let successes = vec![success_result(), success_result(), success_result()];
+        let app = build_test_app(successes.clone());
         let stats = if let Screen::SearchComplete(search_state) = &app.current_screen {
             App::calculate_statistics(&search_state.results)
         } else {
@@ -1030,6 +3996,12 @@ mod tests {
                 num_ignored: 0,
                 errors: vec![],
                 replacement_errors_pos: 0,
+                successes,
+                successes_pos: 0,
+                show_successes: false,
+                num_remaining_matches: None,
+                expanded_context: HashMap::new(),
+                success_context: HashMap::new(),
             }
         );
     }
@@ -1037,10 +4009,11 @@ mod tests {
     #[tokio::test]
     async fn test_calculate_statistics_with_ignores_and_errors() {
         let error_result = error_result();
+        let (success1, success2) = (success_result(), success_result());
         let app = build_test_app(vec![
-            success_result(),
+            success1.clone(),
             ignored_result(),
-            success_result(),
+            success2.clone(),
             error_result.clone(),
             ignored_result(),
         ]);
@@ -1057,6 +4030,12 @@ mod tests {
                 num_ignored: 2,
                 errors: vec![error_result],
                 replacement_errors_pos: 0,
+                successes: vec![success1, success2],
+                successes_pos: 0,
+                show_successes: false,
+                num_remaining_matches: None,
+                expanded_context: HashMap::new(),
+                success_context: HashMap::new(),
             }
         );
     }
@@ -0,0 +1,127 @@
+use std::{fmt, io, path::PathBuf};
+
+#[derive(Debug)]
+pub enum RegexError {
+    Regex(regex::Error),
+    Advanced(Box<fancy_regex::Error>),
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegexError::Regex(e) => e.fmt(f),
+            RegexError::Advanced(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<regex::Error> for RegexError {
+    fn from(e: regex::Error) -> Self {
+        RegexError::Regex(e)
+    }
+}
+
+impl From<fancy_regex::Error> for RegexError {
+    fn from(e: fancy_regex::Error) -> Self {
+        RegexError::Advanced(Box::new(e))
+    }
+}
+
+/// Errors returned from the public API, so that callers can match on the failure without
+/// downcasting
+#[derive(Debug)]
+pub enum ScooterError {
+    InvalidSearchRegex(RegexError),
+    InvalidPathRegex(RegexError),
+    InvalidPathGlob(globset::Error),
+    InvalidDirectory(String),
+    NotGitRepository(PathBuf),
+    Git(String),
+    Io(io::Error),
+    /// Failed to write the replaced file, e.g. because the disk is full
+    DiskWrite(io::Error),
+    /// Failed to copy the replaced contents over a file with other hard links, which may have left
+    /// it incomplete. The replaced contents are kept in the temp file at the given path.
+    IncompleteWrite(PathBuf, io::Error),
+    /// The file of replacements passed with `--map` couldn't be parsed
+    InvalidMapFile(PathBuf, String),
+    /// The saved review passed with `--resume` couldn't be read or written
+    InvalidReviewFile(PathBuf, String),
+    /// A fixed search string is shorter than the minimum length, given here in characters
+    SearchTooShort(usize),
+    /// The fixed search strings couldn't be searched for, e.g. because there are too many of them
+    InvalidFixedStrings(String),
+}
+
+impl fmt::Display for ScooterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScooterError::InvalidSearchRegex(e) | ScooterError::InvalidPathRegex(e) => e.fmt(f),
+            ScooterError::InvalidPathGlob(e) => e.fmt(f),
+            ScooterError::InvalidDirectory(dir) => write!(
+                f,
+                "Directory '{}' does not exist. Please provide a valid directory path.",
+                dir
+            ),
+            ScooterError::NotGitRepository(dir) => {
+                write!(f, "Directory {:?} is not in a git repository", dir)
+            }
+            ScooterError::Git(stderr) => write!(f, "git command failed: {}", stderr),
+            ScooterError::Io(e) => e.fmt(f),
+            ScooterError::DiskWrite(e) => write!(f, "Disk write failed: {}", e),
+            ScooterError::IncompleteWrite(temp_file_path, e) => write!(
+                f,
+                "Disk write failed, so the file may be incomplete: {}. The replaced contents \
+                were kept in {:?}",
+                e, temp_file_path
+            ),
+            ScooterError::InvalidMapFile(path, e) => {
+                write!(f, "Couldn't parse map file {:?}: {}", path, e)
+            }
+            ScooterError::SearchTooShort(min_len) => write!(
+                f,
+                "Fixed search strings must be at least {} {} long",
+                min_len,
+                if *min_len == 1 {
+                    "character"
+                } else {
+                    "characters"
+                }
+            ),
+            ScooterError::InvalidReviewFile(path, e) => {
+                write!(f, "Couldn't read or write saved review {:?}: {}", path, e)
+            }
+            ScooterError::InvalidFixedStrings(e) => {
+                write!(f, "Couldn't search for the fixed strings: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScooterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScooterError::InvalidSearchRegex(RegexError::Regex(e))
+            | ScooterError::InvalidPathRegex(RegexError::Regex(e)) => Some(e),
+            ScooterError::InvalidSearchRegex(RegexError::Advanced(e))
+            | ScooterError::InvalidPathRegex(RegexError::Advanced(e)) => Some(e.as_ref()),
+            ScooterError::InvalidPathGlob(e) => Some(e),
+            ScooterError::InvalidDirectory(_)
+            | ScooterError::NotGitRepository(_)
+            | ScooterError::Git(_)
+            | ScooterError::InvalidMapFile(_, _)
+            | ScooterError::InvalidReviewFile(_, _)
+            | ScooterError::SearchTooShort(_)
+            | ScooterError::InvalidFixedStrings(_) => None,
+            ScooterError::Io(e)
+            | ScooterError::DiskWrite(e)
+            | ScooterError::IncompleteWrite(_, e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ScooterError {
+    fn from(e: io::Error) -> Self {
+        ScooterError::Io(e)
+    }
+}
@@ -1,37 +1,58 @@
 use crossterm::event::{self, Event as CrosstermEvent, KeyEvent, MouseEvent};
 use futures::StreamExt;
-use std::path::PathBuf;
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use std::{io, ops::Range, path::PathBuf};
+use tokio::sync::{mpsc, oneshot};
 
-use crate::app::ReplaceState;
+pub use crate::replace::ReplaceWarning;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+use crate::app::{ReplaceState, ReplacementProgress, SearchState};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ReplaceResult {
     Success,
     Error(String),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SearchResult {
     pub path: PathBuf,
     pub line_number: usize,
     pub line: String,
     pub replacement: String,
+    /// Number of times the search pattern matches in `line`
+    pub num_matches: usize,
     pub included: bool,
     pub replace_result: Option<ReplaceResult>,
+    pub warnings: Vec<ReplaceWarning>,
+    /// Byte ranges of the matches in `line`
+    pub match_spans: Vec<Range<usize>>,
+    /// Byte ranges of `replacement` that the matches were replaced with
+    pub replacement_spans: Vec<Range<usize>>,
 }
 
 #[derive(Debug)]
 pub enum AppEvent {
     Rerender,
     PerformSearch,
+    ValidateFields,
+    /// Shows a message with the search results, e.g. if copying to the clipboard failed
+    ShowNotice(String),
 }
 
 #[derive(Debug)]
 pub enum BackgroundProcessingEvent {
     AddSearchResult(SearchResult),
     SearchCompleted,
-    ReplacementCompleted(ReplaceState),
+    /// Sent instead of `SearchCompleted` when a preview search stops after finding enough results
+    SearchPaused,
+    /// Sent once for each file with lines that matched the search but were skipped because they
+    /// look binary, with the number of such lines
+    BinaryLinesSkipped(usize),
+    /// Sent before replacing in each file
+    ReplacementProgress(ReplacementProgress),
+    /// Sent with the search results, which now record whether each replacement succeeded
+    ReplacementCompleted(ReplaceState, SearchState),
 }
 
 #[derive(Debug)]
@@ -44,10 +65,20 @@ pub enum Event {
     Resize(u16, u16),
 }
 
+/// Sent to the task reading terminal events, e.g. so that it stops reading while another program
+/// has control of the terminal
+#[derive(Debug)]
+enum ReaderControl {
+    /// Stop reading terminal events, notifying the sender once stopped
+    Pause(oneshot::Sender<()>),
+    Resume,
+}
+
 #[derive(Debug)]
 pub struct EventHandler {
     pub receiver: mpsc::UnboundedReceiver<Event>,
     pub app_event_sender: mpsc::UnboundedSender<AppEvent>,
+    reader_control_sender: mpsc::UnboundedSender<ReaderControl>,
 }
 
 #[derive(Debug)]
@@ -56,20 +87,27 @@ pub struct EventHandlingResult {
     pub rerender: bool,
 }
 
+/// The next event from `reader`, or never if reading is paused
+async fn next_event(reader: &mut Option<event::EventStream>) -> Option<io::Result<CrosstermEvent>> {
+    match reader {
+        Some(reader) => reader.next().await,
+        None => std::future::pending().await,
+    }
+}
+
 impl EventHandler {
     pub fn new() -> Self {
         let (sender, receiver) = mpsc::unbounded_channel();
         let (app_event_sender, mut app_event_receiver) = mpsc::unbounded_channel();
+        let (reader_control_sender, mut reader_control_receiver) = mpsc::unbounded_channel();
         tokio::spawn(async move {
-            let mut reader = event::EventStream::new();
+            let mut reader = Some(event::EventStream::new());
             loop {
                 tokio::select! {
-                    Some(Ok(evt)) = reader.next() => {
+                    Some(Ok(evt)) = next_event(&mut reader) => {
                         match evt {
-                            CrosstermEvent::Key(key) => {
-                                if key.kind == crossterm::event::KeyEventKind::Press {
-                                    sender.send(Event::Key(key)).unwrap();
-                                }
+                            CrosstermEvent::Key(key) if key.kind == crossterm::event::KeyEventKind::Press => {
+                                sender.send(Event::Key(key)).unwrap();
                             },
                             CrosstermEvent::Mouse(mouse) => {
                                 sender.send(Event::Mouse(mouse)).unwrap();
@@ -83,6 +121,18 @@ impl EventHandler {
                     Some(app_evt) = app_event_receiver.recv() => {
                         sender.send(Event::App(app_evt)).unwrap();
                     }
+                    Some(control) = reader_control_receiver.recv() => {
+                        match control {
+                            ReaderControl::Pause(paused) => {
+                                // Dropping the stream stops it polling stdin
+                                reader = None;
+                                let _ = paused.send(());
+                            }
+                            ReaderControl::Resume => {
+                                reader.get_or_insert_with(event::EventStream::new);
+                            }
+                        }
+                    }
                     else => break,
                 };
             }
@@ -90,8 +140,26 @@ impl EventHandler {
         Self {
             receiver,
             app_event_sender,
+            reader_control_sender,
         }
     }
+
+    /// Stops reading terminal events until `resume` is called, so that another program can read
+    /// input from the terminal
+    pub async fn pause(&self) {
+        let (paused_sender, paused_receiver) = oneshot::channel();
+        if self
+            .reader_control_sender
+            .send(ReaderControl::Pause(paused_sender))
+            .is_ok()
+        {
+            let _ = paused_receiver.await;
+        }
+    }
+
+    pub fn resume(&self) {
+        let _ = self.reader_control_sender.send(ReaderControl::Resume);
+    }
 }
 
 impl Default for EventHandler {
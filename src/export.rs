@@ -0,0 +1,197 @@
+use std::{fmt::Write, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{event::SearchResult, replace::display_counter_placeholders};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Csv,
+}
+
+/// A search result as written when exporting, with the path relative to the search directory
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultRecord {
+    pub path: String,
+    pub line_number: usize,
+    pub line: String,
+    pub replacement: String,
+    pub included: bool,
+}
+
+impl ResultRecord {
+    pub fn new(result: &SearchResult, relative_path: String) -> Self {
+        Self {
+            path: relative_path,
+            line_number: result.line_number,
+            line: result.line.clone(),
+            replacement: display_counter_placeholders(&result.replacement),
+            included: result.included,
+        }
+    }
+}
+
+/// Writes `records` as CSV with a header row. Fields containing commas, quotes or newlines are
+/// quoted.
+pub fn write_csv<W: io::Write>(
+    writer: W,
+    records: impl IntoIterator<Item = ResultRecord>,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for record in records {
+        writer.serialize(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The included `records` as a unified diff, with a hunk for each run of consecutive lines in a
+/// file. Hunks have no context lines, so the patch can be applied from the search directory with
+/// `patch -p1` or `git apply --unidiff-zero`. Only the first record for each line is used.
+pub fn unified_patch(records: impl IntoIterator<Item = ResultRecord>) -> String {
+    let mut records = records
+        .into_iter()
+        .filter(|record| record.included)
+        .collect::<Vec<_>>();
+    records.sort_by(|a, b| (&a.path, a.line_number).cmp(&(&b.path, b.line_number)));
+    records.dedup_by(|b, a| a.path == b.path && a.line_number == b.line_number);
+
+    let mut patch = String::new();
+    // Number of lines added to the current file by earlier hunks, which shifts later hunks
+    let mut offset = 0;
+    let mut start = 0;
+    while start < records.len() {
+        let path = &records[start].path;
+        if start == 0 || records[start - 1].path != *path {
+            let path = path.replace('\\', "/");
+            let path = path.strip_prefix("./").unwrap_or(&path);
+            writeln!(patch, "--- a/{path}\n+++ b/{path}").unwrap();
+            offset = 0;
+        }
+        let mut end = start + 1;
+        while end < records.len()
+            && records[end].path == *path
+            && records[end].line_number == records[end - 1].line_number + 1
+        {
+            end += 1;
+        }
+
+        let hunk = &records[start..end];
+        let new_lines = hunk
+            .iter()
+            .flat_map(|record| record.replacement.split('\n'))
+            .collect::<Vec<_>>();
+        let line_number = hunk[0].line_number as isize;
+        writeln!(
+            patch,
+            "@@ -{line_number},{} +{},{} @@",
+            hunk.len(),
+            line_number + offset,
+            new_lines.len()
+        )
+        .unwrap();
+        for record in hunk {
+            writeln!(patch, "-{}", record.line).unwrap();
+        }
+        for line in &new_lines {
+            writeln!(patch, "+{line}").unwrap();
+        }
+        offset += new_lines.len() as isize - hunk.len() as isize;
+        start = end;
+    }
+    patch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(line: &str, replacement: &str, included: bool) -> ResultRecord {
+        ResultRecord {
+            path: "./dir/file, with comma.txt".to_owned(),
+            line_number: 3,
+            line: line.to_owned(),
+            replacement: replacement.to_owned(),
+            included,
+        }
+    }
+
+    #[test]
+    fn test_write_csv_round_trip() {
+        let records = vec![
+            record("plain", "text", true),
+            record("a, b, c", "\"quoted\"", false),
+            record("multi\nline", "  leading and trailing  ", true),
+            record("", "", true),
+        ];
+
+        let mut output = vec![];
+        write_csv(&mut output, records.clone()).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("path,line_number,line,replacement,included\n"));
+
+        let parsed = csv::Reader::from_reader(output.as_bytes())
+            .deserialize()
+            .collect::<Result<Vec<ResultRecord>, _>>()
+            .unwrap();
+        assert_eq!(parsed, records);
+    }
+
+    #[test]
+    fn test_write_csv_quoting() {
+        let mut output = vec![];
+        write_csv(&mut output, vec![record("say \"hi\", then", "x", true)]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap().lines().nth(1).unwrap(),
+            "\"./dir/file, with comma.txt\",3,\"say \"\"hi\"\", then\",x,true"
+        );
+    }
+
+    #[test]
+    fn test_unified_patch() {
+        let record =
+            |path: &str, line_number, line: &str, replacement: &str, included| ResultRecord {
+                path: path.to_owned(),
+                line_number,
+                line: line.to_owned(),
+                replacement: replacement.to_owned(),
+                included,
+            };
+        let records = vec![
+            record("./b.txt", 4, "foo", "bar", true),
+            record("./a.txt", 2, "foo 2", "bar 2", true),
+            record("./a.txt", 1, "foo 1", "bar\n1", true),
+            record("./a.txt", 3, "foo 3", "bar 3", false),
+            record("./a.txt", 7, "foo 7", "bar 7", true),
+            record("./c.txt", 1, "foo", "bar", false),
+        ];
+
+        assert_eq!(
+            unified_patch(records),
+            concat!(
+                "--- a/a.txt\n",
+                "+++ b/a.txt\n",
+                "@@ -1,2 +1,3 @@\n",
+                "-foo 1\n",
+                "-foo 2\n",
+                "+bar\n",
+                "+1\n",
+                "+bar 2\n",
+                "@@ -7,1 +8,1 @@\n",
+                "-foo 7\n",
+                "+bar 7\n",
+                "--- a/b.txt\n",
+                "+++ b/b.txt\n",
+                "@@ -4,1 +4,1 @@\n",
+                "-foo\n",
+                "+bar\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_unified_patch_nothing_included() {
+        assert_eq!(unified_patch(vec![record("foo", "bar", false)]), "");
+    }
+}
@@ -1,7 +1,7 @@
 use ratatui::{
     crossterm::event::{KeyCode, KeyModifiers},
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Style, Stylize},
+    style::{Color, Modifier, Style},
     text::Text,
     widgets::{Block, Paragraph},
     Frame,
@@ -18,6 +18,8 @@ pub struct TextField {
     pub text: String,
     pub cursor_idx: usize,
     pub error: Option<FieldError>,
+    /// Hint shown in a dim style while the field is empty
+    pub placeholder: Option<String>,
 }
 
 impl TextField {
@@ -26,8 +28,15 @@ impl TextField {
             text: initial,
             cursor_idx: 0,
             error: None,
+            placeholder: None,
         }
     }
+
+    pub fn with_placeholder(mut self, placeholder: impl Into<String>) -> Self {
+        self.placeholder = Some(placeholder.into());
+        self
+    }
+
     pub fn text(&self) -> String {
         self.text.to_owned()
     }
@@ -152,6 +161,19 @@ impl TextField {
         new_cursor_pos.clamp(0, self.text.chars().count())
     }
 
+    /// Text to display in the field, with tabs and newlines shown as visible single-width
+    /// characters so that the field remains on one line and the cursor position is unaffected
+    pub fn display_text(&self) -> String {
+        self.text
+            .chars()
+            .map(|c| match c {
+                '\t' => '⇥',
+                '\n' => '⏎',
+                c => c,
+            })
+            .collect()
+    }
+
     pub fn clear(&mut self) {
         self.text.clear();
         self.cursor_idx = 0;
@@ -165,7 +187,7 @@ impl TextField {
         self.error = None;
     }
 
-    fn handle_keys(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+    pub fn handle_keys(&mut self, code: KeyCode, modifiers: KeyModifiers) {
         match (code, modifiers) {
             (KeyCode::Char('w'), KeyModifiers::CONTROL)
             | (KeyCode::Backspace, KeyModifiers::ALT) => {
@@ -209,6 +231,14 @@ impl TextField {
             (KeyCode::Delete, _) => {
                 self.delete_char_forward();
             }
+            // Tab and enter are used to change focus and to search, so these are used to enter
+            // literal tabs and newlines instead
+            (KeyCode::Char('t'), KeyModifiers::CONTROL) => {
+                self.enter_char('\t');
+            }
+            (KeyCode::Enter, KeyModifiers::ALT) => {
+                self.enter_char('\n');
+            }
             (KeyCode::Char(value), _) => {
                 self.enter_char(value);
             }
@@ -247,6 +277,14 @@ impl Field {
         Field::Text(TextField::new(initial.into()))
     }
 
+    /// Show `placeholder` while the field is empty. Checkboxes have no placeholder.
+    pub fn with_placeholder(self, placeholder: impl Into<String>) -> Field {
+        match self {
+            Field::Text(f) => Field::Text(f.with_placeholder(placeholder)),
+            Field::Checkbox(_) => self,
+        }
+    }
+
     pub fn checkbox(initial: bool) -> Field {
         Field::Checkbox(CheckboxField::new(initial))
     }
@@ -280,10 +318,22 @@ impl Field {
         }
     }
 
+    /// Border colour to render the field with: red if the field has an error (e.g. the regex
+    /// doesn't compile), otherwise green if the field is highlighted
+    pub fn border_colour(&self, highlighted: bool) -> Option<Color> {
+        if self.error().is_some() {
+            Some(Color::Red)
+        } else if highlighted {
+            Some(Color::Green)
+        } else {
+            None
+        }
+    }
+
     pub fn render(&self, frame: &mut Frame<'_>, area: Rect, title: String, highlighted: bool) {
         let mut block = Block::bordered();
-        if highlighted {
-            block = block.border_style(Style::new().green());
+        if let Some(colour) = self.border_colour(highlighted) {
+            block = block.border_style(Style::new().fg(colour));
         }
 
         let outer_chunks = Layout::default()
@@ -294,7 +344,13 @@ impl Field {
         match self {
             Field::Text(f) => {
                 block = block.title(title);
-                frame.render_widget(Paragraph::new(f.text()).block(block), outer_chunks[0]);
+                let text = match f.placeholder {
+                    Some(ref placeholder) if f.text.is_empty() => {
+                        Text::styled(placeholder, Style::new().add_modifier(Modifier::DIM))
+                    }
+                    _ => Text::raw(f.display_text()),
+                };
+                frame.render_widget(Paragraph::new(text).block(block), outer_chunks[0]);
             }
             Field::Checkbox(f) => {
                 let inner_chunks = Layout::default()
@@ -0,0 +1,116 @@
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+
+use crate::error::ScooterError;
+
+fn run_git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, ScooterError> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(ScooterError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ))
+    }
+}
+
+fn parse_paths<'a>(dir: &'a Path, output: &'a [u8]) -> impl Iterator<Item = PathBuf> + 'a {
+    output
+        .split(|b| *b == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| dir.join(String::from_utf8_lossy(path).as_ref()))
+}
+
+/// Returns the files under `dir` that git reports as modified relative to HEAD, or untracked
+/// (ignoring files matched by `.gitignore` etc.). Paths are `dir` joined with the path relative
+/// to `dir`, so that they can be compared with the paths found when walking `dir`.
+pub fn modified_files(dir: &Path) -> Result<HashSet<PathBuf>, ScooterError> {
+    if run_git(dir, &["rev-parse", "--is-inside-work-tree"]).is_err() {
+        return Err(ScooterError::NotGitRepository(dir.to_path_buf()));
+    }
+
+    let has_head = run_git(dir, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_ok();
+    let changed = if has_head {
+        run_git(dir, &["diff", "--name-only", "--relative", "-z", "HEAD"])?
+    } else {
+        // Everything in the index is new if there are no commits yet
+        run_git(dir, &["ls-files", "--cached", "-z"])?
+    };
+    let untracked = run_git(dir, &["ls-files", "--others", "--exclude-standard", "-z"])?;
+
+    Ok(parse_paths(dir, &changed)
+        .chain(parse_paths(dir, &untracked))
+        .collect())
+}
+
+/// Checks whether files are matched by a `.gitignore`, independently of any directory walk. Each
+/// ignore file is only read once, and shared between clones.
+#[derive(Clone, Debug, Default)]
+pub struct GitignoreMatcher {
+    gitignores: Arc<Mutex<HashMap<PathBuf, Arc<Gitignore>>>>,
+}
+
+impl GitignoreMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `path` is ignored by the `.gitignore` in any of its ancestor directories up to the
+    /// root of its git repository, or by the repository's `.git/info/exclude`. As with git, deeper
+    /// `.gitignore` files take precedence, so that they can whitelist files ignored higher up, and
+    /// `.gitignore` files take precedence over `.git/info/exclude`.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        for dir in path.ancestors().skip(1) {
+            if dir.as_os_str().is_empty() {
+                continue;
+            }
+            let git_dir = dir.join(".git");
+            let is_repo_root = git_dir.exists();
+            let mut ignore_files = vec![dir.join(".gitignore")];
+            if is_repo_root {
+                ignore_files.push(git_dir.join("info").join("exclude"));
+            }
+            for ignore_file in ignore_files {
+                match self
+                    .gitignore(dir, ignore_file)
+                    .matched_path_or_any_parents(path, false)
+                {
+                    Match::None => continue,
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                }
+            }
+            // Files outside the repository don't apply
+            if is_repo_root {
+                break;
+            }
+        }
+        false
+    }
+
+    /// The patterns in `ignore_file`, which are relative to `dir`
+    fn gitignore(&self, dir: &Path, ignore_file: PathBuf) -> Arc<Gitignore> {
+        let mut gitignores = self.gitignores.lock();
+        let gitignore = gitignores
+            .entry(ignore_file)
+            .or_insert_with_key(|ignore_file| {
+                Arc::new(if ignore_file.is_file() {
+                    let mut builder = GitignoreBuilder::new(dir);
+                    builder.add(ignore_file);
+                    builder.build().unwrap_or_else(|_| Gitignore::empty())
+                } else {
+                    Gitignore::empty()
+                })
+            });
+        Arc::clone(gitignore)
+    }
+}
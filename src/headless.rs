@@ -0,0 +1,311 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use crate::{
+    app::{App, Screen},
+    event::{ReplaceResult, SearchResult},
+    export::{write_csv, OutputFormat, ResultRecord},
+    parsed_fields::SearchObserver,
+    replace::Counter,
+    utils::line_ending_len,
+};
+
+/// Exit codes when running without the TUI, for use in scripts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    NoMatches = 1,
+    ReplacementErrors = 2,
+    InvalidArguments = 3,
+    /// Any other error, such as failing to write the output
+    Error = 4,
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    fn from(code: ExitCode) -> Self {
+        std::process::ExitCode::from(code as u8)
+    }
+}
+
+async fn process_background_events(app: &mut App) {
+    while let Some(event) = app.background_processing_recv().await {
+        app.handle_background_processing_event(event);
+    }
+}
+
+/// Searches using the values in `app.search_fields`, returning the exit code if there is
+/// nothing to replace
+async fn search(app: &mut App) -> Result<(), ExitCode> {
+    app.perform_search_if_valid();
+    if app.confirming_large_search {
+        for dir in app.large_search_roots() {
+            eprintln!("Searching {dir:?} may take a long time: pass --yes to search anyway");
+        }
+        return Err(ExitCode::InvalidArguments);
+    }
+    if !matches!(app.current_screen, Screen::SearchProgressing(_)) {
+        for (name, error) in app.search_fields.errors() {
+            eprintln!("{name}: {}", error.long);
+        }
+        return Err(ExitCode::InvalidArguments);
+    }
+
+    process_background_events(app).await;
+    match &app.current_screen {
+        Screen::SearchComplete(search_state) if search_state.results.is_empty() => {
+            Err(ExitCode::NoMatches)
+        }
+        Screen::SearchComplete(_) => Ok(()),
+        // The search found matches, but not the one passed with `--occurrence`
+        Screen::SearchFields => {
+            for (name, error) in app.search_fields.errors() {
+                eprintln!("{name}: {}", error.long);
+            }
+            Err(ExitCode::NoMatches)
+        }
+        screen => {
+            eprintln!("{}", unexpected_screen("SearchComplete", screen));
+            Err(ExitCode::Error)
+        }
+    }
+}
+
+/// The error for when the search or replacement finished on a screen other than `expected`
+fn unexpected_screen(expected: &str, screen: &Screen) -> io::Error {
+    io::Error::other(format!("Expected {expected}, found {screen:?}"))
+}
+
+/// Reports to `writer` that the search stopped with `exit_code`, unless `quiet`. Errors have already
+/// been written to stderr.
+fn search_stopped<W: io::Write>(
+    exit_code: ExitCode,
+    quiet: bool,
+    mut writer: W,
+) -> io::Result<ExitCode> {
+    if exit_code == ExitCode::NoMatches && !quiet {
+        writeln!(writer, "No matches found")?;
+    }
+    Ok(exit_code)
+}
+
+/// Searches and replaces using the values in `app.search_fields`, without any interaction, writing
+/// a summary to `writer` unless `quiet`
+pub async fn run_headless<W: io::Write>(
+    app: &mut App,
+    quiet: bool,
+    mut writer: W,
+) -> io::Result<ExitCode> {
+    if let Err(exit_code) = search(app).await {
+        return search_stopped(exit_code, quiet, writer);
+    }
+
+    app.trigger_replacement();
+    process_background_events(app).await;
+    let Screen::Results(replace_state) = &app.current_screen else {
+        return Err(unexpected_screen("Results", &app.current_screen));
+    };
+
+    if !quiet {
+        writeln!(
+            writer,
+            "Successful replacements: {}",
+            replace_state.num_successes
+        )?;
+        if let Some(num_remaining_matches) = replace_state.num_remaining_matches {
+            writeln!(writer, "Matches remaining: {num_remaining_matches}")?;
+        }
+    }
+    if replace_state.errors.is_empty() {
+        Ok(ExitCode::Success)
+    } else {
+        for error in &replace_state.errors {
+            eprintln!(
+                "Error replacing in {}:{}: {}",
+                app.relative_path(&error.path),
+                error.line_number,
+                match &error.replace_result {
+                    Some(ReplaceResult::Error(e)) => e.as_str(),
+                    _ => "unknown error",
+                }
+            );
+        }
+        Ok(ExitCode::ReplacementErrors)
+    }
+}
+
+/// Searches using the values in `app.search_fields` and writes the results to `writer`, without
+/// replacing anything. Only the results are written to `writer`, so that it can be parsed, and if
+/// there are none then this is reported to stderr unless `quiet`.
+pub async fn export_results<W: io::Write>(
+    app: &mut App,
+    format: OutputFormat,
+    quiet: bool,
+    writer: W,
+) -> Result<ExitCode, csv::Error> {
+    if let Err(exit_code) = search(app).await {
+        return Ok(search_stopped(exit_code, quiet, io::stderr())?);
+    }
+    let Screen::SearchComplete(search_state) = &app.current_screen else {
+        return Err(unexpected_screen("SearchComplete", &app.current_screen).into());
+    };
+
+    let mut results = search_state.results.clone();
+    // Results are found in parallel, so sort them to give a stable output
+    results.sort_by(|a, b| (&a.path, a.line_number).cmp(&(&b.path, b.line_number)));
+    let records = results
+        .iter()
+        .map(|res| ResultRecord::new(res, app.relative_path(&res.path)));
+
+    match format {
+        OutputFormat::Csv => write_csv(writer, records)?,
+    }
+    Ok(ExitCode::Success)
+}
+
+/// Counts the matches for `app.search_fields`, writing the number in each file (sorted by path)
+/// and then, unless `quiet`, the total to `writer`
+pub async fn count_matches<W: io::Write>(
+    app: &mut App,
+    quiet: bool,
+    mut writer: W,
+) -> io::Result<ExitCode> {
+    if let Err(exit_code) = search(app).await {
+        return search_stopped(exit_code, quiet, writer);
+    }
+    let Screen::SearchComplete(search_state) = &app.current_screen else {
+        return Err(unexpected_screen("SearchComplete", &app.current_screen));
+    };
+
+    let counts = search_state
+        .results
+        .iter()
+        .into_grouping_map_by(|res| app.relative_path(&res.path))
+        .fold(0, |count, _, res| count + res.num_matches)
+        .into_iter()
+        .sorted()
+        .collect::<Vec<_>>();
+    for (path, count) in &counts {
+        writeln!(writer, "{path}: {count}")?;
+    }
+    if !quiet {
+        let total = counts.iter().map(|(_, count)| count).sum::<usize>();
+        writeln!(
+            writer,
+            "Total: {total} {} in {} {}",
+            if total == 1 { "match" } else { "matches" },
+            counts.len(),
+            if counts.len() == 1 { "file" } else { "files" },
+        )?;
+    }
+    Ok(ExitCode::Success)
+}
+
+/// Replaces matches for `app.search_fields` in each line read from `reader`, writing every line to
+/// `writer` (replaced or not) without touching any files. Lines that aren't valid UTF-8 are
+/// written unchanged.
+pub fn replace_stream<R: io::BufRead, W: io::Write>(
+    app: &mut App,
+    mut reader: R,
+    mut writer: W,
+) -> io::Result<ExitCode> {
+    let Some(parsed_fields) = app.parsed_fields() else {
+        for (name, error) in app.search_fields.errors() {
+            eprintln!("{name}: {}", error.long);
+        }
+        return Ok(ExitCode::InvalidArguments);
+    };
+    let mut counter = Counter::new(app.counter());
+    // There are no files, so count as if every line were in the same one
+    let path = PathBuf::new();
+
+    let mut num_replaced = 0;
+    let mut line_number = 0;
+    let mut line_bytes = vec![];
+    loop {
+        line_bytes.clear();
+        if reader.read_until(b'\n', &mut line_bytes)? == 0 {
+            break;
+        }
+        let (line, line_ending) =
+            line_bytes.split_at(line_bytes.len() - line_ending_len(&line_bytes));
+        let result = std::str::from_utf8(line).ok().and_then(|line| {
+            parsed_fields.replacement_if_match(path.clone(), line.to_owned(), line_number)
+        });
+        match result {
+            Some(result) => {
+                writer.write_all(counter.apply(&path, &result.replacement).as_bytes())?;
+                writer.write_all(line_ending)?;
+                num_replaced += 1;
+            }
+            None => writer.write_all(&line_bytes)?,
+        }
+        line_number += 1;
+    }
+    writer.flush()?;
+
+    Ok(if num_replaced == 0 {
+        ExitCode::NoMatches
+    } else {
+        ExitCode::Success
+    })
+}
+
+/// Counts and timing from `run_benchmark`, for comparing the performance of searches across
+/// changes to the walker or the line matching
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub files_scanned: usize,
+    pub matching_lines: usize,
+    pub matches: usize,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Default)]
+struct BenchmarkObserver {
+    files_scanned: AtomicUsize,
+    matching_lines: AtomicUsize,
+    matches: AtomicUsize,
+}
+
+impl SearchObserver for BenchmarkObserver {
+    fn on_result(&self, result: &SearchResult) {
+        self.matching_lines.fetch_add(1, Ordering::Relaxed);
+        self.matches
+            .fetch_add(result.num_matches, Ordering::Relaxed);
+    }
+
+    fn on_file_scanned(&self, _path: &Path) {
+        self.files_scanned.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Searches for `app.search_fields` without keeping the results or replacing anything, writing a
+/// `BenchmarkReport` to `writer` as a single line of JSON
+pub fn run_benchmark<W: io::Write>(app: &mut App, mut writer: W) -> io::Result<ExitCode> {
+    let Some(parsed_fields) = app.parsed_fields() else {
+        for (name, error) in app.search_fields.errors() {
+            eprintln!("{name}: {}", error.long);
+        }
+        return Ok(ExitCode::InvalidArguments);
+    };
+
+    let observer = BenchmarkObserver::default();
+    let start = Instant::now();
+    parsed_fields.search_observed(&observer);
+    let report = BenchmarkReport {
+        files_scanned: observer.files_scanned.into_inner(),
+        matching_lines: observer.matching_lines.into_inner(),
+        matches: observer.matches.into_inner(),
+        elapsed_ms: start.elapsed().as_millis() as u64,
+    };
+
+    serde_json::to_writer(&mut writer, &report)?;
+    writeln!(writer)?;
+    Ok(ExitCode::Success)
+}
@@ -1,12 +1,36 @@
+#[cfg(feature = "walker")]
 pub mod app;
+#[cfg(feature = "walker")]
+pub mod error;
+#[cfg(feature = "walker")]
 pub mod event;
+#[cfg(feature = "walker")]
+pub mod export;
+#[cfg(feature = "walker")]
 pub mod fields;
+#[cfg(feature = "walker")]
+pub mod git;
+#[cfg(feature = "walker")]
+pub mod headless;
+#[cfg(feature = "walker")]
 pub mod logging;
+#[cfg(feature = "walker")]
+pub mod map_file;
+#[cfg(feature = "walker")]
 pub mod parsed_fields;
+pub mod replace;
+#[cfg(feature = "walker")]
 pub mod ui;
+#[cfg(feature = "walker")]
 pub mod utils;
 
+#[cfg(feature = "walker")]
 pub use app::*;
+#[cfg(feature = "walker")]
+pub use error::*;
+#[cfg(feature = "walker")]
 pub use event::*;
+#[cfg(feature = "walker")]
 pub use fields::*;
+#[cfg(feature = "walker")]
 pub use ui::*;
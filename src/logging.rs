@@ -1,5 +1,11 @@
 use log::{info, LevelFilter};
-use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
 
 use etcetera::base_strategy::{choose_base_strategy, BaseStrategy};
 
@@ -17,6 +23,11 @@ pub fn default_log_file() -> PathBuf {
     cache_dir().join(format!("{APP_NAME}.log"))
 }
 
+/// Where search results are saved to by default, so that a review can be resumed later
+pub fn default_review_file() -> PathBuf {
+    cache_dir().join("review.json")
+}
+
 fn make_parent_dir(path: &Path) {
     if let Some(parent) = path.parent() {
         if !parent.exists() {
@@ -34,3 +45,97 @@ pub fn setup_logging(level: LevelFilter) -> anyhow::Result<()> {
     info!("Logging initialized at {:?}", log_path);
     Ok(())
 }
+
+/// Reads the last `num_lines` lines of the log file at `path`
+pub fn read_log_tail(path: &Path, num_lines: usize) -> io::Result<Vec<String>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut tail = VecDeque::with_capacity(num_lines);
+    for line in reader.lines() {
+        let line = line?;
+        if num_lines == 0 {
+            continue;
+        }
+        if tail.len() == num_lines {
+            tail.pop_front();
+        }
+        tail.push_back(line);
+    }
+    Ok(tail.into())
+}
+
+/// A file that was modified by a replacement, as written to the audit log
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub path: PathBuf,
+    pub replacements: usize,
+}
+
+/// Appends `records` to the audit log at `path`, one JSON object per line, creating the file if
+/// it doesn't exist
+pub fn append_audit_records(path: &Path, records: &[AuditRecord]) -> io::Result<()> {
+    make_parent_dir(path);
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = BufWriter::new(file);
+    for record in records {
+        serde_json::to_writer(&mut writer, record)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_default_log_file() {
+        let log_file = default_log_file();
+        assert_eq!(log_file.parent(), Some(cache_dir().as_path()));
+        assert!(log_file.ends_with(Path::new("scooter").join("scooter.log")));
+    }
+
+    #[test]
+    fn test_read_log_tail() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("test.log");
+        fs::write(&path, "one\ntwo\nthree\nfour\n").unwrap();
+
+        assert_eq!(read_log_tail(&path, 2).unwrap(), vec!["three", "four"]);
+        assert_eq!(
+            read_log_tail(&path, 10).unwrap(),
+            vec!["one", "two", "three", "four"]
+        );
+        assert!(read_log_tail(&path, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_log_tail_missing_file() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(read_log_tail(&temp_dir.path().join("missing.log"), 5).is_err());
+    }
+
+    #[test]
+    fn test_append_audit_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("audit").join("audit.jsonl");
+        let record = |path: &str, replacements| AuditRecord {
+            timestamp: 1_700_000_000,
+            path: PathBuf::from(path),
+            replacements,
+        };
+
+        append_audit_records(&path, &[record("a.txt", 2)]).unwrap();
+        append_audit_records(&path, &[record("b.txt", 1), record("c.txt", 4)]).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "{\"timestamp\":1700000000,\"path\":\"a.txt\",\"replacements\":2}\n\
+             {\"timestamp\":1700000000,\"path\":\"b.txt\",\"replacements\":1}\n\
+             {\"timestamp\":1700000000,\"path\":\"c.txt\",\"replacements\":4}\n"
+        );
+    }
+}
@@ -1,38 +1,53 @@
-use clap::Parser;
-use event::EventHandlingResult;
-use log::LevelFilter;
-use logging::{setup_logging, DEFAULT_LOG_LEVEL};
+use clap::{ArgGroup, Parser, Subcommand};
+use log::{warn, LevelFilter};
 use ratatui::{backend::CrosstermBackend, Terminal};
-use std::{io, str::FromStr};
-use tui::Tui;
-use utils::validate_directory;
-
-use crate::{
-    app::App,
-    event::{Event, EventHandler},
+use regex::Regex;
+use scooter::{
+    app::{App, SearchFields, DEFAULT_MIN_FIXED_LEN},
+    event::{Event, EventHandler, EventHandlingResult},
+    export::OutputFormat,
+    git,
+    headless::{
+        count_matches, export_results, replace_stream, run_benchmark, run_headless,
+        ExitCode as HeadlessExitCode,
+    },
+    logging::{default_log_file, setup_logging, DEFAULT_LOG_LEVEL},
+    map_file::read_map_file,
+    parsed_fields::DEFAULT_PRUNED_DIRS,
+    replace::CounterConfig,
+    ui::ContentWidth,
+    utils::{editor_command, parse_duration, validate_directory},
 };
+use std::{io, path::PathBuf, process::ExitCode, str::FromStr, time::Duration};
+use tokio::sync::mpsc;
+use tui::Tui;
 
-mod app;
-mod event;
-mod fields;
-mod logging;
-mod parsed_fields;
 mod tui;
-mod ui;
-mod utils;
 
 #[derive(Parser, Debug)]
 #[command(about = "Interactive find and replace TUI.")]
-#[command(version)]
+#[command(version, args_conflicts_with_subcommands = true)]
+#[command(group = ArgGroup::new("headless").args(["no_tui", "stdin"]).multiple(true))]
 struct Args {
-    /// Directory in which to search
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Directories in which to search
     #[arg(index = 1)]
-    directory: Option<String>,
+    directories: Vec<String>,
 
     /// Include hidden files and directories, such as those whose name starts with a dot (.)
     #[arg(short = '.', long, default_value = "false")]
     hidden: bool,
 
+    /// Include files and directories that would otherwise be skipped due to .gitignore, .ignore and similar files
+    #[arg(long, default_value = "false")]
+    no_ignore: bool,
+
+    /// Include hidden and ignored files, equivalent to --hidden --no-ignore
+    #[arg(short = 'u', long, default_value = "false")]
+    all: bool,
+
     /// Log level (trace, debug, info, warn, error)
     #[arg(
         long,
@@ -41,34 +56,531 @@ struct Args {
     )]
     log_level: LevelFilter,
 
+    /// Print the path of the log file and exit
+    #[arg(long, default_value = "false")]
+    print_log_path: bool,
+
+    /// Print the settings that would be used, once the other flags have been applied over the
+    /// defaults, as JSON and exit
+    #[arg(long, default_value = "false")]
+    print_config: bool,
+
     /// Use advanced regex features (including negative look-ahead), at the cost of performance
     #[arg(short = 'a', long, default_value = "false")]
     advanced_regex: bool,
+
+    /// Only search files that git reports as modified relative to HEAD, or untracked
+    #[arg(long, default_value = "false")]
+    git_modified: bool,
+
+    /// Search lines that aren't valid UTF-8, replacing invalid sequences with U+FFFD. Such lines are never replaced.
+    #[arg(long, default_value = "false")]
+    lossy: bool,
+
+    /// Apply the replacement to each line repeatedly until it stops changing, e.g. so that
+    /// replacing 'aa' with 'a' in 'aaaa' gives 'a' rather than 'aa'. Lines are replaced at most
+    /// 10 times, in case they never stop changing
+    #[arg(long, default_value = "false")]
+    repeat: bool,
+
+    /// Maximum number of files to have open at once while searching, for systems with a low limit
+    /// on open file descriptors
+    #[arg(long, value_name = "N")]
+    max_open_files: Option<usize>,
+
+    /// Only search files modified within this long ago, e.g. '30m', '12h', '2d' or '1w'
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    changed_within: Option<Duration>,
+
+    /// Only search files last modified at least this long ago, e.g. '30m', '12h', '2d' or '1w'
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    changed_before: Option<Duration>,
+
+    /// Search symlinked files and directories. Replacing in a symlinked file edits the file it
+    /// links to, keeping the symlink.
+    #[arg(short = 'L', long, default_value = "false")]
+    follow_symlinks: bool,
+
+    /// Expand environment variables, such as $HOME or ${USER}, in the replacement text. Capture
+    /// groups take precedence over environment variables with the same name.
+    #[arg(long, default_value = "false")]
+    expand_env: bool,
+
+    /// Show more results on screen by displaying each as a single inline diff
+    #[arg(long, default_value = "false")]
+    compact: bool,
+
+    /// Number of lines to show either side of each successful replacement on the results screen,
+    /// read from the file after replacing
+    #[arg(long, value_name = "N", default_value = "0", conflicts_with_all = ["no_tui", "stdin"])]
+    context: usize,
+
+    /// Width of the main content, either as a percentage of the terminal (e.g. '80%') or as a
+    /// number of columns (e.g. '120')
+    #[arg(long, default_value = "80%")]
+    width: ContentWidth,
+
+    /// After replacing, search the replaced files again and report how many matches remain
+    #[arg(long, default_value = "false")]
+    verify: bool,
+
+    /// If any replacements fail, return to the list of results with the failures selected so that
+    /// they can be retried, rather than showing the errors on a separate screen
+    #[arg(long, default_value = "false", conflicts_with = "no_tui")]
+    retry_failed: bool,
+
+    /// Don't ask for confirmation before searching very large directories, such as the home
+    /// directory or a filesystem root
+    #[arg(short = 'y', long, default_value = "false")]
+    yes: bool,
+
+    /// Pause the search after finding this many results, to check that the pattern is right
+    /// before searching everything. Press 'r' on the list of results to resume the search
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["no_tui", "stdin"]
+    )]
+    preview: Option<u64>,
+
+    /// Resume reviewing results saved by pressing 's' on the list of results, without searching
+    /// again. Results whose lines have changed since they were saved are left out
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["no_tui", "stdin", "map", "preview"])]
+    resume: Option<PathBuf>,
+
+    /// Show at most N results for each file in the list of results, with a row counting the rest.
+    /// Press 'm' to show all results in the selected file
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["no_tui", "stdin"]
+    )]
+    max_per_file: Option<u64>,
+
+    /// When searching with fixed strings, match each run of whitespace in the search text against
+    /// any run of whitespace, so that 'foo bar' also matches 'foo   bar'
+    #[arg(long, default_value = "false", conflicts_with = "map")]
+    normalize_whitespace: bool,
+
+    /// Collapse runs of whitespace in the lines that are replaced into a single space, rather than
+    /// keeping the original whitespace, when searching with fixed strings. Indentation is kept
+    #[arg(long, default_value = "false", requires = "normalize_whitespace")]
+    write_normalized: bool,
+
+    /// Append a record of each file that is modified, with a timestamp and the number of
+    /// replacements, to FILE as JSON lines
+    #[arg(long = "audit", value_name = "FILE", conflicts_with = "stdin")]
+    audit_file: Option<PathBuf>,
+
+    /// Name of a directory to never search, in addition to .git, node_modules and target. Can be repeated
+    #[arg(long = "prune", value_name = "DIR")]
+    pruned_dirs: Vec<String>,
+
+    /// Don't prune .git, node_modules and target directories by default
+    #[arg(long, default_value = "false")]
+    no_default_prune: bool,
+
+    /// Search inside .git directories, e.g. to edit hooks or config, even if hidden files aren't
+    /// included. Binary files, such as git objects, are still skipped
+    #[arg(long, default_value = "false")]
+    search_git: bool,
+
+    /// Search and replace without the TUI, replacing all matches. Requires --search and --replace,
+    /// or --map. Exits with 0 on success, 1 if there were no matches, 2 if any replacements failed,
+    /// 3 for invalid arguments and 4 for any other error
+    #[arg(long, default_value = "false")]
+    no_tui: bool,
+
+    /// Read text from stdin, replace matches in each line and write the result to stdout, without
+    /// searching or changing any files. Requires --search and --replace, or --map. Exits with 0 if
+    /// anything was replaced, 1 if there were no matches, 3 for invalid arguments and 4 for any
+    /// other error
+    #[arg(
+        long,
+        default_value = "false",
+        conflicts_with_all = ["no_tui", "directories"]
+    )]
+    stdin: bool,
+
+    /// File of strings to replace along with their replacements, to replace them all at once:
+    /// either CSV rows of the form 'old,new', or a JSON object if the file ends in .json
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["search", "replace", "fixed_strings"])]
+    map: Option<PathBuf>,
+
+    /// Search without replacing or keeping the results, and print the number of files scanned,
+    /// matching lines and matches, along with the time taken, as JSON. For catching performance
+    /// regressions
+    #[arg(
+        long,
+        hide = true,
+        requires = "search",
+        conflicts_with_all = ["no_tui", "stdin", "map"]
+    )]
+    bench: bool,
+
+    /// Text to search with. Required with --no-tui or --stdin, and otherwise filled in on the
+    /// search screen
+    #[arg(short, long)]
+    search: Option<String>,
+
+    /// Text to replace the search text with. Required with --no-tui (unless exporting with
+    /// --format) or --stdin, and otherwise filled in on the search screen
+    #[arg(short, long)]
+    replace: Option<String>,
+
+    /// Refuse fixed search strings shorter than this many characters, as they match almost every line
+    #[arg(long = "min-fixed-length", value_name = "N", default_value_t = DEFAULT_MIN_FIXED_LEN)]
+    min_fixed_len: usize,
+
+    /// Search with plain case-sensitive strings rather than regex
+    #[arg(short, long, default_value = "false")]
+    fixed_strings: bool,
+
+    /// Regex that lines must also match to be replaced, e.g. '// keep'. Lines that match the search
+    /// but not this are left as they are
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    line_filter: Option<Regex>,
+
+    /// Regex pattern that file paths must match
+    #[arg(short, long)]
+    path_pattern: Option<String>,
+
+    /// Interpret --path-pattern as a glob, e.g. '*.rs', rather than as a regex
+    #[arg(long, default_value = "false", requires = "path_pattern")]
+    glob: bool,
+
+    /// Match --path-pattern fuzzily, so that e.g. 'srcmain' matches 'src/main.rs'
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "path_pattern",
+        conflicts_with = "glob"
+    )]
+    fuzzy_path: bool,
+
+    /// Don't write anything to stdout other than the results, such as those given by --format,
+    /// when running with --no-tui or --stdin. Errors are still written to stderr.
+    #[arg(short, long, default_value = "false", requires = "headless")]
+    quiet: bool,
+
+    /// Write the search results to stdout in the given format, rather than replacing, when
+    /// running with --no-tui
+    #[arg(long, value_enum, requires = "no_tui")]
+    format: Option<OutputFormat>,
+
+    /// Number that the ${n} replacement token starts counting from
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
+    counter_start: i64,
+
+    /// Amount that the ${n} replacement token is incremented by after each use
+    #[arg(long, default_value = "1", allow_negative_numbers = true)]
+    counter_step: i64,
+
+    /// Restart the count of the ${n} replacement token for each file
+    #[arg(long, default_value = "false")]
+    counter_per_file: bool,
+
+    /// Replace only the match with this number, counting from 1 in order of path and line number,
+    /// leaving all other matches as they are
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    occurrence: Option<u64>,
+
+    /// Count matches for --occurrence within each file, replacing one match in every file
+    #[arg(long, default_value = "false", requires = "occurrence")]
+    occurrence_per_file: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Count the matches in each file, without replacing anything
+    Count(CountArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct CountArgs {
+    /// Text to search with
+    pattern: String,
+
+    /// Directories in which to search
+    directories: Vec<String>,
+
+    /// Include hidden files and directories, such as those whose name starts with a dot (.)
+    #[arg(short = '.', long, default_value = "false")]
+    hidden: bool,
+
+    /// Include files and directories that would otherwise be skipped due to .gitignore, .ignore and similar files
+    #[arg(long, default_value = "false")]
+    no_ignore: bool,
+
+    /// Search with plain case-sensitive strings rather than regex
+    #[arg(short, long, default_value = "false")]
+    fixed_strings: bool,
+
+    /// Use advanced regex features (including negative look-ahead), at the cost of performance
+    #[arg(short = 'a', long, default_value = "false")]
+    advanced_regex: bool,
+
+    /// Don't ask for confirmation before searching very large directories, such as the home
+    /// directory or a filesystem root
+    #[arg(short = 'y', long, default_value = "false")]
+    yes: bool,
+
+    /// Only write the count for each file, without the total or "No matches found"
+    #[arg(short, long, default_value = "false")]
+    quiet: bool,
+}
+
+async fn run_count(args: CountArgs) -> anyhow::Result<ExitCode> {
+    let directories = match args
+        .directories
+        .iter()
+        .map(|d| validate_directory(d))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(directories) => directories,
+        Err(e) => {
+            eprintln!("{e}");
+            return Ok(HeadlessExitCode::InvalidArguments.into());
+        }
+    };
+
+    let (app_event_sender, _app_event_receiver) = mpsc::unbounded_channel();
+    let mut app = App::new(
+        directories,
+        args.hidden,
+        args.advanced_regex,
+        app_event_sender,
+    )
+    .with_include_ignored(args.no_ignore)
+    .with_large_search_confirmed(args.yes)
+    .with_search_only(true);
+    app.search_fields = SearchFields::with_values(args.pattern, "", args.fixed_strings, "")
+        .with_advanced_regex(args.advanced_regex);
+    Ok(count_matches(&mut app, args.quiet, io::stdout().lock())
+        .await?
+        .into())
+}
+
+fn parse_regex(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| e.to_string())
 }
 
 fn parse_log_level(s: &str) -> Result<LevelFilter, String> {
     LevelFilter::from_str(s).map_err(|_| format!("Invalid log level: {}", s))
 }
 
+/// Opens `paths` in the editor given by `$EDITOR` (or `vi` if unset), waiting for it to exit
+async fn open_in_editor(paths: &[PathBuf]) {
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_owned());
+    let Some(command) = editor_command(&editor, paths) else {
+        warn!("Not opening the changed files, as $EDITOR is blank");
+        return;
+    };
+    match tokio::process::Command::from(command).status().await {
+        Ok(status) if !status.success() => warn!("Editor {editor} exited with {status}"),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to run editor {editor}: {e}"),
+    }
+}
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            // Returning the error would exit with 1, which means that there were no matches
+            eprintln!("Error: {e:?}");
+            HeadlessExitCode::Error.into()
+        }
+    }
+}
+
+async fn run() -> anyhow::Result<ExitCode> {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(e) if e.use_stderr() => {
+            e.print()?;
+            return Ok(HeadlessExitCode::InvalidArguments.into());
+        }
+        Err(e) => e.exit(),
+    };
+
+    if args.print_log_path {
+        println!("{}", default_log_file().display());
+        return Ok(ExitCode::SUCCESS);
+    }
 
     setup_logging(args.log_level)?;
 
-    let directory = match args.directory {
-        None => None,
-        Some(d) => Some(validate_directory(&d)?),
+    if let Some(Command::Count(count_args)) = args.command {
+        return run_count(count_args).await;
+    }
+
+    let directories = match args
+        .directories
+        .iter()
+        .map(|d| validate_directory(d))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(directories) => directories,
+        Err(e) if args.no_tui => {
+            eprintln!("{e}");
+            return Ok(HeadlessExitCode::InvalidArguments.into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // Exporting the results doesn't replace anything, so needs no replacement text
+    let search_only = args.format.is_some() && args.map.is_none() && args.replace.is_none();
+    if (args.no_tui || args.stdin)
+        && args.map.is_none()
+        && (args.search.is_none() || (args.replace.is_none() && !search_only))
+    {
+        eprintln!(
+            "--search and --replace are required with --no-tui and --stdin, unless --map is \
+            passed (or --format, which only needs --search)"
+        );
+        return Ok(HeadlessExitCode::InvalidArguments.into());
+    }
+
+    let replacement_map = match args.map.as_deref().map(read_map_file).transpose() {
+        Ok(replacement_map) => replacement_map,
+        Err(e) if args.no_tui || args.stdin => {
+            eprintln!("{e}");
+            return Ok(HeadlessExitCode::InvalidArguments.into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // With a map, search for any of its strings and look up the replacement for each match
+    let (search, replace, fixed_strings) = match &replacement_map {
+        Some(replacement_map) => (replacement_map.search_text(), String::new(), true),
+        None => (
+            args.search.unwrap_or_default(),
+            args.replace.unwrap_or_default(),
+            args.fixed_strings,
+        ),
+    };
+
+    // In the TUI, these fill in the search screen
+    let search_fields = SearchFields::with_values(
+        search,
+        replace,
+        fixed_strings,
+        args.path_pattern.unwrap_or_default(),
+    )
+    .with_advanced_regex(args.advanced_regex)
+    .with_path_glob(args.glob)
+    .with_path_fuzzy(args.fuzzy_path);
+
+    if args.git_modified {
+        // Fail early, rather than searching no files, if not in a git repo
+        if directories.is_empty() {
+            git::modified_files(&std::env::current_dir()?)?;
+        }
+        for dir in &directories {
+            git::modified_files(dir)?;
+        }
+    }
+
+    let pruned_dirs = if args.no_default_prune {
+        vec![]
+    } else {
+        DEFAULT_PRUNED_DIRS.iter().map(|&d| d.to_owned()).collect()
+    }
+    .into_iter()
+    .chain(args.pruned_dirs)
+    .collect();
+
+    let build_app = |app_event_sender| {
+        App::new(
+            directories,
+            args.hidden || args.all,
+            args.advanced_regex,
+            app_event_sender,
+        )
+        .with_include_ignored(args.no_ignore || args.all)
+        .with_counter(CounterConfig {
+            start: args.counter_start,
+            step: args.counter_step,
+            per_file: args.counter_per_file,
+        })
+        .with_git_modified_only(args.git_modified)
+        .with_lossy(args.lossy)
+        .with_repeat(args.repeat)
+        .with_max_open_files(args.max_open_files)
+        .with_changed_within(args.changed_within)
+        .with_changed_before(args.changed_before)
+        .with_follow_symlinks(args.follow_symlinks)
+        .with_expand_env(args.expand_env)
+        .with_compact(args.compact)
+        .with_content_width(args.width)
+        .with_verify(args.verify)
+        .with_large_search_confirmed(args.yes)
+        .with_pruned_dirs(pruned_dirs)
+        .with_search_git(args.search_git)
+        .with_occurrence(args.occurrence.map(|n| n as usize))
+        .with_occurrence_per_file(args.occurrence_per_file)
+        .with_line_filter(args.line_filter)
+        .with_audit_file(args.audit_file)
+        .with_max_results_per_file(args.max_per_file.map(|n| n as usize))
+        .with_normalize_whitespace(args.normalize_whitespace)
+        .with_collapse_whitespace(args.write_normalized)
+        .with_replacement_map(replacement_map)
+        .with_min_fixed_len(args.min_fixed_len)
+        .with_search_only(search_only)
     };
 
+    if args.print_config {
+        let (app_event_sender, _app_event_receiver) = mpsc::unbounded_channel();
+        let mut app = build_app(app_event_sender)
+            .with_retry_failed(args.retry_failed)
+            .with_results_context(args.context)
+            .with_preview_limit(args.preview.map(|n| n as usize));
+        app.search_fields = search_fields;
+        println!("{}", serde_json::to_string_pretty(&app.resolved_config())?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.stdin {
+        let (app_event_sender, _app_event_receiver) = mpsc::unbounded_channel();
+        let mut app = build_app(app_event_sender);
+        app.search_fields = search_fields;
+        let exit_code = replace_stream(&mut app, io::stdin().lock(), io::stdout().lock())?;
+        return Ok(exit_code.into());
+    }
+
+    if args.bench {
+        let (app_event_sender, _app_event_receiver) = mpsc::unbounded_channel();
+        let mut app = build_app(app_event_sender);
+        app.search_fields = search_fields;
+        let exit_code = run_benchmark(&mut app, io::stdout().lock())?;
+        return Ok(exit_code.into());
+    }
+
+    if args.no_tui {
+        let (app_event_sender, _app_event_receiver) = mpsc::unbounded_channel();
+        let mut app = build_app(app_event_sender);
+        app.search_fields = search_fields;
+        let exit_code = match args.format {
+            Some(format) => {
+                export_results(&mut app, format, args.quiet, io::stdout().lock()).await?
+            }
+            None => run_headless(&mut app, args.quiet, io::stdout().lock()).await?,
+        };
+        return Ok(exit_code.into());
+    }
+
     let app_events_handler = EventHandler::new();
-    let app_event_sender = app_events_handler.app_event_sender.clone();
-    let mut app = App::new(
-        directory,
-        args.hidden,
-        args.advanced_regex,
-        app_event_sender,
-    );
+    let mut app = build_app(app_events_handler.app_event_sender.clone())
+        .with_retry_failed(args.retry_failed)
+        .with_results_context(args.context)
+        .with_preview_limit(args.preview.map(|n| n as usize));
+    app.search_fields = search_fields;
+    if let Some(review_file) = &args.resume {
+        app.resume_review(review_file)?;
+    }
 
     let backend = CrosstermBackend::new(io::stdout());
     let terminal = Terminal::new(backend)?;
@@ -92,7 +604,12 @@ async fn main() -> anyhow::Result<()> {
                 app.handle_background_processing_event(event)}
         };
 
-        if rerender {
+        if let Some(paths) = app.take_editor_request() {
+            tui.suspend().await?;
+            open_in_editor(&paths).await;
+            tui.resume()?;
+            tui.draw(&mut app)?;
+        } else if rerender {
             tui.draw(&mut app)?;
         }
         if exit {
@@ -102,5 +619,5 @@ async fn main() -> anyhow::Result<()> {
 
     tui.exit()?;
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,40 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{error::ScooterError, replace::ReplacementMap};
+
+/// Reads the replacements for `--map`. Files ending in `.json` should contain an object mapping
+/// each string to its replacement, and any other file should contain CSV rows of the form
+/// `old,new`, without a header row.
+pub fn read_map_file(path: &Path) -> Result<ReplacementMap, ScooterError> {
+    let contents = fs::read_to_string(path)?;
+    let is_json = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let invalid = |e: String| ScooterError::InvalidMapFile(path.to_path_buf(), e);
+
+    let replacements = if is_json {
+        serde_json::from_str::<HashMap<String, String>>(&contents)
+            .map_err(|e| invalid(e.to_string()))?
+            .into_iter()
+            .collect::<Vec<_>>()
+    } else {
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(contents.as_bytes())
+            .deserialize::<(String, String)>()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(e.to_string()))?
+    };
+    // Each line is searched separately, so a string spanning lines would never match
+    if let Some((from, _)) = replacements
+        .iter()
+        .find(|(from, _)| from.contains('\n') || from.ends_with('\r'))
+    {
+        return Err(invalid(format!("{from:?} contains a line break")));
+    }
+    let map = ReplacementMap::new(replacements);
+    if map.is_empty() {
+        return Err(invalid("no replacements found".to_owned()));
+    }
+    Ok(map)
+}
@@ -1,35 +1,210 @@
 use content_inspector::{inspect, ContentType};
 use fancy_regex::Regex as FancyRegex;
-use ignore::{WalkBuilder, WalkParallel};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use globset::GlobMatcher;
+use ignore::{WalkBuilder, WalkParallel, WalkState};
 use log::warn;
 use regex::Regex;
 use std::{
-    fs::File,
+    collections::HashSet,
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
     io::{BufRead, BufReader},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime},
 };
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+pub use crate::replace::SearchType;
 
 use crate::{
-    event::{BackgroundProcessingEvent, SearchResult},
-    utils::relative_path_from,
+    event::{BackgroundProcessingEvent, ReplaceWarning, SearchResult},
+    git::GitignoreMatcher,
+    replace::{LineReplacement, LineReplacer, ReplacementMap, LARGE_FILE_BYTES},
+    utils::{outermost_dirs, path_contains_components, relative_path_from_roots, OpenFileLimiter},
 };
 
+/// Files with these extensions are never searched
+const BINARY_EXTENSIONS: &[&str] = &["png", "gif", "jpg", "jpeg", "ico", "svg", "pdf"];
+
+/// Directories that are never searched, unless overridden
+pub const DEFAULT_PRUNED_DIRS: &[&str] = &[".git", "node_modules", "target"];
+
+/// Notified as a search progresses, for embedders that want to follow a search without reading
+/// from a channel. Methods may be called concurrently from the walker's threads.
+pub trait SearchObserver: Sync {
+    /// Called with each result as soon as it is found
+    fn on_result(&self, result: &SearchResult);
+
+    /// Called once a file has been searched, whether or not it contained any results
+    fn on_file_scanned(&self, _path: &Path) {}
+
+    /// Called once a file has been searched if any lines in it matched but were skipped because
+    /// they look binary
+    fn on_binary_lines_skipped(&self, _path: &Path, _num_lines: usize) {}
+
+    /// Called once after all files have been searched
+    fn on_complete(&self) {}
+
+    /// Checked after each result, so that the search can stop early, e.g. once nothing is
+    /// listening for results any more
+    fn should_stop(&self) -> bool {
+        false
+    }
+}
+
+/// Forwards results to the channel that the TUI reads from
+impl SearchObserver for UnboundedSender<BackgroundProcessingEvent> {
+    fn on_result(&self, result: &SearchResult) {
+        // Ignore error: the receiver is dropped if the search is cancelled
+        let _ = self.send(BackgroundProcessingEvent::AddSearchResult(result.clone()));
+    }
+
+    fn on_binary_lines_skipped(&self, _path: &Path, num_lines: usize) {
+        let _ = self.send(BackgroundProcessingEvent::BinaryLinesSkipped(num_lines));
+    }
+
+    fn on_complete(&self) {
+        let _ = self.send(BackgroundProcessingEvent::SearchCompleted);
+    }
+
+    fn should_stop(&self) -> bool {
+        // The search is likely to have been cancelled
+        self.is_closed()
+    }
+}
+
+/// Forwards results to the receiver returned by `ParsedFields::search_stream`
+impl SearchObserver for UnboundedSender<SearchResult> {
+    fn on_result(&self, result: &SearchResult) {
+        let _ = self.send(result.clone());
+    }
+
+    fn should_stop(&self) -> bool {
+        self.is_closed()
+    }
+}
+
+/// Passes the first `limit` results on to `observer`, and then stops the search once another
+/// result is found
+struct LimitedObserver<'a, O> {
+    observer: &'a O,
+    limit: usize,
+    num_found: AtomicUsize,
+}
+
+impl<O: SearchObserver> SearchObserver for LimitedObserver<'_, O> {
+    fn on_result(&self, result: &SearchResult) {
+        // Other threads may still find results after the walk has been told to stop
+        if self.num_found.fetch_add(1, Ordering::Relaxed) < self.limit {
+            self.observer.on_result(result);
+        }
+    }
+
+    fn on_file_scanned(&self, path: &Path) {
+        self.observer.on_file_scanned(path);
+    }
+
+    fn on_binary_lines_skipped(&self, path: &Path, num_lines: usize) {
+        self.observer.on_binary_lines_skipped(path, num_lines);
+    }
+
+    fn should_stop(&self) -> bool {
+        self.num_found.load(Ordering::Relaxed) > self.limit || self.observer.should_stop()
+    }
+}
+
+/// Matches paths that contain the characters of a query in order, like fzf, so that e.g.
+/// `srcmain` matches `src/main.rs`. Lowercase queries match case-insensitively.
+#[derive(Clone)]
+pub struct FuzzyPathMatcher {
+    query: String,
+    matcher: Arc<SkimMatcherV2>,
+}
+
+impl FuzzyPathMatcher {
+    pub fn new(query: impl Into<String>) -> Self {
+        Self {
+            query: query.into(),
+            matcher: Arc::new(SkimMatcherV2::default()),
+        }
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.matcher.fuzzy_match(path, &self.query).is_some()
+    }
+}
+
+impl fmt::Debug for FuzzyPathMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FuzzyPathMatcher")
+            .field(&self.query)
+            .finish()
+    }
+}
+
+/// The path pattern, matched against the path of each file relative to the root directories
 #[derive(Clone, Debug)]
-pub enum SearchType {
-    Pattern(Regex),
-    PatternAdvanced(FancyRegex),
+pub enum PathMatcher {
+    Regex(Regex),
+    RegexAdvanced(FancyRegex),
+    /// Matches paths containing the pattern as a run of whole components, so that e.g. `src`
+    /// matches `./src/main.rs` but not `./resrc/main.rs`
     Fixed(String),
+    /// Matched against the relative path without the leading `./`
+    Glob(GlobMatcher),
+    /// Matched against the relative path without the leading `./`, like `Glob`
+    Fuzzy(FuzzyPathMatcher),
+}
+
+impl PathMatcher {
+    pub fn is_match(&self, relative_path: &str) -> bool {
+        let unprefixed_path = || {
+            let path = Path::new(relative_path);
+            path.strip_prefix(".").unwrap_or(path)
+        };
+        match self {
+            PathMatcher::Regex(regex) => regex.is_match(relative_path),
+            PathMatcher::RegexAdvanced(regex) => regex.is_match(relative_path).unwrap_or(false),
+            PathMatcher::Fixed(pattern) => path_contains_components(relative_path, pattern),
+            PathMatcher::Glob(glob) => glob.is_match(unprefixed_path()),
+            PathMatcher::Fuzzy(fuzzy) => fuzzy.is_match(&unprefixed_path().to_string_lossy()),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct ParsedFields {
-    search_pattern: SearchType,
-    replace_string: String,
-    path_pattern: Option<SearchType>,
-    // TODO: `root_dir` and `include_hidden` are duplicated across this and App
-    root_dir: PathBuf,
+    replacer: LineReplacer,
+    path_matcher: Option<PathMatcher>,
+    // If true, files whose paths match `path_matcher` are skipped rather than searched
+    path_pattern_negated: bool,
+    // TODO: `root_dirs`, `include_hidden` and `include_ignored` are duplicated across this and App
+    root_dirs: Vec<PathBuf>,
     include_hidden: bool,
+    include_ignored: bool,
+    // If set, only these files are searched
+    file_filter: Option<Arc<HashSet<PathBuf>>>,
+    lossy: bool,
+    follow_symlinks: bool,
+    pruned_dirs: Arc<HashSet<OsString>>,
+    // If true, `.git` directories are searched even if they're in `pruned_dirs`
+    search_git: bool,
+    // Only set when `include_ignored` is true, to flag results in gitignored files
+    gitignore_matcher: Option<GitignoreMatcher>,
+    // If set, limits the number of files that are open at once while searching
+    open_file_limiter: Option<Arc<OpenFileLimiter>>,
+    // If set, files last modified before `modified_after` or after `modified_before` are skipped
+    modified_after: Option<SystemTime>,
+    modified_before: Option<SystemTime>,
+    // If set, only lines that also match this are replaced
+    line_filter: Option<Regex>,
 
     background_processing_sender: UnboundedSender<BackgroundProcessingEvent>,
 }
@@ -37,63 +212,279 @@ pub struct ParsedFields {
 impl ParsedFields {
     pub fn new(
         search_pattern: SearchType,
-        replace_string: String,
-        path_pattern: Option<SearchType>,
-        root_dir: PathBuf,
+        replace_string: Option<String>,
+        path_matcher: Option<PathMatcher>,
+        root_dirs: Vec<PathBuf>,
         include_hidden: bool,
+        include_ignored: bool,
         background_processing_sender: UnboundedSender<BackgroundProcessingEvent>,
     ) -> Self {
         Self {
-            search_pattern,
-            replace_string,
-            path_pattern,
-            root_dir,
+            replacer: LineReplacer::new(search_pattern, replace_string),
+            path_matcher,
+            path_pattern_negated: false,
+            root_dirs,
             include_hidden,
+            include_ignored,
+            file_filter: None,
+            lossy: false,
+            follow_symlinks: false,
+            pruned_dirs: Arc::new(DEFAULT_PRUNED_DIRS.iter().map(OsString::from).collect()),
+            search_git: false,
+            gitignore_matcher: include_ignored.then(GitignoreMatcher::new),
+            open_file_limiter: None,
+            modified_after: None,
+            modified_before: None,
+            line_filter: None,
             background_processing_sender,
         }
     }
 
+    /// Only replace in lines that also match `line_filter`, e.g. so that `foo` is only replaced on
+    /// lines containing `// keep`
+    pub fn with_line_filter(mut self, line_filter: Option<Regex>) -> Self {
+        self.line_filter = line_filter;
+        self
+    }
+
+    /// Search files whose paths don't match the path pattern, rather than those that do
+    pub fn with_path_pattern_negated(mut self, path_pattern_negated: bool) -> Self {
+        self.path_pattern_negated = path_pattern_negated;
+        self
+    }
+
+    /// Look up the replacement for each match in `replacement_map`
+    pub fn with_replacement_map(mut self, replacement_map: Option<Arc<ReplacementMap>>) -> Self {
+        self.replacer = self.replacer.with_replacement_map(replacement_map);
+        self
+    }
+
+    /// Replace repeatedly in each line until it stops changing
+    pub fn with_repeat(mut self, repeat: bool) -> Self {
+        self.replacer = self.replacer.with_repeat(repeat);
+        self
+    }
+
+    /// Collapse runs of whitespace in the lines that are replaced into a single space
+    pub fn with_collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.replacer = self.replacer.with_collapse_whitespace(collapse_whitespace);
+        self
+    }
+
+    pub fn with_lossy(mut self, lossy: bool) -> Self {
+        self.lossy = lossy;
+        self
+    }
+
+    /// Search symlinked files and directories, rather than skipping them
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Skip any files or directories with these names
+    pub fn with_pruned_dirs(mut self, pruned_dirs: &[String]) -> Self {
+        self.pruned_dirs = Arc::new(pruned_dirs.iter().map(OsString::from).collect());
+        self
+    }
+
+    /// Search inside `.git` directories, which are otherwise pruned. Hidden files must also be
+    /// included for these to be reached, and binary files such as git objects are still skipped.
+    pub fn with_search_git(mut self, search_git: bool) -> Self {
+        self.search_git = search_git;
+        self
+    }
+
+    /// Keep at most this many files open at once while searching
+    pub fn with_max_open_files(mut self, max_open_files: Option<usize>) -> Self {
+        self.open_file_limiter = max_open_files.map(|max| Arc::new(OpenFileLimiter::new(max)));
+        self
+    }
+
+    /// Only search files modified within this long before now
+    pub fn with_changed_within(mut self, changed_within: Option<Duration>) -> Self {
+        self.modified_after = changed_within.and_then(|d| SystemTime::now().checked_sub(d));
+        self
+    }
+
+    /// Only search files last modified at least this long before now
+    pub fn with_changed_before(mut self, changed_before: Option<Duration>) -> Self {
+        self.modified_before = changed_before.and_then(|d| SystemTime::now().checked_sub(d));
+        self
+    }
+
+    pub fn with_file_filter(mut self, files: HashSet<PathBuf>) -> Self {
+        self.file_filter = Some(Arc::new(files));
+        self
+    }
+
+    /// Searches all files under the root directories, sending each result to the background
+    /// processing channel as it is found
+    pub fn search(&self) {
+        self.search_with(&self.background_processing_sender);
+    }
+
+    /// Like `search`, but stops once a result beyond the first `limit` is found, without sending
+    /// it. Returns whether any results were left out in this way.
+    pub fn search_up_to(&self, limit: usize) -> bool {
+        let observer = LimitedObserver {
+            observer: &self.background_processing_sender,
+            limit,
+            num_found: AtomicUsize::new(0),
+        };
+        self.search_with(&observer);
+        observer.num_found.into_inner() > limit
+    }
+
+    /// Searches all files under the root directories on a separate thread, returning a receiver
+    /// that yields each result as it is found. The receiver is closed once the search completes.
+    pub fn search_stream(&self) -> UnboundedReceiver<SearchResult> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let parsed_fields = self.clone();
+        thread::spawn(move || {
+            parsed_fields.search_with(&sender);
+        });
+        receiver
+    }
+
+    /// Walks the root directories in parallel, notifying `observer` of each result and searched
+    /// file. The walk stops early once `observer.should_stop()` returns `true`.
+    fn search_with(&self, observer: &impl SearchObserver) {
+        self.build_walker().run(|| {
+            Box::new(move |entry| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(_) => return WalkState::Continue,
+                };
+
+                if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+                    return WalkState::Continue;
+                };
+
+                if is_binary_extension(entry.path()) {
+                    return WalkState::Continue;
+                }
+
+                if !self.should_search(entry.path()) {
+                    return WalkState::Continue;
+                }
+                if !self.search_file(entry.path(), observer) {
+                    return WalkState::Quit;
+                }
+                observer.on_file_scanned(entry.path());
+                WalkState::Continue
+            })
+        });
+    }
+
+    /// Searches all files under the root directories, notifying `observer` of each result and
+    /// searched file, and then of completion
+    pub fn search_observed(&self, observer: &impl SearchObserver) {
+        self.search_with(observer);
+        observer.on_complete();
+    }
+
     pub fn handle_path(&self, path: &Path) {
-        if let Some(ref p) = self.path_pattern {
-            let relative_path = relative_path_from(&self.root_dir, path);
-            let relative_path = relative_path.as_str();
-
-            let matches_pattern = match p {
-                SearchType::Pattern(ref p) => p.is_match(relative_path),
-                SearchType::PatternAdvanced(ref p) => p.is_match(relative_path).unwrap(),
-                SearchType::Fixed(ref s) => relative_path.contains(s),
-            };
-            if !matches_pattern {
-                return;
+        self.handle_path_observed(path, &self.background_processing_sender);
+    }
+
+    /// Searches the file at `path`, notifying `observer` of each result and then of the file
+    /// having been searched
+    pub fn handle_path_observed(&self, path: &Path, observer: &impl SearchObserver) {
+        if !self.should_search(path) {
+            return;
+        }
+        self.search_file(path, observer);
+        observer.on_file_scanned(path);
+    }
+
+    /// Whether `path` passes the file filter, path pattern and modification time window, if any
+    fn should_search(&self, path: &Path) -> bool {
+        if let Some(ref files) = self.file_filter {
+            if !files.contains(path) {
+                return false;
             }
         }
+        if let Some(matches_pattern) = self.matches_path_pattern(path) {
+            if matches_pattern == self.path_pattern_negated {
+                return false;
+            }
+        }
+        self.modified_in_window(path)
+    }
 
+    fn modified_in_window(&self, path: &Path) -> bool {
+        if self.modified_after.is_none() && self.modified_before.is_none() {
+            return true;
+        }
+        let modified = match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(err) => {
+                warn!("Couldn't read modification time of {:?}: {err}", path);
+                return false;
+            }
+        };
+        self.modified_after.is_none_or(|after| modified >= after)
+            && self.modified_before.is_none_or(|before| modified <= before)
+    }
+
+    /// Searches the file at `path`, without checking whether it should be searched, notifying
+    /// `observer` of each result. Returns `false` if `observer` asked for the search to stop, in
+    /// which case the rest of the file is skipped.
+    fn search_file(&self, path: &Path, observer: &impl SearchObserver) -> bool {
+        let _permit = self
+            .open_file_limiter
+            .as_ref()
+            .map(|limiter| limiter.acquire());
+        let mut is_gitignored = None;
+        let mut num_binary_lines = 0;
         match File::open(path) {
             Ok(file) => {
+                let is_large = file
+                    .metadata()
+                    .is_ok_and(|metadata| metadata.len() > LARGE_FILE_BYTES);
                 let reader = BufReader::new(file);
 
-                for (line_number, line) in reader.lines().enumerate() {
-                    match line {
-                        Ok(line) => {
-                            if let Some(result) = self.replacement_if_match(
-                                path.to_path_buf(),
-                                line.clone(),
-                                line_number,
-                            ) {
-                                if let ContentType::BINARY = inspect(line.as_bytes()) {
-                                    continue;
-                                }
-                                let send_result = self
-                                    .background_processing_sender
-                                    .send(BackgroundProcessingEvent::AddSearchResult(result));
-                                if send_result.is_err() {
-                                    // likely state reset, thread about to be killed
-                                    return;
-                                }
-                            }
+                for (line_number, line) in reader.split(b'\n').enumerate() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(err) => {
+                            warn!("Error retrieving line {} of {:?}: {err}", line_number, path);
+                            continue;
+                        }
+                    };
+                    let mut line = match String::from_utf8(line) {
+                        Ok(line) => line,
+                        Err(err) if self.lossy => {
+                            String::from_utf8_lossy(err.as_bytes()).into_owned()
                         }
                         Err(err) => {
                             warn!("Error retrieving line {} of {:?}: {err}", line_number, path);
+                            continue;
+                        }
+                    };
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+
+                    if let Some(mut result) =
+                        self.replacement_if_match(path.to_path_buf(), line.clone(), line_number)
+                    {
+                        if let ContentType::BINARY = inspect(line.as_bytes()) {
+                            num_binary_lines += 1;
+                            continue;
+                        }
+                        if let Some(matcher) = &self.gitignore_matcher {
+                            if *is_gitignored.get_or_insert_with(|| matcher.is_ignored(path)) {
+                                result.warnings.push(ReplaceWarning::Gitignored);
+                            }
+                        }
+                        if is_large {
+                            result.warnings.push(ReplaceWarning::LargeFile);
+                        }
+                        observer.on_result(&result);
+                        if observer.should_stop() {
+                            return false;
                         }
                     }
                 }
@@ -102,52 +493,96 @@ impl ParsedFields {
                 warn!("Error opening file {:?}: {err}", path);
             }
         }
+        if num_binary_lines > 0 {
+            observer.on_binary_lines_skipped(path, num_binary_lines);
+        }
+        true
     }
 
-    fn replacement_if_match(
+    /// Whether the path of `path` relative to the root directories matches the path pattern, or
+    /// `None` if there is no path pattern
+    fn matches_path_pattern(&self, path: &Path) -> Option<bool> {
+        let path_matcher = self.path_matcher.as_ref()?;
+        Some(path_matcher.is_match(&relative_path_from_roots(&self.root_dirs, path)))
+    }
+
+    pub(crate) fn replacement_if_match(
         &self,
         path: PathBuf,
         line: String,
         line_number: usize,
     ) -> Option<SearchResult> {
-        let maybe_replacement = match self.search_pattern {
-            SearchType::Fixed(ref s) => {
-                if line.contains(s) {
-                    Some(line.replace(s, &self.replace_string))
-                } else {
-                    None
-                }
-            }
-            SearchType::Pattern(ref p) => {
-                if p.is_match(&line) {
-                    Some(p.replace_all(&line, &self.replace_string).to_string())
-                } else {
-                    None
-                }
-            }
-            SearchType::PatternAdvanced(ref p) => {
-                // TODO: try catch
-                match p.is_match(&line) {
-                    Ok(true) => Some(p.replace_all(&line, &self.replace_string).to_string()),
-                    _ => None,
-                }
-            }
-        };
-
-        maybe_replacement.map(|replacement| SearchResult {
+        if self
+            .line_filter
+            .as_ref()
+            .is_some_and(|line_filter| !line_filter.is_match(&line))
+        {
+            return None;
+        }
+        let LineReplacement {
+            replacement,
+            num_matches,
+            warnings,
+            match_spans,
+            replacement_spans,
+        } = self.replacer.replace_line(&line)?;
+        Some(SearchResult {
             path,
             line_number: line_number + 1,
-            line: line.clone(),
+            line,
             replacement,
+            num_matches,
             included: true,
             replace_result: None,
+            warnings,
+            match_spans,
+            replacement_spans,
         })
     }
 
-    pub(crate) fn build_walker(&self) -> WalkParallel {
-        WalkBuilder::new(&self.root_dir)
-            .hidden(!self.include_hidden)
-            .filter_entry(|entry| entry.file_name() != ".git")
+    fn build_walker(&self) -> WalkParallel {
+        // Walking a directory and also one inside it, e.g. `.` and `./src`, would search the files
+        // in the inner directory twice
+        let root_dirs = outermost_dirs(&self.root_dirs);
+        let (first_root, other_roots) = root_dirs
+            .split_first()
+            .expect("At least one root directory is required");
+        let mut builder = WalkBuilder::new(first_root);
+        for root_dir in other_roots {
+            builder.add(root_dir);
+        }
+        builder
+            // `.git` is itself hidden, so when searching it hidden files are filtered out below
+            // instead
+            .hidden(!self.include_hidden && !self.search_git)
+            .git_ignore(!self.include_ignored)
+            .git_global(!self.include_ignored)
+            .git_exclude(!self.include_ignored)
+            .ignore(!self.include_ignored)
+            .parents(!self.include_ignored)
+            .follow_links(self.follow_symlinks)
+            .filter_entry({
+                let pruned_dirs = Arc::clone(&self.pruned_dirs);
+                let search_git = self.search_git;
+                let include_hidden = self.include_hidden;
+                move |entry| {
+                    if search_git && entry.file_name() == ".git" {
+                        return true;
+                    }
+                    let is_hidden =
+                        entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.');
+                    // Files that happen to share a name with a pruned directory are still searched
+                    let is_pruned = entry.file_type().is_some_and(|t| t.is_dir())
+                        && pruned_dirs.contains(entry.file_name());
+                    (include_hidden || !is_hidden) && !is_pruned
+                }
+            })
             .build_parallel()
     }
 }
+
+fn is_binary_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| BINARY_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
@@ -0,0 +1,1045 @@
+use aho_corasick::{AhoCorasick, Input, MatchKind};
+use fancy_regex::Regex as FancyRegex;
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    env, mem,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Token that can be used in the replacement text, which is substituted with an incrementing
+/// number when the replacement is performed. Note that this shadows any capture group named `n`.
+pub const COUNTER_TOKEN: &str = "${n}";
+
+// Stand-in for `COUNTER_TOKEN` between searching and replacing. This is a private-use character,
+// so shouldn't appear in real text, and isn't interpreted by the regex replacement syntax.
+const COUNTER_PLACEHOLDER: char = '\u{E000}';
+
+#[derive(Clone, Debug)]
+pub enum SearchType {
+    Pattern(Regex),
+    PatternAdvanced(FancyRegex),
+    Fixed(String),
+    /// Any of several fixed strings, found in a single pass over each line
+    MultiFixed(AhoCorasick),
+    /// Fixed strings in which each run of whitespace matches any run of whitespace, so that
+    /// `foo bar` matches `foo   bar`. Replacements are used literally, as with `Fixed`.
+    FixedAnyWhitespace(Regex),
+}
+
+impl SearchType {
+    /// Searches for any of `strings`, preferring the longest where several match at the same
+    /// position. Empty strings are skipped. Fails if there are too many strings to search for.
+    pub fn multi_fixed<S: AsRef<str>>(
+        strings: impl IntoIterator<Item = S>,
+    ) -> Result<Self, aho_corasick::BuildError> {
+        let strings = strings
+            .into_iter()
+            .filter(|s| !s.as_ref().is_empty())
+            .map(|s| s.as_ref().to_owned())
+            .collect::<Vec<_>>();
+        let automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(strings)?;
+        Ok(SearchType::MultiFixed(automaton))
+    }
+
+    /// Searches for any of `strings`, treating each run of whitespace in them as matching any run
+    /// of whitespace. Empty strings are skipped. Fails if the strings are too long to search for.
+    pub fn fixed_any_whitespace<S: AsRef<str>>(
+        strings: impl IntoIterator<Item = S>,
+    ) -> Result<Self, regex::Error> {
+        let pattern = strings
+            .into_iter()
+            .filter(|s| !s.as_ref().is_empty())
+            .map(|s| any_whitespace_pattern(s.as_ref()))
+            .collect::<Vec<_>>()
+            .join("|");
+        Ok(SearchType::FixedAnyWhitespace(Regex::new(&pattern)?))
+    }
+}
+
+/// A regex matching `text` literally, other than each run of whitespace, which matches any run
+fn any_whitespace_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !in_whitespace {
+                pattern.push_str(r"\s+");
+            }
+        } else {
+            pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+        }
+        in_whitespace = c.is_whitespace();
+    }
+    pattern
+}
+
+/// Collapses each run of whitespace between other characters into a single space, leaving
+/// indentation and trailing whitespace as they are
+pub fn collapse_whitespace(line: &str) -> String {
+    let content = line.trim();
+    let start = line.len() - line.trim_start().len();
+    let end = start + content.len();
+    let mut collapsed = String::with_capacity(line.len());
+    collapsed.push_str(&line[..start]);
+    for (idx, word) in content.split_whitespace().enumerate() {
+        if idx > 0 {
+            collapsed.push(' ');
+        }
+        collapsed.push_str(word);
+    }
+    collapsed.push_str(&line[end..]);
+    collapsed
+}
+
+/// Possible surprises in a replacement, found before anything is written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "walker", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReplaceWarning {
+    /// The search pattern also matches text overlapping one of the matches, which isn't replaced
+    /// as matches are replaced from left to right
+    OverlappingMatches,
+    /// The search pattern matches the replaced line, so searching again would find it again
+    ReplacementMatches,
+    /// The file is matched by a `.gitignore`, and so is likely generated or vendored
+    Gitignored,
+    /// The file is larger than `LARGE_FILE_BYTES`, and will be rewritten in full
+    LargeFile,
+}
+
+/// Files larger than this are flagged with `ReplaceWarning::LargeFile`
+pub const LARGE_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+impl ReplaceWarning {
+    pub fn description(&self) -> &str {
+        match self {
+            ReplaceWarning::OverlappingMatches => "overlapping matches",
+            ReplaceWarning::ReplacementMatches => "replacement matches search",
+            ReplaceWarning::Gitignored => "file is gitignored",
+            ReplaceWarning::LargeFile => "file is larger than 10 MiB",
+        }
+    }
+}
+
+/// Prepares the replacement text so that counter tokens survive the regex replacement, to be
+/// substituted later by `Counter::apply`
+pub fn insert_counter_placeholders(replace_string: &str) -> String {
+    replace_string.replace(COUNTER_TOKEN, &COUNTER_PLACEHOLDER.to_string())
+}
+
+/// Inverse of `insert_counter_placeholders`, for displaying a replacement before it has been
+/// performed
+pub fn display_counter_placeholders(replacement: &str) -> String {
+    replacement.replace(COUNTER_PLACEHOLDER, COUNTER_TOKEN)
+}
+
+/// Moves a byte range of `replacement` to cover the same text once counter placeholders have
+/// been displayed with `display_counter_placeholders`
+pub fn display_counter_placeholder_span(replacement: &str, span: &Range<usize>) -> Range<usize> {
+    let shift = |idx: usize| {
+        let num_placeholders = replacement
+            .get(..idx)
+            .map_or(0, |before| before.matches(COUNTER_PLACEHOLDER).count());
+        idx + num_placeholders * (COUNTER_TOKEN.len() - COUNTER_PLACEHOLDER.len_utf8())
+    };
+    shift(span.start)..shift(span.end)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseConversion {
+    Unchanged,
+    Upper,
+    Lower,
+}
+
+impl CaseConversion {
+    pub fn apply(self, s: &str) -> String {
+        match self {
+            CaseConversion::Unchanged => s.to_owned(),
+            CaseConversion::Upper => s.to_uppercase(),
+            CaseConversion::Lower => s.to_lowercase(),
+        }
+    }
+}
+
+/// Splits a regex replacement template at each `\U` (uppercase what follows), `\L` (lowercase
+/// what follows) and `\E` (end case conversion), so that each part can be expanded separately
+/// and then converted. Returns `None` if the template contains none of these.
+pub fn parse_case_conversions(template: &str) -> Option<Vec<(CaseConversion, String)>> {
+    let mut parts = vec![];
+    let mut case = CaseConversion::Unchanged;
+    let mut current = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next_case = match (c, chars.peek()) {
+            ('\\', Some('U')) => Some(CaseConversion::Upper),
+            ('\\', Some('L')) => Some(CaseConversion::Lower),
+            ('\\', Some('E')) => Some(CaseConversion::Unchanged),
+            _ => None,
+        };
+        match next_case {
+            Some(next_case) => {
+                chars.next();
+                if !current.is_empty() {
+                    parts.push((case, mem::take(&mut current)));
+                }
+                case = next_case;
+            }
+            None => current.push(c),
+        }
+    }
+
+    if parts.is_empty() && case == CaseConversion::Unchanged {
+        // No conversions were found, although an empty `\E` may have been removed
+        return (current != template).then(|| vec![(case, current)]);
+    }
+    if !current.is_empty() {
+        parts.push((case, current));
+    }
+    Some(parts)
+}
+
+/// Replacement text for each of several fixed strings, for replacing them all in one pass, e.g.
+/// to rename many symbols at once
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplacementMap {
+    replacements: HashMap<String, String>,
+}
+
+impl ReplacementMap {
+    pub fn new(replacements: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            replacements: replacements
+                .into_iter()
+                .filter(|(from, _)| !from.is_empty())
+                .collect(),
+        }
+    }
+
+    /// The strings to replace, one per line, which when searching with fixed strings matches any
+    /// of them
+    pub fn search_text(&self) -> String {
+        let mut keys = self
+            .replacements
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.join("\n")
+    }
+
+    /// Replacement for `matched`, or `matched` itself if it isn't in the map
+    pub fn replacement<'a>(&'a self, matched: &'a str) -> &'a str {
+        self.replacements
+            .get(matched)
+            .map_or(matched, String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replacements.is_empty()
+    }
+}
+
+/// Maximum number of times a line is replaced when replacing repeatedly
+pub const MAX_REPEAT_ITERATIONS: usize = 10;
+
+/// The replacement for a single line that the search pattern matches
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineReplacement {
+    pub replacement: String,
+    /// Number of times the search pattern matches in the line
+    pub num_matches: usize,
+    pub warnings: Vec<ReplaceWarning>,
+    /// Byte ranges of the matches in the line
+    pub match_spans: Vec<Range<usize>>,
+    /// Byte ranges of `replacement` that the matches were replaced with, in the same order as
+    /// `match_spans`. This is empty if the line was replaced repeatedly.
+    pub replacement_spans: Vec<Range<usize>>,
+}
+
+/// Matches and replaces within individual lines of text. This never touches the filesystem, so
+/// is also available without the `walker` feature, e.g. to replace in text held in memory.
+#[derive(Clone, Debug)]
+pub struct LineReplacer {
+    search_pattern: SearchType,
+    // `None` when the run doesn't write any files, e.g. when only counting matches
+    replace_string: Option<String>,
+    // `replace_string` split into parts by case conversion, if it contains any `\U`, `\L` or `\E`
+    replace_case_conversions: Option<Vec<(CaseConversion, String)>>,
+    // If true, the replacement is applied again until the line no longer changes
+    repeat: bool,
+    // If set, used in place of `replace_string` to look up the replacement for each match
+    replacement_map: Option<Arc<ReplacementMap>>,
+    // If true, runs of whitespace in replaced lines are collapsed into a single space
+    collapse_whitespace: bool,
+}
+
+fn expand_with_case_conversions(
+    parts: &[(CaseConversion, String)],
+    expand: impl Fn(&str, &mut String),
+) -> String {
+    parts
+        .iter()
+        .map(|(case, part)| {
+            let mut expanded = String::new();
+            expand(part, &mut expanded);
+            case.apply(&expanded)
+        })
+        .collect()
+}
+
+impl LineReplacer {
+    pub fn new(search_pattern: SearchType, replace_string: Option<String>) -> Self {
+        let replace_string = replace_string.as_deref().map(insert_counter_placeholders);
+        let replace_case_conversions = match search_pattern {
+            // Fixed replacement strings are used literally
+            SearchType::Fixed(_)
+            | SearchType::MultiFixed(_)
+            | SearchType::FixedAnyWhitespace(_) => None,
+            SearchType::Pattern(_) | SearchType::PatternAdvanced(_) => {
+                replace_string.as_deref().and_then(parse_case_conversions)
+            }
+        };
+        Self {
+            search_pattern,
+            replace_string,
+            replace_case_conversions,
+            repeat: false,
+            replacement_map: None,
+            collapse_whitespace: false,
+        }
+    }
+
+    /// Collapse each run of whitespace in the lines that are replaced into a single space, e.g.
+    /// when searching with `SearchType::FixedAnyWhitespace`, so that the whitespace is normalised
+    /// rather than kept as it was. Indentation and trailing whitespace are kept.
+    pub fn with_collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.collapse_whitespace = collapse_whitespace;
+        self
+    }
+
+    /// Replace each match with its entry in `replacement_map`, rather than with the replacement
+    /// string. Matches that aren't in the map are left as they are.
+    pub fn with_replacement_map(mut self, replacement_map: Option<Arc<ReplacementMap>>) -> Self {
+        self.replacement_map = replacement_map;
+        self
+    }
+
+    /// Replace repeatedly until the line stops changing, so that e.g. replacing `aa` with `a` in
+    /// `aaaa` gives `a` rather than `aa`. This stops after `MAX_REPEAT_ITERATIONS` replacements,
+    /// in case the line never stabilises, such as when replacing `a` with `aa`.
+    pub fn with_repeat(mut self, repeat: bool) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    /// Whether the search pattern matches `line`. Replacing a line should generally give a line
+    /// for which this is false, otherwise searching again would find the line again.
+    pub fn would_match(&self, line: &str) -> bool {
+        match self.search_pattern {
+            SearchType::Fixed(ref s) => line.contains(s),
+            SearchType::MultiFixed(ref ac) => ac.is_match(line),
+            SearchType::Pattern(ref p) | SearchType::FixedAnyWhitespace(ref p) => p.is_match(line),
+            // TODO: try catch
+            SearchType::PatternAdvanced(ref p) => p.is_match(line).unwrap_or(false),
+        }
+    }
+
+    /// Replaces every match in `line`, which shouldn't include its line ending, or returns `None`
+    /// if there are no matches. Any counter tokens are left as placeholders, to be substituted
+    /// by `Counter::apply`.
+    pub fn replace_line(&self, line: &str) -> Option<LineReplacement> {
+        if !self.would_match(line) {
+            return None;
+        }
+
+        let match_spans = self.match_spans(line);
+        let (mut replacement, mut replacement_spans) = self.replace_all(line);
+        if self.repeat {
+            for _ in 1..MAX_REPEAT_ITERATIONS {
+                if !self.would_match(&replacement) {
+                    break;
+                }
+                let (next, _) = self.replace_all(&replacement);
+                if next == replacement {
+                    break;
+                }
+                replacement = next;
+                // Later passes can replace text on either side of earlier replacements, so the
+                // spans no longer line up with the original matches
+                replacement_spans.clear();
+            }
+        }
+        if self.collapse_whitespace && self.replace_string.is_some() {
+            let collapsed = collapse_whitespace(&replacement);
+            if collapsed != replacement {
+                replacement = collapsed;
+                // Whitespace may have been collapsed inside or around the replaced text
+                replacement_spans.clear();
+            }
+        }
+
+        let mut warnings = vec![];
+        if self.has_overlapping_match(line, &match_spans) {
+            warnings.push(ReplaceWarning::OverlappingMatches);
+        }
+        if self.replace_string.is_some() && replacement != line && self.would_match(&replacement) {
+            warnings.push(ReplaceWarning::ReplacementMatches);
+        }
+
+        Some(LineReplacement {
+            replacement,
+            num_matches: match_spans.len(),
+            warnings,
+            match_spans,
+            replacement_spans,
+        })
+    }
+
+    /// Replaces every match in `line` once, from left to right, returning the replaced line along
+    /// with the byte range that each match was replaced with
+    fn replace_all(&self, line: &str) -> (String, Vec<Range<usize>>) {
+        let Some(ref replace_string) = self.replace_string else {
+            return (line.to_owned(), self.match_spans(line));
+        };
+
+        let mut replaced = String::with_capacity(line.len());
+        let mut replacement_spans = vec![];
+        let mut last_end = 0;
+        let mut push_replacement = |span: Range<usize>, replacement: &str| {
+            replaced.push_str(&line[last_end..span.start]);
+            let start = replaced.len();
+            replaced.push_str(replacement);
+            replacement_spans.push(start..replaced.len());
+            last_end = span.end;
+        };
+        // Expands the replacement for a single match, with `expand` substituting capture groups
+        let expand_match = |matched: &str, expand: &dyn Fn(&str, &mut String)| {
+            if let Some(ref map) = self.replacement_map {
+                return map.replacement(matched).to_owned();
+            }
+            match self.replace_case_conversions {
+                Some(ref parts) => expand_with_case_conversions(parts, expand),
+                None => {
+                    let mut expanded = String::new();
+                    expand(replace_string, &mut expanded);
+                    expanded
+                }
+            }
+        };
+        // Fixed replacement strings are used literally
+        let literal = |part: &str, dst: &mut String| dst.push_str(part);
+
+        match self.search_pattern {
+            SearchType::Fixed(ref s) => {
+                for (start, matched) in line.match_indices(s.as_str()) {
+                    let replacement = expand_match(matched, &literal);
+                    push_replacement(start..start + matched.len(), &replacement);
+                }
+            }
+            SearchType::MultiFixed(ref ac) => {
+                for m in ac.find_iter(line) {
+                    let replacement = expand_match(&line[m.range()], &literal);
+                    push_replacement(m.range(), &replacement);
+                }
+            }
+            SearchType::FixedAnyWhitespace(ref p) => {
+                for m in p.find_iter(line) {
+                    let replacement = expand_match(m.as_str(), &literal);
+                    push_replacement(m.range(), &replacement);
+                }
+            }
+            SearchType::Pattern(ref p) => {
+                for caps in p.captures_iter(line) {
+                    let m = caps.get(0).unwrap(); // The whole match is always present
+                    let replacement = expand_match(m.as_str(), &|part, dst| caps.expand(part, dst));
+                    push_replacement(m.range(), &replacement);
+                }
+            }
+            SearchType::PatternAdvanced(ref p) => {
+                for caps in p.captures_iter(line).flatten() {
+                    let m = caps.get(0).unwrap(); // The whole match is always present
+                    let replacement = expand_match(m.as_str(), &|part, dst| caps.expand(part, dst));
+                    push_replacement(m.range(), &replacement);
+                }
+            }
+        }
+        replaced.push_str(&line[last_end..]);
+        (replaced, replacement_spans)
+    }
+
+    /// Replaces matches in each line of `text`, keeping line endings as they are and substituting
+    /// counter tokens with `counter`
+    pub fn replace_text(&self, text: &str, counter: &mut Counter) -> String {
+        // There are no files, so count as if every line were in the same one
+        let path = PathBuf::new();
+        let mut result = String::with_capacity(text.len());
+        for line_with_ending in text.split_inclusive('\n') {
+            let line = line_with_ending
+                .strip_suffix('\n')
+                .map_or(line_with_ending, |line| {
+                    line.strip_suffix('\r').unwrap_or(line)
+                });
+            match self.replace_line(line) {
+                Some(replaced) => {
+                    result.push_str(&counter.apply(&path, &replaced.replacement));
+                    result.push_str(&line_with_ending[line.len()..]);
+                }
+                None => result.push_str(line_with_ending),
+            }
+        }
+        result
+    }
+
+    /// Byte ranges of the matches that would be replaced, from left to right
+    fn match_spans(&self, line: &str) -> Vec<Range<usize>> {
+        match self.search_pattern {
+            SearchType::Fixed(ref s) => line
+                .match_indices(s.as_str())
+                .map(|(start, m)| start..start + m.len())
+                .collect(),
+            SearchType::MultiFixed(ref ac) => ac.find_iter(line).map(|m| m.range()).collect(),
+            SearchType::Pattern(ref p) | SearchType::FixedAnyWhitespace(ref p) => {
+                p.find_iter(line).map(|m| m.range()).collect()
+            }
+            SearchType::PatternAdvanced(ref p) => {
+                p.find_iter(line).flatten().map(|m| m.range()).collect()
+            }
+        }
+    }
+
+    /// Start and end of the first match beginning at or after `start`
+    fn find_from(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        match self.search_pattern {
+            SearchType::Fixed(ref s) => line[start..]
+                .find(s.as_str())
+                .map(|idx| start + idx..start + idx + s.len()),
+            SearchType::MultiFixed(ref ac) => {
+                ac.find(Input::new(line).range(start..)).map(|m| m.range())
+            }
+            SearchType::Pattern(ref p) | SearchType::FixedAnyWhitespace(ref p) => {
+                p.find_at(line, start).map(|m| m.range())
+            }
+            SearchType::PatternAdvanced(ref p) => p
+                .find_from_pos(line, start)
+                .ok()
+                .flatten()
+                .map(|m| m.range()),
+        }
+    }
+
+    /// Whether another match starts inside any of `match_spans` and ends after it, such as `bc`
+    /// when replacing `ab|bc` in `abc`. Matches that end within the span, e.g. `aa` when replacing
+    /// `a+` in `aaa`, are just shorter versions of the same match so aren't counted. The line is
+    /// searched once from left to right, alongside the spans.
+    fn has_overlapping_match(&self, line: &str, match_spans: &[Range<usize>]) -> bool {
+        let next_start = |idx: usize| idx + line[idx..].chars().next().map_or(1, char::len_utf8);
+        let mut spans = match_spans.iter().peekable();
+        let mut start = 0;
+        while let Some(span) = spans.peek() {
+            start = start.max(next_start(span.start));
+            if start >= span.end {
+                spans.next();
+                continue;
+            }
+            let Some(m) = self.find_from(line, start) else {
+                return false;
+            };
+            while spans.next_if(|span| span.end <= m.start).is_some() {}
+            if let Some(span) = spans.peek() {
+                if m.start > span.start {
+                    if m.end > span.end {
+                        return true;
+                    }
+                    start = next_start(m.start);
+                }
+            }
+        }
+        false
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "walker", derive(serde::Serialize))]
+pub struct CounterConfig {
+    pub start: i64,
+    pub step: i64,
+    /// Restart the count for each file, rather than counting across the whole run
+    pub per_file: bool,
+}
+
+impl Default for CounterConfig {
+    fn default() -> Self {
+        Self {
+            start: 0,
+            step: 1,
+            per_file: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Counter {
+    config: CounterConfig,
+    run_count: i64,
+    file_counts: HashMap<PathBuf, i64>,
+}
+
+impl Counter {
+    pub fn new(config: CounterConfig) -> Self {
+        Self {
+            run_count: config.start,
+            config,
+            file_counts: HashMap::new(),
+        }
+    }
+
+    /// Substitutes each counter placeholder in `replacement` with the next number in the count
+    pub fn apply(&mut self, path: &Path, replacement: &str) -> String {
+        if !replacement.contains(COUNTER_PLACEHOLDER) {
+            return replacement.to_owned();
+        }
+
+        let count = if self.config.per_file {
+            self.file_counts
+                .entry(path.to_path_buf())
+                .or_insert(self.config.start)
+        } else {
+            &mut self.run_count
+        };
+
+        let mut result = String::with_capacity(replacement.len());
+        for c in replacement.chars() {
+            if c == COUNTER_PLACEHOLDER {
+                result.push_str(&count.to_string());
+                *count += self.config.step;
+            } else {
+                result.push(c);
+            }
+        }
+        result
+    }
+}
+
+/// Expands references to environment variables in the replacement text, written as `$NAME` or
+/// `${NAME}`. References to variables that aren't set, or that share a name with a capture group
+/// in the search pattern, are left as they are, as is the counter token.
+pub fn expand_env_vars(replace_string: &str, search_pattern: &SearchType) -> String {
+    expand_env_vars_with(replace_string, search_pattern, |name| env::var(name).ok())
+}
+
+fn expand_env_vars_with(
+    replace_string: &str,
+    search_pattern: &SearchType,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> String {
+    let capture_names = match search_pattern {
+        SearchType::Fixed(_) | SearchType::MultiFixed(_) | SearchType::FixedAnyWhitespace(_) => {
+            vec![]
+        }
+        SearchType::Pattern(p) => p.capture_names().flatten().map(str::to_owned).collect(),
+        SearchType::PatternAdvanced(p) => p.capture_names().flatten().map(str::to_owned).collect(),
+    };
+    let lookup = |name: &str| {
+        if name.is_empty() || capture_names.iter().any(|c| c == name) {
+            None
+        } else {
+            lookup(name)
+        }
+    };
+    // Values are inserted literally, so `$` must be escaped if the replacement is a regex template
+    let escape = |value: String| match search_pattern {
+        SearchType::Fixed(_) | SearchType::MultiFixed(_) | SearchType::FixedAnyWhitespace(_) => {
+            value
+        }
+        SearchType::Pattern(_) | SearchType::PatternAdvanced(_) => value.replace('$', "$$"),
+    };
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut result = String::with_capacity(replace_string.len());
+    let mut rest = replace_string;
+    while let Some(idx) = rest.find('$') {
+        result.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        let (reference, name) = if rest.starts_with(COUNTER_TOKEN) {
+            (COUNTER_TOKEN, None)
+        } else if rest.starts_with("$$") {
+            ("$$", None)
+        } else if let Some(braced) = rest.strip_prefix("${") {
+            match braced.find('}') {
+                Some(end) => (&rest[..end + 3], Some(&braced[..end])),
+                None => (rest, None),
+            }
+        } else {
+            let end = rest[1..]
+                .find(|c| !is_name_char(c))
+                .map_or(rest.len(), |end| end + 1);
+            (&rest[..end], Some(&rest[1..end]))
+        };
+        match name.and_then(lookup) {
+            Some(value) => result.push_str(&escape(value)),
+            None => result.push_str(reference),
+        }
+        rest = &rest[reference.len()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_per_run() {
+        let mut counter = Counter::new(CounterConfig::default());
+        let replacement = insert_counter_placeholders("item_${n}");
+        let (path1, path2) = (PathBuf::from("a.txt"), PathBuf::from("b.txt"));
+        assert_eq!(counter.apply(&path1, &replacement), "item_0");
+        assert_eq!(counter.apply(&path1, &replacement), "item_1");
+        assert_eq!(counter.apply(&path2, &replacement), "item_2");
+    }
+
+    #[test]
+    fn test_counter_multiple_tokens_in_line() {
+        let mut counter = Counter::new(CounterConfig::default());
+        let replacement = insert_counter_placeholders("a_${n} b_${n}");
+        assert_eq!(
+            counter.apply(&PathBuf::from("a.txt"), &replacement),
+            "a_0 b_1"
+        );
+    }
+
+    #[test]
+    fn test_counter_per_file_with_start_and_step() {
+        let mut counter = Counter::new(CounterConfig {
+            start: 10,
+            step: 5,
+            per_file: true,
+        });
+        let replacement = insert_counter_placeholders("${n}");
+        let (path1, path2) = (PathBuf::from("a.txt"), PathBuf::from("b.txt"));
+        assert_eq!(counter.apply(&path1, &replacement), "10");
+        assert_eq!(counter.apply(&path2, &replacement), "10");
+        assert_eq!(counter.apply(&path1, &replacement), "15");
+        assert_eq!(counter.apply(&path2, &replacement), "15");
+    }
+
+    #[test]
+    fn test_counter_without_token() {
+        let mut counter = Counter::new(CounterConfig::default());
+        assert_eq!(counter.apply(&PathBuf::from("a.txt"), "foo"), "foo");
+        assert_eq!(
+            counter.apply(
+                &PathBuf::from("a.txt"),
+                &insert_counter_placeholders("${n}")
+            ),
+            "0"
+        );
+    }
+
+    #[test]
+    fn test_parse_case_conversions() {
+        assert_eq!(parse_case_conversions("foo_$1"), None);
+        assert_eq!(
+            parse_case_conversions(r"\U$1\E_$2"),
+            Some(vec![
+                (CaseConversion::Upper, "$1".to_owned()),
+                (CaseConversion::Unchanged, "_$2".to_owned()),
+            ])
+        );
+        assert_eq!(
+            parse_case_conversions(r"a\Lb\Uc"),
+            Some(vec![
+                (CaseConversion::Unchanged, "a".to_owned()),
+                (CaseConversion::Lower, "b".to_owned()),
+                (CaseConversion::Upper, "c".to_owned()),
+            ])
+        );
+        assert_eq!(
+            parse_case_conversions(r"foo\E"),
+            Some(vec![(CaseConversion::Unchanged, "foo".to_owned())])
+        );
+        // Other escapes are left as they are
+        assert_eq!(parse_case_conversions(r"C:\Windows\n"), None);
+    }
+
+    #[test]
+    fn test_case_conversion_apply() {
+        assert_eq!(CaseConversion::Upper.apply("fooBar"), "FOOBAR");
+        assert_eq!(CaseConversion::Lower.apply("fooBar"), "foobar");
+        assert_eq!(CaseConversion::Unchanged.apply("fooBar"), "fooBar");
+    }
+
+    #[test]
+    fn test_display_counter_placeholders() {
+        let replacement = insert_counter_placeholders("item_${n}");
+        assert_ne!(replacement, "item_${n}");
+        assert_eq!(display_counter_placeholders(&replacement), "item_${n}");
+    }
+
+    fn expand_env_vars_test(replace_string: &str, search_pattern: &SearchType) -> String {
+        expand_env_vars_with(replace_string, search_pattern, |name| match name {
+            "HOME" => Some("/home/user".to_owned()),
+            "USER" => Some("user".to_owned()),
+            "PRICE" => Some("$5".to_owned()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_expand_env_vars() {
+        let fixed = SearchType::Fixed("foo".to_owned());
+        assert_eq!(expand_env_vars_test("$HOME/bar", &fixed), "/home/user/bar");
+        assert_eq!(expand_env_vars_test("${USER}_x", &fixed), "user_x");
+        assert_eq!(expand_env_vars_test("$USER_x", &fixed), "$USER_x");
+        assert_eq!(
+            expand_env_vars_test("$MISSING ${MISSING}", &fixed),
+            "$MISSING ${MISSING}"
+        );
+        assert_eq!(
+            expand_env_vars_test("$$HOME ${n} $", &fixed),
+            "$$HOME ${n} $"
+        );
+        assert_eq!(expand_env_vars_test("${HOME", &fixed), "${HOME");
+        assert_eq!(expand_env_vars_test("$PRICE", &fixed), "$5");
+    }
+
+    #[test]
+    fn test_expand_env_vars_in_regex_replacement() {
+        let pattern = SearchType::Pattern(regex::Regex::new(r"(?<USER>\w+) (\d)").unwrap());
+        // Capture groups take precedence, and values are escaped
+        assert_eq!(
+            expand_env_vars_test("$1 ${USER} $HOME $PRICE", &pattern),
+            "$1 ${USER} /home/user $$5"
+        );
+    }
+
+    fn fixed_replacer(search: &str, replace: &str) -> LineReplacer {
+        LineReplacer::new(
+            SearchType::Fixed(search.to_owned()),
+            Some(replace.to_owned()),
+        )
+    }
+
+    #[test]
+    fn test_replace_line() {
+        let replacer = fixed_replacer("foo", "bar");
+        assert_eq!(replacer.replace_line("no match"), None);
+        assert_eq!(
+            replacer.replace_line("foo and foo"),
+            Some(LineReplacement {
+                replacement: "bar and bar".to_owned(),
+                num_matches: 2,
+                warnings: vec![],
+                match_spans: vec![0..3, 8..11],
+                replacement_spans: vec![0..3, 8..11],
+            })
+        );
+    }
+
+    #[test]
+    fn test_replace_line_with_warnings() {
+        let replacer = LineReplacer::new(
+            SearchType::Pattern(Regex::new("ab|bc").unwrap()),
+            Some("x".to_owned()),
+        );
+        let replaced = replacer.replace_line("abc").unwrap();
+        assert_eq!(replaced.replacement, "xc");
+        assert_eq!(replaced.warnings, vec![ReplaceWarning::OverlappingMatches]);
+
+        let replaced = fixed_replacer("a", "aa").replace_line("a").unwrap();
+        assert_eq!(replaced.warnings, vec![ReplaceWarning::ReplacementMatches]);
+    }
+
+    #[test]
+    fn test_replace_line_with_case_conversions() {
+        let replacer = LineReplacer::new(
+            SearchType::PatternAdvanced(FancyRegex::new(r"(\w+)_(\w+)").unwrap()),
+            Some(r"\U$1\E_$2".to_owned()),
+        );
+        assert_eq!(
+            replacer.replace_line("foo_bar").unwrap().replacement,
+            "FOO_bar"
+        );
+    }
+
+    #[test]
+    fn test_replace_line_spans() {
+        let replacer = LineReplacer::new(
+            SearchType::Pattern(Regex::new(r"(\w+)@example").unwrap()),
+            Some("<$1>".to_owned()),
+        );
+        let replaced = replacer
+            .replace_line("to alice@example, bo@example")
+            .unwrap();
+        assert_eq!(replaced.replacement, "to <alice>, <bo>");
+        assert_eq!(replaced.match_spans, vec![3..16, 18..28]);
+        assert_eq!(replaced.replacement_spans, vec![3..10, 12..16]);
+
+        // Spans can't be tracked through repeated replacements
+        let replaced = fixed_replacer("aa", "a")
+            .with_repeat(true)
+            .replace_line("aaaa")
+            .unwrap();
+        assert_eq!(replaced.match_spans, vec![0..2, 2..4]);
+        assert!(replaced.replacement_spans.is_empty());
+    }
+
+    #[test]
+    fn test_replace_line_with_any_whitespace() {
+        let replacer = LineReplacer::new(
+            SearchType::fixed_any_whitespace(["foo bar", "a.b\tc"]).unwrap(),
+            Some("$1 baz".to_owned()),
+        );
+        let replaced = replacer
+            .replace_line("  foo   bar, foo\tbar and a.b  c")
+            .unwrap();
+        // The replacement is used literally, and whitespace outside the matches is kept
+        assert_eq!(replaced.replacement, "  $1 baz, $1 baz and $1 baz");
+        assert_eq!(replaced.match_spans, vec![2..11, 13..20, 25..31]);
+        assert_eq!(replacer.replace_line("foobar"), None);
+        assert_eq!(replacer.replace_line("axb c"), None);
+
+        let replaced = LineReplacer::new(
+            SearchType::fixed_any_whitespace(["foo bar"]).unwrap(),
+            Some("baz".to_owned()),
+        )
+        .with_collapse_whitespace(true)
+        .replace_line("    let x =   foo  bar;  ")
+        .unwrap();
+        assert_eq!(replaced.replacement, "    let x = baz;  ");
+        assert!(replaced.replacement_spans.is_empty());
+    }
+
+    #[test]
+    fn test_collapse_whitespace() {
+        assert_eq!(collapse_whitespace("a  b\t\tc"), "a b c");
+        assert_eq!(collapse_whitespace("\t  a   b  "), "\t  a b  ");
+        assert_eq!(collapse_whitespace("   "), "   ");
+        assert_eq!(collapse_whitespace(""), "");
+    }
+
+    #[test]
+    fn test_replace_text() {
+        let replacer = LineReplacer::new(
+            SearchType::Pattern(Regex::new(r"item(\d)").unwrap()),
+            Some("entry_${n}".to_owned()),
+        );
+        let mut counter = Counter::new(CounterConfig::default());
+        assert_eq!(
+            replacer.replace_text("item1\r\nother\nitem2", &mut counter),
+            "entry_0\r\nother\nentry_1"
+        );
+    }
+
+    #[test]
+    fn test_replace_line_repeat_until_stable() {
+        assert_eq!(
+            fixed_replacer("aa", "a")
+                .replace_line("aaaa")
+                .unwrap()
+                .replacement,
+            "aa"
+        );
+
+        let replaced = fixed_replacer("aa", "a")
+            .with_repeat(true)
+            .replace_line("aaaa")
+            .unwrap();
+        assert_eq!(replaced.replacement, "a");
+        assert_eq!(replaced.num_matches, 2);
+        assert!(!replaced
+            .warnings
+            .contains(&ReplaceWarning::ReplacementMatches));
+    }
+
+    #[test]
+    fn test_replace_line_repeat_stops_when_growing() {
+        let replaced = fixed_replacer("a", "aa")
+            .with_repeat(true)
+            .replace_line("a")
+            .unwrap();
+        assert_eq!(
+            replaced.replacement.len(),
+            2usize.pow(MAX_REPEAT_ITERATIONS as u32)
+        );
+        assert_eq!(replaced.warnings, vec![ReplaceWarning::ReplacementMatches]);
+    }
+
+    #[test]
+    fn test_replace_line_with_replacement_map() {
+        let map = ReplacementMap::new([
+            ("foo".to_owned(), "bar".to_owned()),
+            ("foobar".to_owned(), "baz".to_owned()),
+            ("qux".to_owned(), "quux".to_owned()),
+        ]);
+        assert_eq!(map.search_text(), "foo\nfoobar\nqux");
+        let replacer = LineReplacer::new(
+            SearchType::multi_fixed(map.search_text().lines()).unwrap(),
+            Some(String::new()),
+        )
+        .with_replacement_map(Some(Arc::new(map)));
+        assert_eq!(
+            replacer
+                .replace_line("foo foobar qux.foo")
+                .unwrap()
+                .replacement,
+            "bar baz quux.bar"
+        );
+    }
+
+    #[test]
+    fn test_multi_fixed_matches_naive_replacement() {
+        for (search, line) in [
+            ("aa", "aaa"),
+            ("aa", "aaaa"),
+            ("foo", "foofoo"),
+            ("foo", "foo bar foo"),
+            ("ab", "abab ba"),
+        ] {
+            let naive = fixed_replacer(search, "X").replace_line(line);
+            let multi = LineReplacer::new(
+                SearchType::multi_fixed([search]).unwrap(),
+                Some("X".to_owned()),
+            )
+            .replace_line(line);
+            assert_eq!(multi, naive, "searching for {search:?} in {line:?}");
+        }
+    }
+
+    #[test]
+    fn test_multi_fixed_matches_regex_alternation() {
+        let strings = ["foo", "foobar", "bar", "o"];
+        let alternation = LineReplacer::new(
+            SearchType::Pattern(Regex::new("foobar|foo|bar|o").unwrap()),
+            Some("-".to_owned()),
+        );
+        let multi = LineReplacer::new(
+            SearchType::multi_fixed(strings).unwrap(),
+            Some("-".to_owned()),
+        );
+        for line in ["foobarfoo", "barfoo o", "fobar", "oooo", "none"] {
+            assert_eq!(
+                multi.replace_line(line),
+                alternation.replace_line(line),
+                "searching in {line:?}"
+            );
+        }
+
+        // Fixed replacements are inserted literally
+        let multi = LineReplacer::new(
+            SearchType::multi_fixed(strings).unwrap(),
+            Some("<$0>".to_owned()),
+        );
+        assert_eq!(
+            multi.replace_line("foo-bar").unwrap().replacement,
+            "<$0>-<$0>"
+        );
+    }
+}
@@ -1,12 +1,42 @@
-use crate::app::App;
-use crate::event::EventHandler;
-use crate::ui;
+use crossterm::cursor::Show;
 use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
 use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use log::error;
 use ratatui::backend::Backend;
 use ratatui::Terminal;
+use scooter::app::App;
+use scooter::event::EventHandler;
+use scooter::ui;
 use std::io;
-use std::panic;
+use std::panic::{self, PanicHookInfo};
+
+fn reset_terminal() -> anyhow::Result<()> {
+    terminal::disable_raw_mode()?;
+    crossterm::execute!(
+        io::stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        Show
+    )?;
+    Ok(())
+}
+
+type PanicHook = Box<dyn Fn(&PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// Wraps `panic_hook` so that `restore` is called before it, e.g. so that the terminal is usable
+/// again by the time the panic message is printed
+fn restore_before_panic_hook(
+    restore: impl Fn() -> anyhow::Result<()> + Sync + Send + 'static,
+    panic_hook: PanicHook,
+) -> PanicHook {
+    Box::new(move |panic| {
+        // Panicking again here would abort without printing the original panic
+        if let Err(e) = restore() {
+            error!("Failed to reset the terminal: {e}");
+        }
+        panic_hook(panic);
+    })
+}
 
 #[derive(Debug)]
 pub struct Tui<B: Backend> {
@@ -20,34 +50,102 @@ impl<B: Backend> Tui<B> {
     }
 
     pub fn init(&mut self) -> anyhow::Result<()> {
+        panic::set_hook(restore_before_panic_hook(
+            reset_terminal,
+            panic::take_hook(),
+        ));
+        self.enter()
+    }
+
+    fn enter(&mut self) -> anyhow::Result<()> {
         terminal::enable_raw_mode()?;
         crossterm::execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
-
-        let panic_hook = panic::take_hook();
-        panic::set_hook(Box::new(move |panic| {
-            Self::reset().expect("failed to reset the terminal");
-            panic_hook(panic);
-        }));
-
         self.terminal.hide_cursor()?;
         self.terminal.clear()?;
         Ok(())
     }
 
-    pub fn draw(&mut self, app: &mut App) -> anyhow::Result<()> {
-        self.terminal.draw(|frame| ui::render(app, frame))?;
+    /// Hands the terminal back, e.g. so that an editor can be run, until `resume` is called
+    pub async fn suspend(&mut self) -> anyhow::Result<()> {
+        self.events.pause().await;
+        self.exit()
+    }
+
+    pub fn resume(&mut self) -> anyhow::Result<()> {
+        self.enter()?;
+        self.events.resume();
         Ok(())
     }
 
-    fn reset() -> anyhow::Result<()> {
-        terminal::disable_raw_mode()?;
-        crossterm::execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
+    pub fn draw(&mut self, app: &mut App) -> anyhow::Result<()> {
+        self.terminal.draw(|frame| ui::render(app, frame))?;
         Ok(())
     }
 
     pub fn exit(&mut self) -> anyhow::Result<()> {
-        Self::reset()?;
+        reset_terminal()?;
         self.terminal.show_cursor()?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    };
+
+    // These replace the global panic hook, so mustn't run at the same time
+    #[test]
+    #[serial]
+    fn test_restore_before_panic_hook() {
+        let restored = Arc::new(AtomicBool::new(false));
+        let events = Arc::new(Mutex::new(vec![]));
+
+        let hook = restore_before_panic_hook(
+            {
+                let (restored, events) = (Arc::clone(&restored), Arc::clone(&events));
+                move || {
+                    restored.store(true, Ordering::SeqCst);
+                    events.lock().unwrap().push("restore");
+                    Ok(())
+                }
+            },
+            Box::new({
+                let events = Arc::clone(&events);
+                move |_| events.lock().unwrap().push("hook")
+            }),
+        );
+        let default_hook = panic::take_hook();
+        panic::set_hook(hook);
+        let result = panic::catch_unwind(|| panic!("simulated panic"));
+        panic::set_hook(default_hook);
+
+        assert!(result.is_err());
+        assert!(restored.load(Ordering::SeqCst));
+        assert_eq!(*events.lock().unwrap(), vec!["restore", "hook"]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_restore_before_panic_hook_when_restore_fails() {
+        let called = Arc::new(AtomicBool::new(false));
+        let hook = restore_before_panic_hook(
+            || Err(anyhow::anyhow!("no terminal")),
+            Box::new({
+                let called = Arc::clone(&called);
+                move |_| called.store(true, Ordering::SeqCst)
+            }),
+        );
+        let default_hook = panic::take_hook();
+        panic::set_hook(hook);
+        let result = panic::catch_unwind(|| panic!("simulated panic"));
+        panic::set_hook(default_hook);
+
+        assert!(result.is_err());
+        assert!(called.load(Ordering::SeqCst));
+    }
+}
@@ -1,22 +1,88 @@
 use itertools::Itertools;
 use ratatui::{
     layout::{Alignment, Constraint, Direction, Flex, Layout, Rect},
-    style::{Color, Style, Stylize},
+    style::{Color, Modifier, Style, Stylize},
     text::{Line, Span, Text},
-    widgets::{Block, Clear, List, ListItem, Paragraph},
+    widgets::{Block, Clear, List, ListItem, Paragraph, Wrap},
     Frame,
 };
 use similar::{Change, ChangeTag, TextDiff};
-use std::{cmp::min, iter};
+use std::{
+    cmp::{max, min},
+    fmt, iter,
+    ops::Range,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 use crate::{
     app::{
-        App, FieldName, ReplaceState, Screen, SearchField, SearchInProgressState, NUM_SEARCH_FIELDS,
+        App, FieldName, FileConfirmationState, LogViewerState, PerformingReplacementState,
+        ReplaceState, ReplacementSummary, Screen, SearchField, SearchInProgressState,
+        NUM_SEARCH_FIELDS,
     },
-    event::{ReplaceResult, SearchResult},
-    utils::{first_chars, group_by},
+    event::{ReplaceResult, ReplaceWarning, SearchResult},
+    fields::TextField,
+    replace::LARGE_FILE_BYTES,
+    replace::{display_counter_placeholder_span, display_counter_placeholders},
+    utils::{first_chars, group_by, wrap_line},
 };
 
+/// Width of the main content of each screen, which is centred horizontally
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentWidth {
+    /// Percentage of the terminal width, from 1 to 100
+    Percentage(u16),
+    /// Number of columns, or the full terminal width if smaller
+    Columns(u16),
+}
+
+impl Default for ContentWidth {
+    fn default() -> Self {
+        ContentWidth::Percentage(80)
+    }
+}
+
+impl FromStr for ContentWidth {
+    type Err = String;
+
+    /// Parses e.g. `80%` as a percentage, or `120` as a number of columns
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid width '{s}': expected e.g. '80%' or '120'");
+        match s.strip_suffix('%') {
+            Some(percentage) => match percentage.parse() {
+                Ok(percentage @ 1..=100) => Ok(ContentWidth::Percentage(percentage)),
+                _ => Err(invalid()),
+            },
+            None => match s.parse() {
+                Ok(columns @ 1..) => Ok(ContentWidth::Columns(columns)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ContentWidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentWidth::Percentage(percentage) => write!(f, "{percentage}%"),
+            ContentWidth::Columns(columns) => write!(f, "{columns}"),
+        }
+    }
+}
+
+/// The area within `rect` that the main content is rendered in
+pub fn content_area(rect: Rect, width: ContentWidth) -> Rect {
+    let constraint = match width {
+        ContentWidth::Percentage(percentage) => Constraint::Percentage(percentage),
+        ContentWidth::Columns(columns) => Constraint::Length(columns),
+    };
+    let [area] = Layout::horizontal([constraint])
+        .flex(Flex::Center)
+        .areas(rect);
+    area
+}
+
 impl FieldName {
     pub(crate) fn title(&self) -> &str {
         match self {
@@ -24,18 +90,19 @@ impl FieldName {
             FieldName::Replace => "Replace text",
             FieldName::FixedStrings => "Fixed strings",
             FieldName::PathPattern => "Path pattern (regex)",
+            FieldName::PathGlob => "Path pattern is glob",
         }
     }
 }
 
 fn render_search_view(frame: &mut Frame<'_>, app: &App, rect: Rect) {
-    let [area] = Layout::horizontal([Constraint::Percentage(80)])
-        .flex(Flex::Center)
-        .areas(rect);
-    let areas: [Rect; NUM_SEARCH_FIELDS] =
-        Layout::vertical(iter::repeat(Constraint::Length(4)).take(app.search_fields.fields.len()))
-            .flex(Flex::Center)
-            .areas(area);
+    let area = content_area(rect, app.content_width());
+    let areas: [Rect; NUM_SEARCH_FIELDS] = Layout::vertical(iter::repeat_n(
+        Constraint::Length(4),
+        app.search_fields.fields.len(),
+    ))
+    .flex(Flex::Center)
+    .areas(area);
 
     app.search_fields
         .fields
@@ -43,53 +110,65 @@ fn render_search_view(frame: &mut Frame<'_>, app: &App, rect: Rect) {
         .zip(areas)
         .enumerate()
         .for_each(|(idx, (SearchField { name, field }, field_area))| {
+            let title = match name {
+                FieldName::Search if app.is_read_only(name) => "Search text (from map file)",
+                FieldName::Replace if app.is_read_only(name) => "Replace text (from map file)",
+                FieldName::PathPattern if app.search_fields.path_fuzzy() => "Path pattern (fuzzy)",
+                FieldName::PathPattern if app.search_fields.path_glob().checked => {
+                    "Path pattern (glob)"
+                }
+                FieldName::PathPattern if app.search_fields.fixed_strings().checked => {
+                    "Path pattern (fixed)"
+                }
+                FieldName::PathPattern if app.search_fields.advanced_regex() => {
+                    "Path pattern (advanced regex)"
+                }
+                _ => name.title(),
+            };
             field.read().render(
                 frame,
                 field_area,
-                name.title().to_owned(),
+                title.to_owned(),
                 idx == app.search_fields.highlighted,
             )
         });
 
     if app.search_fields.show_error_popup {
+        let popup_area = center(
+            area,
+            Constraint::Percentage(80),
+            Constraint::Percentage(100),
+        );
+        let block = Block::bordered()
+            .title("Errors")
+            .title_alignment(Alignment::Center);
+        let inner_width = block.inner(popup_area).width as usize;
+
         let error_lines: Vec<Line<'_>> = app
             .search_fields
-            .errors()
-            .iter()
-            .flat_map(|(name, error)| {
-                let name_line = Line::from(vec![Span::styled(*name, Style::default().bold())]);
-
-                let error_lines: Vec<Line<'_>> = error
-                    .long
-                    .lines()
-                    .map(|line| {
-                        Line::from(vec![Span::styled(
-                            line.to_string(),
-                            Style::default().fg(Color::Red),
-                        )])
-                    })
-                    .collect();
-
-                std::iter::once(name_line)
-                    .chain(error_lines)
-                    .chain(std::iter::once(Line::from("")))
-                    .collect::<Vec<_>>()
+            .error_popup_lines()
+            .into_iter()
+            .skip(app.search_fields.error_popup_scroll)
+            .flat_map(|(line, is_title)| {
+                let style = if is_title {
+                    Style::default().bold()
+                } else {
+                    Style::default().fg(Color::Red)
+                };
+                wrap_line(&line, inner_width)
+                    .into_iter()
+                    .map(move |line| Line::from(Span::styled(line, style)))
             })
             .collect();
 
         let content_height = error_lines.len() as u16 + 1;
-
         let popup_area = center(
             area,
             Constraint::Percentage(80),
-            Constraint::Length(content_height),
+            Constraint::Length(min(content_height, area.height)),
         );
 
-        let popup = Paragraph::new(error_lines).block(
-            Block::bordered()
-                .title("Errors")
-                .title_alignment(Alignment::Center),
-        );
+        let popup = Paragraph::new(error_lines).block(block);
         frame.render_widget(Clear, popup_area);
         frame.render_widget(popup, popup_area);
     } else if let Some(cursor_idx) = app.search_fields.highlighted_field().read().cursor_idx() {
@@ -107,62 +186,83 @@ pub struct Diff {
     pub text: String,
     pub fg_colour: Color,
     pub bg_colour: Color,
+    /// Whether the text is part of a match, or of what a match was replaced with
+    pub emphasised: bool,
+}
+
+impl Diff {
+    pub fn new(text: impl Into<String>, fg_colour: Color, bg_colour: Color) -> Self {
+        Self {
+            text: text.into(),
+            fg_colour,
+            bg_colour,
+            emphasised: false,
+        }
+    }
 }
 
 fn diff_to_line(diff: Vec<Diff>) -> Line<'static> {
     let diff_iter = diff.into_iter().map(|d| {
-        let style = Style::new().fg(d.fg_colour).bg(d.bg_colour);
+        let mut style = Style::new().fg(d.fg_colour).bg(d.bg_colour);
+        if d.emphasised {
+            style = style.add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+        }
         Span::styled(d.text, style)
     });
     Line::from_iter(diff_iter)
 }
 
-pub fn line_diff<'a>(old_line: &'a str, new_line: &'a str) -> (Vec<Diff>, Vec<Diff>) {
+/// How long to spend diffing a line before falling back to a simplified diff
+const DIFF_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Character diff of the two lines, or `None` if diffing hit the timeout, in which case `similar`
+/// would otherwise return a coarse diff which can look wrong
+fn char_diff<'a>(
+    old_line: &'a str,
+    new_line: &'a str,
+    timeout: Duration,
+) -> Option<TextDiff<'a, 'a, 'a, str>> {
+    let start = Instant::now();
     let diff = TextDiff::configure()
         .algorithm(similar::Algorithm::Myers)
-        .timeout(std::time::Duration::from_millis(100))
+        .timeout(timeout)
         .diff_chars(old_line, new_line);
+    (start.elapsed() < timeout).then_some(diff)
+}
+
+/// Marks a diff which was simplified to replacing the whole line, because diffing timed out
+const SIMPLIFIED_MARKER: &str = "(simplified) ";
+
+pub fn line_diff<'a>(old_line: &'a str, new_line: &'a str) -> (Vec<Diff>, Vec<Diff>) {
+    line_diff_with_timeout(old_line, new_line, DIFF_TIMEOUT)
+}
+
+/// As `line_diff`, but falls back to replacing the whole line if diffing takes longer than `timeout`
+pub fn line_diff_with_timeout<'a>(
+    old_line: &'a str,
+    new_line: &'a str,
+    timeout: Duration,
+) -> (Vec<Diff>, Vec<Diff>) {
+    let Some(diff) = char_diff(old_line, new_line, timeout) else {
+        return simplified_line_diff(old_line, new_line);
+    };
 
-    let mut old_spans = vec![Diff {
-        text: "- ".to_owned(),
-        fg_colour: Color::Red,
-        bg_colour: Color::Reset,
-    }];
-    let mut new_spans = vec![Diff {
-        text: "+ ".to_owned(),
-        fg_colour: Color::Green,
-        bg_colour: Color::Reset,
-    }];
+    let mut old_spans = vec![Diff::new("- ", Color::Red, Color::Reset)];
+    let mut new_spans = vec![Diff::new("+ ", Color::Green, Color::Reset)];
 
     for change_group in group_by(diff.iter_all_changes(), |c1, c2| c1.tag() == c2.tag()) {
         let first_change = change_group.first().unwrap(); // group_by should never return an empty group
-        let text = change_group.iter().map(Change::value).collect();
+        let text: String = change_group.iter().map(Change::value).collect();
         match first_change.tag() {
             ChangeTag::Delete => {
-                old_spans.push(Diff {
-                    text,
-                    fg_colour: Color::Black,
-                    bg_colour: Color::Red,
-                });
+                old_spans.push(Diff::new(text, Color::Black, Color::Red));
             }
             ChangeTag::Insert => {
-                new_spans.push(Diff {
-                    text,
-                    fg_colour: Color::Black,
-                    bg_colour: Color::Green,
-                });
+                new_spans.push(Diff::new(text, Color::Black, Color::Green));
             }
             ChangeTag::Equal => {
-                old_spans.push(Diff {
-                    text: text.clone(),
-                    fg_colour: Color::Red,
-                    bg_colour: Color::Reset,
-                });
-                new_spans.push(Diff {
-                    text,
-                    fg_colour: Color::Green,
-                    bg_colour: Color::Reset,
-                });
+                old_spans.push(Diff::new(text.clone(), Color::Red, Color::Reset));
+                new_spans.push(Diff::new(text, Color::Green, Color::Reset));
             }
         };
     }
@@ -170,74 +270,416 @@ pub fn line_diff<'a>(old_line: &'a str, new_line: &'a str) -> (Vec<Diff>, Vec<Di
     (old_spans, new_spans)
 }
 
-fn render_confirmation_view(frame: &mut Frame<'_>, app: &App, rect: Rect) {
-    let [area] = Layout::horizontal([Constraint::Percentage(80)])
-        .flex(Flex::Center)
-        .areas(rect);
-    let [num_results_area, list_area] =
-        Layout::vertical([Constraint::Length(2), Constraint::Fill(1)])
-            .flex(Flex::Start)
-            .areas(area);
+/// As `line_diff`, but also emphasises `old_matches` in the old line and `new_matches` in the new
+/// line, which are byte ranges such as those of the matches and their replacements. This is
+/// layered on top of the diff colours, so that matched text stays distinct even when the diff
+/// treats part of it as unchanged.
+pub fn line_diff_with_matches<'a>(
+    old_line: &'a str,
+    new_line: &'a str,
+    old_matches: &[Range<usize>],
+    new_matches: &[Range<usize>],
+) -> (Vec<Diff>, Vec<Diff>) {
+    let (old_diff, new_diff) = line_diff(old_line, new_line);
+    (
+        emphasise_spans(old_diff, old_matches),
+        emphasise_spans(new_diff, new_matches),
+    )
+}
+
+/// Splits the diff wherever `spans` start or end, emphasising the text within them. Spans are byte
+/// ranges into the text following the leading marker, and any parts past the end are ignored.
+fn emphasise_spans(mut diff: Vec<Diff>, spans: &[Range<usize>]) -> Vec<Diff> {
+    if spans.is_empty() {
+        return diff;
+    }
+    let content = diff.split_off(min(1, diff.len()));
+    let mut spans = spans.to_vec();
+    spans.sort_by_key(|span| span.start);
+    let mut spans = spans.into_iter().peekable();
+
+    let mut pos = 0;
+    for d in content {
+        let end = pos + d.text.len();
+        let first_idx = diff.len();
+        let mut start = pos;
+        while start < end {
+            while spans.next_if(|span| span.end <= start).is_some() {}
+            let (mut split, emphasised) = match spans.peek() {
+                Some(span) if span.start <= start => (min(span.end, end), true),
+                Some(span) => (min(span.start, end), false),
+                None => (end, false),
+            };
+            while !d.text.is_char_boundary(split - pos) {
+                split += 1;
+            }
+            let text = &d.text[start - pos..split - pos];
+            let diff_len = diff.len();
+            match diff.last_mut().filter(|last| last.emphasised == emphasised) {
+                Some(last) if first_idx < diff_len => last.text.push_str(text),
+                _ => diff.push(Diff {
+                    emphasised,
+                    ..Diff::new(text, d.fg_colour, d.bg_colour)
+                }),
+            }
+            start = split;
+        }
+        pos = end;
+    }
+    diff
+}
+
+/// Diff of the result's line and replacement, with the matches and their replacements emphasised
+pub fn result_line_diff(result: &SearchResult) -> (Vec<Diff>, Vec<Diff>) {
+    // Bound the work done diffing very long lines, e.g. in minified files
+    let before = first_chars(&result.line, MAX_DIFF_CHARS);
+    let replacement = display_counter_placeholders(&result.replacement);
+    let after = first_chars(&replacement, MAX_DIFF_CHARS);
+    let replacement_spans = result
+        .replacement_spans
+        .iter()
+        .map(|span| display_counter_placeholder_span(&result.replacement, span))
+        .collect::<Vec<_>>();
+    line_diff_with_matches(before, after, &result.match_spans, &replacement_spans)
+}
+
+/// The diff for `result` as shown in the list of results, cropped to `width`: a single inline diff
+/// if `compact`, otherwise the old and new lines. Excluded results are dimmed and struck through,
+/// so that it's clear at a glance that they won't be replaced.
+pub fn result_diff_lines(result: &SearchResult, compact: bool, width: usize) -> Vec<Line<'static>> {
+    let diffs = if compact {
+        // Bound the work done diffing very long lines, e.g. in minified files
+        let before = first_chars(&result.line, MAX_DIFF_CHARS);
+        let replacement = display_counter_placeholders(&result.replacement);
+        let after = first_chars(&replacement, MAX_DIFF_CHARS);
+        vec![inline_diff(before, after)]
+    } else {
+        let (old_line, new_line) = result_line_diff(result);
+        vec![old_line, new_line]
+    };
+    diffs
+        .into_iter()
+        .map(|diff| {
+            let mut line = diff_to_line(crop_diff(diff, width));
+            if !result.included {
+                for span in &mut line.spans {
+                    span.style = span
+                        .style
+                        .add_modifier(Modifier::DIM | Modifier::CROSSED_OUT);
+                }
+            }
+            line
+        })
+        .collect()
+}
+
+/// Plain-text version of the diff for `result` without any colours, for pasting elsewhere, with
+/// the lines in `context` that come before and after it:
+///
+/// ```text
+/// ./src/main.rs:12
+///   fn main() {
+/// - let x = foo;
+/// + let x = bar;
+///   }
+/// ```
+pub fn plain_text_diff(result: &SearchResult, path: &str, context: &[(usize, String)]) -> String {
+    let (before, after): (Vec<_>, Vec<_>) = context
+        .iter()
+        .filter(|(line_number, _)| *line_number != result.line_number)
+        .partition(|(line_number, _)| *line_number < result.line_number);
+    let context_lines = |lines: Vec<&(usize, String)>| {
+        lines
+            .into_iter()
+            .map(|(_, line)| format!("  {line}\n"))
+            .collect::<String>()
+    };
+    format!(
+        "{path}:{}\n{}- {}\n+ {}\n{}",
+        result.line_number,
+        context_lines(before),
+        result.line,
+        display_counter_placeholders(&result.replacement),
+        context_lines(after),
+    )
+}
+
+fn simplified_line_diff(old_line: &str, new_line: &str) -> (Vec<Diff>, Vec<Diff>) {
+    let line = |marker: &str, text: &str, colour: Color| {
+        vec![
+            Diff::new(
+                format!("{marker} {SIMPLIFIED_MARKER}"),
+                colour,
+                Color::Reset,
+            ),
+            Diff::new(text, Color::Black, colour),
+        ]
+    };
+    (
+        line("-", old_line, Color::Red),
+        line("+", new_line, Color::Green),
+    )
+}
+
+/// Diff of the two lines combined into a single line, with deletions and insertions highlighted
+/// inline, for use in compact mode
+pub fn inline_diff(old_line: &str, new_line: &str) -> Vec<Diff> {
+    let Some(diff) = char_diff(old_line, new_line, DIFF_TIMEOUT) else {
+        return vec![
+            Diff::new(format!("~ {SIMPLIFIED_MARKER}"), Color::Reset, Color::Reset),
+            Diff::new(old_line, Color::Black, Color::Red),
+            Diff::new(new_line, Color::Black, Color::Green),
+        ];
+    };
+
+    let mut spans = vec![Diff::new("~ ", Color::Reset, Color::Reset)];
+
+    for change_group in group_by(diff.iter_all_changes(), |c1, c2| c1.tag() == c2.tag()) {
+        let first_change = change_group.first().unwrap(); // group_by should never return an empty group
+        let text: String = change_group.iter().map(Change::value).collect();
+        let (fg_colour, bg_colour) = match first_change.tag() {
+            ChangeTag::Delete => (Color::Black, Color::Red),
+            ChangeTag::Insert => (Color::Black, Color::Green),
+            ChangeTag::Equal => (Color::Reset, Color::Reset),
+        };
+        spans.push(Diff::new(text, fg_colour, bg_colour));
+    }
+
+    spans
+}
+
+/// Crops a diff from `line_diff` or `inline_diff` to `width` characters. The leading marker (e.g.
+/// "- ") is always kept, and the rest of the line is centred on the first change, so that changes
+/// far along a long line are still visible.
+pub fn crop_diff(mut diff: Vec<Diff>, width: usize) -> Vec<Diff> {
+    let content = diff.split_off(min(1, diff.len()));
+    let marker_len = diff.iter().map(|d| d.text.chars().count()).sum::<usize>();
+    let content_width = width.saturating_sub(marker_len);
+    let content_len = content
+        .iter()
+        .map(|d| d.text.chars().count())
+        .sum::<usize>();
+    if content_len <= content_width {
+        diff.extend(content);
+        return diff;
+    }
+
+    let change_start = content
+        .iter()
+        .take_while(|d| d.bg_colour == Color::Reset)
+        .map(|d| d.text.chars().count())
+        .sum::<usize>();
+    let change_len = content
+        .iter()
+        .find(|d| d.bg_colour != Color::Reset)
+        .map_or(0, |d| d.text.chars().count());
+    // Centre on the middle of the change, unless that would hide the start of it
+    let start = min(
+        change_start,
+        (change_start + change_len / 2).saturating_sub(content_width / 2),
+    );
+    let start = min(start, content_len - content_width);
+    let end = start + content_width;
 
-    let (is_complete, search_results) = match &app.current_screen {
-        Screen::SearchProgressing(SearchInProgressState { search_state, .. }) => {
-            (false, search_state)
+    let mut pos = 0;
+    for d in content {
+        let len = d.text.chars().count();
+        let (span_start, span_end) = (max(pos, start), min(pos + len, end));
+        if span_start < span_end {
+            diff.push(Diff {
+                text: d
+                    .text
+                    .chars()
+                    .skip(span_start - pos)
+                    .take(span_end - span_start)
+                    .collect(),
+                ..d
+            });
         }
-        Screen::SearchComplete(search_state) => (true, search_state),
+        pos += len;
+    }
+    diff
+}
+
+/// Lines are only diffed up to this many characters
+const MAX_DIFF_CHARS: usize = 10_000;
+const RESULT_ITEM_HEIGHT: usize = 4;
+const COMPACT_RESULT_ITEM_HEIGHT: usize = 2;
+
+/// Number of rows taken up by each result on the confirmation screen
+pub fn result_item_height(compact: bool) -> usize {
+    if compact {
+        COMPACT_RESULT_ITEM_HEIGHT
+    } else {
+        RESULT_ITEM_HEIGHT
+    }
+}
+
+fn render_confirmation_view(frame: &mut Frame<'_>, app: &App, rect: Rect) {
+    let area = content_area(rect, app.content_width());
+
+    let (is_complete, follow, search_results) = match &app.current_screen {
+        Screen::SearchProgressing(SearchInProgressState {
+            search_state,
+            follow,
+            ..
+        }) => (false, *follow, search_state),
+        Screen::SearchComplete(search_state) => (true, false, search_state),
         // prevent race condition when state is being reset
         _ => return,
     };
 
+    let num_gitignored = search_results.num_gitignored();
+    let mut notices = vec![];
+    if let Some(notice) = app.notice() {
+        notices.push(notice.to_owned());
+    }
+    if num_gitignored > 0 {
+        notices.push(if num_gitignored == 1 {
+            "1 result is in a gitignored file".to_owned()
+        } else {
+            format!("{num_gitignored} results are in gitignored files")
+        });
+    }
+    let binary_lines_skipped = app.binary_lines_skipped();
+    if binary_lines_skipped > 0 {
+        let binary_files_skipped = app.binary_files_skipped();
+        notices.push(format!(
+            "{} matching {} skipped as binary, in {} {}",
+            binary_lines_skipped,
+            if binary_lines_skipped == 1 {
+                "line was"
+            } else {
+                "lines were"
+            },
+            binary_files_skipped,
+            if binary_files_skipped == 1 {
+                "file"
+            } else {
+                "files"
+            },
+        ));
+    }
+    let [num_results_area, notices_area, list_area] = Layout::vertical([
+        Constraint::Length(if notices.is_empty() { 2 } else { 1 }),
+        Constraint::Length(if notices.is_empty() {
+            0
+        } else {
+            notices.len() as u16 + 1
+        }),
+        Constraint::Fill(1),
+    ])
+    .flex(Flex::Start)
+    .areas(area);
+
     let list_area_height = list_area.height as usize;
-    let item_height = 4; // TODO: find a better way of doing this
-    let midpoint = list_area_height / (2 * item_height);
+    let item_height = result_item_height(app.compact());
     let num_results = search_results.results.len();
+    let num_files = search_results.num_files();
+
+    if !notices.is_empty() {
+        frame.render_widget(
+            Paragraph::new(
+                notices
+                    .into_iter()
+                    .map(|notice| Line::styled(notice, Style::new().fg(Color::Yellow)))
+                    .collect::<Vec<_>>(),
+            ),
+            notices_area,
+        );
+    }
 
     frame.render_widget(
         Span::raw(format!(
-            "Results: {} {}",
+            "Results: {} in {} {} ({} of {} selected) {}{}{}",
+            num_results,
+            num_files,
+            if num_files == 1 { "file" } else { "files" },
+            search_results.num_included(),
             num_results,
-            if is_complete {
+            if is_complete && app.search_paused() {
+                "[Search paused]"
+            } else if is_complete {
                 "[Search complete]"
+            } else if follow {
+                "[Still searching...] [Following]"
             } else {
                 "[Still searching...]"
+            },
+            if search_results.hide_unchanged {
+                " [Hiding unchanged]"
+            } else {
+                ""
+            },
+            if search_results.visual_anchor.is_some() {
+                " [Visual]"
+            } else {
+                ""
             }
         )),
         num_results_area,
     );
 
+    let visible_range = search_results.visible_range(list_area_height, item_height);
     let results_iter = search_results
-        .results
-        .iter()
-        .enumerate()
-        .skip(min(
-            search_results.selected.saturating_sub(midpoint),
-            num_results.saturating_sub(list_area_height / item_height),
-        ))
-        .take(list_area_height / item_height + 1); // We shouldn't need the +1, but let's keep it in to ensure we have buffer when rendering
-
-    let search_results = results_iter.flat_map(|(idx, result)| {
-        let width = list_area.width;
-        let before = first_chars(&result.line, width as usize);
-        let after = first_chars(&result.replacement, width as usize);
-        let (old_line, new_line) = line_diff(before, after);
+        .shown_indices()
+        .skip(visible_range.start)
+        .take(visible_range.len() + 1) // We shouldn't need the +1, but let's keep it in to ensure we have buffer when rendering
+        .map(|idx| (idx, &search_results.results[idx]));
 
+    let visual_range = search_results.visual_range();
+    let num_hidden_per_file = search_results.num_hidden_per_file();
+    let items = results_iter.flat_map(|(idx, result)| {
+        let replace_error = match &result.replace_result {
+            Some(ReplaceResult::Error(error)) => Some(error),
+            _ => None,
+        };
         let file_path_style = if search_results.selected == idx {
             Style::new().bg(if result.included {
                 Color::Blue
             } else {
                 Color::Red
             })
+        } else if visual_range
+            .as_ref()
+            .is_some_and(|range| range.contains(&idx))
+        {
+            Style::new().bg(Color::DarkGray)
+        } else if replace_error.is_some() {
+            Style::new().fg(Color::Red)
+        } else if !result.warnings.is_empty() {
+            Style::new().fg(Color::Yellow)
         } else {
             Style::new()
         };
         let right_content = format!(" ({})", idx);
         let right_content_len = right_content.len() as u16;
         let left_content = format!(
-            "[{}] {}:{}",
+            "[{}] {}:{}{}{}{}",
             if result.included { 'x' } else { ' ' },
             app.relative_path(&result.path),
             result.line_number,
+            if result.num_matches > 1 {
+                format!(" ({} matches)", result.num_matches)
+            } else {
+                String::new()
+            },
+            match replace_error {
+                Some(error) => format!(" [failed: {}]", error),
+                None => String::new(),
+            },
+            if result.warnings.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    " [warning: {}]",
+                    result
+                        .warnings
+                        .iter()
+                        .map(ReplaceWarning::description)
+                        .join(", ")
+                )
+            },
         );
         let left_content_trimmed = left_content
             .chars()
@@ -257,39 +699,71 @@ fn render_confirmation_view(frame: &mut Frame<'_>, app: &App, rect: Rect) {
         ])
         .style(file_path_style);
 
-        [
-            ListItem::new(file_path),
-            ListItem::new(diff_to_line(old_line)),
-            ListItem::new(diff_to_line(new_line)),
-            ListItem::new(""),
-        ]
+        let diff_lines = result_diff_lines(result, app.compact(), list_area.width as usize);
+        let mut items = iter::once(ListItem::new(file_path))
+            .chain(diff_lines.into_iter().map(ListItem::new))
+            .collect::<Vec<_>>();
+        if let Some(num_hidden) = num_hidden_per_file.get(&idx) {
+            items.push(ListItem::new(Line::styled(
+                format!("+{num_hidden} more in this file"),
+                Style::new().fg(Color::DarkGray),
+            )));
+        }
+        if !app.compact() {
+            items.push(ListItem::new(""));
+        }
+        items
     });
 
-    frame.render_widget(List::new(search_results), list_area);
+    frame.render_widget(List::new(items), list_area);
 }
 
 fn render_results_view(replace_state: &ReplaceState) -> impl Fn(&mut Frame<'_>, &App, Rect) + '_ {
-    move |frame: &mut Frame<'_>, _app: &App, rect: Rect| {
-        let [area] = Layout::horizontal([Constraint::Percentage(80)])
-            .flex(Flex::Center)
-            .areas(rect);
+    move |frame: &mut Frame<'_>, app: &App, rect: Rect| {
+        let area = content_area(rect, app.content_width());
 
-        if replace_state.errors.is_empty() {
+        // Without context there is nothing more to show about each success than the tallies
+        if replace_state.errors.is_empty() && app.results_context() == 0 {
             render_results_success(area, replace_state, frame);
         } else {
-            render_results_errors(area, replace_state, frame);
+            render_results_list(area, replace_state, app, frame);
         }
     }
 }
 
 const ERROR_ITEM_HEIGHT: u16 = 3;
-const NUM_TALLIES: usize = 3;
+
+fn results_tallies(replace_state: &ReplaceState) -> Vec<(&'static str, usize, Style)> {
+    let mut tallies = vec![
+        (
+            "Successful replacements:",
+            replace_state.num_successes,
+            Style::new(),
+        ),
+        ("Ignored:", replace_state.num_ignored, Style::new()),
+        ("Errors:", replace_state.errors.len(), Style::new()),
+    ];
+    if let Some(num_remaining_matches) = replace_state.num_remaining_matches {
+        // Highlight matches that remain, as the replacement may not have done what was expected
+        let style = if num_remaining_matches > 0 {
+            Style::new().fg(Color::Yellow)
+        } else {
+            Style::new()
+        };
+        tallies.push(("Matches remaining:", num_remaining_matches, style));
+    }
+    tallies
+}
+
+fn tallies_height(replace_state: &ReplaceState) -> u16 {
+    ERROR_ITEM_HEIGHT * results_tallies(replace_state).len() as u16
+}
 
 fn render_results_success(area: Rect, replace_state: &ReplaceState, frame: &mut Frame<'_>) {
     let [_, success_title_area, results_area, _] = Layout::vertical([
         Constraint::Fill(1),
         Constraint::Length(3),
-        Constraint::Length(ERROR_ITEM_HEIGHT * NUM_TALLIES as u16), // TODO: find a better way of doing this
+        Constraint::Length(tallies_height(replace_state)),
         Constraint::Fill(1),
     ])
     .flex(Flex::Start)
@@ -306,66 +780,71 @@ fn render_results_success(area: Rect, replace_state: &ReplaceState, frame: &mut
     frame.render_widget(Text::raw(text), area);
 }
 
-fn render_results_errors(area: Rect, replace_state: &ReplaceState, frame: &mut Frame<'_>) {
+fn render_results_list(area: Rect, replace_state: &ReplaceState, app: &App, frame: &mut Frame<'_>) {
+    let context = app.results_context();
     let [results_area, list_title_area, list_area] = Layout::vertical([
-        Constraint::Length(ERROR_ITEM_HEIGHT * NUM_TALLIES as u16), // TODO: find a better way of doing this
+        Constraint::Length(tallies_height(replace_state)),
         Constraint::Length(1),
         Constraint::Fill(1),
     ])
     .flex(Flex::Start)
     .areas(area);
 
-    let errors = replace_state
-        .errors
-        .iter()
-        .map(|res| {
-            error_result(
-                res,
-                match &res.replace_result {
+    let num_items = list_area.height as usize / 3 + 1; // TODO: don't hardcode height
+    let (title, items) = if replace_state.showing_successes() {
+        let num_items = list_area.height as usize / (3 + 2 * context) + 1;
+        let successes = replace_state
+            .successes
+            .iter()
+            .enumerate()
+            .skip(replace_state.successes_pos)
+            .take(num_items)
+            .flat_map(|(idx, res)| {
+                let path = app.relative_path(&res.path);
+                match replace_state.success_context.get(&idx) {
+                    Some(lines) => result_item_with_context(res, &path, lines),
+                    None => result_item(res, &path, &res.replacement, Color::Green).into(),
+                }
+            });
+        ("Successful replacements:", successes.collect::<Vec<_>>())
+    } else {
+        let errors = replace_state
+            .errors
+            .iter()
+            .map(|res| {
+                let error = match &res.replace_result {
                     Some(ReplaceResult::Error(error)) => error,
                     None => panic!("Found error result with no error message"),
                     Some(ReplaceResult::Success) => {
                         panic!("Found successful result in errors: {:?}", res)
                     }
-                },
-            )
-        })
-        .skip(replace_state.replacement_errors_pos)
-        .take(list_area.height as usize / 3 + 1); // TODO: don't hardcode height
+                };
+                result_item(res, &app.relative_path(&res.path), error, Color::Red)
+            })
+            .skip(replace_state.replacement_errors_pos)
+            .take(num_items);
+        ("Errors:", errors.flatten().collect::<Vec<_>>())
+    };
 
     render_results_tallies(results_area, frame, replace_state);
 
-    frame.render_widget(Text::raw("Errors:"), list_title_area);
-    frame.render_widget(List::new(errors.flatten()), list_area);
+    frame.render_widget(Text::raw(title), list_title_area);
+    frame.render_widget(List::new(items), list_area);
 }
 
 fn render_results_tallies(results_area: Rect, frame: &mut Frame<'_>, replace_state: &ReplaceState) {
-    let [success_area, ignored_area, errors_area] = Layout::vertical([
-        Constraint::Length(3),
-        Constraint::Length(3),
-        Constraint::Length(3),
-    ])
-    .flex(Flex::Start)
-    .areas(results_area);
-    let widgets: [_; NUM_TALLIES] = [
-        (
-            "Successful replacements:",
-            replace_state.num_successes,
-            success_area,
-        ),
-        ("Ignored:", replace_state.num_ignored, ignored_area),
-        ("Errors:", replace_state.errors.len(), errors_area),
-    ];
-    let widgets = widgets.into_iter().map(|(title, num, area)| {
-        (
-            Paragraph::new(num.to_string())
-                .block(Block::bordered().border_style(Style::new()).title(title)),
-            area,
-        )
-    });
-    widgets.for_each(|(widget, area)| {
-        frame.render_widget(widget, area);
-    });
+    let tallies = results_tallies(replace_state);
+    let areas = Layout::vertical(iter::repeat_n(Constraint::Length(3), tallies.len()))
+        .flex(Flex::Start)
+        .split(results_area);
+    tallies
+        .into_iter()
+        .zip(areas.iter())
+        .for_each(|((title, num, border_style), area)| {
+            let widget = Paragraph::new(num.to_string())
+                .block(Block::bordered().border_style(border_style).title(title));
+            frame.render_widget(widget, *area);
+        });
 }
 
 fn center(area: Rect, horizontal: Constraint, vertical: Constraint) -> Rect {
@@ -390,27 +869,255 @@ fn render_loading_view(text: String) -> impl Fn(&mut Frame<'_>, &App, Rect) {
     }
 }
 
-fn error_result(result: &SearchResult, error: &str) -> [ratatui::widgets::ListItem<'static>; 3] {
-    [
-        ("".to_owned(), Style::default()),
+/// Formats a change in file size, e.g. "+12 bytes"
+pub fn format_byte_delta(delta: isize) -> String {
+    format!(
+        "{}{} {}",
+        if delta > 0 { "+" } else { "" },
+        delta,
+        if delta.abs() == 1 { "byte" } else { "bytes" }
+    )
+}
+
+fn render_file_confirmation_view(
+    state: &FileConfirmationState,
+) -> impl Fn(&mut Frame<'_>, &App, Rect) + '_ {
+    move |frame: &mut Frame<'_>, app: &App, rect: Rect| {
+        let Some((path, indices)) = state.current_file() else {
+            return;
+        };
+        let area = content_area(rect, app.content_width());
+        let [header_area, list_area] =
+            Layout::vertical([Constraint::Length(2), Constraint::Fill(1)]).areas(area);
+
+        frame.render_widget(
+            Span::raw(format!(
+                "File {} of {}: {} ({} {}, {})",
+                state.current + 1,
+                state.files.len(),
+                app.relative_path(path),
+                indices.len(),
+                if indices.len() == 1 {
+                    "result"
+                } else {
+                    "results"
+                },
+                format_byte_delta(state.search_state.byte_size_delta(path)),
+            )),
+            header_area,
+        );
+
+        let width = list_area.width as usize;
+        let items = indices.iter().skip(state.scroll_pos).flat_map(|&idx| {
+            let result = &state.search_state.results[idx];
+            let (old_line, new_line) = result_line_diff(result);
+            vec![
+                ListItem::new(format!("Line {}", result.line_number)).dim(),
+                ListItem::new(diff_to_line(crop_diff(old_line, width))),
+                ListItem::new(diff_to_line(crop_diff(new_line, width))),
+                ListItem::new(""),
+            ]
+        });
+        frame.render_widget(List::new(items), list_area);
+    }
+}
+
+/// Tallies shown on the replacement summary, with warnings highlighted
+pub fn replacement_summary_tallies(summary: &ReplacementSummary) -> Vec<(String, usize, Style)> {
+    let warning_style = Style::new().fg(Color::Yellow);
+    let mut tallies = vec![
+        ("Files:".to_owned(), summary.num_files, Style::new()),
+        ("Lines:".to_owned(), summary.num_lines, Style::new()),
         (
+            "Substitutions:".to_owned(),
+            summary.num_substitutions,
+            Style::new(),
+        ),
+    ];
+    for (warning, count) in &summary.warnings {
+        tallies.push((
+            format!("Warning - {}:", warning.description()),
+            *count,
+            warning_style,
+        ));
+    }
+    if summary.num_large_files > 0 {
+        tallies.push((
             format!(
-                "{}:{}",
-                result
-                    .path
-                    .clone()
-                    .into_os_string()
-                    .into_string()
-                    .expect("Failed to display path"),
-                result.line_number
+                "Warning - files larger than {} MiB:",
+                LARGE_FILE_BYTES / (1024 * 1024)
             ),
-            Style::default(),
-        ),
-        (error.to_owned(), Style::default().fg(Color::Red)),
+            summary.num_large_files,
+            warning_style,
+        ));
+    }
+    if summary.num_binary_files > 0 {
+        tallies.push((
+            "Warning - binary files skipped:".to_owned(),
+            summary.num_binary_files,
+            warning_style,
+        ));
+    }
+    tallies
+}
+
+fn render_replacement_summary_view(
+    summary: &ReplacementSummary,
+) -> impl Fn(&mut Frame<'_>, &App, Rect) + '_ {
+    move |frame: &mut Frame<'_>, app: &App, rect: Rect| {
+        let area = content_area(rect, app.content_width());
+        let tallies = replacement_summary_tallies(summary);
+        let [_, title_area, tallies_area, _] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(3),
+            Constraint::Length(ERROR_ITEM_HEIGHT * tallies.len() as u16),
+            Constraint::Fill(1),
+        ])
+        .flex(Flex::Start)
+        .areas(area);
+
+        let title = "Replace?";
+        frame.render_widget(
+            Text::raw(title),
+            center(
+                title_area,
+                Constraint::Length(title.len() as u16),
+                Constraint::Length(1),
+            ),
+        );
+
+        let areas = Layout::vertical(iter::repeat_n(Constraint::Length(3), tallies.len()))
+            .flex(Flex::Start)
+            .split(tallies_area);
+        for ((title, num, border_style), area) in tallies.into_iter().zip(areas.iter()) {
+            let widget = Paragraph::new(num.to_string())
+                .block(Block::bordered().border_style(border_style).title(title));
+            frame.render_widget(widget, *area);
+        }
+    }
+}
+
+fn render_replacement_progress_view(
+    state: &PerformingReplacementState,
+) -> impl Fn(&mut Frame<'_>, &App, Rect) + '_ {
+    move |frame: &mut Frame<'_>, app: &App, rect: Rect| {
+        let Some(progress) = &state.progress else {
+            return render_loading_view("Performing replacement...".to_owned())(frame, app, rect);
+        };
+        let [area] = Layout::vertical([Constraint::Length(4)])
+            .flex(Flex::Center)
+            .areas(content_area(rect, app.content_width()));
+
+        let text = Paragraph::new(vec![
+            Line::from(format!(
+                "Performing replacement... ({} of {} {} done)",
+                progress.num_files_done,
+                progress.num_files,
+                if progress.num_files == 1 {
+                    "file"
+                } else {
+                    "files"
+                },
+            )),
+            Line::from(app.relative_path(&progress.current_file)).dim(),
+        ])
+        .block(Block::default())
+        .alignment(Alignment::Center);
+
+        frame.render_widget(text, area);
+    }
+}
+
+/// The result's path (as displayed by `App::relative_path`) and line number, followed by `detail`
+fn result_item(
+    result: &SearchResult,
+    path: &str,
+    detail: &str,
+    detail_colour: Color,
+) -> [ratatui::widgets::ListItem<'static>; 3] {
+    [
+        ("".to_owned(), Style::default()),
+        (format!("{}:{}", path, result.line_number), Style::default()),
+        (detail.to_owned(), Style::default().fg(detail_colour)),
     ]
     .map(|(s, style)| ListItem::new(Text::styled(s, style)))
 }
 
+/// Like `result_item`, but with the lines around the result, each prefixed with its line number
+fn result_item_with_context(
+    result: &SearchResult,
+    path: &str,
+    lines: &[(usize, String)],
+) -> Vec<ListItem<'static>> {
+    let [blank, path, _] = result_item(result, path, "", Color::Reset);
+    let width = lines
+        .last()
+        .map_or(0, |(line_number, _)| line_number.to_string().len());
+    let lines = lines.iter().map(|(line_number, line)| {
+        let style = if *line_number == result.line_number {
+            Style::default().fg(Color::Green)
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+        ListItem::new(Text::styled(format!("{line_number:>width$} {line}"), style))
+    });
+    [blank, path].into_iter().chain(lines).collect()
+}
+
+fn render_large_search_prompt(frame: &mut Frame<'_>, app: &App, rect: Rect) {
+    let mut lines = app
+        .large_search_roots()
+        .into_iter()
+        .map(|dir| Line::from(format!("Searching {} may take a long time.", dir.display())))
+        .collect::<Vec<_>>();
+    lines.push(Line::from(""));
+    lines.push(Line::from("Continue? <y> yes / <any other key> no").bold());
+
+    let area = center(
+        rect,
+        Constraint::Percentage(60),
+        Constraint::Length(lines.len() as u16 + 2),
+    );
+    let popup = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+        Block::bordered()
+            .title("Large directory")
+            .title_alignment(Alignment::Center),
+    );
+    frame.render_widget(Clear, area);
+    frame.render_widget(popup, area);
+}
+
+fn render_replacement_editor(frame: &mut Frame<'_>, field: &TextField, rect: Rect) {
+    let area = center(rect, Constraint::Percentage(80), Constraint::Length(3));
+    let block = Block::bordered()
+        .title("Replacement")
+        .border_style(Style::new().fg(Color::Green));
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(field.display_text()).block(block), area);
+    frame.set_cursor(area.x + field.cursor_idx() as u16 + 1, area.y + 1);
+}
+
+fn render_log_viewer(frame: &mut Frame<'_>, log_viewer: &LogViewerState, rect: Rect) {
+    let area = center(rect, Constraint::Percentage(90), Constraint::Percentage(90));
+    let block = Block::bordered()
+        .title(format!("Logs ({})", log_viewer.path.display()))
+        .title_alignment(Alignment::Center);
+    let visible_height = block.inner(area).height as usize;
+
+    // Keep the selected line at the bottom of the popup, so that the latest logs are shown first
+    let start = (log_viewer.scroll + 1).saturating_sub(visible_height);
+    let lines = log_viewer
+        .lines
+        .iter()
+        .skip(start)
+        .take(visible_height)
+        .map(|line| Line::from(line.as_str()))
+        .collect::<Vec<_>>();
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
 type RenderFn<'a> = Box<dyn Fn(&mut Frame<'_>, &'a App, Rect) + 'a>;
 
 pub fn render(app: &App, frame: &mut Frame<'_>) {
@@ -434,43 +1141,156 @@ pub fn render(app: &App, frame: &mut Frame<'_>) {
         Screen::SearchProgressing(_) | Screen::SearchComplete(_) => {
             Box::new(render_confirmation_view)
         }
-        Screen::PerformingReplacement(_) => {
-            Box::new(render_loading_view("Performing replacement...".to_owned()))
+        Screen::ConfirmingFiles(ref state) => Box::new(render_file_confirmation_view(state)),
+        Screen::ReviewingReplacement(ref summary) => {
+            Box::new(render_replacement_summary_view(summary))
+        }
+        Screen::PerformingReplacement(ref state) => {
+            Box::new(render_replacement_progress_view(state))
         }
         Screen::Results(ref replace_state) => Box::new(render_results_view(replace_state)),
     };
     render_fn(frame, app, chunks[1]);
 
+    if app.confirming_large_search {
+        render_large_search_prompt(frame, app, chunks[1]);
+    }
+    if let Some(field) = &app.editing_replacement {
+        render_replacement_editor(frame, field, chunks[1]);
+    }
+    if let Some(log_viewer) = &app.log_viewer {
+        render_log_viewer(frame, log_viewer, chunks[1]);
+    }
+
     let current_keys = match app.current_screen {
+        Screen::SearchFields if app.search_fields.show_error_popup => {
+            let mut keys = vec!["<j> down", "<k> up"];
+            if app.search_fields.can_search_literally() {
+                keys.push("<l> search literally");
+            }
+            keys.push("<any> close");
+            keys
+        }
         Screen::SearchFields => {
-            vec!["<enter> search", "<tab> focus next", "<S-tab> focus prev"]
+            let mut keys = vec![
+                "<enter> search",
+                "<tab> focus next",
+                "<S-tab> focus prev",
+                "<A-r> toggle advanced regex",
+                "<A-f> toggle fuzzy path",
+            ];
+            if app.can_restore_last_search() {
+                keys.push("<C-z> restore last search");
+            }
+            keys
         }
         Screen::SearchProgressing(_) | Screen::SearchComplete(_) => {
-            let mut keys = if let Screen::SearchComplete(_) = app.current_screen {
-                vec!["<enter> replace"]
+            let mut keys = if let Screen::SearchComplete(ref search_state) = app.current_screen {
+                let replace_key = if search_state.visual_anchor.is_some() {
+                    "<enter> replace range"
+                } else {
+                    "<enter> replace"
+                };
+                let hidden_key = if app.include_hidden() {
+                    "<h> skip hidden"
+                } else {
+                    "<h> search hidden"
+                };
+                let mut keys = vec![replace_key, hidden_key];
+                if app.search_paused() {
+                    keys.push("<r> resume search");
+                }
+                keys.push("<s> save review");
+                keys.push("<i> edit replacement");
+                keys
             } else {
-                vec![]
+                vec!["<f> follow"]
             };
             keys.append(&mut vec![
                 "<space> toggle",
                 "<a> toggle all",
+                "<e> toggle extension",
+                "<J> toggle to end",
+                "<K> toggle to start",
+                "<u> hide unchanged",
+            ]);
+            if let Screen::SearchProgressing(SearchInProgressState { search_state, .. })
+            | Screen::SearchComplete(search_state) = &app.current_screen
+            {
+                if search_state.max_per_file.is_some() {
+                    keys.push("<m> more in file");
+                }
+            }
+            keys.append(&mut vec![
+                "<v> visual range",
+                "<c> confirm each file",
+                "<y> copy diff",
+                "<Y> copy patch",
+                "<o> open directory",
                 "<j> down",
                 "<k> up",
+                "<}> next file",
+                "<{> prev file",
                 "<C-o> back",
             ]);
             keys
         }
+        Screen::ConfirmingFiles(_) => vec![
+            "<y> replace in file",
+            "<n> skip file",
+            "<j> down",
+            "<k> up",
+            "<o> open directory",
+            "<C-o> back",
+        ],
+        Screen::ReviewingReplacement(_) => vec!["<enter> replace", "<esc> back"],
         Screen::PerformingReplacement(_) => vec![],
         Screen::Results(ref replace_state) => {
             if !replace_state.errors.is_empty() {
-                vec!["<j> down", "<k> up"]
+                let mut keys = vec!["<j> down", "<k> up", "<o> open directory"];
+                if replace_state.showing_successes() {
+                    keys.push("<+> more context");
+                }
+                if !replace_state.successes.is_empty() {
+                    keys.push(if replace_state.show_successes {
+                        "<s> show errors"
+                    } else {
+                        "<s> show successes"
+                    });
+                }
+                keys.push("<e> open in editor");
+                keys
+            } else if !replace_state.successes.is_empty() {
+                if app.results_context() > 0 {
+                    vec![
+                        "<j> down",
+                        "<k> up",
+                        "<+> more context",
+                        "<e> open in editor",
+                    ]
+                } else {
+                    vec!["<e> open in editor"]
+                }
             } else {
                 vec![]
             }
         }
     };
 
-    let additional_keys = ["<C-r> reset", "<esc> quit"];
+    let current_keys = if app.log_viewer.is_some() {
+        vec!["<j> down", "<k> up", "<esc> close logs"]
+    } else if app.editing_replacement.is_some() {
+        vec!["<enter> save replacement", "<esc> cancel"]
+    } else {
+        current_keys
+    };
+    let additional_keys = if matches!(app.current_screen, Screen::ReviewingReplacement(_))
+        || app.editing_replacement.is_some()
+    {
+        vec!["<C-l> logs", "<C-r> reset", "<C-c> quit"]
+    } else {
+        vec!["<C-l> logs", "<C-r> reset", "<esc> quit"]
+    };
 
     let all_keys = current_keys
         .iter()
@@ -1,5 +1,17 @@
-use anyhow::{anyhow, Result};
-use std::path::{Path, PathBuf};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use log::warn;
+use parking_lot::{Condvar, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{error::ScooterError, event::AppEvent};
 
 pub fn replace_start(s: String, from: &str, to: &str) -> String {
     if let Some(stripped) = s.strip_prefix(from) {
@@ -15,6 +27,89 @@ pub fn relative_path_from(root_dir: &Path, path: &Path) -> String {
     replace_start(path, root_dir, ".")
 }
 
+/// Path relative to the nearest (i.e. most deeply nested) root containing it
+pub fn relative_path_from_roots(root_dirs: &[PathBuf], path: &Path) -> String {
+    match root_dirs
+        .iter()
+        .filter(|root_dir| path.starts_with(root_dir))
+        .max_by_key(|root_dir| root_dir.components().count())
+    {
+        Some(root_dir) => relative_path_from(root_dir, path),
+        None => path.to_str().unwrap().to_owned(),
+    }
+}
+
+/// `dirs` without any that are inside another of `dirs`, or that appear more than once, so that
+/// walking each of them visits every file once. Directories are compared by their canonical paths
+/// where possible, so that e.g. `./src` is recognised as being inside `.`.
+pub fn outermost_dirs(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let canonical = dirs
+        .iter()
+        .map(|dir| std::fs::canonicalize(dir).unwrap_or_else(|_| dir.clone()))
+        .collect::<Vec<_>>();
+    dirs.iter()
+        .enumerate()
+        .filter(|&(idx, _)| {
+            !canonical.iter().enumerate().any(|(other_idx, other)| {
+                other_idx != idx
+                    && canonical[idx].starts_with(other)
+                    // Keep the first of any duplicates
+                    && (canonical[idx] != *other || other_idx < idx)
+            })
+        })
+        .map(|(_, dir)| dir.clone())
+        .collect()
+}
+
+fn path_components(path: &str) -> Vec<&str> {
+    path.split(['/', '\\'])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect()
+}
+
+/// Returns whether `pattern` appears in `path` as a run of whole path components, so that
+/// e.g. `src` matches `./src/main.rs` but not `./resrc/main.rs`.
+pub fn path_contains_components(path: &str, pattern: &str) -> bool {
+    let components = path_components(path);
+    let pattern_components = path_components(pattern);
+    (0..=components.len()).any(|start| components[start..].starts_with(&pattern_components))
+}
+
+/// Wraps `line` so that no line is longer than `width` chars, breaking at spaces where possible.
+/// Leading whitespace is kept, so that e.g. the caret under a regex error stays aligned.
+pub fn wrap_line(line: &str, width: usize) -> Vec<String> {
+    if width == 0 {
+        return vec![line.to_owned()];
+    }
+
+    let mut wrapped = vec![];
+    let mut rest = line.chars().collect::<Vec<_>>();
+    while rest.len() > width {
+        let split_idx = rest[..=width]
+            .iter()
+            .rposition(|c| *c == ' ')
+            .filter(|idx| *idx > 0 && rest[..*idx].iter().any(|c| *c != ' '));
+        match split_idx {
+            Some(idx) => {
+                wrapped.push(rest[..idx].iter().collect());
+                let next_start = rest[idx..]
+                    .iter()
+                    .position(|c| *c != ' ')
+                    .map_or(rest.len(), |offset| idx + offset);
+                rest.drain(..next_start);
+            }
+            None => {
+                wrapped.push(rest[..width].iter().collect());
+                rest.drain(..width);
+            }
+        }
+    }
+    if !rest.is_empty() || wrapped.is_empty() {
+        wrapped.push(rest.into_iter().collect());
+    }
+    wrapped
+}
+
 pub fn group_by<I, T, F>(iter: I, predicate: F) -> Vec<Vec<T>>
 where
     I: IntoIterator<Item = T>,
@@ -39,16 +134,22 @@ where
     result
 }
 
-pub fn validate_directory(dir_str: &str) -> Result<PathBuf> {
-    let path = Path::new(dir_str);
-    if path.exists() {
-        Ok(path.to_path_buf())
-    } else {
-        Err(anyhow!(
-            "Directory '{}' does not exist. Please provide a valid directory path.",
-            dir_str
-        ))
-    }
+/// Checks that `dir_str` exists, returning it as an absolute path so that paths displayed relative
+/// to it don't depend on how it was written (e.g. `.` or `../foo/`) or on the working directory
+pub fn validate_directory(dir_str: &str) -> Result<PathBuf, ScooterError> {
+    Path::new(dir_str)
+        .canonicalize()
+        .map_err(|_| ScooterError::InvalidDirectory(dir_str.to_owned()))
+}
+
+/// Whether searching `dir` is likely to take a very long time, e.g. if it is a filesystem root or
+/// one of `large_dirs` (such as the home directory)
+pub fn is_large_search_root(dir: &Path, large_dirs: &[PathBuf]) -> bool {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    dir.parent().is_none()
+        || large_dirs
+            .iter()
+            .any(|large_dir| large_dir.canonicalize().is_ok_and(|d| d == dir))
 }
 
 pub fn first_chars(s: &str, n: usize) -> &str {
@@ -58,12 +159,245 @@ pub fn first_chars(s: &str, n: usize) -> &str {
     }
 }
 
+/// Parses a duration such as `30m`, `2d` or `1w`: a whole number followed by one of `s`, `m`, `h`,
+/// `d` or `w`, for seconds, minutes, hours, days or weeks
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration '{s}', expected e.g. 30m, 12h, 2d or 1w");
+    let unit_idx = s
+        .find(|c: char| !c.is_ascii_digit())
+        .filter(|&idx| idx > 0)
+        .ok_or_else(invalid)?;
+    let (amount, unit) = s.split_at(unit_idx);
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let amount = amount.parse::<u64>().map_err(|_| invalid())?;
+    amount
+        .checked_mul(unit_secs)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+/// The command that opens `dir` in the system file manager on `os` (one of the values of
+/// `std::env::consts::OS`), or `None` if there is no known way to do so
+pub fn open_directory_command(os: &str, dir: &Path) -> Option<Command> {
+    let program = match os {
+        "macos" => "open",
+        "windows" => "explorer",
+        "linux" | "freebsd" | "openbsd" | "netbsd" | "dragonfly" => "xdg-open",
+        _ => return None,
+    };
+    let mut command = Command::new(program);
+    command.arg(dir);
+    Some(command)
+}
+
+/// Opens the directory containing `path` in the system file manager, without waiting for the
+/// file manager to exit
+pub fn open_containing_directory(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut command = open_directory_command(std::env::consts::OS, dir).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "No file manager available on this platform",
+        )
+    })?;
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reap the process once it exits, so that it isn't left as a zombie
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// The command that opens all of `paths` in `editor`, which may include arguments (e.g.
+/// `code --wait`), or `None` if `editor` is blank. On Unix, `editor` is run by the shell, as with
+/// git, so that it can quote its arguments or refer to other variables.
+pub fn editor_command(editor: &str, paths: &[PathBuf]) -> Option<Command> {
+    if editor.trim().is_empty() {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("{editor} \"$@\""))
+            // The name that the script is run as, i.e. `$0`, so that `$@` holds just the paths
+            .arg(editor)
+            .args(paths);
+        Some(command)
+    }
+    #[cfg(not(unix))]
+    {
+        let mut words = editor.split_whitespace();
+        let mut command = Command::new(words.next()?);
+        command.args(words).args(paths);
+        Some(command)
+    }
+}
+
+/// Identifies the file at `path` by its device and inode, if it has more than one hard link, so
+/// that paths linking to the same file can be recognised. Always `None` on non-Unix platforms.
+pub fn hard_link_id(path: &Path) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::metadata(path).ok()?;
+        (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Copies `text` to the system clipboard in the background, sending a notice to `app_event_sender`
+/// if there is no clipboard available, e.g. when running over SSH without a display
+pub fn copy_to_clipboard(text: String, app_event_sender: UnboundedSender<AppEvent>) {
+    thread::spawn(move || {
+        let result = arboard::Clipboard::new().and_then(|mut clipboard| {
+            let set = clipboard.set();
+            // On Linux the clipboard contents are only available while we own them, so keep the
+            // thread alive until another program takes ownership
+            #[cfg(target_os = "linux")]
+            let set = {
+                use arboard::SetExtLinux;
+                set.wait()
+            };
+            set.text(text)
+        });
+        if let Err(e) = result {
+            warn!("Couldn't copy to the clipboard: {e}");
+            // Ignore error: the app may have exited
+            let _ = app_event_sender.send(AppEvent::ShowNotice(format!(
+                "Couldn't copy to the clipboard: {e}"
+            )));
+        }
+    });
+}
+
+/// The lines of the file at `path` from `num_lines` before `line_number` (counted from 1) to
+/// `num_lines` after it, along with their line numbers, as they are now
+pub fn read_lines_around(
+    path: &Path,
+    line_number: usize,
+    num_lines: usize,
+) -> io::Result<Vec<(usize, String)>> {
+    let first_line_number = line_number.saturating_sub(num_lines).max(1);
+    BufReader::new(File::open(path)?)
+        .split(b'\n')
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line))
+        .skip(first_line_number - 1)
+        .take(line_number + num_lines + 1 - first_line_number)
+        .map(|(line_number, line)| {
+            let mut line = String::from_utf8_lossy(&line?).into_owned();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+            Ok((line_number, line))
+        })
+        .collect()
+}
+
+/// Length of the `\n` or `\r\n` at the end of `line`, if any
+pub fn line_ending_len(line: &[u8]) -> usize {
+    if line.ends_with(b"\r\n") {
+        2
+    } else if line.ends_with(b"\n") || line.ends_with(b"\r") {
+        1
+    } else {
+        0
+    }
+}
+
+/// Limits how many files can be open at once across threads, to avoid running out of file
+/// descriptors when the limit set by `ulimit` is low
+#[derive(Debug)]
+pub struct OpenFileLimiter {
+    max_open: usize,
+    num_open: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Held while a file is open, allowing another file to be opened once dropped
+pub struct OpenFilePermit<'a> {
+    limiter: &'a OpenFileLimiter,
+}
+
+impl OpenFileLimiter {
+    pub fn new(max_open: usize) -> Self {
+        Self {
+            // Otherwise nothing could ever be opened
+            max_open: max_open.max(1),
+            num_open: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until fewer than the maximum number of files are open
+    pub fn acquire(&self) -> OpenFilePermit<'_> {
+        let mut num_open = self.num_open.lock();
+        while *num_open >= self.max_open {
+            self.released.wait(&mut num_open);
+        }
+        *num_open += 1;
+        OpenFilePermit { limiter: self }
+    }
+}
+
+impl Drop for OpenFilePermit<'_> {
+    fn drop(&mut self) {
+        *self.limiter.num_open.lock() -= 1;
+        self.limiter.released.notify_one();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
     use tempfile::TempDir;
 
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(
+            parse_duration("2d"),
+            Ok(Duration::from_secs(2 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("1w"),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        for invalid in [
+            "",
+            "d",
+            "2",
+            "2x",
+            "2dd",
+            "-1d",
+            "1.5h",
+            "99999999999999999999w",
+        ] {
+            assert!(parse_duration(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
     #[test]
     fn test_replace_start_matching_prefix() {
         assert_eq!(replace_start("abac".to_string(), "a", "z"), "zbac");
@@ -97,6 +431,115 @@ mod tests {
         assert_eq!(replace_start("abc".to_string(), "", "xyz"), "xyzabc");
     }
 
+    #[test]
+    fn test_path_contains_components_matches_directory() {
+        assert!(path_contains_components("./src/x.rs", "src"));
+        assert!(path_contains_components("./foo/src/x.rs", "src"));
+    }
+
+    #[test]
+    fn test_path_contains_components_rejects_partial_component() {
+        assert!(!path_contains_components("./resrc/x.rs", "src"));
+        assert!(!path_contains_components("./srcs/x.rs", "src"));
+    }
+
+    #[test]
+    fn test_path_contains_components_multiple_components() {
+        assert!(path_contains_components("./foo/src/x.rs", "src/x.rs"));
+        assert!(path_contains_components("./foo/src/x.rs", "./foo/src"));
+        assert!(!path_contains_components("./foo/src/x.rs", "foo/x.rs"));
+        assert!(path_contains_components(".\\foo\\src\\x.rs", "foo/src"));
+    }
+
+    #[test]
+    fn test_path_contains_components_empty_pattern() {
+        assert!(path_contains_components("./foo/x.rs", ""));
+    }
+
+    #[test]
+    fn test_wrap_line_breaks_at_spaces() {
+        assert_eq!(
+            wrap_line("error: unclosed group here", 10),
+            vec!["error:", "unclosed", "group here"]
+        );
+    }
+
+    #[test]
+    fn test_wrap_line_keeps_short_lines() {
+        assert_eq!(wrap_line("    foo(", 10), vec!["    foo("]);
+        assert_eq!(wrap_line("", 10), vec![""]);
+    }
+
+    #[test]
+    fn test_wrap_line_splits_long_words() {
+        assert_eq!(wrap_line("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(wrap_line("      ^^^^^^", 4), vec!["    ", "  ^^", "^^^^"]);
+    }
+
+    #[test]
+    fn test_is_large_search_root() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let large_dirs = vec![temp_dir.path().to_path_buf()];
+        assert!(is_large_search_root(Path::new("/"), &[]));
+        assert!(is_large_search_root(temp_dir.path(), &large_dirs));
+        assert!(!is_large_search_root(temp_dir.path(), &[]));
+        assert!(!is_large_search_root(
+            &temp_dir.path().join("..").join("..").join("foo"),
+            &large_dirs
+        ));
+    }
+
+    #[test]
+    fn test_outermost_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir_all(root.join("c")).unwrap();
+
+        let dirs = vec![
+            root.join("a/b"),
+            root.join("c"),
+            root.join("a"),
+            root.join("c/../c"),
+        ];
+        assert_eq!(outermost_dirs(&dirs), vec![root.join("c"), root.join("a")]);
+        assert_eq!(
+            outermost_dirs(&[root.to_path_buf(), root.join("a/b")]),
+            vec![root.to_path_buf()]
+        );
+        // Sibling directories whose names share a prefix aren't nested
+        fs::create_dir_all(root.join("ab")).unwrap();
+        assert_eq!(
+            outermost_dirs(&[root.join("a"), root.join("ab")]),
+            vec![root.join("a"), root.join("ab")]
+        );
+    }
+
+    #[test]
+    fn test_relative_path_from_roots() {
+        let roots = vec![
+            PathBuf::from("/foo"),
+            PathBuf::from("/bar"),
+            PathBuf::from("/foo/baz"),
+        ];
+        assert_eq!(
+            relative_path_from_roots(&roots, Path::new("/foo/a.txt")),
+            "./a.txt"
+        );
+        assert_eq!(
+            relative_path_from_roots(&roots, Path::new("/bar/b/c.txt")),
+            "./b/c.txt"
+        );
+        assert_eq!(
+            relative_path_from_roots(&roots, Path::new("/foo/baz/d.txt")),
+            "./d.txt"
+        );
+        assert_eq!(
+            relative_path_from_roots(&roots, Path::new("/foobar/e.txt")),
+            "/foobar/e.txt"
+        );
+    }
+
     #[test]
     fn test_vec() {
         let numbers = vec![1, 2, 2, 3, 4, 4, 4, 5];
@@ -166,7 +609,7 @@ mod tests {
 
         let result = validate_directory(dir_path);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), PathBuf::from(dir_path));
+        assert_eq!(result.unwrap(), temp_dir.path().canonicalize().unwrap());
     }
 
     #[test]
@@ -174,7 +617,9 @@ mod tests {
         let nonexistent_path = "/path/that/definitely/does/not/exist/12345";
         let result = validate_directory(nonexistent_path);
 
-        assert!(result.is_err());
+        assert!(
+            matches!(result, Err(ScooterError::InvalidDirectory(ref dir)) if dir == nonexistent_path)
+        );
         let err = result.unwrap_err().to_string();
         assert!(err.contains("does not exist"));
         assert!(err.contains(nonexistent_path));
@@ -190,7 +635,7 @@ mod tests {
         let result = validate_directory(dir_path);
 
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), nested_dir);
+        assert_eq!(result.unwrap(), nested_dir.canonicalize().unwrap());
     }
 
     #[test]
@@ -203,7 +648,7 @@ mod tests {
         let result = validate_directory(dir_path);
 
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), special_dir);
+        assert_eq!(result.unwrap(), special_dir.canonicalize().unwrap());
     }
 
     #[test]
@@ -215,4 +660,129 @@ mod tests {
         assert_eq!(first_chars(text, 8), "Hello, 世");
         assert_eq!(first_chars(text, 100), "Hello, 世界!");
     }
+
+    fn command_parts(command: &Command) -> (&std::ffi::OsStr, Vec<&std::ffi::OsStr>) {
+        (command.get_program(), command.get_args().collect())
+    }
+
+    #[test]
+    fn test_open_directory_command() {
+        let dir = Path::new("/foo/bar baz");
+        for (os, program) in [
+            ("macos", "open"),
+            ("windows", "explorer"),
+            ("linux", "xdg-open"),
+            ("freebsd", "xdg-open"),
+        ] {
+            let command = open_directory_command(os, dir).unwrap();
+            assert_eq!(
+                command_parts(&command),
+                (program.as_ref(), vec![dir.as_os_str()]),
+                "Unexpected command for {os}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_open_directory_command_unknown_platform() {
+        assert!(open_directory_command("unknown", Path::new("/foo")).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_editor_command() {
+        let paths = vec![
+            PathBuf::from("src/a.rs"),
+            PathBuf::from("dir with space/b.rs"),
+        ];
+        let command = editor_command("code --wait", &paths).unwrap();
+        assert_eq!(
+            command_parts(&command),
+            (
+                "sh".as_ref(),
+                vec![
+                    "-c".as_ref(),
+                    "code --wait \"$@\"".as_ref(),
+                    "code --wait".as_ref(),
+                    paths[0].as_os_str(),
+                    paths[1].as_os_str()
+                ]
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_editor_command_runs_through_shell() {
+        // Quoted arguments, and paths containing spaces, are each passed as a single argument
+        let paths = [PathBuf::from("a.rs"), PathBuf::from("dir with space/b.rs")];
+        let output = editor_command("printf '%s|' 'first arg'", &paths)
+            .unwrap()
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "first arg|a.rs|dir with space/b.rs|"
+        );
+    }
+
+    #[cfg(not(unix))]
+    #[test]
+    fn test_editor_command() {
+        let paths = vec![
+            PathBuf::from("src/a.rs"),
+            PathBuf::from("dir with space/b.rs"),
+        ];
+        let command = editor_command("code --wait", &paths).unwrap();
+        assert_eq!(
+            command_parts(&command),
+            (
+                "code".as_ref(),
+                vec![
+                    "--wait".as_ref(),
+                    paths[0].as_os_str(),
+                    paths[1].as_os_str()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_editor_command_blank_editor() {
+        assert!(editor_command("  ", &[PathBuf::from("a.rs")]).is_none());
+    }
+
+    #[test]
+    fn test_open_file_limiter() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+        use std::time::Duration;
+
+        let limiter = Arc::new(OpenFileLimiter::new(3));
+        let num_open = Arc::new(AtomicUsize::new(0));
+        let max_num_open = Arc::new(AtomicUsize::new(0));
+        let handles = (0..12)
+            .map(|_| {
+                let (limiter, num_open, max_num_open) = (
+                    Arc::clone(&limiter),
+                    Arc::clone(&num_open),
+                    Arc::clone(&max_num_open),
+                );
+                thread::spawn(move || {
+                    let _permit = limiter.acquire();
+                    let now_open = num_open.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_num_open.fetch_max(now_open, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    num_open.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!((1..=3).contains(&max_num_open.load(Ordering::SeqCst)));
+    }
 }
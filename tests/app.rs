@@ -1,16 +1,26 @@
+#![cfg(feature = "walker")]
+
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers};
+use regex::Regex;
+use scooter::logging::AuditRecord;
+use scooter::replace::{CounterConfig, ReplacementMap};
+use scooter::utils::validate_directory;
 use scooter::{
-    App, EventHandler, ReplaceResult, ReplaceState, Screen, SearchFields, SearchResult, SearchState,
+    App, AppEvent, BackgroundProcessingEvent, EventHandler, ReplaceResult, ReplaceState,
+    ReplaceWarning, ScooterError, Screen, SearchFields, SearchResult, SearchState,
 };
 use serial_test::serial;
 use std::cmp::max;
+use std::collections::HashMap;
 use std::fs::{self, create_dir_all};
+use std::mem;
 use std::path::{Path, PathBuf};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 use tempfile::TempDir;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
 
 #[tokio::test]
 async fn test_search_state() {
@@ -21,19 +31,21 @@ async fn test_search_state() {
                 line_number: 1,
                 line: "test line 1".to_string(),
                 replacement: "replacement 1".to_string(),
+                num_matches: 1,
                 included: true,
-                replace_result: None,
+                ..Default::default()
             },
             SearchResult {
                 path: PathBuf::from("test2.txt"),
                 line_number: 2,
                 line: "test line 2".to_string(),
                 replacement: "replacement 2".to_string(),
+                num_matches: 1,
                 included: false,
-                replace_result: None,
+                ..Default::default()
             },
         ],
-        selected: 0,
+        ..Default::default()
     };
 
     state.move_selected_down();
@@ -52,6 +64,500 @@ async fn test_search_state() {
     assert!(state.results[1].included);
 }
 
+#[tokio::test]
+async fn test_search_state_selection_with_no_results() {
+    let mut state = SearchState {
+        results: vec![],
+        selected: 3,
+        visual_anchor: Some(2),
+        ..Default::default()
+    };
+
+    state.clamp_selection();
+    assert_eq!(state.selected, 0);
+    assert_eq!(state.visual_anchor, None);
+
+    state.selected = 3;
+    state.move_selected_up();
+    assert_eq!(state.selected, 0);
+    state.selected = 3;
+    state.move_selected_down();
+    assert_eq!(state.selected, 0);
+    state.selected = 3;
+    state.toggle_selected_inclusion();
+    assert_eq!(state.selected, 0);
+}
+
+#[tokio::test]
+async fn test_search_state_selection_with_one_result() {
+    let mut state = SearchState {
+        results: vec![SearchResult {
+            path: PathBuf::from("test.txt"),
+            line_number: 1,
+            line: "foo".to_string(),
+            replacement: "bar".to_string(),
+            num_matches: 1,
+            included: true,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    state.move_selected_up();
+    assert_eq!(state.selected, 0);
+    state.move_selected_down();
+    assert_eq!(state.selected, 0);
+
+    state.selected = 1;
+    state.visual_anchor = Some(4);
+    state.clamp_selection();
+    assert_eq!(state.selected, 0);
+    assert_eq!(state.visual_anchor, Some(0));
+
+    state.selected = 1;
+    state.toggle_selected_inclusion();
+    assert_eq!(state.selected, 0);
+    assert!(!state.results[0].included);
+}
+
+#[tokio::test]
+async fn test_search_state_num_files() {
+    let search_result = |path: &str, line_number: usize| SearchResult {
+        path: PathBuf::from(path),
+        line_number,
+        line: "foo".to_string(),
+        replacement: "bar".to_string(),
+        num_matches: 1,
+        included: true,
+        ..Default::default()
+    };
+    let mut state = SearchState {
+        results: vec![],
+        ..Default::default()
+    };
+    assert_eq!(state.num_files(), 0);
+
+    state.results.push(search_result("dir/file1.txt", 1));
+    state.results.push(search_result("dir/file1.txt", 5));
+    assert_eq!(state.num_files(), 1);
+
+    state.results.push(search_result("dir/file2.txt", 2));
+    state.results.push(search_result("file3.txt", 3));
+    state.results.push(search_result("dir/file1.txt", 8));
+    assert_eq!(state.results.len(), 5);
+    assert_eq!(state.num_files(), 3);
+}
+
+#[tokio::test]
+async fn test_search_state_visible_range() {
+    let mut state = SearchState {
+        results: (1..=20)
+            .map(|line_number| SearchResult {
+                path: PathBuf::from("file.txt"),
+                line_number,
+                line: "foo".to_string(),
+                replacement: "bar".to_string(),
+                num_matches: 1,
+                included: true,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    // Near the top, the list isn't scrolled
+    assert_eq!(state.visible_range(6, 1), 0..6);
+    state.selected = 3;
+    assert_eq!(state.visible_range(6, 1), 0..6);
+
+    // In the middle, the selected result is centred
+    state.selected = 10;
+    assert_eq!(state.visible_range(6, 1), 7..13);
+    assert_eq!(state.visible_range(12, 2), 7..13);
+
+    // Near the bottom, the list is scrolled no further than the last result
+    state.selected = 18;
+    assert_eq!(state.visible_range(6, 1), 14..20);
+    state.selected = 19;
+    assert_eq!(state.visible_range(6, 1), 14..20);
+
+    // All results fit
+    assert_eq!(state.visible_range(30, 1), 0..20);
+    state.results.clear();
+    state.selected = 0;
+    assert_eq!(state.visible_range(6, 1), 0..0);
+}
+
+#[tokio::test]
+async fn test_search_state_visible_range_counts_hidden_rows() {
+    let mut state = SearchState {
+        results: ["a.txt", "b.txt", "c.txt", "d.txt"]
+            .into_iter()
+            .flat_map(|path| (1..=3).map(move |line_number| (path, line_number)))
+            .map(|(path, line_number)| SearchResult {
+                path: PathBuf::from(path),
+                line_number,
+                line: "foo".to_string(),
+                replacement: "bar".to_string(),
+                num_matches: 1,
+                included: true,
+                ..Default::default()
+            })
+            .collect(),
+        max_per_file: Some(1),
+        ..Default::default()
+    };
+    assert_eq!(state.shown_indices().collect::<Vec<_>>(), vec![0, 3, 6, 9]);
+
+    // Each result is followed by a row counting the results hidden in its file
+    assert_eq!(state.visible_range(4, 1), 0..2);
+    state.selected = 9;
+    assert_eq!(state.visible_range(4, 1), 2..4);
+    assert_eq!(state.visible_range(5, 1), 2..4);
+}
+
+#[tokio::test]
+async fn test_search_state_hide_unchanged() {
+    let mut state = SearchState {
+        results: ["bar", "foo", "foo", "bar", "foo"]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, replacement)| SearchResult {
+                path: PathBuf::from("file.txt"),
+                line_number: idx + 1,
+                line: "foo".to_string(),
+                replacement: replacement.to_string(),
+                num_matches: 1,
+                included: true,
+                ..Default::default()
+            })
+            .collect(),
+        selected: 1,
+        ..Default::default()
+    };
+    assert_eq!(
+        state.shown_indices().collect::<Vec<_>>(),
+        vec![0, 1, 2, 3, 4]
+    );
+
+    // The selected result is hidden, so the next shown result is selected
+    state.toggle_hide_unchanged();
+    assert_eq!(state.shown_indices().collect::<Vec<_>>(), vec![0, 3]);
+    assert_eq!(state.selected, 3);
+    assert_eq!(state.visible_range(6, 1), 0..2);
+
+    // Navigation skips hidden results
+    state.move_selected_down();
+    assert_eq!(state.selected, 0);
+    state.move_selected_down();
+    assert_eq!(state.selected, 3);
+    state.move_selected_up();
+    assert_eq!(state.selected, 0);
+    state.move_selected_up();
+    assert_eq!(state.selected, 3);
+
+    // Hidden results are unaffected, and are still included
+    state.toggle_selected_inclusion();
+    assert_eq!(
+        state
+            .results
+            .iter()
+            .map(|res| res.included)
+            .collect::<Vec<_>>(),
+        vec![true, true, true, false, true]
+    );
+
+    state.toggle_hide_unchanged();
+    assert_eq!(
+        state.shown_indices().collect::<Vec<_>>(),
+        vec![0, 1, 2, 3, 4]
+    );
+    assert_eq!(state.selected, 3);
+    state.move_selected_down();
+    assert_eq!(state.selected, 4);
+}
+
+#[tokio::test]
+async fn test_search_state_hide_unchanged_when_all_unchanged() {
+    let mut state = SearchState {
+        results: (1..=3)
+            .map(|line_number| SearchResult {
+                path: PathBuf::from("file.txt"),
+                line_number,
+                line: "foo".to_string(),
+                replacement: "foo".to_string(),
+                num_matches: 1,
+                included: true,
+                ..Default::default()
+            })
+            .collect(),
+        selected: 5,
+        ..Default::default()
+    };
+
+    state.toggle_hide_unchanged();
+    assert_eq!(state.shown_indices().count(), 0);
+    assert_eq!(state.selected, 2);
+    assert_eq!(state.visible_range(6, 1), 0..0);
+    state.move_selected_down();
+    state.move_to_next_file();
+    state.move_to_prev_file();
+    assert_eq!(state.selected, 2);
+
+    state.toggle_hide_unchanged();
+    assert_eq!(state.selected, 2);
+    state.move_selected_down();
+    assert_eq!(state.selected, 0);
+}
+
+#[tokio::test]
+async fn test_search_state_toggle_to_end_and_start() {
+    let mut state = SearchState {
+        results: (1..=5)
+            .map(|line_number| SearchResult {
+                path: PathBuf::from("file.txt"),
+                line_number,
+                line: "foo".to_string(),
+                replacement: "bar".to_string(),
+                num_matches: 1,
+                included: true,
+                ..Default::default()
+            })
+            .collect(),
+        selected: 2,
+        ..Default::default()
+    };
+    let included = |state: &SearchState| {
+        state
+            .results
+            .iter()
+            .map(|res| res.included)
+            .collect::<Vec<_>>()
+    };
+
+    state.toggle_to_end();
+    assert_eq!(included(&state), vec![true, true, false, false, false]);
+
+    // Not all results in the range are included, so all become included
+    state.toggle_to_start();
+    assert_eq!(included(&state), vec![true, true, true, false, false]);
+    state.selected = 3;
+    state.toggle_to_end();
+    assert_eq!(included(&state), vec![true, true, true, true, true]);
+    state.selected = 1;
+    state.toggle_to_start();
+    assert_eq!(included(&state), vec![false, false, true, true, true]);
+    state.toggle_to_start();
+    assert_eq!(included(&state), vec![true, true, true, true, true]);
+
+    // The ranges include the first and last results
+    state.selected = 0;
+    state.toggle_to_start();
+    assert_eq!(included(&state), vec![false, true, true, true, true]);
+    state.selected = 4;
+    state.toggle_to_end();
+    assert_eq!(included(&state), vec![false, true, true, true, false]);
+
+    state.results.clear();
+    state.toggle_to_end();
+    state.toggle_to_start();
+}
+
+#[tokio::test]
+async fn test_search_state_max_per_file() {
+    let mut state = SearchState {
+        results: ["a.txt", "a.txt", "a.txt", "a.txt", "b.txt", "b.txt"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| SearchResult {
+                path: PathBuf::from(path),
+                line_number: i + 1,
+                line: "foo".to_string(),
+                replacement: "bar".to_string(),
+                num_matches: 1,
+                included: true,
+                ..Default::default()
+            })
+            .collect(),
+        max_per_file: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(state.shown_indices().collect::<Vec<_>>(), vec![0, 1, 4, 5]);
+    assert_eq!(state.num_hidden_per_file(), HashMap::from([(1, 2)]));
+
+    // Expanding a file without hidden results changes nothing
+    state.selected = 4;
+    state.toggle_selected_file_expanded();
+    assert_eq!(state.shown_indices().collect::<Vec<_>>(), vec![0, 1, 4, 5]);
+    state.toggle_selected_file_expanded();
+
+    state.selected = 1;
+    state.toggle_selected_file_expanded();
+    assert_eq!(
+        state.shown_indices().collect::<Vec<_>>(),
+        vec![0, 1, 2, 3, 4, 5]
+    );
+    assert!(state.num_hidden_per_file().is_empty());
+
+    // Collapsing the file again moves the selection off the results that are hidden
+    state.selected = 3;
+    state.toggle_selected_file_expanded();
+    assert_eq!(state.shown_indices().collect::<Vec<_>>(), vec![0, 1, 4, 5]);
+    assert_eq!(state.selected, 1);
+
+    // Unchanged results don't count towards the limit when they're hidden
+    state.results[0].replacement = "foo".to_string();
+    state.toggle_hide_unchanged();
+    assert_eq!(state.shown_indices().collect::<Vec<_>>(), vec![1, 2, 4, 5]);
+    assert_eq!(state.num_hidden_per_file(), HashMap::from([(2, 1)]));
+}
+
+#[tokio::test]
+async fn test_search_state_move_between_files() {
+    let mut state = SearchState {
+        results: ["a.txt", "a.txt", "b.txt", "c.txt", "c.txt"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| SearchResult {
+                path: PathBuf::from(path),
+                line_number: i + 1,
+                line: "foo".to_string(),
+                replacement: "bar".to_string(),
+                num_matches: 1,
+                included: true,
+                ..Default::default()
+            })
+            .collect(),
+        selected: 1,
+        ..Default::default()
+    };
+
+    state.move_to_next_file();
+    assert_eq!(state.selected, 2);
+    state.move_to_next_file();
+    assert_eq!(state.selected, 3);
+    state.move_to_next_file();
+    assert_eq!(state.selected, 0);
+
+    state.move_to_prev_file();
+    assert_eq!(state.selected, 3);
+    state.selected = 4;
+    state.move_to_prev_file();
+    assert_eq!(state.selected, 2);
+    state.move_to_prev_file();
+    assert_eq!(state.selected, 0);
+
+    // Hidden results are skipped
+    state.results[2].replacement = "foo".to_string();
+    state.toggle_hide_unchanged();
+    state.move_to_next_file();
+    assert_eq!(state.selected, 3);
+    state.move_to_prev_file();
+    assert_eq!(state.selected, 0);
+
+    state.results.clear();
+    state.move_to_next_file();
+    state.move_to_prev_file();
+}
+
+#[tokio::test]
+async fn test_search_state_move_between_interleaved_files() {
+    // Results from files searched in parallel arrive interleaved
+    let mut state = SearchState {
+        results: [
+            ("b.txt", 1),
+            ("a.txt", 3),
+            ("b.txt", 2),
+            ("a.txt", 1),
+            ("c.txt", 1),
+        ]
+        .into_iter()
+        .map(|(path, line_number)| SearchResult {
+            path: PathBuf::from(path),
+            line_number,
+            line: "foo".to_string(),
+            replacement: "bar".to_string(),
+            num_matches: 1,
+            included: true,
+            ..Default::default()
+        })
+        .collect(),
+        selected: 2,
+        visual_anchor: Some(1),
+        ..Default::default()
+    };
+
+    state.sort_by_file();
+    let order = state
+        .results
+        .iter()
+        .map(|res| (res.path.to_str().unwrap(), res.line_number))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        order,
+        vec![
+            ("a.txt", 1),
+            ("a.txt", 3),
+            ("b.txt", 1),
+            ("b.txt", 2),
+            ("c.txt", 1)
+        ]
+    );
+    // The same results stay selected and anchoring the visual range
+    assert_eq!(state.selected, 3);
+    assert_eq!(state.visual_anchor, Some(1));
+
+    // Each file is visited once
+    state.selected = 0;
+    let mut visited = vec![];
+    for _ in 0..3 {
+        state.move_to_next_file();
+        visited.push(state.results[state.selected].path.clone());
+    }
+    assert_eq!(
+        visited,
+        vec![
+            PathBuf::from("b.txt"),
+            PathBuf::from("c.txt"),
+            PathBuf::from("a.txt")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_search_state_num_included() {
+    let mut state = SearchState {
+        results: (1..=4)
+            .map(|line_number| SearchResult {
+                path: PathBuf::from("file.txt"),
+                line_number,
+                line: "foo".to_string(),
+                replacement: "bar".to_string(),
+                num_matches: 1,
+                included: true,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    assert_eq!(state.num_included(), 4);
+
+    state.toggle_selected_inclusion();
+    assert_eq!(state.num_included(), 3);
+    state.move_selected_down();
+    state.toggle_selected_inclusion();
+    assert_eq!(state.num_included(), 2);
+
+    // Not all results are included, so all become included
+    state.toggle_all_selected();
+    assert_eq!(state.num_included(), 4);
+    state.toggle_all_selected();
+    assert_eq!(state.num_included(), 0);
+
+    state.toggle_selected_inclusion();
+    assert_eq!(state.num_included(), 1);
+}
+
 #[tokio::test]
 async fn test_replace_state() {
     let mut state = ReplaceState {
@@ -63,11 +569,19 @@ async fn test_replace_state() {
                 line_number: 1,
                 line: format!("line {}", n),
                 replacement: format!("error replacement {}", n),
+                num_matches: 1,
                 included: true,
                 replace_result: Some(ReplaceResult::Error(format!("Test error {}", n))),
+                ..Default::default()
             })
             .collect::<Vec<_>>(),
         replacement_errors_pos: 0,
+        successes: vec![],
+        successes_pos: 0,
+        show_successes: false,
+        num_remaining_matches: None,
+        expanded_context: HashMap::new(),
+        success_context: HashMap::new(),
     };
 
     state.scroll_replacement_errors_down();
@@ -80,15 +594,46 @@ async fn test_replace_state() {
     assert_eq!(state.replacement_errors_pos, 0);
 }
 
+#[tokio::test]
+async fn test_app_accessors() {
+    let events = EventHandler::new();
+    let app = App::new(
+        vec![PathBuf::from("/some/dir"), PathBuf::from("other")],
+        true,
+        true,
+        events.app_event_sender.clone(),
+    );
+    assert_eq!(app.directory(), Path::new("/some/dir"));
+    assert_eq!(
+        app.directories(),
+        [PathBuf::from("/some/dir"), PathBuf::from("other")]
+    );
+    assert!(app.include_hidden());
+    assert!(app.advanced_regex());
+
+    // The current directory is searched if no directories are given
+    let app = App::new(vec![], false, false, events.app_event_sender);
+    assert_eq!(app.directory(), std::env::current_dir().unwrap());
+    assert_eq!(app.directories().len(), 1);
+    assert!(!app.include_hidden());
+    assert!(!app.advanced_regex());
+}
+
 #[tokio::test]
 async fn test_app_reset() {
     let events = EventHandler::new();
-    let mut app = App::new(None, false, false, events.app_event_sender);
+    let mut app = App::new(vec![], false, false, events.app_event_sender);
     app.current_screen = Screen::Results(ReplaceState {
         num_successes: 5,
         num_ignored: 2,
         errors: vec![],
         replacement_errors_pos: 0,
+        successes: vec![],
+        successes_pos: 0,
+        show_successes: false,
+        num_remaining_matches: None,
+        expanded_context: HashMap::new(),
+        success_context: HashMap::new(),
     });
 
     app.reset();
@@ -96,13 +641,68 @@ async fn test_app_reset() {
     assert!(matches!(app.current_screen, Screen::SearchFields));
 }
 
+#[tokio::test]
+async fn test_app_reset_keeps_config() {
+    let temp_dir = TempDir::new().unwrap();
+    let events = EventHandler::new();
+    let mut app = App::new(
+        vec![temp_dir.path().to_path_buf()],
+        true,
+        true,
+        events.app_event_sender,
+    )
+    .with_include_ignored(true)
+    .with_lossy(true)
+    .with_repeat(true)
+    .with_git_modified_only(true)
+    .with_follow_symlinks(true)
+    .with_changed_within(Some(Duration::from_secs(60)));
+    app.search_fields = SearchFields::with_values("foo", "bar", false, "")
+        .with_advanced_regex(true)
+        .with_path_fuzzy(true);
+    let config = app.resolved_config();
+
+    app.reset();
+
+    assert_eq!(app.search_fields.search().text(), "");
+    assert_eq!(app.resolved_config(), config);
+}
+
+#[tokio::test]
+async fn test_search_and_replace_read_only_with_map() {
+    let events = EventHandler::new();
+    let map = ReplacementMap::new([
+        ("foo".to_owned(), "bar".to_owned()),
+        ("baz".to_owned(), "qux".to_owned()),
+    ]);
+    let mut app = App::new(vec![], false, false, events.app_event_sender)
+        .with_replacement_map(Some(map.clone()));
+    app.search_fields = SearchFields::with_values(map.search_text(), "", true, "");
+
+    for _ in 0..3 {
+        app.handle_key_events(&KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE))
+            .unwrap();
+        app.handle_key_events(&KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE))
+            .unwrap();
+        app.search_fields.focus_next();
+    }
+    assert_eq!(app.search_fields.search().text(), "baz\nfoo");
+    assert_eq!(app.search_fields.replace().text(), "");
+    assert!(app.search_fields.fixed_strings().checked);
+
+    // The map's strings are still searched for after resetting
+    app.reset();
+    assert_eq!(app.search_fields.search().text(), "baz\nfoo");
+    assert!(app.search_fields.fixed_strings().checked);
+}
+
 #[tokio::test]
 async fn test_back_from_results() {
     let events = EventHandler::new();
-    let mut app = App::new(None, false, false, events.app_event_sender);
+    let mut app = App::new(vec![], false, false, events.app_event_sender);
     app.current_screen = Screen::SearchComplete(SearchState {
         results: vec![],
-        selected: 0,
+        ..Default::default()
     });
     app.search_fields = SearchFields::with_values("foo", "bar", true, "pattern");
 
@@ -126,7 +726,7 @@ async fn test_back_from_results() {
 #[tokio::test]
 async fn test_error_popup() {
     let events = EventHandler::new();
-    let mut app = App::new(None, false, false, events.app_event_sender.clone());
+    let mut app = App::new(vec![], false, false, events.app_event_sender.clone());
     app.current_screen = Screen::SearchFields;
     app.search_fields =
         SearchFields::with_values("search invalid regex(", "replacement", false, "");
@@ -136,6 +736,12 @@ async fn test_error_popup() {
     assert!(matches!(app.current_screen, Screen::SearchFields));
     assert!(app.search_fields.show_error_popup);
 
+    // Scrolling keeps the popup open
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('j'), KeyModifiers::NONE))
+        .unwrap();
+    assert!(app.search_fields.show_error_popup);
+    assert_eq!(app.search_fields.error_popup_scroll, 1);
+
     let res = app
         .handle_key_events(&KeyEvent {
             code: KeyCode::Esc,
@@ -146,6 +752,7 @@ async fn test_error_popup() {
         .unwrap();
     assert!(!res.exit);
     assert!(!app.search_fields.show_error_popup);
+    assert_eq!(app.search_fields.error_popup_scroll, 0);
 
     let res = app
         .handle_key_events(&KeyEvent {
@@ -158,6 +765,201 @@ async fn test_error_popup() {
     assert!(res.exit);
 }
 
+#[tokio::test]
+async fn test_search_literally_only_offered_for_invalid_regex() {
+    let search_fields = SearchFields::with_values("foo(", "", true, "");
+    assert!(!search_fields.can_search_literally());
+    let search_fields = SearchFields::with_values("foo(?=bar)", "", false, "");
+    assert!(search_fields.can_search_literally());
+    let search_fields = search_fields.with_advanced_regex(true);
+    assert!(!search_fields.can_search_literally());
+    let search_fields = SearchFields::with_values("foo", "", false, "");
+    assert!(!search_fields.can_search_literally());
+}
+
+#[tokio::test]
+async fn test_validate_fields_while_typing() {
+    let events = EventHandler::new();
+    let mut app = App::new(vec![], false, false, events.app_event_sender.clone());
+    app.current_screen = Screen::SearchFields;
+
+    for c in "foo(".chars() {
+        app.handle_key_events(&KeyEvent {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::NONE,
+            kind: KeyEventKind::Press,
+            state: KeyEventState::NONE,
+        })
+        .unwrap();
+    }
+    assert!(app.search_fields.search().error.is_none());
+
+    app.handle_app_event(AppEvent::ValidateFields).await;
+    assert!(app.search_fields.search().error.is_some());
+    assert!(!app.search_fields.show_error_popup);
+
+    app.handle_key_events(&KeyEvent {
+        code: KeyCode::Char(')'),
+        modifiers: KeyModifiers::NONE,
+        kind: KeyEventKind::Press,
+        state: KeyEventState::NONE,
+    })
+    .unwrap();
+    app.handle_app_event(AppEvent::ValidateFields).await;
+    assert!(app.search_fields.search().error.is_none());
+}
+
+#[tokio::test]
+async fn test_refuses_short_fixed_strings() {
+    let events = EventHandler::new();
+    let too_short = |search: &str, fixed_strings: bool, min_fixed_len: Option<usize>| {
+        let mut app = App::new(vec![], false, false, events.app_event_sender.clone());
+        if let Some(min_fixed_len) = min_fixed_len {
+            app = app.with_min_fixed_len(min_fixed_len);
+        }
+        app.search_fields = SearchFields::with_values(search, "", fixed_strings, "");
+        app.parsed_fields().is_none()
+            && app
+                .search_fields
+                .search()
+                .error
+                .as_ref()
+                .is_some_and(|error| error.short == "Search is too short")
+    };
+
+    // By default only empty fixed strings are refused
+    assert!(too_short("", true, None));
+    assert!(!too_short("a", true, None));
+    assert!(too_short("\n", true, None));
+    // Empty lines aren't searched for, so don't make the search too short
+    assert!(!too_short("foo\n", true, None));
+    assert!(!too_short("foo\n\nbar\n", true, None));
+    // Regexes aren't affected
+    assert!(!too_short("", false, None));
+
+    assert!(too_short("ab", true, Some(3)));
+    assert!(!too_short("abc", true, Some(3)));
+    // Characters are counted rather than bytes
+    assert!(!too_short("äöü", true, Some(3)));
+    assert!(too_short("abc\nde", true, Some(3)));
+    assert!(!too_short("abc\n\ndef\n", true, Some(3)));
+    assert!(!too_short("", true, Some(0)));
+
+    let mut app =
+        App::new(vec![], false, false, events.app_event_sender.clone()).with_min_fixed_len(3);
+    app.search_fields = SearchFields::with_values("ab", "", true, "");
+    assert!(app.parsed_fields().is_none());
+    let error = app.search_fields.search().error.clone().unwrap();
+    assert!(error
+        .long
+        .starts_with("Fixed search strings must be at least 3 characters long"));
+    assert!(error.long.contains("--min-fixed-length"));
+
+    // The minimum is kept when the fields are reset
+    app.reset();
+    app.search_fields = SearchFields::with_values("ab", "", true, "");
+    assert!(app.parsed_fields().is_none());
+}
+
+#[tokio::test]
+async fn test_toggle_fixed_strings_updates_errors() {
+    let events = EventHandler::new();
+    let mut app = App::new(vec![], false, false, events.app_event_sender.clone());
+    app.search_fields = SearchFields::with_values("foo(", "", false, "[a-");
+    app.search_fields.validate_patterns();
+    assert!(app.search_fields.search().error.is_some());
+    assert!(app.search_fields.path_pattern().error.is_some());
+
+    let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+    app.handle_key_events(&key(KeyCode::Tab)).unwrap();
+    app.handle_key_events(&key(KeyCode::Tab)).unwrap();
+
+    // Checking the box: both patterns are now fixed strings, so are valid
+    app.handle_key_events(&key(KeyCode::Char(' '))).unwrap();
+    assert!(app.search_fields.fixed_strings().checked);
+    assert!(app.search_fields.search().error.is_none());
+    assert!(app.search_fields.path_pattern().error.is_none());
+
+    // Unchecking the box: the regex errors are shown again
+    app.handle_key_events(&key(KeyCode::Char(' '))).unwrap();
+    assert!(!app.search_fields.fixed_strings().checked);
+    assert!(app.search_fields.search().error.is_some());
+    assert!(app.search_fields.path_pattern().error.is_some());
+    assert!(!app.search_fields.show_error_popup);
+}
+
+#[tokio::test]
+async fn test_toggle_fixed_strings_keeps_valid_regex_error_free() {
+    let events = EventHandler::new();
+    let mut app = App::new(vec![], false, false, events.app_event_sender.clone());
+    app.search_fields = SearchFields::with_values("foo(bar)", "", true, "");
+    app.search_fields.highlighted = 2;
+
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE))
+        .unwrap();
+    assert!(!app.search_fields.fixed_strings().checked);
+    assert!(app.search_fields.search().error.is_none());
+}
+
+#[tokio::test]
+async fn test_alt_enter_inserts_newline_rather_than_searching() {
+    let (app_event_sender, mut app_event_receiver) = mpsc::unbounded_channel();
+    let mut app = App::new(vec![], false, false, app_event_sender);
+    app.search_fields = SearchFields::with_values("foo", "", false, "");
+    app.search_fields.search_mut().move_cursor_end();
+
+    app.handle_key_events(&KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT))
+        .unwrap();
+    assert_eq!(app.search_fields.search().text(), "foo\n");
+    assert!(app_event_receiver.try_recv().is_err());
+
+    app.handle_key_events(&KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE))
+        .unwrap();
+    assert!(matches!(
+        app_event_receiver.try_recv(),
+        Ok(AppEvent::PerformSearch)
+    ));
+}
+
+#[tokio::test]
+async fn test_toggle_advanced_regex_key() {
+    let events = EventHandler::new();
+    let mut app = App::new(vec![], false, false, events.app_event_sender.clone());
+    app.search_fields = SearchFields::with_values("foo(?!bar)", "", false, "");
+
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('r'), KeyModifiers::ALT))
+        .unwrap();
+    assert!(app.search_fields.advanced_regex());
+    assert_eq!(app.search_fields.search().text(), "foo(?!bar)");
+    assert!(app.search_fields.search().error.is_none());
+}
+
+#[tokio::test]
+async fn test_restore_last_search_after_reset() {
+    let events = EventHandler::new();
+    let mut app = App::new(vec![], false, false, events.app_event_sender.clone());
+    app.search_fields = SearchFields::with_values("foo", "bar", true, "src/");
+    assert!(!app.can_restore_last_search());
+
+    let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+    app.handle_key_events(&ctrl('r')).unwrap();
+    assert!(app.search_fields.is_empty());
+    assert!(app.can_restore_last_search());
+
+    // Resetting the empty fields keeps the previous search
+    app.handle_key_events(&ctrl('r')).unwrap();
+    app.handle_key_events(&ctrl('z')).unwrap();
+    assert_eq!(app.search_fields.search().text(), "foo");
+    assert_eq!(app.search_fields.replace().text(), "bar");
+    assert!(app.search_fields.fixed_strings().checked);
+    assert_eq!(app.search_fields.path_pattern().text(), "src/");
+    assert!(!app.can_restore_last_search());
+
+    // Nothing to restore, so the fields are unchanged
+    app.handle_key_events(&ctrl('z')).unwrap();
+    assert_eq!(app.search_fields.search().text(), "foo");
+}
+
 macro_rules! create_test_files {
     ($($name:expr => {$($line:expr),+ $(,)?}),+ $(,)?) => {
         {
@@ -279,7 +1081,7 @@ macro_rules! wait_for_screen {
 fn setup_app(temp_dir: &TempDir, search_fields: SearchFields, include_hidden: bool) -> App {
     let events = EventHandler::new();
     let mut app = App::new(
-        Some(temp_dir.path().to_path_buf()),
+        vec![temp_dir.path().to_path_buf()],
         include_hidden,
         false,
         events.app_event_sender,
@@ -288,6 +1090,34 @@ fn setup_app(temp_dir: &TempDir, search_fields: SearchFields, include_hidden: bo
     app
 }
 
+async fn search_complete(app: &mut App) -> &mut SearchState {
+    app.perform_search_if_valid();
+    process_bp_events(app).await;
+    assert!(wait_for_screen!(app, Screen::SearchComplete));
+    match &mut app.current_screen {
+        Screen::SearchComplete(search_state) => {
+            search_state.results.sort_by_key(|res| res.path.clone());
+            search_state
+        }
+        screen => panic!("Expected SearchComplete, found {:?}", screen),
+    }
+}
+
+async fn replace_complete(app: &mut App) -> &mut ReplaceState {
+    app.trigger_replacement();
+    process_bp_events(app).await;
+    assert!(wait_for_screen!(app, Screen::Results));
+    match &mut app.current_screen {
+        Screen::Results(replace_state) => replace_state,
+        screen => panic!("Expected Results, found {:?}", screen),
+    }
+}
+
+async fn search_and_replace(app: &mut App) -> &mut ReplaceState {
+    search_complete(app).await;
+    replace_complete(app).await
+}
+
 // TODO: simplify this test - it is somewhat tied to the current implementation
 async fn search_and_replace_test(
     temp_dir: &TempDir,
@@ -301,52 +1131,28 @@ async fn search_and_replace_test(
         .sum::<usize>();
 
     let mut app = setup_app(temp_dir, search_fields, include_hidden);
-    let res = app.perform_search_if_valid();
-    assert!(!res.exit);
+    let search_state = search_complete(&mut app).await;
+    for (file_path, num_matches) in &expected_matches {
+        assert_eq!(
+            search_state
+                .results
+                .iter()
+                .filter(|result| {
+                    let result_path = result.path.to_str().unwrap();
+                    let file_path = file_path.to_str().unwrap();
+                    result_path.contains(file_path)
+                })
+                .count(),
+            *num_matches
+        );
+    }
+    assert_eq!(search_state.results.len(), num_expected_matches);
 
-    process_bp_events(&mut app).await;
-    assert!(wait_for_screen!(&app, Screen::SearchComplete));
-
-    if let Screen::SearchComplete(search_state) = &mut app.current_screen {
-        for (file_path, num_matches) in &expected_matches {
-            assert_eq!(
-                search_state
-                    .results
-                    .iter()
-                    .filter(|result| {
-                        let result_path = result.path.to_str().unwrap();
-                        let file_path = file_path.to_str().unwrap();
-                        result_path.contains(file_path)
-                    })
-                    .count(),
-                *num_matches
-            );
-        }
-
-        assert_eq!(search_state.results.len(), num_expected_matches);
-    } else {
-        panic!(
-            "Expected SearchComplete results, found {:?}",
-            app.current_screen
-        );
-    };
-
-    app.trigger_replacement();
-
-    process_bp_events(&mut app).await;
-    assert!(wait_for_screen!(&app, Screen::Results));
-
-    if let Screen::Results(search_state) = &app.current_screen {
-        assert_eq!(search_state.num_successes, num_expected_matches);
-        assert_eq!(search_state.num_ignored, 0);
-        assert_eq!(search_state.errors.len(), 0);
-    } else {
-        panic!(
-            "Expected screen to be Screen::Results, instead found {:?}",
-            app.current_screen
-        );
-    }
-}
+    let replace_state = replace_complete(&mut app).await;
+    assert_eq!(replace_state.num_successes, num_expected_matches);
+    assert_eq!(replace_state.num_ignored, 0);
+    assert_eq!(replace_state.errors.len(), 0);
+}
 
 macro_rules! test_with_both_regex_modes {
     ($name:ident, $test_fn:expr) => {
@@ -812,8 +1618,2411 @@ test_with_both_regex_modes!(
     }
 );
 
-// TODO:
-// - Add:
-//   - more tests for replacing in files
-//   - tests for passing in directory via CLI arg
-// - Tidy up tests - lots of duplication
+test_with_both_regex_modes!(
+    test_includes_hidden_and_ignored_files_with_all,
+    |advanced_regex: bool| async move {
+        let temp_dir = &create_test_files! {
+            "dir1/file1.txt" => {
+                "This is a text file",
+            },
+            ".file2.txt" => {
+                "This is a hidden text file",
+            },
+            "ignored/file3.txt" => {
+                "This is an ignored text file",
+            },
+            ".gitignore" => {
+                "ignored/",
+            }
+        };
+        create_dir_all(temp_dir.path().join(".git")).unwrap();
+
+        let search_fields = SearchFields::with_values(r"\bis\b", "REPLACED", false, "")
+            .with_advanced_regex(advanced_regex);
+
+        let mut app = setup_app(temp_dir, search_fields, true).with_include_ignored(true);
+        app.perform_search_if_valid();
+        process_bp_events(&mut app).await;
+        assert!(wait_for_screen!(&app, Screen::SearchComplete));
+
+        if let Screen::SearchComplete(search_state) = &app.current_screen {
+            let mut paths = search_state
+                .results
+                .iter()
+                .map(|res| app.relative_path(&res.path).replace('\\', "/"))
+                .collect::<Vec<_>>();
+            paths.sort();
+            assert_eq!(
+                paths,
+                vec!["./.file2.txt", "./dir1/file1.txt", "./ignored/file3.txt"]
+            );
+        } else {
+            panic!(
+                "Expected SearchComplete results, found {:?}",
+                app.current_screen
+            );
+        }
+    }
+);
+
+test_with_both_regex_modes!(
+    test_ignores_gitignored_files_by_default,
+    |advanced_regex: bool| async move {
+        let temp_dir = &create_test_files! {
+            "dir1/file1.txt" => {
+                "This is a text file",
+            },
+            "ignored/file3.txt" => {
+                "This is an ignored text file",
+            },
+            ".gitignore" => {
+                "ignored/",
+            }
+        };
+        create_dir_all(temp_dir.path().join(".git")).unwrap();
+
+        let search_fields = SearchFields::with_values(r"\bis\b", "REPLACED", false, "")
+            .with_advanced_regex(advanced_regex);
+        search_and_replace_test(
+            temp_dir,
+            search_fields,
+            true,
+            vec![
+                (&Path::new("dir1").join("file1.txt"), 1),
+                (&Path::new("ignored").join("file3.txt"), 0),
+            ],
+        )
+        .await;
+    }
+);
+
+test_with_both_regex_modes!(
+    test_replace_with_counter_single_file,
+    |advanced_regex: bool| async move {
+        let temp_dir = &create_test_files! {
+            "file1.txt" => {
+                "ITEM",
+                "no match",
+                "ITEM and ITEM",
+                "ITEM",
+            }
+        };
+
+        let search_fields = SearchFields::with_values("ITEM", "item_${n}", false, "")
+            .with_advanced_regex(advanced_regex);
+        search_and_replace_test(
+            temp_dir,
+            search_fields,
+            false,
+            vec![(Path::new("file1.txt"), 3)],
+        )
+        .await;
+
+        assert_test_files! {
+            temp_dir,
+            "file1.txt" => {
+                "item_0",
+                "no match",
+                "item_1 and item_2",
+                "item_3",
+            }
+        };
+    }
+);
+
+async fn search_and_replace_with_counter(temp_dir: &TempDir, counter: CounterConfig) {
+    let search_fields = SearchFields::with_values("ITEM", "item_${n}", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_counter(counter);
+    search_and_replace(&mut app).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replace_with_counter_across_files() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "ITEM",
+            "ITEM",
+        },
+        "dir/file2.txt" => {
+            "ITEM",
+            "ITEM",
+        }
+    };
+
+    search_and_replace_with_counter(temp_dir, CounterConfig::default()).await;
+
+    // Files are searched in parallel, so the order in which results are numbered isn't fixed
+    let mut all_numbers = vec![];
+    for file in ["file1.txt", "dir/file2.txt"] {
+        let numbers = fs::read_to_string(temp_dir.path().join(file))
+            .unwrap()
+            .lines()
+            .map(|line| line.strip_prefix("item_").unwrap().parse().unwrap())
+            .collect::<Vec<usize>>();
+        assert!(numbers.is_sorted(), "{file} not numbered in order");
+        all_numbers.extend(numbers);
+    }
+    all_numbers.sort();
+    assert_eq!(all_numbers, vec![0, 1, 2, 3]);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replace_with_counter_per_file() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "ITEM",
+            "ITEM",
+        },
+        "dir/file2.txt" => {
+            "ITEM",
+            "ITEM",
+            "ITEM",
+        }
+    };
+
+    search_and_replace_with_counter(
+        temp_dir,
+        CounterConfig {
+            start: 1,
+            step: 2,
+            per_file: true,
+        },
+    )
+    .await;
+
+    assert_test_files! {
+        temp_dir,
+        "file1.txt" => {
+            "item_1",
+            "item_3",
+        },
+        "dir/file2.txt" => {
+            "item_1",
+            "item_3",
+            "item_5",
+        }
+    };
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn test_replace_in_hard_linked_files() {
+    use std::os::unix::fs::MetadataExt;
+
+    let temp_dir = &create_test_files! {
+        "a.txt" => {
+            "foo",
+            "baz",
+            "foo",
+        }
+    };
+    let (path_a, path_b) = (temp_dir.path().join("a.txt"), temp_dir.path().join("b.txt"));
+    fs::hard_link(&path_a, &path_b).unwrap();
+    let inode = fs::metadata(&path_a).unwrap().ino();
+
+    let search_fields = SearchFields::with_values("foo", "bar", true, "");
+    search_and_replace_test(
+        temp_dir,
+        search_fields,
+        false,
+        vec![(Path::new("a.txt"), 2), (Path::new("b.txt"), 2)],
+    )
+    .await;
+
+    // The file is replaced in once, and both paths still link to it
+    for path in [&path_a, &path_b] {
+        assert_eq!(fs::read_to_string(path).unwrap(), "bar\nbaz\nbar\n");
+        assert_eq!(fs::metadata(path).unwrap().ino(), inode);
+    }
+    assert!(!temp_dir.path().join("a.tmp").exists());
+    assert!(!temp_dir.path().join("b.tmp").exists());
+}
+
+async fn search_and_replace_occurrence(temp_dir: &TempDir, occurrence: usize, per_file: bool) {
+    let search_fields = SearchFields::with_values("foo", "bar", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false)
+        .with_occurrence(Some(occurrence))
+        .with_occurrence_per_file(per_file);
+    search_and_replace(&mut app).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_search_literally_from_error_popup() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "call(foo",
+            "call foo",
+        },
+        "file2.txt" => {
+            "[call(foo]",
+        }
+    };
+
+    let search_fields = SearchFields::with_values("call(foo", "run(bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    app.perform_search_if_valid();
+    assert!(matches!(app.current_screen, Screen::SearchFields));
+    assert!(app.search_fields.show_error_popup);
+    assert!(app.search_fields.can_search_literally());
+    // The hint is only part of the popup, as the error itself is also shown outside of the TUI
+    let hint = "To search for the text literally instead, press <l>";
+    let error = app.search_fields.search().error.clone().unwrap();
+    assert!(!error.long.contains(hint));
+    assert!(app
+        .search_fields
+        .error_popup_lines()
+        .contains(&(hint.to_owned(), false)));
+
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('l'), KeyModifiers::NONE))
+        .unwrap();
+    assert!(!app.search_fields.show_error_popup);
+    assert_eq!(app.search_fields.search().text, r"call\(foo");
+    assert!(app.search_fields.search().error.is_none());
+
+    search_and_replace(&mut app).await;
+
+    assert_test_files! {
+        temp_dir,
+        "file1.txt" => {
+            "run(bar",
+            "call foo",
+        },
+        "file2.txt" => {
+            "[run(bar]",
+        }
+    };
+}
+
+#[tokio::test]
+#[serial]
+async fn test_audit_log_records_modified_files() {
+    let temp_dir = &create_test_files! {
+        "a.txt" => {
+            "foo foo",
+            "bar",
+            "foo",
+        },
+        "b.txt" => {
+            "foo",
+        },
+        "c.txt" => {
+            "bar",
+        }
+    };
+    // The file is only replaced in once, so its matches are only counted once
+    #[cfg(unix)]
+    fs::hard_link(temp_dir.path().join("a.txt"), temp_dir.path().join("d.txt")).unwrap();
+    let audit_dir = TempDir::new().unwrap();
+    let audit_file = audit_dir.path().join("audit.jsonl");
+
+    let search_fields = SearchFields::with_values("foo", "baz", true, "");
+    let mut app =
+        setup_app(temp_dir, search_fields, false).with_audit_file(Some(audit_file.clone()));
+    search_and_replace(&mut app).await;
+
+    let mut records = fs::read_to_string(&audit_file)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<AuditRecord>(line).unwrap())
+        .collect::<Vec<_>>();
+    records.sort_by_key(|record| record.path.clone());
+    assert_eq!(
+        records
+            .iter()
+            .map(|record| (
+                record.path.file_name().unwrap().to_str().unwrap(),
+                record.replacements
+            ))
+            .collect::<Vec<_>>(),
+        vec![("a.txt", 3), ("b.txt", 1)]
+    );
+    assert!(records.iter().all(|record| record.timestamp > 0));
+}
+
+async fn search_and_replace_normalizing_whitespace(
+    temp_dir: &TempDir,
+    search_fields: SearchFields,
+    collapse: bool,
+) {
+    let mut app = setup_app(temp_dir, search_fields, false)
+        .with_normalize_whitespace(true)
+        .with_collapse_whitespace(collapse);
+    search_and_replace(&mut app).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replace_with_normalized_whitespace_keeping_original() {
+    let temp_dir = &create_test_files! {
+        "file.txt" => {
+            "foo bar",
+            "  x =  foo   bar;",
+            "\tfoo\t\tbar  y",
+            "foobar",
+            "a  b",
+        }
+    };
+
+    search_and_replace_normalizing_whitespace(
+        temp_dir,
+        SearchFields::with_values("foo bar", "baz", true, ""),
+        false,
+    )
+    .await;
+
+    assert_test_files! {
+        temp_dir,
+        "file.txt" => {
+            "baz",
+            "  x =  baz;",
+            "\tbaz  y",
+            "foobar",
+            "a  b",
+        }
+    };
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replace_with_normalized_whitespace_writing_normalized() {
+    let temp_dir = &create_test_files! {
+        "file.txt" => {
+            "foo bar",
+            "  x =  foo   bar;",
+            "\tfoo\t\tbar  y",
+            "foobar",
+            "a  b",
+        }
+    };
+
+    search_and_replace_normalizing_whitespace(
+        temp_dir,
+        SearchFields::with_values("foo bar", "baz", true, ""),
+        true,
+    )
+    .await;
+
+    // Lines without matches are left as they are
+    assert_test_files! {
+        temp_dir,
+        "file.txt" => {
+            "baz",
+            "  x = baz;",
+            "\tbaz y",
+            "foobar",
+            "a  b",
+        }
+    };
+}
+
+#[tokio::test]
+#[serial]
+async fn test_normalized_whitespace_only_applies_to_fixed_strings() {
+    let temp_dir = &create_test_files! {
+        "file.txt" => {
+            "foo bar",
+            "  x =  foo   bar;",
+            "foo  x",
+        }
+    };
+
+    search_and_replace_normalizing_whitespace(
+        temp_dir,
+        SearchFields::with_values("foo +bar", "baz", false, ""),
+        true,
+    )
+    .await;
+
+    assert_test_files! {
+        temp_dir,
+        "file.txt" => {
+            "baz",
+            "  x =  baz;",
+            "foo  x",
+        }
+    };
+}
+
+// Changes the working directory, so must be serial
+#[tokio::test]
+#[serial]
+async fn test_relative_directory_gives_consistent_relative_paths() {
+    let temp_dir = &create_test_files! {
+        "dir/file1.txt" => {
+            "foo",
+        },
+        "dir/nested/file2.txt" => {
+            "foo",
+        },
+        "other/file3.txt" => {
+            "foo",
+        }
+    };
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(temp_dir.path().join("other")).unwrap();
+    let apps = ["../dir", "../dir/", "../other/../dir/."].map(|dir| {
+        let directory = validate_directory(dir).unwrap();
+        let events = EventHandler::new();
+        let mut app = App::new(vec![directory], false, false, events.app_event_sender);
+        app.search_fields = SearchFields::with_values("foo", "bar", false, "");
+        app
+    });
+    // The directories should still be found, and displayed the same way, from elsewhere
+    std::env::set_current_dir(original_dir).unwrap();
+
+    for mut app in apps {
+        assert_eq!(
+            app.directory(),
+            temp_dir.path().join("dir").canonicalize().unwrap()
+        );
+        let search_state = search_complete(&mut app).await;
+        let paths = search_state
+            .results
+            .iter()
+            .map(|res| res.path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths
+                .iter()
+                .map(|path| app.relative_path(path).replace('\\', "/"))
+                .collect::<Vec<_>>(),
+            ["./file1.txt", "./nested/file2.txt"]
+        );
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replace_with_line_filter() {
+    let temp_dir = &create_test_files! {
+        "a.txt" => {
+            "foo // keep",
+            "foo",
+            "// keep",
+        },
+        "b.txt" => {
+            "foo foo",
+        }
+    };
+
+    let search_fields = SearchFields::with_values("foo", "bar", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false)
+        .with_line_filter(Some(Regex::new("// keep").unwrap()));
+    assert_eq!(search_complete(&mut app).await.results.len(), 1);
+    replace_complete(&mut app).await;
+
+    assert_test_files! {
+        temp_dir,
+        "a.txt" => {
+            "bar // keep",
+            "foo",
+            "// keep",
+        },
+        "b.txt" => {
+            "foo foo",
+        }
+    };
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replace_only_occurrence() {
+    let temp_dir = &create_test_files! {
+        "a.txt" => {
+            "foo foo",
+            "no match",
+        },
+        "b.txt" => {
+            "foo foo foo",
+            "foo",
+        }
+    };
+
+    // The first file has two matches, so the fourth is the second on the first line of the next
+    search_and_replace_occurrence(temp_dir, 4, false).await;
+
+    assert_test_files! {
+        temp_dir,
+        "a.txt" => {
+            "foo foo",
+            "no match",
+        },
+        "b.txt" => {
+            "foo bar foo",
+            "foo",
+        }
+    };
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replace_only_occurrence_per_file() {
+    let temp_dir = &create_test_files! {
+        "a.txt" => {
+            "foo foo",
+            "no match",
+        },
+        "b.txt" => {
+            "foo",
+            "foo foo",
+        },
+        "c.txt" => {
+            "foo",
+        }
+    };
+
+    search_and_replace_occurrence(temp_dir, 2, true).await;
+
+    assert_test_files! {
+        temp_dir,
+        "a.txt" => {
+            "foo bar",
+            "no match",
+        },
+        "b.txt" => {
+            "foo",
+            "bar foo",
+        },
+        "c.txt" => {
+            "foo",
+        }
+    };
+}
+
+#[tokio::test]
+#[serial]
+async fn test_occurrence_not_found_shows_error() {
+    let temp_dir = &create_test_files! {
+        "a.txt" => {
+            "foo foo",
+        },
+        "b.txt" => {
+            "foo",
+        }
+    };
+
+    for (occurrence, per_file, error) in [
+        (
+            4,
+            false,
+            "Only 3 matches were found, so there is no match 4 to replace",
+        ),
+        (
+            3,
+            true,
+            "No file has 3 matches, so there is nothing to replace",
+        ),
+    ] {
+        let search_fields = SearchFields::with_values("foo", "bar", true, "");
+        let mut app = setup_app(temp_dir, search_fields, false)
+            .with_occurrence(Some(occurrence))
+            .with_occurrence_per_file(per_file);
+        app.perform_search_if_valid();
+        process_bp_events(&mut app).await;
+
+        assert!(matches!(app.current_screen, Screen::SearchFields));
+        assert!(app.search_fields.show_error_popup);
+        assert_eq!(app.search_fields.errors()[0].1.long, error);
+    }
+}
+
+test_with_both_regex_modes!(
+    test_search_multiple_directories,
+    |advanced_regex: bool| async move {
+        let temp_dir1 = &create_test_files! {
+            "file1.txt" => {
+                "foo in first directory",
+            },
+            "sub/file2.txt" => {
+                "foo nested in first directory",
+            }
+        };
+        let temp_dir2 = &create_test_files! {
+            "file3.txt" => {
+                "foo in second directory",
+                "no match",
+            }
+        };
+
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![
+                temp_dir1.path().to_path_buf(),
+                temp_dir2.path().to_path_buf(),
+            ],
+            false,
+            advanced_regex,
+            events.app_event_sender,
+        );
+        app.search_fields =
+            SearchFields::with_values("foo", "bar", false, "").with_advanced_regex(advanced_regex);
+        app.perform_search_if_valid();
+        process_bp_events(&mut app).await;
+        assert!(wait_for_screen!(&app, Screen::SearchComplete));
+
+        if let Screen::SearchComplete(search_state) = &app.current_screen {
+            let mut paths = search_state
+                .results
+                .iter()
+                .map(|res| app.relative_path(&res.path).replace('\\', "/"))
+                .collect::<Vec<_>>();
+            paths.sort();
+            assert_eq!(paths, vec!["./file1.txt", "./file3.txt", "./sub/file2.txt"]);
+        } else {
+            panic!(
+                "Expected SearchComplete results, found {:?}",
+                app.current_screen
+            );
+        }
+
+        replace_complete(&mut app).await;
+
+        assert_test_files! {
+            temp_dir1,
+            "file1.txt" => {
+                "bar in first directory",
+            },
+            "sub/file2.txt" => {
+                "bar nested in first directory",
+            }
+        };
+        assert_test_files! {
+            temp_dir2,
+            "file3.txt" => {
+                "bar in second directory",
+                "no match",
+            }
+        };
+    }
+);
+
+test_with_both_regex_modes!(
+    test_search_nested_directories_once,
+    |advanced_regex: bool| async move {
+        let temp_dir = &create_test_files! {
+            "file1.txt" => {
+                "foo at the top level",
+            },
+            "sub/file2.txt" => {
+                "foo nested",
+            }
+        };
+
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![
+                temp_dir.path().join("sub"),
+                temp_dir.path().to_path_buf(),
+                temp_dir.path().join("sub/."),
+            ],
+            false,
+            advanced_regex,
+            events.app_event_sender,
+        );
+        app.search_fields =
+            SearchFields::with_values("foo", "bar", false, "").with_advanced_regex(advanced_regex);
+        app.perform_search_if_valid();
+        process_bp_events(&mut app).await;
+        assert!(wait_for_screen!(&app, Screen::SearchComplete));
+
+        if let Screen::SearchComplete(search_state) = &app.current_screen {
+            let mut paths = search_state
+                .results
+                .iter()
+                .map(|res| res.path.clone())
+                .collect::<Vec<_>>();
+            paths.sort();
+            assert_eq!(
+                paths,
+                vec![
+                    temp_dir.path().join("file1.txt"),
+                    temp_dir.path().join("sub/file2.txt")
+                ]
+            );
+        } else {
+            panic!(
+                "Expected SearchComplete results, found {:?}",
+                app.current_screen
+            );
+        }
+
+        replace_complete(&mut app).await;
+
+        assert_test_files! {
+            temp_dir,
+            "file1.txt" => {
+                "bar at the top level",
+            },
+            "sub/file2.txt" => {
+                "bar nested",
+            }
+        };
+    }
+);
+
+async fn searched_paths(app: &mut App) -> Vec<String> {
+    app.perform_search_if_valid();
+    process_bp_events(app).await;
+    assert!(wait_for_screen!(app, Screen::SearchComplete));
+
+    if let Screen::SearchComplete(search_state) = &app.current_screen {
+        let mut paths = search_state
+            .results
+            .iter()
+            .map(|res| app.relative_path(&res.path).replace('\\', "/"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    } else {
+        panic!(
+            "Expected SearchComplete results, found {:?}",
+            app.current_screen
+        );
+    }
+}
+
+macro_rules! create_pruning_test_files {
+    () => {
+        create_test_files! {
+            "src/file1.txt" => {
+                "foo",
+            },
+            "node_modules/pkg/file2.txt" => {
+                "foo",
+            },
+            "build/file3.txt" => {
+                "foo",
+            },
+            "src/build/file4.txt" => {
+                "foo",
+            },
+            // Only directories are pruned, not files with the same name
+            "src/target" => {
+                "foo",
+            },
+            "lib/build" => {
+                "foo",
+            }
+        }
+    };
+}
+
+#[tokio::test]
+#[serial]
+async fn test_default_pruned_dirs_not_searched() {
+    let temp_dir = &create_pruning_test_files!();
+    let search_fields = SearchFields::with_values("foo", "bar", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+
+    assert_eq!(
+        searched_paths(&mut app).await,
+        vec![
+            "./build/file3.txt",
+            "./lib/build",
+            "./src/build/file4.txt",
+            "./src/file1.txt",
+            "./src/target"
+        ]
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_custom_pruned_dirs_not_searched() {
+    let temp_dir = &create_pruning_test_files!();
+    let search_fields = SearchFields::with_values("foo", "bar", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false)
+        .with_pruned_dirs(vec![".git".to_owned(), "build".to_owned()]);
+
+    assert_eq!(
+        searched_paths(&mut app).await,
+        vec![
+            "./lib/build",
+            "./node_modules/pkg/file2.txt",
+            "./src/file1.txt",
+            "./src/target"
+        ]
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_fixed_path_pattern_matched_literally() {
+    let temp_dir = &create_test_files! {
+        "a+b/file1.txt" => {
+            "foo",
+        },
+        "aab/file2.txt" => {
+            "foo",
+        },
+        "src/a+b.txt" => {
+            "foo",
+        }
+    };
+
+    let search_fields = SearchFields::with_values("foo", "bar", false, "a+b");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    assert_eq!(searched_paths(&mut app).await, vec!["./aab/file2.txt"]);
+
+    let search_fields = SearchFields::with_values("foo", "bar", true, "a+b");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    assert_eq!(searched_paths(&mut app).await, vec!["./a+b/file1.txt"]);
+}
+
+async fn replace_with_verify(temp_dir: &TempDir, search: &str, replace: &str) -> ReplaceState {
+    let search_fields = SearchFields::with_values(search, replace, false, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_verify(true);
+    let search_state = search_complete(&mut app).await;
+
+    // Excluded results aren't counted as remaining
+    search_state.selected = search_state
+        .results
+        .iter()
+        .position(|res| res.path.ends_with("excluded.txt"))
+        .unwrap();
+    search_state.toggle_selected_inclusion();
+
+    replace_complete(&mut app).await;
+    match mem::replace(&mut app.current_screen, Screen::SearchFields) {
+        Screen::Results(replace_state) => replace_state,
+        screen => panic!("Expected Results, found {:?}", screen),
+    }
+}
+
+macro_rules! create_verify_test_files {
+    () => {
+        create_test_files! {
+            "file1.txt" => {
+                "foo",
+                "bar foo",
+            },
+            "dir/file2.txt" => {
+                "foo",
+            },
+            "excluded.txt" => {
+                "foo",
+            }
+        }
+    };
+}
+
+#[tokio::test]
+#[serial]
+async fn test_verify_no_remaining_matches() {
+    let temp_dir = &create_verify_test_files!();
+    let replace_state = replace_with_verify(temp_dir, "foo", "baz").await;
+
+    assert_eq!(replace_state.num_successes, 3);
+    assert_eq!(replace_state.num_remaining_matches, Some(0));
+    assert_test_files!(
+        temp_dir,
+        "file1.txt" => {
+            "baz",
+            "bar baz",
+        },
+        "dir/file2.txt" => {
+            "baz",
+        },
+        "excluded.txt" => {
+            "foo",
+        }
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_verify_replacement_matches_search() {
+    let temp_dir = &create_verify_test_files!();
+    let replace_state = replace_with_verify(temp_dir, "foo", "foobar").await;
+
+    assert_eq!(replace_state.num_successes, 3);
+    assert_eq!(replace_state.num_remaining_matches, Some(3));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_no_verification_by_default() {
+    let temp_dir = &create_verify_test_files!();
+    let search_fields = SearchFields::with_values("foo", "baz", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    let replace_state = search_and_replace(&mut app).await;
+    assert_eq!(replace_state.num_remaining_matches, None);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_negated_path_pattern() {
+    let temp_dir = &create_test_files! {
+        "src/file1.txt" => {
+            "foo",
+        },
+        "tests/file2.txt" => {
+            "foo",
+        },
+        "tests/nested/file3.txt" => {
+            "foo",
+        },
+        "file4.txt" => {
+            "foo",
+        }
+    };
+
+    for fixed_strings in [false, true] {
+        let search_fields = SearchFields::with_values("foo", "bar", fixed_strings, "!tests");
+        let mut app = setup_app(temp_dir, search_fields, false);
+        assert_eq!(
+            searched_paths(&mut app).await,
+            vec!["./file4.txt", "./src/file1.txt"]
+        );
+    }
+
+    // Without the `!`, only the excluded files are searched
+    let search_fields = SearchFields::with_values("foo", "bar", false, "tests");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    assert_eq!(
+        searched_paths(&mut app).await,
+        vec!["./tests/file2.txt", "./tests/nested/file3.txt"]
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_path_pattern_as_glob() {
+    let temp_dir = &create_test_files! {
+        "src/main.rs" => {
+            "foo",
+        },
+        "src/nested/lib.rs" => {
+            "foo",
+        },
+        "src/notes.txt" => {
+            "foo",
+        },
+        "build.rs" => {
+            "foo",
+        }
+    };
+
+    let glob_search_fields = |path_pattern| {
+        SearchFields::with_values("foo", "bar", false, path_pattern).with_path_glob(true)
+    };
+
+    let mut app = setup_app(temp_dir, glob_search_fields("*.rs"), false);
+    assert_eq!(
+        searched_paths(&mut app).await,
+        vec!["./build.rs", "./src/main.rs", "./src/nested/lib.rs"]
+    );
+
+    let mut app = setup_app(temp_dir, glob_search_fields("src/*.rs"), false);
+    assert_eq!(
+        searched_paths(&mut app).await,
+        vec!["./src/main.rs", "./src/nested/lib.rs"]
+    );
+
+    let mut app = setup_app(temp_dir, glob_search_fields("!*.rs"), false);
+    assert_eq!(searched_paths(&mut app).await, vec!["./src/notes.txt"]);
+
+    // As a regex, `*.rs` is invalid
+    let search_fields = SearchFields::with_values("foo", "bar", false, "*.rs");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    app.perform_search_if_valid();
+    assert!(matches!(app.current_screen, Screen::SearchFields));
+    assert!(app.search_fields.show_error_popup);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_path_pattern_fuzzy() {
+    let temp_dir = &create_test_files! {
+        "src/main.rs" => {
+            "foo",
+        },
+        "src/lib.rs" => {
+            "foo",
+        },
+        "tests/main.rs" => {
+            "foo",
+        }
+    };
+
+    let search_fields = SearchFields::with_values("foo", "bar", false, "srcmain");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    assert!(searched_paths(&mut app).await.is_empty());
+
+    app.cancel_search();
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('f'), KeyModifiers::ALT))
+        .unwrap();
+    assert!(app.search_fields.path_fuzzy());
+    assert_eq!(searched_paths(&mut app).await, vec!["./src/main.rs"]);
+
+    app.cancel_search();
+    app.search_fields =
+        SearchFields::with_values("foo", "bar", false, "!srcmain").with_path_fuzzy(true);
+    assert_eq!(
+        searched_paths(&mut app).await,
+        vec!["./src/lib.rs", "./tests/main.rs"]
+    );
+}
+
+async fn replace_after_changing_file(retry_failed: bool) -> App {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        },
+        "file2.txt" => {
+            "foo",
+            "bar",
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "baz", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_retry_failed(retry_failed);
+    app.perform_search_if_valid();
+    process_bp_events(&mut app).await;
+    assert!(wait_for_screen!(&app, Screen::SearchComplete));
+    if let Screen::SearchComplete(search_state) = &mut app.current_screen {
+        search_state
+            .results
+            .sort_by_key(|res| (res.path.clone(), res.line_number));
+    }
+
+    // The last line no longer matches, so can't be replaced
+    fs::write(temp_dir.path().join("file2.txt"), "foo\nbar\nchanged\n").unwrap();
+
+    app.trigger_replacement();
+    process_bp_events(&mut app).await;
+    app
+}
+
+#[tokio::test]
+#[serial]
+async fn test_failed_replacements_shown_separately_by_default() {
+    let app = replace_after_changing_file(false).await;
+    assert!(wait_for_screen!(&app, Screen::Results));
+    if let Screen::Results(replace_state) = &app.current_screen {
+        assert_eq!(replace_state.num_successes, 2);
+        assert_eq!(replace_state.errors.len(), 1);
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_failed_replacements_shown_in_confirmation_list() {
+    let mut app = replace_after_changing_file(true).await;
+    assert!(wait_for_screen!(&app, Screen::SearchComplete));
+    let Screen::SearchComplete(search_state) = &mut app.current_screen else {
+        panic!("Expected SearchComplete, found {:?}", app.current_screen);
+    };
+
+    assert_eq!(
+        search_state
+            .results
+            .iter()
+            .map(|res| (res.line_number, res.included, res.replace_result.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (1, false, Some(ReplaceResult::Success)),
+            (1, false, Some(ReplaceResult::Success)),
+            (
+                3,
+                true,
+                Some(ReplaceResult::Error(
+                    "File changed since last search".to_owned()
+                ))
+            ),
+        ]
+    );
+    assert_eq!(search_state.selected, 2);
+
+    // All results can still be navigated to and toggled
+    search_state.move_selected_down();
+    assert_eq!(search_state.selected, 0);
+    search_state.toggle_selected_inclusion();
+    assert!(search_state.results[0].included);
+    search_state.move_selected_up();
+    assert_eq!(search_state.selected, 2);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_save_and_resume_review() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+            "bar foo",
+        },
+        "file2.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "baz", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    let search_state = search_complete(&mut app).await;
+    search_state.results[1].included = false;
+    search_state.selected = 2;
+
+    let review_dir = TempDir::new().unwrap();
+    let review_file = review_dir.path().join("review.json");
+    search_state.save(&review_file).unwrap();
+    let loaded = SearchState::load(&review_file).unwrap();
+    assert_eq!(&loaded, search_state);
+    assert!(!loaded.results[1].included);
+    assert_eq!(loaded.selected, 2);
+
+    // Results are resumed without searching again, leaving out any whose lines have changed,
+    // including to invalid UTF-8
+    fs::write(temp_dir.path().join("file1.txt"), b"\xff\nbar foo\n").unwrap();
+    let (app_event_sender, _) = mpsc::unbounded_channel();
+    let mut resumed_app = App::new(
+        vec![temp_dir.path().to_path_buf()],
+        false,
+        false,
+        app_event_sender,
+    );
+    resumed_app.resume_review(&review_file).unwrap();
+    if let Screen::SearchComplete(search_state) = &resumed_app.current_screen {
+        assert_eq!(search_state.results.len(), 2);
+        assert!(!search_state.results[0].included);
+        assert!(search_state.results[1].path.ends_with("file2.txt"));
+        assert_eq!(search_state.selected, 1);
+    } else {
+        panic!(
+            "Expected SearchComplete, found {:?}",
+            resumed_app.current_screen
+        );
+    }
+
+    resumed_app
+        .handle_key_events(&KeyEvent::new(KeyCode::Char('s'), KeyModifiers::NONE))
+        .unwrap();
+    assert_eq!(
+        resumed_app.notice(),
+        Some(format!("Saved review to {}", review_file.display()).as_str())
+    );
+
+    fs::write(&review_file, "not json").unwrap();
+    assert!(matches!(
+        resumed_app.resume_review(&review_file),
+        Err(ScooterError::InvalidReviewFile(_, _))
+    ));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_inclusion_decisions_kept_when_searching_again() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        },
+        "file2.txt" => {
+            "foo",
+        },
+        "file3.txt" => {
+            "food",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+
+    let search_state = search_complete(&mut app).await;
+    search_state.selected = 1;
+    search_state.toggle_selected_inclusion();
+
+    // Refine the search pattern, which still matches the excluded result
+    app.cancel_search();
+    app.search_fields = SearchFields::with_values("fo+", "bar", false, "");
+    let search_state = search_complete(&mut app).await;
+    assert_eq!(
+        search_state
+            .results
+            .iter()
+            .map(|res| res.included)
+            .collect::<Vec<_>>(),
+        vec![true, false, true]
+    );
+
+    // Decisions are forgotten on reset
+    app.reset();
+    app.search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let search_state = search_complete(&mut app).await;
+    assert!(search_state.results.iter().all(|res| res.included));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_toggle_hidden_and_search() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        },
+        ".hidden/file2.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    assert_eq!(search_complete(&mut app).await.results.len(), 1);
+
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('h'), KeyModifiers::NONE))
+        .unwrap();
+    assert!(app.include_hidden());
+    assert_eq!(app.search_fields.search().text(), "foo");
+    process_bp_events(&mut app).await;
+    assert!(wait_for_screen!(&app, Screen::SearchComplete));
+    let Screen::SearchComplete(search_state) = &app.current_screen else {
+        panic!("Expected SearchComplete, found {:?}", app.current_screen);
+    };
+    let mut paths = search_state
+        .results
+        .iter()
+        .map(|res| app.relative_path(&res.path).replace('\\', "/"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(paths, vec!["./.hidden/file2.txt", "./file1.txt"]);
+
+    // Toggling again goes back to skipping hidden files
+    app.toggle_hidden_and_search();
+    assert!(!app.include_hidden());
+    process_bp_events(&mut app).await;
+    assert!(wait_for_screen!(&app, Screen::SearchComplete));
+    let Screen::SearchComplete(search_state) = &app.current_screen else {
+        panic!("Expected SearchComplete, found {:?}", app.current_screen);
+    };
+    assert_eq!(search_state.results.len(), 1);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_toggle_hidden_and_search_restores_include_ignored() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_include_ignored(true);
+    search_complete(&mut app).await;
+
+    app.toggle_hidden_and_search();
+    assert!(app.include_hidden());
+    assert!(app.include_ignored());
+    search_complete(&mut app).await;
+
+    // Ignored files were included without hidden files, as with `--no-ignore`, so they still are
+    app.toggle_hidden_and_search();
+    assert!(!app.include_hidden());
+    assert!(app.include_ignored());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_preview_search_pauses_and_resumes() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+            "foo",
+        },
+        "file2.txt" => {
+            "foo",
+        },
+        "dir/file3.txt" => {
+            "foo",
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_preview_limit(Some(2));
+
+    let search_state = search_complete(&mut app).await;
+    assert_eq!(search_state.results.len(), 2);
+    search_state.selected = 0;
+    search_state.toggle_selected_inclusion();
+    let excluded = search_state.results[0].clone();
+    assert!(app.search_paused());
+
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('r'), KeyModifiers::NONE))
+        .unwrap();
+    process_bp_events(&mut app).await;
+    assert!(wait_for_screen!(&app, Screen::SearchComplete));
+    assert!(!app.search_paused());
+    let Screen::SearchComplete(search_state) = &app.current_screen else {
+        panic!("Expected SearchComplete, found {:?}", app.current_screen);
+    };
+    assert_eq!(search_state.results.len(), 5);
+    // Choices made on the preview are kept
+    assert_eq!(
+        search_state
+            .results
+            .iter()
+            .filter(|res| !res.included)
+            .collect::<Vec<_>>(),
+        vec![&SearchResult {
+            included: false,
+            ..excluded
+        }]
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_preview_search_not_paused_when_nothing_left_out() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        },
+        "file2.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_preview_limit(Some(2));
+
+    let search_state = search_complete(&mut app).await;
+    assert_eq!(search_state.results.len(), 2);
+    assert!(!app.search_paused());
+}
+
+fn create_file_with_invalid_utf8() -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    let mut contents = b"foo first\n".to_vec();
+    contents.extend_from_slice(b"foo \xff invalid\n");
+    contents.extend_from_slice(b"foo last\n");
+    fs::write(temp_dir.path().join("file.txt"), contents).unwrap();
+    temp_dir
+}
+
+async fn search_with_lossy(temp_dir: &TempDir, lossy: bool) -> App {
+    let search_fields = SearchFields::with_values("foo", "bar", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_lossy(lossy);
+    search_complete(&mut app).await;
+    app
+}
+
+#[tokio::test]
+#[serial]
+async fn test_invalid_utf8_line_skipped_by_default() {
+    let temp_dir = &create_file_with_invalid_utf8();
+    let mut app = search_with_lossy(temp_dir, false).await;
+
+    if let Screen::SearchComplete(search_state) = &app.current_screen {
+        let lines = search_state
+            .results
+            .iter()
+            .map(|res| res.line.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec!["foo first", "foo last"]);
+    } else {
+        panic!("Expected SearchComplete, found {:?}", app.current_screen);
+    }
+
+    replace_complete(&mut app).await;
+    assert_eq!(
+        fs::read(temp_dir.path().join("file.txt")).unwrap(),
+        b"bar first\nfoo \xff invalid\nbar last\n"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_invalid_utf8_line_searched_with_lossy() {
+    let temp_dir = &create_file_with_invalid_utf8();
+    let mut app = search_with_lossy(temp_dir, true).await;
+
+    if let Screen::SearchComplete(search_state) = &app.current_screen {
+        let lines = search_state
+            .results
+            .iter()
+            .map(|res| res.line.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec!["foo first", "foo \u{FFFD} invalid", "foo last"]);
+    } else {
+        panic!("Expected SearchComplete, found {:?}", app.current_screen);
+    }
+
+    let replace_state = replace_complete(&mut app).await;
+    assert_eq!(replace_state.num_successes, 2);
+    assert_eq!(replace_state.errors.len(), 1);
+    assert_eq!(replace_state.errors[0].line_number, 2);
+    // The invalid line is left untouched
+    assert_eq!(
+        fs::read(temp_dir.path().join("file.txt")).unwrap(),
+        b"bar first\nfoo \xff invalid\nbar last\n"
+    );
+}
+
+fn git_available() -> bool {
+    std::process::Command::new("git")
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+fn run_git(dir: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_search_git_modified_only() {
+    if !git_available() {
+        return;
+    }
+
+    let temp_dir = &create_test_files! {
+        "unchanged.txt" => {
+            "foo",
+        },
+        "dir/modified.txt" => {
+            "foo",
+        },
+        "staged.txt" => {
+            "foo",
+        }
+    };
+    run_git(temp_dir.path(), &["init", "--quiet"]);
+    run_git(temp_dir.path(), &["add", "."]);
+    run_git(
+        temp_dir.path(),
+        &["commit", "--quiet", "-m", "Initial commit"],
+    );
+
+    fs::write(temp_dir.path().join("dir/modified.txt"), "foo bar\n").unwrap();
+    fs::write(temp_dir.path().join("staged.txt"), "foo baz\n").unwrap();
+    run_git(temp_dir.path(), &["add", "staged.txt"]);
+    fs::write(temp_dir.path().join("untracked.txt"), "foo\n").unwrap();
+
+    let search_fields = SearchFields::with_values("foo", "replaced", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_git_modified_only(true);
+    app.perform_search_if_valid();
+    process_bp_events(&mut app).await;
+    assert!(wait_for_screen!(&app, Screen::SearchComplete));
+
+    if let Screen::SearchComplete(search_state) = &app.current_screen {
+        let mut paths = search_state
+            .results
+            .iter()
+            .map(|res| app.relative_path(&res.path).replace('\\', "/"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec!["./dir/modified.txt", "./staged.txt", "./untracked.txt"]
+        );
+    } else {
+        panic!(
+            "Expected SearchComplete results, found {:?}",
+            app.current_screen
+        );
+    }
+}
+
+#[test]
+fn test_git_modified_files_outside_repo() {
+    if !git_available() {
+        return;
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    assert!(matches!(
+        scooter::git::modified_files(temp_dir.path()),
+        Err(ScooterError::NotGitRepository(_))
+    ));
+}
+
+#[test]
+fn test_gitignore_matcher_stops_at_repo_root() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    let repo = root.join("repo");
+    fs::create_dir_all(repo.join(".git/info")).unwrap();
+    fs::create_dir_all(repo.join("src")).unwrap();
+    fs::write(root.join(".gitignore"), "*.txt\n").unwrap();
+    fs::write(repo.join(".git/info/exclude"), "*.log\n").unwrap();
+    fs::write(repo.join("src/.gitignore"), "!keep.log\n").unwrap();
+
+    let matcher = scooter::git::GitignoreMatcher::new();
+    // The `.gitignore` outside the repository doesn't apply
+    assert!(!matcher.is_ignored(&repo.join("src/file.txt")));
+    assert!(matcher.is_ignored(&root.join("file.txt")));
+    // `.git/info/exclude` applies, but `.gitignore` files take precedence
+    assert!(matcher.is_ignored(&repo.join("src/file.log")));
+    assert!(!matcher.is_ignored(&repo.join("src/keep.log")));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_search_git_modified_only_outside_repo() {
+    if !git_available() {
+        return;
+    }
+
+    let temp_dir = &create_test_files! {
+        "file.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_git_modified_only(true);
+    app.perform_search_if_valid();
+
+    assert!(matches!(app.current_screen, Screen::SearchFields));
+    assert!(app.search_fields.show_error_popup);
+    let errors = app.search_fields.errors();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].1.long.contains("is not in a git repository"));
+}
+
+// TODO:
+// - Add:
+//   - more tests for replacing in files
+//   - tests for passing in directory via CLI arg
+// - Tidy up tests - lots of duplication
+
+#[tokio::test]
+#[serial]
+async fn test_results_in_gitignored_files_are_flagged() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        },
+        "generated/file2.txt" => {
+            "foo",
+            "foo bar",
+        },
+        ".gitignore" => {
+            "generated/",
+        }
+    };
+    create_dir_all(temp_dir.path().join(".git")).unwrap();
+
+    let search_fields = SearchFields::with_values("foo", "baz", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_include_ignored(true);
+    let search_state = search_complete(&mut app).await;
+
+    assert_eq!(search_state.results.len(), 3);
+    assert_eq!(search_state.num_gitignored(), 2);
+    assert!(search_state.results[0].warnings.is_empty());
+    assert!(search_state.results[1..]
+        .iter()
+        .all(|res| res.warnings == vec![ReplaceWarning::Gitignored]));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_binary_lines_skipped_are_counted() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+            "foo\0\x01\x02",
+            "foo \0",
+        },
+        "file2.txt" => {
+            "\0 foo",
+        },
+        "file3.txt" => {
+            "foo",
+            "\0 bar",
+        }
+    };
+
+    let search_fields = SearchFields::with_values("foo", "baz", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    let search_state = search_complete(&mut app).await;
+
+    assert_eq!(search_state.results.len(), 2);
+    assert_eq!(app.binary_lines_skipped(), 3);
+    assert_eq!(app.binary_files_skipped(), 2);
+
+    assert_test_files! {
+        temp_dir,
+        "file1.txt" => {
+            "foo",
+            "foo\0\x01\x02",
+            "foo \0",
+        },
+        "file2.txt" => {
+            "\0 foo",
+        },
+        "file3.txt" => {
+            "foo",
+            "\0 bar",
+        }
+    };
+}
+
+#[tokio::test]
+#[serial]
+async fn test_gitignored_files_not_flagged_when_ignored_files_excluded() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        },
+        ".gitignore" => {
+            "file1.txt",
+        }
+    };
+
+    let search_fields = SearchFields::with_values("foo", "baz", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    let search_state = search_complete(&mut app).await;
+
+    assert_eq!(search_state.results.len(), 1);
+    assert_eq!(search_state.num_gitignored(), 0);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replace_visual_range_only() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        },
+        "file2.txt" => {
+            "foo",
+        },
+        "file3.txt" => {
+            "foo",
+        },
+        "file4.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    let search_state = search_complete(&mut app).await;
+    search_state.selected = 2;
+
+    let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+    app.handle_key_events(&key('v')).unwrap();
+    app.handle_key_events(&key('k')).unwrap();
+    if let Screen::SearchComplete(search_state) = &app.current_screen {
+        assert_eq!(search_state.visual_range(), Some(1..=2));
+    } else {
+        panic!("Expected SearchComplete, found {:?}", app.current_screen);
+    }
+
+    let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+    app.handle_key_events(&enter).unwrap();
+    if let Screen::ReviewingReplacement(summary) = &app.current_screen {
+        assert_eq!(summary.num_lines, 2);
+    } else {
+        panic!(
+            "Expected ReviewingReplacement, found {:?}",
+            app.current_screen
+        );
+    }
+
+    // Backing out of the summary leaves the results and the visual range as they were
+    app.handle_key_events(&KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE))
+        .unwrap();
+    if let Screen::SearchComplete(search_state) = &app.current_screen {
+        assert_eq!(search_state.visual_range(), Some(1..=2));
+        assert!(search_state.results.iter().all(|res| res.included));
+    } else {
+        panic!("Expected SearchComplete, found {:?}", app.current_screen);
+    }
+
+    app.handle_key_events(&enter).unwrap();
+    app.handle_key_events(&enter).unwrap();
+    process_bp_events(&mut app).await;
+    assert!(wait_for_screen!(&app, Screen::Results));
+    if let Screen::Results(replace_state) = &app.current_screen {
+        assert_eq!(replace_state.num_successes, 2);
+        assert_eq!(replace_state.num_ignored, 2);
+    } else {
+        panic!("Expected Results, found {:?}", app.current_screen);
+    }
+
+    assert_test_files!(
+        temp_dir,
+        "file1.txt" => {
+            "foo",
+        },
+        "file2.txt" => {
+            "bar",
+        },
+        "file3.txt" => {
+            "bar",
+        },
+        "file4.txt" => {
+            "foo",
+        }
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_confirm_each_file() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+            "something",
+            "foo again",
+        },
+        "file2.txt" => {
+            "foo",
+            "and foo",
+        },
+        "file3.txt" => {
+            "no match",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    search_complete(&mut app).await;
+
+    let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+    app.handle_key_events(&key('c')).unwrap();
+    if let Screen::ConfirmingFiles(state) = &app.current_screen {
+        assert_eq!(state.files.len(), 2);
+        assert_eq!(state.current, 0);
+        let (path, indices) = state.current_file().unwrap();
+        assert!(path.ends_with("file1.txt"));
+        assert_eq!(indices.len(), 2);
+    } else {
+        panic!("Expected ConfirmingFiles, found {:?}", app.current_screen);
+    }
+
+    // Scrolling stops at the last result of the file
+    for c in ['j', 'j'] {
+        app.handle_key_events(&key(c)).unwrap();
+    }
+    // Like `n`, `y` only responds without modifiers
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('y'), KeyModifiers::CONTROL))
+        .unwrap();
+    if let Screen::ConfirmingFiles(state) = &app.current_screen {
+        assert_eq!(state.current, 0);
+        assert_eq!(state.scroll_pos, 1);
+    } else {
+        panic!("Expected ConfirmingFiles, found {:?}", app.current_screen);
+    }
+
+    app.handle_key_events(&key('n')).unwrap();
+    if let Screen::ConfirmingFiles(state) = &app.current_screen {
+        assert_eq!(state.current, 1);
+        assert_eq!(state.scroll_pos, 0);
+        assert!(state.current_file().unwrap().0.ends_with("file2.txt"));
+    } else {
+        panic!("Expected ConfirmingFiles, found {:?}", app.current_screen);
+    }
+
+    app.handle_key_events(&key('y')).unwrap();
+    assert!(matches!(
+        app.current_screen,
+        Screen::ReviewingReplacement(_)
+    ));
+    app.handle_key_events(&KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE))
+        .unwrap();
+    process_bp_events(&mut app).await;
+    assert!(wait_for_screen!(&app, Screen::Results));
+    if let Screen::Results(replace_state) = &app.current_screen {
+        assert_eq!(replace_state.num_successes, 2);
+        assert_eq!(replace_state.num_ignored, 2);
+    } else {
+        panic!("Expected Results, found {:?}", app.current_screen);
+    }
+
+    assert_test_files!(
+        temp_dir,
+        "file1.txt" => {
+            "foo",
+            "something",
+            "foo again",
+        },
+        "file2.txt" => {
+            "bar",
+            "and bar",
+        },
+        "file3.txt" => {
+            "no match",
+        }
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replacement_summary() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo foo",
+            "something",
+            "foo again",
+        },
+        "file2.txt" => {
+            "foo",
+        },
+        "file3.txt" => {
+            "no match",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    search_complete(&mut app).await;
+
+    let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+    app.handle_key_events(&enter).unwrap();
+    if let Screen::ReviewingReplacement(summary) = &app.current_screen {
+        assert_eq!(summary.num_files, 2);
+        assert_eq!(summary.num_lines, 3);
+        assert_eq!(summary.num_substitutions, 4);
+        assert!(summary.warnings.is_empty());
+        assert_eq!(summary.num_large_files, 0);
+    } else {
+        panic!(
+            "Expected ReviewingReplacement, found {:?}",
+            app.current_screen
+        );
+    }
+
+    // Backing out returns to the results without replacing anything
+    let result = app
+        .handle_key_events(&KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE))
+        .unwrap();
+    assert!(!result.exit);
+    assert!(matches!(app.current_screen, Screen::SearchComplete(_)));
+    assert_test_files!(
+        temp_dir,
+        "file1.txt" => {
+            "foo foo",
+            "something",
+            "foo again",
+        },
+        "file2.txt" => {
+            "foo",
+        },
+        "file3.txt" => {
+            "no match",
+        }
+    );
+
+    // Proceeding from the summary performs the replacement
+    app.handle_key_events(&enter).unwrap();
+    app.handle_key_events(&enter).unwrap();
+    process_bp_events(&mut app).await;
+    assert!(wait_for_screen!(&app, Screen::Results));
+    assert_test_files!(
+        temp_dir,
+        "file1.txt" => {
+            "bar bar",
+            "something",
+            "bar again",
+        },
+        "file2.txt" => {
+            "bar",
+        },
+        "file3.txt" => {
+            "no match",
+        }
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_confirm_each_file_back_keeps_decisions() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        },
+        "file2.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    search_complete(&mut app).await;
+
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('c'), KeyModifiers::NONE))
+        .unwrap();
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE))
+        .unwrap();
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL))
+        .unwrap();
+    if let Screen::SearchComplete(search_state) = &app.current_screen {
+        assert_eq!(search_state.num_included(), 1);
+    } else {
+        panic!("Expected SearchComplete, found {:?}", app.current_screen);
+    }
+}
+
+#[test]
+fn test_search_state_byte_size_delta() {
+    let result = |path: &str, line: &str, replacement: &str, included: bool| SearchResult {
+        path: PathBuf::from(path),
+        line_number: 1,
+        line: line.to_string(),
+        replacement: replacement.to_string(),
+        num_matches: 1,
+        included,
+        ..Default::default()
+    };
+    let search_state = SearchState {
+        results: vec![
+            result("file1.txt", "let x = foo;", "let x = foobarbazqux;", true),
+            result("file1.txt", "let y = foobar;", "let y = f;", true),
+            result("file1.txt", "let z = foo;", "let z = foo_excluded;", false),
+            result("file1.txt", "café", "cafe", true),
+            result("file2.txt", "foo", "", true),
+        ],
+        ..Default::default()
+    };
+
+    // +9 from growing, -5 from shrinking, -1 from replacing a multi-byte character
+    assert_eq!(search_state.byte_size_delta(Path::new("file1.txt")), 3);
+    assert_eq!(search_state.byte_size_delta(Path::new("file2.txt")), -3);
+    assert_eq!(search_state.byte_size_delta(Path::new("file3.txt")), 0);
+}
+
+#[test]
+fn test_search_state_toggle_visual_mode() {
+    let mut search_state = SearchState {
+        results: (1..=4)
+            .map(|line_number| SearchResult {
+                path: PathBuf::from("file.txt"),
+                line_number,
+                line: "foo".to_string(),
+                replacement: "bar".to_string(),
+                num_matches: 1,
+                included: true,
+                ..Default::default()
+            })
+            .collect(),
+        selected: 2,
+        ..Default::default()
+    };
+    assert_eq!(search_state.visual_range(), None);
+
+    search_state.toggle_visual_mode();
+    assert_eq!(search_state.visual_range(), Some(2..=2));
+    search_state.move_selected_down();
+    assert_eq!(search_state.visual_range(), Some(2..=3));
+    search_state.move_selected_up();
+    search_state.move_selected_up();
+    search_state.move_selected_up();
+    assert_eq!(search_state.visual_range(), Some(0..=2));
+
+    search_state.toggle_visual_mode();
+    assert_eq!(search_state.visual_range(), None);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replace_with_env_vars_expanded() {
+    std::env::set_var("SCOOTER_TEST_EXPAND_DIR", "/expanded/dir");
+    let temp_dir = &create_test_files! {
+        "file.txt" => {
+            "path = foo",
+        }
+    };
+
+    let search_fields =
+        SearchFields::with_values("(foo)", "$SCOOTER_TEST_EXPAND_DIR/$1", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_expand_env(true);
+    let search_state = search_complete(&mut app).await;
+    assert_eq!(
+        search_state.results[0].replacement,
+        "path = /expanded/dir/foo"
+    );
+
+    // Without expansion, the reference is left as it is
+    let search_fields = SearchFields::with_values("foo", "$SCOOTER_TEST_EXPAND_DIR/foo", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    let search_state = search_complete(&mut app).await;
+    assert_eq!(
+        search_state.results[0].replacement,
+        "path = $SCOOTER_TEST_EXPAND_DIR/foo"
+    );
+    std::env::remove_var("SCOOTER_TEST_EXPAND_DIR");
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replacement_reports_progress() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        },
+        "file2.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    search_complete(&mut app).await;
+
+    app.trigger_replacement();
+    let mut progress = vec![];
+    while let Some(event) = app.background_processing_recv().await {
+        if let BackgroundProcessingEvent::ReplacementProgress(p) = &event {
+            progress.push(p.clone());
+        }
+        app.handle_background_processing_event(event);
+    }
+    assert!(matches!(app.current_screen, Screen::Results(_)));
+
+    let progress = progress
+        .into_iter()
+        .map(|p| {
+            let name = p.current_file.file_name().unwrap().to_owned();
+            (name, p.num_files_done, p.num_files)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        progress,
+        vec![("file1.txt".into(), 0, 2), ("file2.txt".into(), 1, 2)]
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_completed_search_results_are_sorted_by_file() {
+    let temp_dir = &create_test_files! {
+        "b.txt" => {
+            "foo",
+            "foo",
+        },
+        "a.txt" => {
+            "foo",
+        },
+        "dir/c.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    let search_state = search_complete(&mut app).await;
+
+    let order = search_state
+        .results
+        .iter()
+        .map(|res| (res.path.clone(), res.line_number))
+        .collect::<Vec<_>>();
+    let mut sorted = order.clone();
+    sorted.sort();
+    assert_eq!(order, sorted);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_changeset_patch_covers_included_results() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+            "bar",
+            "foo",
+        },
+        "dir/file2.txt" => {
+            "foo",
+        },
+        "file3.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "baz", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    let search_state = search_complete(&mut app).await;
+    let excluded = search_state
+        .results
+        .iter_mut()
+        .find(|res| res.path.ends_with("file3.txt"))
+        .unwrap();
+    excluded.included = false;
+
+    assert_eq!(
+        app.changeset_patch().unwrap(),
+        concat!(
+            "--- a/dir/file2.txt\n",
+            "+++ b/dir/file2.txt\n",
+            "@@ -1,1 +1,1 @@\n",
+            "-foo\n",
+            "+baz\n",
+            "--- a/file1.txt\n",
+            "+++ b/file1.txt\n",
+            "@@ -1,1 +1,1 @@\n",
+            "-foo\n",
+            "+baz\n",
+            "@@ -3,1 +3,1 @@\n",
+            "-foo\n",
+            "+baz\n",
+        )
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_changeset_patch_numbers_counter_tokens() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+            "bar",
+            "foo foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "item_${n}", true, "");
+    let mut app = setup_app(temp_dir, search_fields, false).with_counter(CounterConfig {
+        start: 1,
+        ..CounterConfig::default()
+    });
+    search_complete(&mut app).await;
+
+    assert_eq!(
+        app.changeset_patch().unwrap(),
+        concat!(
+            "--- a/file1.txt\n",
+            "+++ b/file1.txt\n",
+            "@@ -1,1 +1,1 @@\n",
+            "-foo\n",
+            "+item_1\n",
+            "@@ -3,1 +3,1 @@\n",
+            "-foo foo\n",
+            "+item_2 item_3\n",
+        )
+    );
+
+    app.current_screen = Screen::SearchFields;
+    assert_eq!(app.changeset_patch(), None);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_copying_large_patch_shows_notice() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        }
+    };
+    fs::write(
+        temp_dir.path().join("file1.txt"),
+        format!("foo{}\n", "x".repeat(600_000)),
+    )
+    .unwrap();
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    search_complete(&mut app).await;
+
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('Y'), KeyModifiers::SHIFT))
+        .unwrap();
+    assert!(app.notice().unwrap().contains("may be too large to paste"));
+
+    // The notice is cleared by the next key press
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('j'), KeyModifiers::NONE))
+        .unwrap();
+    assert_eq!(app.notice(), None);
+}
+
+#[tokio::test]
+async fn test_show_notice_event() {
+    let events = EventHandler::new();
+    let mut app = App::new(vec![], false, false, events.app_event_sender);
+    let result = app
+        .handle_app_event(AppEvent::ShowNotice(
+            "Couldn't copy to the clipboard".to_owned(),
+        ))
+        .await;
+    assert!(result.rerender);
+    assert_eq!(app.notice(), Some("Couldn't copy to the clipboard"));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_results_switch_between_successes_and_errors() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+        },
+        "file2.txt" => {
+            "foo",
+        },
+        "file3.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    search_complete(&mut app).await;
+    fs::write(temp_dir.path().join("file2.txt"), "changed\n").unwrap();
+
+    let replace_state = replace_complete(&mut app).await;
+    let file_names = |results: &[SearchResult]| {
+        results
+            .iter()
+            .map(|res| res.path.file_name().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        file_names(&replace_state.successes),
+        ["file1.txt", "file3.txt"]
+    );
+    assert_eq!(file_names(&replace_state.errors), ["file2.txt"]);
+    assert!(!replace_state.show_successes);
+
+    let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+    app.handle_key_events(&key('s')).unwrap();
+    app.handle_key_events(&key('j')).unwrap();
+    let Screen::Results(replace_state) = &app.current_screen else {
+        panic!("Expected Results, found {:?}", app.current_screen);
+    };
+    assert!(replace_state.show_successes);
+    assert_eq!(replace_state.successes_pos, 1);
+    assert_eq!(replace_state.replacement_errors_pos, 0);
+
+    app.handle_key_events(&key('s')).unwrap();
+    let Screen::Results(replace_state) = &app.current_screen else {
+        panic!("Expected Results, found {:?}", app.current_screen);
+    };
+    assert!(!replace_state.show_successes);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_open_changed_files_in_editor() {
+    let temp_dir = &create_test_files! {
+        "file1.txt" => {
+            "foo",
+            "foo again",
+        },
+        "file2.txt" => {
+            "foo",
+        },
+        "file3.txt" => {
+            "foo",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    search_complete(&mut app).await;
+    fs::write(temp_dir.path().join("file2.txt"), "changed\n").unwrap();
+
+    replace_complete(&mut app).await;
+    assert_eq!(app.take_editor_request(), None);
+
+    app.handle_key_events(&KeyEvent::new(KeyCode::Char('e'), KeyModifiers::NONE))
+        .unwrap();
+    let changed_files = app.take_editor_request().unwrap();
+    let mut expected = vec![
+        temp_dir.path().join("file1.txt"),
+        temp_dir.path().join("file3.txt"),
+    ];
+    let mut sorted = changed_files.clone();
+    sorted.sort();
+    expected.sort();
+    assert_eq!(sorted, expected);
+    assert_eq!(app.take_editor_request(), None);
+
+    // The files are passed to the editor after any arguments it was given
+    let command = scooter::utils::editor_command("vim -p", &changed_files).unwrap();
+    let args = command.get_args().collect::<Vec<_>>();
+    assert!(args.ends_with(
+        &changed_files
+            .iter()
+            .map(|path| path.as_os_str())
+            .collect::<Vec<_>>()
+    ));
+}
+
+#[tokio::test]
+async fn test_resolved_config() {
+    let (app_event_sender, _app_event_receiver) = mpsc::unbounded_channel();
+    let app = App::new(
+        vec![PathBuf::from("dir")],
+        false,
+        false,
+        app_event_sender.clone(),
+    );
+    let defaults = app.resolved_config();
+    assert_eq!(defaults.directories, [PathBuf::from("dir")]);
+    assert!(!defaults.include_hidden);
+    assert!(!defaults.advanced_regex);
+    assert_eq!(defaults.changed_within, None);
+    assert_eq!(defaults.counter, CounterConfig::default());
+    assert_eq!(defaults.content_width, "80%");
+    assert_eq!(defaults.pruned_dirs, [".git", "node_modules", "target"]);
+
+    let mut app = App::new(vec![PathBuf::from("dir")], true, true, app_event_sender)
+        .with_include_ignored(true)
+        .with_changed_within(Some(Duration::from_secs(3600)))
+        .with_content_width("120".parse().unwrap())
+        .with_pruned_dirs(vec!["build".to_owned()])
+        .with_counter(CounterConfig {
+            start: 5,
+            step: 2,
+            per_file: true,
+        });
+    app = app.with_min_fixed_len(3);
+    app.search_fields =
+        SearchFields::with_values("foo", "bar", true, "src/").with_advanced_regex(true);
+    let config = app.resolved_config();
+    assert!(config.include_hidden);
+    assert!(config.include_ignored);
+    assert!(config.fixed_strings);
+    assert!(config.advanced_regex);
+    assert_eq!(config.path_pattern, "src/");
+    assert_eq!(config.min_fixed_len, 3);
+    assert_eq!(config.changed_within, Some(3600));
+    assert_eq!(config.content_width, "120");
+    assert_eq!(config.pruned_dirs, ["build"]);
+    assert_eq!(config.counter.start, 5);
+
+    let json = serde_json::to_value(&config).unwrap();
+    assert_eq!(json["include_hidden"], true);
+    assert_eq!(json["changed_within"], 3600);
+    assert_eq!(json["counter"]["per_file"], true);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_edit_replacement() {
+    let temp_dir = &create_test_files! {
+        "file.txt" => {
+            "foo one",
+            "foo two",
+        }
+    };
+    let search_fields = SearchFields::with_values("foo", "bar", false, "");
+    let mut app = setup_app(temp_dir, search_fields, false);
+    search_complete(&mut app).await;
+
+    let press = |app: &mut App, code: KeyCode, modifiers: KeyModifiers| {
+        app.handle_key_events(&KeyEvent::new(code, modifiers))
+            .unwrap()
+    };
+    press(&mut app, KeyCode::Char('i'), KeyModifiers::NONE);
+    assert_eq!(app.editing_replacement.as_ref().unwrap().text, "bar one");
+    press(&mut app, KeyCode::Char('u'), KeyModifiers::CONTROL);
+    for c in "custom".chars() {
+        press(&mut app, KeyCode::Char(c), KeyModifiers::NONE);
+    }
+    press(&mut app, KeyCode::Enter, KeyModifiers::NONE);
+    assert!(app.editing_replacement.is_none());
+
+    // Cancelling an edit leaves the replacement as it was
+    press(&mut app, KeyCode::Char('j'), KeyModifiers::NONE);
+    press(&mut app, KeyCode::Char('i'), KeyModifiers::NONE);
+    press(&mut app, KeyCode::Char('x'), KeyModifiers::NONE);
+    let result = press(&mut app, KeyCode::Esc, KeyModifiers::NONE);
+    assert!(!result.exit);
+    assert!(app.editing_replacement.is_none());
+
+    press(&mut app, KeyCode::Enter, KeyModifiers::NONE);
+    press(&mut app, KeyCode::Enter, KeyModifiers::NONE);
+    process_bp_events(&mut app).await;
+    assert!(wait_for_screen!(&app, Screen::Results));
+    assert_test_files!(
+        temp_dir,
+        "file.txt" => {
+            "custom",
+            "bar two",
+        }
+    );
+}
@@ -1,5 +1,15 @@
-use ratatui::crossterm::event::{KeyCode, KeyModifiers};
-use scooter::{parsed_fields::SearchType, CheckboxField, SearchFields, TextField};
+#![cfg(feature = "walker")]
+
+use ratatui::{
+    backend::TestBackend,
+    crossterm::event::{KeyCode, KeyModifiers},
+    style::{Color, Modifier},
+    Terminal,
+};
+use scooter::{
+    parsed_fields::{PathMatcher, SearchType},
+    CheckboxField, Field, RegexError, ScooterError, SearchFields, TextField,
+};
 
 #[test]
 fn test_text_field_operations() {
@@ -78,9 +88,11 @@ fn test_search_fields() {
     search_fields.focus_next();
     assert_eq!(search_fields.highlighted, 3);
     search_fields.focus_next();
+    assert_eq!(search_fields.highlighted, 4);
+    search_fields.focus_next();
     assert_eq!(search_fields.highlighted, 0);
     search_fields.focus_prev();
-    assert_eq!(search_fields.highlighted, 3);
+    assert_eq!(search_fields.highlighted, 4);
     search_fields.focus_next();
     assert_eq!(search_fields.highlighted, 0);
 
@@ -126,3 +138,274 @@ fn test_search_fields() {
         _ => panic!("Expected Pattern, got {:?}", search_type),
     }
 }
+
+#[test]
+fn test_border_colour_reflects_regex_validity() {
+    let search_fields = SearchFields::with_values("foo(", "", false, "");
+    search_fields.validate_patterns();
+    let search_field = search_fields.fields[0].field.read();
+    assert_eq!(search_field.border_colour(true), Some(Color::Red));
+    assert_eq!(search_field.border_colour(false), Some(Color::Red));
+    drop(search_field);
+
+    search_fields.search_mut().move_cursor_end();
+    search_fields.search_mut().enter_char(')');
+    search_fields.validate_patterns();
+    let search_field = search_fields.fields[0].field.read();
+    assert_eq!(search_field.border_colour(true), Some(Color::Green));
+    assert_eq!(search_field.border_colour(false), None);
+}
+
+#[test]
+fn test_invalid_search_regex_error() {
+    let search_fields = SearchFields::with_values("foo(", "", false, "");
+    assert!(matches!(
+        search_fields.search_type(),
+        Err(ScooterError::InvalidSearchRegex(RegexError::Regex(_)))
+    ));
+
+    let search_fields = SearchFields::with_values("foo(", "", false, "").with_advanced_regex(true);
+    assert!(matches!(
+        search_fields.search_type(),
+        Err(ScooterError::InvalidSearchRegex(RegexError::Advanced(_)))
+    ));
+
+    let search_fields = SearchFields::with_values("foo(", "", true, "");
+    assert!(search_fields.search_type().is_ok());
+}
+
+#[test]
+fn test_invalid_path_regex_error() {
+    let search_fields = SearchFields::with_values("foo", "", false, "[a-");
+    assert!(matches!(
+        search_fields.path_pattern_parsed(),
+        Err(ScooterError::InvalidPathRegex(RegexError::Regex(_)))
+    ));
+
+    let search_fields =
+        SearchFields::with_values("foo", "", false, "[a-").with_advanced_regex(true);
+    assert!(matches!(
+        search_fields.path_pattern_parsed(),
+        Err(ScooterError::InvalidPathRegex(RegexError::Advanced(_)))
+    ));
+}
+
+#[test]
+fn test_fixed_path_pattern() {
+    let search_fields = SearchFields::with_values("foo", "", true, "[a-");
+    match search_fields.path_pattern_parsed().unwrap() {
+        Some(PathMatcher::Fixed(s)) => assert_eq!(s, "[a-"),
+        path_pattern => panic!("Expected Fixed, got {:?}", path_pattern),
+    }
+
+    // An invalid regex error is cleared when switching to fixed strings
+    let search_fields = SearchFields::with_values("foo", "", false, "[a-");
+    search_fields.validate_patterns();
+    assert!(search_fields.fields[3].field.read().error().is_some());
+    search_fields.fields[2]
+        .field
+        .write()
+        .handle_keys(KeyCode::Char(' '), KeyModifiers::NONE);
+    search_fields.validate_patterns();
+    assert!(search_fields.fields[3].field.read().error().is_none());
+}
+
+#[test]
+fn test_insert_tab_and_newline() {
+    let mut search_fields = SearchFields::with_values("", "", false, "");
+    search_fields.focus_next();
+    let handle_key = |code, modifiers| {
+        search_fields
+            .highlighted_field()
+            .write()
+            .handle_keys(code, modifiers)
+    };
+    handle_key(KeyCode::Char('a'), KeyModifiers::NONE);
+    handle_key(KeyCode::Char('t'), KeyModifiers::CONTROL);
+    handle_key(KeyCode::Char('b'), KeyModifiers::NONE);
+    handle_key(KeyCode::Enter, KeyModifiers::ALT);
+    handle_key(KeyCode::Char('c'), KeyModifiers::NONE);
+
+    let replace = search_fields.replace();
+    assert_eq!(replace.text(), "a\tb\nc");
+    assert_eq!(replace.cursor_idx(), 5);
+    assert_eq!(replace.display_text(), "a⇥b⏎c");
+}
+
+#[test]
+fn test_toggle_advanced_regex() {
+    let mut search_fields = SearchFields::with_values("foo(?!bar)", "", false, "");
+    search_fields.validate_patterns();
+    assert!(matches!(
+        search_fields.search_type(),
+        Err(ScooterError::InvalidSearchRegex(RegexError::Regex(_)))
+    ));
+    assert!(search_fields.search().error.is_some());
+
+    search_fields.toggle_advanced_regex();
+    assert!(search_fields.advanced_regex());
+    assert!(matches!(
+        search_fields.search_type(),
+        Ok(SearchType::PatternAdvanced(_))
+    ));
+    assert!(search_fields.search().error.is_none());
+
+    search_fields.toggle_advanced_regex();
+    assert!(!search_fields.advanced_regex());
+    assert!(search_fields.search().error.is_some());
+}
+
+#[test]
+fn test_negated_path_pattern() {
+    let search_fields = SearchFields::with_values("foo", "", false, "!tests/");
+    assert!(search_fields.path_pattern_negated());
+    match search_fields.path_pattern_parsed().unwrap() {
+        Some(PathMatcher::Regex(p)) => assert_eq!(p.as_str(), "tests/"),
+        path_pattern => panic!("Expected Regex, got {:?}", path_pattern),
+    }
+
+    let search_fields = SearchFields::with_values("foo", "", false, "!");
+    assert!(search_fields.path_pattern_negated());
+    assert!(search_fields.path_pattern_parsed().unwrap().is_none());
+
+    // An escaped `!` is matched literally
+    let search_fields = SearchFields::with_values("foo", "", false, r"\!tests");
+    assert!(!search_fields.path_pattern_negated());
+    assert!(search_fields.path_pattern_parsed().unwrap().is_some());
+}
+
+#[test]
+fn test_path_pattern_as_glob() {
+    let search_fields = SearchFields::with_values("foo", "", false, "*.rs").with_path_glob(true);
+    let glob = search_fields.path_pattern_parsed().unwrap().unwrap();
+    assert!(matches!(glob, PathMatcher::Glob(_)));
+    assert!(glob.is_match("./main.rs"));
+    assert!(glob.is_match("./src/main.rs"));
+    assert!(!glob.is_match("./src/main.rs.bak"));
+
+    // The glob takes precedence over fixed strings
+    let search_fields = SearchFields::with_values("foo", "", true, "*.rs").with_path_glob(true);
+    assert!(matches!(
+        search_fields.path_pattern_parsed(),
+        Ok(Some(PathMatcher::Glob(_)))
+    ));
+
+    let search_fields = SearchFields::with_values("foo", "", false, "*.rs");
+    assert!(search_fields.path_pattern_parsed().is_err());
+}
+
+#[test]
+fn test_invalid_path_glob() {
+    let search_fields =
+        SearchFields::with_values("foo", "", false, "src/{a,b").with_path_glob(true);
+    assert!(matches!(
+        search_fields.path_pattern_parsed(),
+        Err(ScooterError::InvalidPathGlob(_))
+    ));
+    search_fields.validate_patterns();
+    let path_pattern = search_fields.path_pattern();
+    assert_eq!(
+        path_pattern.error.as_ref().unwrap().short,
+        "Couldn't parse glob"
+    );
+}
+
+#[test]
+fn test_path_pattern_fuzzy() {
+    let mut search_fields =
+        SearchFields::with_values("foo", "", false, "*.rs").with_path_glob(true);
+    search_fields.toggle_path_fuzzy();
+    assert!(search_fields.path_fuzzy());
+    assert!(matches!(
+        search_fields.path_pattern_parsed(),
+        Ok(Some(PathMatcher::Fuzzy(_)))
+    ));
+
+    // Invalid as a regex, but fine as a fuzzy query
+    let mut search_fields = SearchFields::with_values("foo", "", false, "(src");
+    search_fields.validate_patterns();
+    assert!(search_fields.path_pattern().error.is_some());
+    search_fields.toggle_path_fuzzy();
+    assert!(search_fields.path_pattern().error.is_none());
+    search_fields.toggle_path_fuzzy();
+    assert!(search_fields.path_pattern().error.is_some());
+    assert!(search_fields.path_pattern_parsed().is_err());
+}
+
+#[test]
+fn test_placeholder_rendered_only_when_empty() {
+    let render = |field: &Field| {
+        let mut terminal = Terminal::new(TestBackend::new(30, 4)).unwrap();
+        terminal
+            .draw(|frame| field.render(frame, frame.size(), "Search".to_owned(), false))
+            .unwrap();
+        // The text starts inside the border
+        let buffer = terminal.backend().buffer().clone();
+        let text = (1..29)
+            .map(|x| buffer.get(x, 1).symbol())
+            .collect::<String>();
+        (text.trim_end().to_owned(), buffer.get(1, 1).modifier)
+    };
+
+    let mut field = Field::text("").with_placeholder("e.g. foo.*bar");
+    let (text, modifier) = render(&field);
+    assert_eq!(text, "e.g. foo.*bar");
+    assert!(modifier.contains(Modifier::DIM));
+
+    field.handle_keys(KeyCode::Char('x'), KeyModifiers::NONE);
+    let (text, modifier) = render(&field);
+    assert_eq!(text, "x");
+    assert!(!modifier.contains(Modifier::DIM));
+
+    field.handle_keys(KeyCode::Backspace, KeyModifiers::NONE);
+    assert_eq!(render(&field).0, "e.g. foo.*bar");
+}
+
+#[test]
+fn test_lookahead_in_basic_mode_suggests_advanced_regex() {
+    let search_fields = SearchFields::with_values("foo(?=bar)", "", false, "");
+    search_fields.validate_patterns();
+    let error = search_fields.search().error.clone().unwrap();
+    assert_eq!(error.short, "Couldn't parse regex, try advanced regex");
+    assert!(error.long.contains("toggle it with <A-r>"));
+
+    let search_fields = search_fields.with_advanced_regex(true);
+    search_fields.validate_patterns();
+    assert!(search_fields.search().error.is_none());
+}
+
+#[test]
+fn test_invalid_regex_in_both_modes_does_not_suggest_advanced_regex() {
+    let search_fields = SearchFields::with_values("foo(", "", false, "bar(?<=baz)(");
+    search_fields.validate_patterns();
+    assert_eq!(
+        search_fields.search().error.clone().unwrap().short,
+        "Couldn't parse regex"
+    );
+    assert_eq!(
+        search_fields.path_pattern().error.clone().unwrap().short,
+        "Couldn't parse regex"
+    );
+
+    let search_fields = SearchFields::with_values("foo", "", false, r"(\w+)/\1");
+    search_fields.validate_patterns();
+    assert_eq!(
+        search_fields.path_pattern().error.clone().unwrap().short,
+        "Couldn't parse regex, try advanced regex"
+    );
+}
+
+#[test]
+fn test_search_type_with_multiple_fixed_strings() {
+    let search_fields = SearchFields::with_values("foo\nbar", "", true, "");
+    match search_fields.search_type().unwrap() {
+        SearchType::MultiFixed(ac) => {
+            let matches = ac
+                .find_iter("foo and bar")
+                .map(|m| m.range())
+                .collect::<Vec<_>>();
+            assert_eq!(matches, vec![0..3, 8..11]);
+        }
+        search_type => panic!("Expected MultiFixed, got {:?}", search_type),
+    }
+}
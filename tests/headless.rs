@@ -0,0 +1,553 @@
+#![cfg(feature = "walker")]
+
+use scooter::{
+    export::OutputFormat,
+    headless::{
+        count_matches, export_results, replace_stream, run_benchmark, run_headless,
+        BenchmarkReport, ExitCode,
+    },
+    map_file::read_map_file,
+    App, ScooterError, SearchFields,
+};
+use serial_test::serial;
+use std::{fs, io, path::PathBuf};
+use tempfile::TempDir;
+use tokio::sync::mpsc;
+
+fn setup_app(temp_dir: &TempDir, search_fields: SearchFields) -> App {
+    let (app_event_sender, _) = mpsc::unbounded_channel();
+    let mut app = App::new(
+        vec![temp_dir.path().to_path_buf()],
+        false,
+        false,
+        app_event_sender,
+    );
+    app.search_fields = search_fields;
+    app
+}
+
+fn create_test_file(contents: &[u8]) -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("file.txt"), contents).unwrap();
+    temp_dir
+}
+
+#[tokio::test]
+#[serial]
+async fn test_exit_code_success() {
+    let temp_dir = create_test_file(b"foo\nbar\nfoo\n");
+    let mut app = setup_app(
+        &temp_dir,
+        SearchFields::with_values("foo", "baz", false, ""),
+    );
+
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::Success
+    );
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("file.txt")).unwrap(),
+        "baz\nbar\nbaz\n"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_exit_code_no_matches() {
+    let temp_dir = create_test_file(b"foo\nbar\n");
+    let mut app = setup_app(
+        &temp_dir,
+        SearchFields::with_values("qux", "baz", false, ""),
+    );
+
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::NoMatches
+    );
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("file.txt")).unwrap(),
+        "foo\nbar\n"
+    );
+}
+
+struct FailingWriter;
+
+impl io::Write for FailingWriter {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_write_error_is_returned() {
+    let temp_dir = create_test_file(b"foo\n");
+    let mut app = setup_app(
+        &temp_dir,
+        SearchFields::with_values("qux", "baz", false, ""),
+    );
+
+    // Rather than the exit code for there being no matches, which was all that was left to report
+    let err = run_headless(&mut app, false, FailingWriter)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    assert_eq!(ExitCode::Error as u8, 4);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_exit_code_occurrence_not_found() {
+    let temp_dir = create_test_file(b"foo\nbar foo\n");
+    let mut app = setup_app(
+        &temp_dir,
+        SearchFields::with_values("foo", "baz", false, ""),
+    )
+    .with_occurrence(Some(3));
+
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::NoMatches
+    );
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("file.txt")).unwrap(),
+        "foo\nbar foo\n"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_exit_code_replacement_errors() {
+    // Lines that aren't valid UTF-8 are searched in lossy mode but never replaced
+    let temp_dir = create_test_file(b"foo\nfoo \xff\n");
+    let mut app =
+        setup_app(&temp_dir, SearchFields::with_values("foo", "baz", true, "")).with_lossy(true);
+
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::ReplacementErrors
+    );
+    assert_eq!(
+        fs::read(temp_dir.path().join("file.txt")).unwrap(),
+        b"baz\nfoo \xff\n"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_exit_code_invalid_regex() {
+    let temp_dir = create_test_file(b"foo\n");
+    let mut app = setup_app(
+        &temp_dir,
+        SearchFields::with_values("foo(", "baz", false, ""),
+    );
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::InvalidArguments
+    );
+
+    let mut app = setup_app(
+        &temp_dir,
+        SearchFields::with_values("foo", "baz", false, "[a-"),
+    );
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::InvalidArguments
+    );
+
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("file.txt")).unwrap(),
+        "foo\n"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_quiet_writes_nothing() {
+    let temp_dir = create_test_file(b"foo\nbar\n");
+    for (quiet, expected_output) in [(false, "No matches found\n"), (true, "")] {
+        let mut app = setup_app(
+            &temp_dir,
+            SearchFields::with_values("qux", "baz", false, ""),
+        );
+        let mut output = vec![];
+        assert_eq!(
+            run_headless(&mut app, quiet, &mut output).await.unwrap(),
+            ExitCode::NoMatches
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected_output);
+    }
+
+    for (quiet, expected_output) in [(false, "Successful replacements: 1\n"), (true, "")] {
+        let mut app = setup_app(
+            &temp_dir,
+            SearchFields::with_values("foo", "foo", false, ""),
+        );
+        let mut output = vec![];
+        assert_eq!(
+            run_headless(&mut app, quiet, &mut output).await.unwrap(),
+            ExitCode::Success
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected_output);
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_quiet_export_still_writes_results() {
+    let temp_dir = create_test_file(b"foo\n");
+    let mut app = setup_app(
+        &temp_dir,
+        SearchFields::with_values("foo", "bar", false, ""),
+    );
+
+    let mut output = vec![];
+    assert_eq!(
+        export_results(&mut app, OutputFormat::Csv, true, &mut output)
+            .await
+            .unwrap(),
+        ExitCode::Success
+    );
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "path,line_number,line,replacement,included\n./file.txt,1,foo,bar,true\n"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_exit_code_git_modified_outside_repo() {
+    let temp_dir = create_test_file(b"foo\n");
+    let mut app = setup_app(&temp_dir, SearchFields::with_values("foo", "baz", true, ""))
+        .with_git_modified_only(true);
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::InvalidArguments
+    );
+    assert_eq!(
+        fs::read(temp_dir.path().join("file.txt")).unwrap(),
+        b"foo\n"
+    );
+}
+
+#[test]
+fn test_exit_code_values() {
+    assert_eq!(ExitCode::Success as u8, 0);
+    assert_eq!(ExitCode::NoMatches as u8, 1);
+    assert_eq!(ExitCode::ReplacementErrors as u8, 2);
+    assert_eq!(ExitCode::InvalidArguments as u8, 3);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_export_results_no_matches_writes_nothing() {
+    let temp_dir = create_test_file(b"foo\n");
+    let mut app = setup_app(&temp_dir, SearchFields::with_values("bar", "", false, ""));
+
+    // "No matches found" goes to stderr, so that the output can always be parsed as CSV
+    let mut output = vec![];
+    assert_eq!(
+        export_results(&mut app, OutputFormat::Csv, false, &mut output)
+            .await
+            .unwrap(),
+        ExitCode::NoMatches
+    );
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_export_results_search_only() {
+    let temp_dir = create_test_file(b"foo\n");
+    let mut app = setup_app(&temp_dir, SearchFields::with_values("foo", "", false, ""))
+        .with_search_only(true);
+
+    let mut output = vec![];
+    assert_eq!(
+        export_results(&mut app, OutputFormat::Csv, false, &mut output)
+            .await
+            .unwrap(),
+        ExitCode::Success
+    );
+    // Without a replacement, each line is left as it is
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "path,line_number,line,replacement,included\n./file.txt,1,foo,foo,true\n"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_export_results_as_csv() {
+    let temp_dir = create_test_file(b"foo, bar\nbaz\n\"foo\"\n");
+    let mut app = setup_app(
+        &temp_dir,
+        SearchFields::with_values("foo", "qux", false, ""),
+    );
+
+    let mut output = vec![];
+    assert_eq!(
+        export_results(&mut app, OutputFormat::Csv, false, &mut output)
+            .await
+            .unwrap(),
+        ExitCode::Success
+    );
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        concat!(
+            "path,line_number,line,replacement,included\n",
+            "./file.txt,1,\"foo, bar\",\"qux, bar\",true\n",
+            "./file.txt,3,\"\"\"foo\"\"\",\"\"\"qux\"\"\",true\n",
+        )
+    );
+
+    // Nothing is replaced
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("file.txt")).unwrap(),
+        "foo, bar\nbaz\n\"foo\"\n"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_repeated_replacement_finds_no_matches() {
+    let temp_dir = create_test_file(b"foo\nbar foo\n");
+    let search_fields = || SearchFields::with_values("fo+", "baz", false, "");
+
+    let mut app = setup_app(&temp_dir, search_fields());
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::Success
+    );
+    let mut app = setup_app(&temp_dir, search_fields());
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::NoMatches
+    );
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("file.txt")).unwrap(),
+        "baz\nbar baz\n"
+    );
+}
+
+#[tokio::test]
+async fn test_searching_filesystem_root_requires_confirmation() {
+    let (app_event_sender, _) = mpsc::unbounded_channel();
+    let mut app = App::new(vec![PathBuf::from("/")], false, false, app_event_sender);
+    app.search_fields = SearchFields::with_values("foo", "bar", false, "");
+
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::InvalidArguments
+    );
+    assert!(app.confirming_large_search);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_count_matches() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("dir")).unwrap();
+    fs::write(temp_dir.path().join("b.txt"), "foo foo\nbar\nfoo\n").unwrap();
+    fs::write(temp_dir.path().join("dir/a.txt"), "foo\n").unwrap();
+    fs::write(temp_dir.path().join("c.txt"), "bar\n").unwrap();
+    let mut app = setup_app(&temp_dir, SearchFields::with_values("foo", "", false, ""));
+
+    let mut output = vec![];
+    let exit_code = count_matches(&mut app, false, &mut output).await.unwrap();
+
+    assert_eq!(exit_code, ExitCode::Success);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "./b.txt: 3\n./dir/a.txt: 1\nTotal: 4 matches in 2 files\n"
+    );
+
+    // Only the counts for each file are written when quiet
+    let mut output = vec![];
+    count_matches(&mut app, true, &mut output).await.unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "./b.txt: 3\n./dir/a.txt: 1\n"
+    );
+    // Nothing is replaced
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("b.txt")).unwrap(),
+        "foo foo\nbar\nfoo\n"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_run_benchmark() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("dir")).unwrap();
+    fs::write(temp_dir.path().join("b.txt"), "foo foo\nbar\nfoo\n").unwrap();
+    fs::write(temp_dir.path().join("dir/a.txt"), "foo\n").unwrap();
+    fs::write(temp_dir.path().join("c.txt"), "bar\n").unwrap();
+    let mut app = setup_app(
+        &temp_dir,
+        SearchFields::with_values("foo", "baz", false, ""),
+    );
+
+    let mut output = vec![];
+    let exit_code = run_benchmark(&mut app, &mut output).unwrap();
+
+    assert_eq!(exit_code, ExitCode::Success);
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.lines().count(), 1);
+    let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let mut fields = json.as_object().unwrap().keys().collect::<Vec<_>>();
+    fields.sort();
+    assert_eq!(
+        fields,
+        ["elapsed_ms", "files_scanned", "matches", "matching_lines"]
+    );
+
+    let report: BenchmarkReport = serde_json::from_str(&output).unwrap();
+    assert_eq!(report.files_scanned, 3);
+    assert_eq!(report.matching_lines, 3);
+    assert_eq!(report.matches, 4);
+    // Nothing is replaced
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("b.txt")).unwrap(),
+        "foo foo\nbar\nfoo\n"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_count_matches_no_matches() {
+    let temp_dir = create_test_file(b"foo\n");
+    let mut app = setup_app(&temp_dir, SearchFields::with_values("bar", "", false, ""));
+
+    for (quiet, expected_output) in [(false, "No matches found\n"), (true, "")] {
+        let mut output = vec![];
+        let exit_code = count_matches(&mut app, quiet, &mut output).await.unwrap();
+
+        assert_eq!(exit_code, ExitCode::NoMatches);
+        assert_eq!(String::from_utf8(output).unwrap(), expected_output);
+    }
+}
+
+#[test]
+fn test_replace_stream() {
+    let (app_event_sender, _) = mpsc::unbounded_channel();
+    let mut app = App::new(vec![], false, false, app_event_sender);
+    app.search_fields = SearchFields::with_values(r"(\w+)@example\.com", "$1 (${n})", false, "");
+
+    let input = "alice@example.com\r\nnothing here\nbob@example.com, carol@example.com";
+    let mut output = vec![];
+    assert_eq!(
+        replace_stream(&mut app, input.as_bytes(), &mut output).unwrap(),
+        ExitCode::Success
+    );
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "alice (0)\r\nnothing here\nbob (1), carol (2)"
+    );
+}
+
+#[test]
+fn test_replace_stream_no_matches() {
+    let (app_event_sender, _) = mpsc::unbounded_channel();
+    let mut app = App::new(vec![], false, false, app_event_sender);
+    app.search_fields = SearchFields::with_values("qux", "baz", false, "");
+
+    let mut output = vec![];
+    assert_eq!(
+        replace_stream(&mut app, "foo\nbar\n".as_bytes(), &mut output).unwrap(),
+        ExitCode::NoMatches
+    );
+    assert_eq!(output, b"foo\nbar\n");
+}
+
+#[test]
+fn test_replace_stream_invalid_regex() {
+    let (app_event_sender, _) = mpsc::unbounded_channel();
+    let mut app = App::new(vec![], false, false, app_event_sender);
+    app.search_fields = SearchFields::with_values("(foo", "baz", false, "");
+
+    let mut output = vec![];
+    assert_eq!(
+        replace_stream(&mut app, "foo\n".as_bytes(), &mut output).unwrap(),
+        ExitCode::InvalidArguments
+    );
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_replace_with_map_file_across_files() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("file1.txt"),
+        "let old_name = OldType::new();\n",
+    )
+    .unwrap();
+    fs::write(
+        temp_dir.path().join("file2.txt"),
+        "fn old_name_helper(x: OldType) {}\nunchanged\n",
+    )
+    .unwrap();
+    let map_dir = TempDir::new().unwrap();
+    let map_path = map_dir.path().join("map.csv");
+    fs::write(
+        &map_path,
+        "old_name,new_name\nold_name_helper,helper\nOldType,NewType\n",
+    )
+    .unwrap();
+
+    let map = read_map_file(&map_path).unwrap();
+    let mut app = setup_app(
+        &temp_dir,
+        SearchFields::with_values(map.search_text(), "", true, ""),
+    )
+    .with_replacement_map(Some(map));
+
+    assert_eq!(
+        run_headless(&mut app, false, io::sink()).await.unwrap(),
+        ExitCode::Success
+    );
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("file1.txt")).unwrap(),
+        "let new_name = NewType::new();\n"
+    );
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("file2.txt")).unwrap(),
+        "fn helper(x: NewType) {}\nunchanged\n"
+    );
+}
+
+#[test]
+fn test_read_map_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let json_path = temp_dir.path().join("map.json");
+    fs::write(&json_path, r#"{"foo": "bar", "a,b": "c"}"#).unwrap();
+    let map = read_map_file(&json_path).unwrap();
+    assert_eq!(map.replacement("foo"), "bar");
+    assert_eq!(map.replacement("a,b"), "c");
+    assert_eq!(map.replacement("baz"), "baz");
+
+    let csv_path = temp_dir.path().join("map.csv");
+    fs::write(&csv_path, "foo,bar\n\"a,b\",c\n").unwrap();
+    assert_eq!(read_map_file(&csv_path).unwrap(), map);
+
+    fs::write(&csv_path, "foo\n").unwrap();
+    assert!(matches!(
+        read_map_file(&csv_path),
+        Err(ScooterError::InvalidMapFile(_, _))
+    ));
+
+    // Lines are searched one at a time, so strings can't contain line breaks
+    for contents in [r#"{"foo\nbar": "baz"}"#, r#"{"foo\r": "baz"}"#] {
+        fs::write(&json_path, contents).unwrap();
+        assert!(matches!(
+            read_map_file(&json_path),
+            Err(ScooterError::InvalidMapFile(_, _))
+        ));
+    }
+}
@@ -0,0 +1,486 @@
+#![cfg(feature = "walker")]
+
+use fancy_regex::Regex as FancyRegex;
+use globset::Glob;
+use regex::Regex;
+use scooter::{
+    parsed_fields::{FuzzyPathMatcher, ParsedFields, PathMatcher, SearchObserver, SearchType},
+    replace::LineReplacer,
+    BackgroundProcessingEvent, ReplaceWarning, SearchResult,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use tempfile::TempDir;
+use tokio::sync::mpsc;
+
+#[test]
+fn test_count_without_replacement() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("file.txt");
+    fs::write(&path, "foo bar\nbaz\nfoo foo\n").unwrap();
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let parsed_fields = ParsedFields::new(
+        SearchType::Fixed("foo".to_owned()),
+        None,
+        None,
+        vec![temp_dir.path().to_path_buf()],
+        false,
+        false,
+        sender,
+    );
+    parsed_fields.handle_path(&path);
+
+    let mut results = vec![];
+    while let Ok(BackgroundProcessingEvent::AddSearchResult(result)) = receiver.try_recv() {
+        results.push(result);
+    }
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|res| res.line == res.replacement));
+}
+
+fn search_file(search_pattern: SearchType, replace: &str, contents: &str) -> Vec<SearchResult> {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("file.txt");
+    fs::write(&path, contents).unwrap();
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let replacer = replacer_for(search_pattern.clone());
+    let parsed_fields = ParsedFields::new(
+        search_pattern,
+        Some(replace.to_owned()),
+        None,
+        vec![temp_dir.path().to_path_buf()],
+        false,
+        false,
+        sender,
+    );
+    parsed_fields.handle_path(&path);
+
+    let mut results = vec![];
+    while let Ok(BackgroundProcessingEvent::AddSearchResult(result)) = receiver.try_recv() {
+        assert!(replacer.would_match(&result.line));
+        results.push(result);
+    }
+    results
+}
+
+fn replacer_for(search_pattern: SearchType) -> LineReplacer {
+    LineReplacer::new(search_pattern, None)
+}
+
+#[test]
+fn test_would_match_false_after_idempotent_replacement() {
+    let contents = "foo bar\nbaz\nfoo foo\n";
+    let search_patterns = [
+        SearchType::Fixed("foo".to_owned()),
+        SearchType::Pattern(Regex::new(r"fo+").unwrap()),
+        SearchType::PatternAdvanced(FancyRegex::new(r"foo(?! bar)|foo").unwrap()),
+    ];
+    for search_pattern in search_patterns {
+        let results = search_file(search_pattern.clone(), "qux", contents);
+        assert_eq!(results.len(), 2);
+
+        let replacer = replacer_for(search_pattern);
+        for result in results {
+            assert!(
+                !replacer.would_match(&result.replacement),
+                "{:?} still matches",
+                result.replacement
+            );
+        }
+    }
+}
+
+#[test]
+fn test_would_match_true_after_non_idempotent_replacement() {
+    let search_pattern = SearchType::Fixed("foo".to_owned());
+    let results = search_file(search_pattern.clone(), "foobar", "foo\nbaz\n");
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].replacement, "foobar");
+    assert!(replacer_for(search_pattern).would_match(&results[0].replacement));
+}
+
+#[test]
+fn test_num_matches() {
+    let contents = "foo bar foo\nbaz\nfoofoo foo\nfoo\n";
+    let search_patterns = [
+        SearchType::Fixed("foo".to_owned()),
+        SearchType::Pattern(Regex::new(r"fo+").unwrap()),
+        SearchType::PatternAdvanced(FancyRegex::new(r"foo(?!bar)").unwrap()),
+    ];
+    for search_pattern in search_patterns {
+        let mut results = search_file(search_pattern.clone(), "qux", contents);
+        results.sort_by_key(|res| res.line_number);
+        assert_eq!(
+            results
+                .iter()
+                .map(|res| (res.line_number, res.num_matches))
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (3, 3), (4, 1)],
+            "{:?}",
+            search_pattern
+        );
+    }
+}
+
+#[test]
+fn test_replace_with_case_conversion() {
+    let search_patterns = [
+        SearchType::Pattern(Regex::new(r"(\w+)_(\w+)").unwrap()),
+        SearchType::PatternAdvanced(FancyRegex::new(r"(\w+)_(\w+)").unwrap()),
+    ];
+    for search_pattern in search_patterns {
+        let replacement = |replace: &str| {
+            search_file(search_pattern.clone(), replace, "let foo_Bar = 1;\n")[0]
+                .replacement
+                .clone()
+        };
+        assert_eq!(replacement(r"\U$1\E_$2"), "let FOO_Bar = 1;");
+        assert_eq!(replacement(r"$1\L$2"), "let foobar = 1;");
+        assert_eq!(replacement(r"\U$1\L$2\E!"), "let FOObar! = 1;");
+        assert_eq!(replacement(r"\Lconst_\U$2"), "let const_BAR = 1;");
+    }
+}
+
+#[test]
+fn test_case_conversion_not_applied_with_fixed_strings() {
+    let results = search_file(SearchType::Fixed("foo".to_owned()), r"\Ubar", "foo\n");
+    assert_eq!(results[0].replacement, r"\Ubar");
+}
+
+fn warnings(search_pattern: SearchType, replace: &str, line: &str) -> Vec<ReplaceWarning> {
+    let results = search_file(search_pattern, replace, &format!("{line}\n"));
+    assert_eq!(results.len(), 1);
+    results[0].warnings.clone()
+}
+
+#[test]
+fn test_overlapping_alternation_warning() {
+    let search_patterns = [
+        SearchType::Pattern(Regex::new(r"ab|bc").unwrap()),
+        SearchType::PatternAdvanced(FancyRegex::new(r"ab|bc").unwrap()),
+    ];
+    for search_pattern in search_patterns {
+        assert_eq!(
+            warnings(search_pattern.clone(), "x", "abc"),
+            vec![ReplaceWarning::OverlappingMatches]
+        );
+        assert!(warnings(search_pattern.clone(), "x", "ab bc").is_empty());
+        // Only a later match overlaps
+        assert_eq!(
+            warnings(search_pattern, "x", "ab bc abc"),
+            vec![ReplaceWarning::OverlappingMatches]
+        );
+    }
+    assert_eq!(
+        warnings(SearchType::Fixed("aa".to_owned()), "b", "aaa"),
+        vec![ReplaceWarning::OverlappingMatches]
+    );
+
+    // Shorter matches within a greedy match aren't overlaps
+    for (pattern, line) in [(r"a+", "aaa"), (r"\w+", "foo bar")] {
+        let search_pattern = SearchType::Pattern(Regex::new(pattern).unwrap());
+        assert!(!warnings(search_pattern, "x", line).contains(&ReplaceWarning::OverlappingMatches));
+    }
+}
+
+#[test]
+fn test_replacement_matches_search_warning() {
+    let search_patterns = [
+        SearchType::Fixed("foo".to_owned()),
+        SearchType::Pattern(Regex::new(r"fo+").unwrap()),
+        SearchType::PatternAdvanced(FancyRegex::new(r"fo+").unwrap()),
+    ];
+    for search_pattern in search_patterns {
+        assert_eq!(
+            warnings(search_pattern.clone(), "foobar", "let foo = 1;"),
+            vec![ReplaceWarning::ReplacementMatches]
+        );
+        assert!(warnings(search_pattern.clone(), "bar", "let foo = 1;").is_empty());
+        // Replacing a match with itself changes nothing, so isn't flagged
+        assert!(warnings(search_pattern, "foo", "let foo = 1;").is_empty());
+    }
+}
+
+#[test]
+fn test_fuzzy_path_matcher() {
+    let matcher = FuzzyPathMatcher::new("srcmain");
+    assert!(matcher.is_match("src/main.rs"));
+    assert!(matcher.is_match("src/app/domain.rs"));
+    assert!(!matcher.is_match("tests/main.rs"));
+    assert!(!matcher.is_match("README.md"));
+
+    // Lowercase queries ignore case, but uppercase letters must match exactly
+    assert!(FuzzyPathMatcher::new("readme").is_match("README.md"));
+    assert!(!FuzzyPathMatcher::new("Readme").is_match("README.md"));
+}
+
+#[test]
+fn test_path_matcher() {
+    let fixed = PathMatcher::Fixed("src/app".to_owned());
+    assert!(fixed.is_match("./src/app/mod.rs"));
+    assert!(!fixed.is_match("./src/apps/mod.rs"));
+
+    let regex = PathMatcher::Regex(Regex::new(r"^\./src").unwrap());
+    assert!(regex.is_match("./src/main.rs"));
+    assert!(!regex.is_match("./tests/src.rs"));
+
+    // Globs and fuzzy queries are matched without the leading `./`
+    let glob = PathMatcher::Glob(Glob::new("src/*.rs").unwrap().compile_matcher());
+    assert!(glob.is_match("./src/main.rs"));
+    assert!(!glob.is_match("./tests/main.rs"));
+    let fuzzy = PathMatcher::Fuzzy(FuzzyPathMatcher::new("srcmain"));
+    assert!(fuzzy.is_match("./src/main.rs"));
+    assert!(!fuzzy.is_match("./tests/main.rs"));
+}
+
+#[test]
+fn test_path_matcher_backtrack_limit() {
+    // Exceeds the backtracking limit, which is treated as not matching rather than panicking
+    let regex = PathMatcher::RegexAdvanced(FancyRegex::new(r"(a|aa)+\1b").unwrap());
+    assert!(!regex.is_match(&format!("./{}", "a".repeat(40))));
+}
+
+#[tokio::test]
+async fn test_search_stream_yields_all_results() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("dir")).unwrap();
+    fs::write(temp_dir.path().join("a.txt"), "foo\nbar\nfoo bar\n").unwrap();
+    fs::write(temp_dir.path().join("dir/b.txt"), "baz foo\n").unwrap();
+    fs::write(temp_dir.path().join("dir/c.txt"), "no match\n").unwrap();
+    fs::write(temp_dir.path().join("image.png"), "foo\n").unwrap();
+
+    let (sender, _receiver) = mpsc::unbounded_channel();
+    let parsed_fields = ParsedFields::new(
+        SearchType::Fixed("foo".to_owned()),
+        Some("qux".to_owned()),
+        None,
+        vec![temp_dir.path().to_path_buf()],
+        false,
+        false,
+        sender,
+    );
+
+    let mut stream = parsed_fields.search_stream();
+    let mut results = vec![];
+    while let Some(result) = stream.recv().await {
+        results.push(result);
+    }
+    let mut results = results
+        .into_iter()
+        .map(|res| {
+            let path = res.path.strip_prefix(temp_dir.path()).unwrap().to_owned();
+            (path, res.line_number, res.replacement)
+        })
+        .collect::<Vec<_>>();
+    results.sort();
+
+    assert_eq!(
+        results,
+        vec![
+            ("a.txt".into(), 1, "qux".to_owned()),
+            ("a.txt".into(), 3, "qux bar".to_owned()),
+            ("dir/b.txt".into(), 1, "baz qux".to_owned()),
+        ]
+    );
+}
+
+#[derive(Default)]
+struct RecordingObserver {
+    results: Mutex<Vec<(PathBuf, usize)>>,
+    scanned: Mutex<Vec<PathBuf>>,
+    binary_lines_skipped: Mutex<Vec<(PathBuf, usize)>>,
+    num_completed: Mutex<usize>,
+}
+
+impl SearchObserver for RecordingObserver {
+    fn on_result(&self, result: &SearchResult) {
+        assert_eq!(*self.num_completed.lock().unwrap(), 0);
+        self.results
+            .lock()
+            .unwrap()
+            .push((result.path.clone(), result.line_number));
+    }
+
+    fn on_file_scanned(&self, path: &Path) {
+        self.scanned.lock().unwrap().push(path.to_owned());
+    }
+
+    fn on_binary_lines_skipped(&self, path: &Path, num_lines: usize) {
+        self.binary_lines_skipped
+            .lock()
+            .unwrap()
+            .push((path.to_owned(), num_lines));
+    }
+
+    fn on_complete(&self) {
+        *self.num_completed.lock().unwrap() += 1;
+    }
+}
+
+#[test]
+fn test_search_observer_callbacks() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("a.txt"), "foo\nbar\nfoo bar\n").unwrap();
+    fs::write(temp_dir.path().join("b.txt"), "no match\n").unwrap();
+    fs::write(temp_dir.path().join("c.bin"), b"foo\x00\x01\nfoo\x00\x02\n").unwrap();
+    fs::write(temp_dir.path().join("image.png"), "foo\n").unwrap();
+
+    let (sender, _receiver) = mpsc::unbounded_channel();
+    let parsed_fields = ParsedFields::new(
+        SearchType::Fixed("foo".to_owned()),
+        Some("qux".to_owned()),
+        None,
+        vec![temp_dir.path().to_path_buf()],
+        false,
+        false,
+        sender,
+    );
+
+    let observer = RecordingObserver::default();
+    parsed_fields.search_observed(&observer);
+
+    let a_path = temp_dir.path().join("a.txt");
+    let mut results = observer.results.into_inner().unwrap();
+    results.sort();
+    assert_eq!(results, vec![(a_path.clone(), 1), (a_path.clone(), 3)]);
+    let mut scanned = observer.scanned.into_inner().unwrap();
+    scanned.sort();
+    let c_path = temp_dir.path().join("c.bin");
+    assert_eq!(
+        scanned,
+        vec![a_path, temp_dir.path().join("b.txt"), c_path.clone()]
+    );
+    assert_eq!(
+        observer.binary_lines_skipped.into_inner().unwrap(),
+        vec![(c_path, 2)]
+    );
+    assert_eq!(observer.num_completed.into_inner().unwrap(), 1);
+
+    // Searching a single path reports the file as scanned, but doesn't complete the search
+    let observer = RecordingObserver::default();
+    parsed_fields.handle_path_observed(&temp_dir.path().join("b.txt"), &observer);
+    assert!(observer.results.into_inner().unwrap().is_empty());
+    assert_eq!(observer.scanned.into_inner().unwrap().len(), 1);
+    assert_eq!(observer.num_completed.into_inner().unwrap(), 0);
+}
+
+#[test]
+fn test_search_files_changed_within_window() {
+    let temp_dir = TempDir::new().unwrap();
+    let now = SystemTime::now();
+    let day = Duration::from_secs(24 * 60 * 60);
+    for (name, age) in [("new.txt", 0), ("week_old.txt", 7), ("month_old.txt", 30)] {
+        let path = temp_dir.path().join(name);
+        fs::write(&path, "foo\n").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(now - day * age).unwrap();
+    }
+
+    let searched_files = |changed_within: Option<Duration>, changed_before: Option<Duration>| {
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let parsed_fields = ParsedFields::new(
+            SearchType::Fixed("foo".to_owned()),
+            None,
+            None,
+            vec![temp_dir.path().to_path_buf()],
+            false,
+            false,
+            sender,
+        )
+        .with_changed_within(changed_within)
+        .with_changed_before(changed_before);
+        let observer = RecordingObserver::default();
+        parsed_fields.search_observed(&observer);
+        let mut names = observer
+            .scanned
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+
+    assert_eq!(
+        searched_files(None, None),
+        vec!["month_old.txt", "new.txt", "week_old.txt"]
+    );
+    assert_eq!(searched_files(Some(day * 2), None), vec!["new.txt"]);
+    assert_eq!(
+        searched_files(None, Some(day)),
+        vec!["month_old.txt", "week_old.txt"]
+    );
+    assert_eq!(
+        searched_files(Some(day * 14), Some(day)),
+        vec!["week_old.txt"]
+    );
+}
+
+#[test]
+fn test_search_git_directory() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir_all(temp_dir.path().join(".git/hooks")).unwrap();
+    fs::create_dir_all(temp_dir.path().join(".git/objects")).unwrap();
+    fs::write(temp_dir.path().join(".git/hooks/pre-commit"), "foo\n").unwrap();
+    fs::write(temp_dir.path().join(".git/objects/abc"), b"foo\x00\x01\x02").unwrap();
+    fs::write(temp_dir.path().join(".hidden.txt"), "foo\n").unwrap();
+    fs::write(temp_dir.path().join("file.txt"), "foo\n").unwrap();
+
+    let searched_files = |search_git: bool, include_hidden: bool| {
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let parsed_fields = ParsedFields::new(
+            SearchType::Fixed("foo".to_owned()),
+            None,
+            None,
+            vec![temp_dir.path().to_path_buf()],
+            include_hidden,
+            false,
+            sender,
+        )
+        .with_search_git(search_git);
+        let observer = RecordingObserver::default();
+        parsed_fields.search_observed(&observer);
+        let mut paths = observer
+            .results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path.strip_prefix(temp_dir.path()).unwrap().to_owned())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    };
+
+    assert_eq!(
+        searched_files(false, false),
+        vec![PathBuf::from("file.txt")]
+    );
+    assert_eq!(
+        searched_files(false, true),
+        vec![PathBuf::from(".hidden.txt"), PathBuf::from("file.txt")]
+    );
+    // Binary files such as git objects are still skipped, and other hidden files are only
+    // searched if hidden files are included
+    assert_eq!(
+        searched_files(true, false),
+        vec![
+            PathBuf::from(".git/hooks/pre-commit"),
+            PathBuf::from("file.txt")
+        ]
+    );
+    assert_eq!(
+        searched_files(true, true),
+        vec![
+            PathBuf::from(".git/hooks/pre-commit"),
+            PathBuf::from(".hidden.txt"),
+            PathBuf::from("file.txt")
+        ]
+    );
+}
@@ -1,36 +1,37 @@
+#![cfg(feature = "walker")]
+
 #[cfg(test)]
 mod tests {
-    use ratatui::style::Color;
-    use scooter::{line_diff, Diff};
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use ratatui::{
+        backend::TestBackend,
+        layout::Rect,
+        style::{Color, Modifier},
+        Terminal,
+    };
+    use regex::Regex;
+    use scooter::replace::{LineReplacer, SearchType};
+    use scooter::{
+        content_area, crop_diff, format_byte_delta, inline_diff, line_diff, line_diff_with_matches,
+        line_diff_with_timeout, plain_text_diff, render, result_diff_lines, result_item_height,
+        result_line_diff, App, BackgroundProcessingEvent, ContentWidth, Diff, EventHandler,
+        FileConfirmationState, PerformingReplacementState, ReplaceResult, ReplaceState,
+        ReplaceWarning, ReplacementProgress, ReplacementSummary, Screen, SearchResult, SearchState,
+    };
+    use std::{collections::HashMap, path::PathBuf, time::Duration};
 
     #[test]
     fn test_identical_lines() {
         let (old_actual, new_actual) = line_diff("hello", "hello");
 
         let old_expected = vec![
-            Diff {
-                text: "- ".to_owned(),
-                fg_colour: Color::Red,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "hello".to_owned(),
-                fg_colour: Color::Red,
-                bg_colour: Color::Reset,
-            },
+            Diff::new("- ", Color::Red, Color::Reset),
+            Diff::new("hello", Color::Red, Color::Reset),
         ];
 
         let new_expected = vec![
-            Diff {
-                text: "+ ".to_owned(),
-                fg_colour: Color::Green,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "hello".to_owned(),
-                fg_colour: Color::Green,
-                bg_colour: Color::Reset,
-            },
+            Diff::new("+ ", Color::Green, Color::Reset),
+            Diff::new("hello", Color::Green, Color::Reset),
         ];
 
         assert_eq!(old_expected, old_actual);
@@ -42,49 +43,17 @@ mod tests {
         let (old_actual, new_actual) = line_diff("hello", "hallo");
 
         let old_expected = vec![
-            Diff {
-                text: "- ".to_owned(),
-                fg_colour: Color::Red,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "h".to_owned(),
-                fg_colour: Color::Red,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "e".to_owned(),
-                fg_colour: Color::Black,
-                bg_colour: Color::Red,
-            },
-            Diff {
-                text: "llo".to_owned(),
-                fg_colour: Color::Red,
-                bg_colour: Color::Reset,
-            },
+            Diff::new("- ", Color::Red, Color::Reset),
+            Diff::new("h", Color::Red, Color::Reset),
+            Diff::new("e", Color::Black, Color::Red),
+            Diff::new("llo", Color::Red, Color::Reset),
         ];
 
         let new_expected = vec![
-            Diff {
-                text: "+ ".to_owned(),
-                fg_colour: Color::Green,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "h".to_owned(),
-                fg_colour: Color::Green,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "a".to_owned(),
-                fg_colour: Color::Black,
-                bg_colour: Color::Green,
-            },
-            Diff {
-                text: "llo".to_owned(),
-                fg_colour: Color::Green,
-                bg_colour: Color::Reset,
-            },
+            Diff::new("+ ", Color::Green, Color::Reset),
+            Diff::new("h", Color::Green, Color::Reset),
+            Diff::new("a", Color::Black, Color::Green),
+            Diff::new("llo", Color::Green, Color::Reset),
         ];
 
         assert_eq!(old_expected, old_actual);
@@ -96,29 +65,13 @@ mod tests {
         let (old_actual, new_actual) = line_diff("foo", "bar");
 
         let old_expected = vec![
-            Diff {
-                text: "- ".to_owned(),
-                fg_colour: Color::Red,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "foo".to_owned(),
-                fg_colour: Color::Black,
-                bg_colour: Color::Red,
-            },
+            Diff::new("- ", Color::Red, Color::Reset),
+            Diff::new("foo", Color::Black, Color::Red),
         ];
 
         let new_expected = vec![
-            Diff {
-                text: "+ ".to_owned(),
-                fg_colour: Color::Green,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "bar".to_owned(),
-                fg_colour: Color::Black,
-                bg_colour: Color::Green,
-            },
+            Diff::new("+ ", Color::Green, Color::Reset),
+            Diff::new("bar", Color::Black, Color::Green),
         ];
 
         assert_eq!(old_expected, old_actual);
@@ -129,17 +82,9 @@ mod tests {
     fn test_empty_strings() {
         let (old_actual, new_actual) = line_diff("", "");
 
-        let old_expected = vec![Diff {
-            text: "- ".to_owned(),
-            fg_colour: Color::Red,
-            bg_colour: Color::Reset,
-        }];
+        let old_expected = vec![Diff::new("- ", Color::Red, Color::Reset)];
 
-        let new_expected = vec![Diff {
-            text: "+ ".to_owned(),
-            fg_colour: Color::Green,
-            bg_colour: Color::Reset,
-        }];
+        let new_expected = vec![Diff::new("+ ", Color::Green, Color::Reset)];
 
         assert_eq!(old_expected, old_actual);
         assert_eq!(new_expected, new_actual);
@@ -150,34 +95,14 @@ mod tests {
         let (old_actual, new_actual) = line_diff("hello", "hello!");
 
         let old_expected = vec![
-            Diff {
-                text: "- ".to_owned(),
-                fg_colour: Color::Red,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "hello".to_owned(),
-                fg_colour: Color::Red,
-                bg_colour: Color::Reset,
-            },
+            Diff::new("- ", Color::Red, Color::Reset),
+            Diff::new("hello", Color::Red, Color::Reset),
         ];
 
         let new_expected = vec![
-            Diff {
-                text: "+ ".to_owned(),
-                fg_colour: Color::Green,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "hello".to_owned(),
-                fg_colour: Color::Green,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "!".to_owned(),
-                fg_colour: Color::Black,
-                bg_colour: Color::Green,
-            },
+            Diff::new("+ ", Color::Green, Color::Reset),
+            Diff::new("hello", Color::Green, Color::Reset),
+            Diff::new("!", Color::Black, Color::Green),
         ];
 
         assert_eq!(old_expected, old_actual);
@@ -189,37 +114,1028 @@ mod tests {
         let (old_actual, new_actual) = line_diff("hello", "!hello");
 
         let old_expected = vec![
-            Diff {
-                text: "- ".to_owned(),
-                fg_colour: Color::Red,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "hello".to_owned(),
-                fg_colour: Color::Red,
-                bg_colour: Color::Reset,
-            },
+            Diff::new("- ", Color::Red, Color::Reset),
+            Diff::new("hello", Color::Red, Color::Reset),
         ];
 
         let new_expected = vec![
-            Diff {
-                text: "+ ".to_owned(),
-                fg_colour: Color::Green,
-                bg_colour: Color::Reset,
-            },
-            Diff {
-                text: "!".to_owned(),
-                fg_colour: Color::Black,
-                bg_colour: Color::Green,
-            },
-            Diff {
-                text: "hello".to_owned(),
-                fg_colour: Color::Green,
-                bg_colour: Color::Reset,
-            },
+            Diff::new("+ ", Color::Green, Color::Reset),
+            Diff::new("!", Color::Black, Color::Green),
+            Diff::new("hello", Color::Green, Color::Reset),
         ];
 
         assert_eq!(old_expected, old_actual);
         assert_eq!(new_expected, new_actual);
     }
+
+    fn diff_text(diff: &[Diff]) -> String {
+        diff.iter().map(|d| d.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_crop_diff_short_line_unchanged() {
+        let (old_line, _) = line_diff("foo bar", "foo baz");
+        assert_eq!(crop_diff(old_line.clone(), 20), old_line);
+    }
+
+    #[test]
+    fn test_crop_diff_change_at_start() {
+        let line = format!("foo {}", "x".repeat(100));
+        let (old_line, new_line) = line_diff(&line, &line.replacen("foo", "bar", 1));
+        assert_eq!(diff_text(&crop_diff(old_line, 12)), "- foo xxxxxx");
+        assert_eq!(diff_text(&crop_diff(new_line, 12)), "+ bar xxxxxx");
+    }
+
+    #[test]
+    fn test_crop_diff_change_in_middle() {
+        let line = format!("{}foo{}", "a".repeat(50), "b".repeat(50));
+        let (old_line, new_line) = line_diff(&line, &line.replacen("foo", "qux", 1));
+        assert_eq!(diff_text(&crop_diff(old_line, 13)), "- aaaafoobbbb");
+
+        let cropped = crop_diff(new_line, 13);
+        assert_eq!(diff_text(&cropped), "+ aaaaquxbbbb");
+        let changed = cropped
+            .iter()
+            .filter(|d| d.bg_colour == Color::Green)
+            .map(|d| d.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec!["qux"]);
+    }
+
+    #[test]
+    fn test_crop_diff_change_at_end() {
+        let line = format!("{} foo", "x".repeat(100));
+        let (old_line, _) = line_diff(&line, &line.replace("foo", "bar"));
+        assert_eq!(diff_text(&crop_diff(old_line, 12)), "- xxxxxx foo");
+
+        let inline = inline_diff(&line, &line.replace("foo", "bar"));
+        assert_eq!(diff_text(&crop_diff(inline, 12)), "~ xxx foobar");
+    }
+
+    #[test]
+    fn test_line_diff_falls_back_to_whole_line_on_timeout() {
+        // Lines with little in common are the worst case for Myers diffing
+        let pseudo_random = |seed: u64| {
+            let mut state = seed;
+            (0..10_000)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (b'a' + (state >> 60) as u8) as char
+                })
+                .collect::<String>()
+        };
+        let (old, new) = (pseudo_random(1), pseudo_random(2));
+
+        let (old_actual, new_actual) = line_diff_with_timeout(&old, &new, Duration::from_millis(1));
+
+        let old_expected = vec![
+            Diff::new("- (simplified) ", Color::Red, Color::Reset),
+            Diff::new(old, Color::Black, Color::Red),
+        ];
+        let new_expected = vec![
+            Diff::new("+ (simplified) ", Color::Green, Color::Reset),
+            Diff::new(new, Color::Black, Color::Green),
+        ];
+        assert_eq!(old_actual, old_expected);
+        assert_eq!(new_actual, new_expected);
+
+        // The marker is kept when cropping, so the indicator is always visible
+        assert_eq!(
+            diff_text(&crop_diff(old_actual, 20)),
+            format!("- (simplified) {}", &old_expected[1].text[..5])
+        );
+    }
+
+    /// Text of each emphasised run, along with its diff background
+    fn emphasised(diff: &[Diff]) -> Vec<(String, Color)> {
+        diff.iter()
+            .filter(|d| d.emphasised)
+            .map(|d| (d.text.clone(), d.bg_colour))
+            .collect()
+    }
+
+    #[test]
+    fn test_line_diff_emphasises_matches() {
+        let replacer = LineReplacer::new(
+            SearchType::Pattern(Regex::new(r"fo+").unwrap()),
+            Some("foobar".to_owned()),
+        );
+        let line = "let foo = fooo;";
+        let replaced = replacer.replace_line(line).unwrap();
+        assert_eq!(replaced.replacement, "let foobar = foobar;");
+
+        let (old_line, new_line) = line_diff_with_matches(
+            line,
+            &replaced.replacement,
+            &replaced.match_spans,
+            &replaced.replacement_spans,
+        );
+        // The diff treats the first "foo" as unchanged, but it's still emphasised as a match, and
+        // the emphasis spans both the unchanged and inserted parts of each replacement
+        let emphasised_text = |diff: &[Diff]| {
+            emphasised(diff)
+                .into_iter()
+                .map(|(text, _)| text)
+                .collect::<String>()
+        };
+        assert_eq!(emphasised_text(&old_line), "foofooo");
+        assert_eq!(emphasised_text(&new_line), "foobarfoobar");
+        assert_eq!(emphasised(&old_line)[0], ("foo".to_owned(), Color::Reset));
+        assert!(emphasised(&new_line).contains(&("bar".to_owned(), Color::Green)));
+        // Text outside the matches isn't emphasised
+        assert!(old_line
+            .iter()
+            .filter(|d| d.text.contains('='))
+            .all(|d| !d.emphasised));
+        // Splitting for emphasis doesn't change the text, and the markers aren't emphasised
+        assert_eq!(diff_text(&old_line), format!("- {line}"));
+        assert_eq!(diff_text(&new_line), format!("+ {}", replaced.replacement));
+        assert!(!old_line[0].emphasised && !new_line[0].emphasised);
+    }
+
+    #[test]
+    fn test_line_diff_emphasises_unsorted_and_adjacent_spans() {
+        let (old_line, _) = line_diff_with_matches("aébcdef", "aébcdef", &[5..7, 1..3, 3..5], &[]);
+        assert_eq!(
+            old_line,
+            vec![
+                Diff::new("- ", Color::Red, Color::Reset),
+                Diff::new("a", Color::Red, Color::Reset),
+                Diff {
+                    emphasised: true,
+                    ..Diff::new("ébcde", Color::Red, Color::Reset)
+                },
+                Diff::new("f", Color::Red, Color::Reset),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_result_line_diff_emphasises_counter_tokens() {
+        let replacer = LineReplacer::new(
+            SearchType::Fixed("foo".to_owned()),
+            Some("x${n}".to_owned()),
+        );
+        let replaced = replacer.replace_line("foo foo").unwrap();
+        let result = SearchResult {
+            path: PathBuf::from("file.txt"),
+            line_number: 1,
+            line: "foo foo".to_owned(),
+            replacement: replaced.replacement,
+            num_matches: replaced.num_matches,
+            included: true,
+            match_spans: replaced.match_spans,
+            replacement_spans: replaced.replacement_spans,
+            ..Default::default()
+        };
+
+        let (_, new_line) = result_line_diff(&result);
+        let emphasised_text = new_line
+            .iter()
+            .filter(|d| d.emphasised)
+            .map(|d| d.text.as_str())
+            .collect::<String>();
+        assert_eq!(emphasised_text, "x${n}x${n}");
+        assert_eq!(diff_text(&new_line), "+ x${n} x${n}");
+    }
+
+    #[test]
+    fn test_result_diff_lines_dims_excluded_results() {
+        let replacer =
+            LineReplacer::new(SearchType::Fixed("foo".to_owned()), Some("baz".to_owned()));
+        let replaced = replacer.replace_line("foo bar").unwrap();
+        let mut result = SearchResult {
+            path: PathBuf::from("file.txt"),
+            line_number: 1,
+            line: "foo bar".to_owned(),
+            replacement: replaced.replacement,
+            num_matches: replaced.num_matches,
+            included: true,
+            match_spans: replaced.match_spans,
+            replacement_spans: replaced.replacement_spans,
+            ..Default::default()
+        };
+        let dimmed = |lines: &[ratatui::text::Line<'_>]| {
+            lines.iter().flat_map(|line| &line.spans).all(|span| {
+                span.style
+                    .add_modifier
+                    .contains(Modifier::DIM | Modifier::CROSSED_OUT)
+            })
+        };
+
+        for compact in [false, true] {
+            result.included = true;
+            let lines = result_diff_lines(&result, compact, 80);
+            assert_eq!(lines.len(), if compact { 1 } else { 2 });
+            assert!(lines
+                .iter()
+                .flat_map(|line| &line.spans)
+                .all(|span| !span.style.add_modifier.contains(Modifier::DIM)));
+
+            result.included = false;
+            let lines = result_diff_lines(&result, compact, 80);
+            assert!(dimmed(&lines));
+            // The colours of the diff are kept
+            assert!(lines
+                .iter()
+                .flat_map(|line| &line.spans)
+                .any(|span| span.style.bg.is_some_and(|bg| bg != Color::Reset)));
+        }
+    }
+
+    #[test]
+    fn test_inline_diff() {
+        let actual = inline_diff("hello world", "hello there");
+
+        let expected = vec![
+            Diff::new("~ ", Color::Reset, Color::Reset),
+            Diff::new("hello ", Color::Reset, Color::Reset),
+            Diff::new("wo", Color::Black, Color::Red),
+            Diff::new("the", Color::Black, Color::Green),
+            Diff::new("r", Color::Reset, Color::Reset),
+            Diff::new("ld", Color::Black, Color::Red),
+            Diff::new("e", Color::Black, Color::Green),
+        ];
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_result_item_height() {
+        assert_eq!(result_item_height(false), 4);
+        assert_eq!(result_item_height(true), 2);
+    }
+
+    fn render_results(compact: bool) -> Vec<String> {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            false,
+            events.app_event_sender,
+        )
+        .with_compact(compact);
+        app.current_screen = Screen::SearchComplete(SearchState {
+            results: (1..=20)
+                .map(|n| SearchResult {
+                    path: PathBuf::from(format!("/dir/file{n}.txt")),
+                    line_number: n,
+                    line: "foo".to_owned(),
+                    replacement: "bar".to_owned(),
+                    num_matches: 1,
+                    included: true,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        });
+
+        let mut terminal = Terminal::new(TestBackend::new(50, 30)).unwrap();
+        terminal.draw(|frame| render(&app, frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer.get(x, y).symbol())
+                    .collect::<String>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_render_compact_results() {
+        let count_matching = |lines: &[String], pattern: &str| {
+            lines.iter().filter(|line| line.contains(pattern)).count()
+        };
+
+        // 26 rows are available for the list, so the last result is partially shown
+        let lines = render_results(false);
+        assert_eq!(count_matching(&lines, "[x] ./file"), 7);
+        assert_eq!(count_matching(&lines, "- foo"), 7);
+        assert_eq!(count_matching(&lines, "+ bar"), 6);
+        assert_eq!(count_matching(&lines, "~ "), 0);
+
+        let compact_lines = render_results(true);
+        assert_eq!(count_matching(&compact_lines, "[x] ./file"), 13);
+        assert_eq!(count_matching(&compact_lines, "~ foobar"), 13);
+        assert_eq!(count_matching(&compact_lines, "- foo"), 0);
+    }
+
+    fn render_to_lines(app: &App, width: u16, height: u16) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|frame| render(app, frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer.get(x, y).symbol())
+                    .collect::<String>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_render_long_error_popup() {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            false,
+            events.app_event_sender,
+        );
+        app.search_fields.search_mut().set_error(
+            "Couldn't parse regex".to_owned(),
+            "first line\nthe second line is long enough that it has to be wrapped".to_owned(),
+        );
+        app.search_fields.show_error_popup = true;
+
+        // The popup is 32 columns wide, so 30 columns are available for text
+        let lines = render_to_lines(&app, 50, 30);
+        let popup_text = |lines: &[String]| {
+            let lines = lines
+                .iter()
+                .map(|line| line.chars().collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            let (top, title) = lines
+                .iter()
+                .enumerate()
+                .find_map(|(idx, line)| {
+                    line.windows(6)
+                        .position(|w| String::from_iter(w) == "Errors")
+                        .map(|title| (idx, title))
+                })
+                .unwrap();
+            // The popup may overlap the border of a field, so find the corner nearest the title
+            let left = lines[top][..title].iter().rposition(|c| *c == '┌').unwrap();
+            lines[top + 1..]
+                .iter()
+                .take_while(|line| line[left] == '│')
+                .map(|line| {
+                    String::from_iter(&line[left + 1..left + 31])
+                        .trim_end()
+                        .to_owned()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            popup_text(&lines),
+            vec![
+                "Search text",
+                "first line",
+                "the second line is long enough",
+                "that it has to be wrapped",
+            ]
+        );
+
+        app.search_fields.scroll_error_popup_down();
+        app.search_fields.scroll_error_popup_down();
+        let lines = render_to_lines(&app, 50, 30);
+        assert_eq!(
+            popup_text(&lines),
+            vec![
+                "the second line is long enough",
+                "that it has to be wrapped"
+            ]
+        );
+
+        // Can't scroll past the last line
+        for _ in 0..10 {
+            app.search_fields.scroll_error_popup_down();
+        }
+        assert_eq!(app.search_fields.error_popup_scroll, 3);
+        app.search_fields.scroll_error_popup_up();
+        assert_eq!(app.search_fields.error_popup_scroll, 2);
+    }
+
+    #[tokio::test]
+    async fn test_render_num_matches() {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            false,
+            events.app_event_sender,
+        );
+        app.current_screen = Screen::SearchComplete(SearchState {
+            results: [1, 3]
+                .into_iter()
+                .enumerate()
+                .map(|(idx, num_matches)| SearchResult {
+                    path: PathBuf::from("/dir/file.txt"),
+                    line_number: idx + 1,
+                    line: "foo foo foo".to_owned(),
+                    replacement: "bar bar bar".to_owned(),
+                    num_matches,
+                    included: true,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        });
+
+        let lines = render_to_lines(&app, 80, 20);
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("[x] ./file.txt:1 ") && !line.contains("matches")));
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("[x] ./file.txt:2 (3 matches)")));
+    }
+
+    #[tokio::test]
+    async fn test_render_failed_replacement() {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            false,
+            events.app_event_sender,
+        );
+        app.current_screen = Screen::SearchComplete(SearchState {
+            results: [None, Some(ReplaceResult::Error("File changed".to_owned()))]
+                .into_iter()
+                .enumerate()
+                .map(|(idx, replace_result)| SearchResult {
+                    path: PathBuf::from("/dir/file.txt"),
+                    line_number: idx + 1,
+                    line: "foo".to_owned(),
+                    replacement: "bar".to_owned(),
+                    num_matches: 1,
+                    included: true,
+                    replace_result,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        });
+
+        let lines = render_to_lines(&app, 80, 20);
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("[x] ./file.txt:1 ") && !line.contains("failed")));
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("[x] ./file.txt:2 [failed: File changed]")));
+    }
+
+    #[tokio::test]
+    async fn test_render_hide_unchanged() {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            false,
+            events.app_event_sender,
+        );
+        let mut search_state = SearchState {
+            results: ["bar", "foo"]
+                .into_iter()
+                .enumerate()
+                .map(|(idx, replacement)| SearchResult {
+                    path: PathBuf::from("/dir/file.txt"),
+                    line_number: idx + 1,
+                    line: "foo".to_owned(),
+                    replacement: replacement.to_owned(),
+                    num_matches: 1,
+                    included: true,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        search_state.toggle_hide_unchanged();
+        app.current_screen = Screen::SearchComplete(search_state);
+
+        let lines = render_to_lines(&app, 120, 20);
+        assert!(lines.iter().any(|line| line.contains("[Hiding unchanged]")));
+        assert!(lines.iter().any(|line| line.contains("./file.txt:1 ")));
+        assert!(!lines.iter().any(|line| line.contains("./file.txt:2 ")));
+    }
+
+    #[tokio::test]
+    async fn test_render_max_per_file() {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            false,
+            events.app_event_sender,
+        );
+        app.current_screen = Screen::SearchComplete(SearchState {
+            results: ["a.txt", "a.txt", "a.txt", "a.txt", "a.txt", "b.txt"]
+                .into_iter()
+                .enumerate()
+                .map(|(idx, file)| SearchResult {
+                    path: PathBuf::from("/dir").join(file),
+                    line_number: idx + 1,
+                    line: "foo".to_owned(),
+                    replacement: "bar".to_owned(),
+                    num_matches: 1,
+                    included: true,
+                    ..Default::default()
+                })
+                .collect(),
+            max_per_file: Some(2),
+            ..Default::default()
+        });
+
+        let lines = render_to_lines(&app, 120, 40);
+        let shown = |lines: &[String], name: &str| lines.iter().any(|line| line.contains(name));
+        assert!(shown(&lines, "./a.txt:1 "));
+        assert!(shown(&lines, "./a.txt:2 "));
+        assert!(!shown(&lines, "./a.txt:3 "));
+        assert!(!shown(&lines, "./a.txt:5 "));
+        assert!(shown(&lines, "+3 more in this file"));
+        assert!(shown(&lines, "./b.txt:6 "));
+
+        app.handle_key_events(&KeyEvent::new(KeyCode::Char('m'), KeyModifiers::NONE))
+            .unwrap();
+        let lines = render_to_lines(&app, 120, 40);
+        assert!(shown(&lines, "./a.txt:5 "));
+        assert!(!shown(&lines, "more in this file"));
+    }
+
+    #[tokio::test]
+    async fn test_render_gitignored_count() {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            true,
+            events.app_event_sender,
+        );
+        app.current_screen = Screen::SearchComplete(SearchState {
+            results: [
+                vec![],
+                vec![ReplaceWarning::Gitignored],
+                vec![ReplaceWarning::Gitignored],
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, warnings)| SearchResult {
+                path: PathBuf::from("/dir/file.txt"),
+                line_number: idx + 1,
+                line: "foo".to_owned(),
+                replacement: "bar".to_owned(),
+                num_matches: 1,
+                included: true,
+                warnings,
+                ..Default::default()
+            })
+            .collect(),
+            ..Default::default()
+        });
+
+        let lines = render_to_lines(&app, 120, 20);
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("2 results are in gitignored files")));
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("./file.txt:2 [warning: file is gitignored]")));
+    }
+
+    #[tokio::test]
+    async fn test_render_binary_lines_skipped() {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            true,
+            events.app_event_sender,
+        );
+        app.handle_background_processing_event(BackgroundProcessingEvent::BinaryLinesSkipped(2));
+        app.handle_background_processing_event(BackgroundProcessingEvent::BinaryLinesSkipped(1));
+        app.current_screen = Screen::SearchComplete(SearchState {
+            results: vec![],
+            ..Default::default()
+        });
+
+        let lines = render_to_lines(&app, 120, 20);
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("3 matching lines were skipped as binary, in 2 files")));
+    }
+
+    #[tokio::test]
+    async fn test_render_replacement_summary() {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            true,
+            events.app_event_sender,
+        );
+        let search_state = SearchState {
+            results: [
+                ("/dir/a.txt", vec![]),
+                ("/dir/a.txt", vec![ReplaceWarning::Gitignored]),
+                (
+                    "/dir/b.txt",
+                    vec![ReplaceWarning::Gitignored, ReplaceWarning::LargeFile],
+                ),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (path, warnings))| SearchResult {
+                path: PathBuf::from(path),
+                line_number: idx + 1,
+                line: "foo foo".to_owned(),
+                replacement: "bar bar".to_owned(),
+                num_matches: 2,
+                included: true,
+                warnings,
+                ..Default::default()
+            })
+            .collect(),
+            ..Default::default()
+        };
+        app.current_screen = Screen::ReviewingReplacement(ReplacementSummary::new(search_state, 1));
+
+        let lines = render_to_lines(&app, 120, 40);
+        let line_after = |title: &str| {
+            let idx = lines.iter().position(|line| line.contains(title)).unwrap();
+            lines[idx + 1].clone()
+        };
+        assert!(line_after("Files:").contains('2'));
+        assert!(line_after("Lines:").contains('3'));
+        assert!(line_after("Substitutions:").contains('6'));
+        assert!(line_after("Warning - file is gitignored:").contains('2'));
+        assert!(line_after("Warning - files larger than 10 MiB:").contains('1'));
+        assert!(line_after("Warning - binary files skipped:").contains('1'));
+        assert!(lines.iter().any(|line| line.contains("<esc> back")));
+    }
+
+    #[tokio::test]
+    async fn test_render_replacement_progress() {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            false,
+            events.app_event_sender,
+        );
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        app.current_screen =
+            Screen::PerformingReplacement(PerformingReplacementState::new(None, sender, receiver));
+        let lines = render_to_lines(&app, 80, 20);
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("Performing replacement...")));
+
+        if let Screen::PerformingReplacement(state) = &mut app.current_screen {
+            state.progress = Some(ReplacementProgress {
+                current_file: PathBuf::from("/dir/src/main.rs"),
+                num_files_done: 2,
+                num_files: 5,
+            });
+        }
+        let lines = render_to_lines(&app, 80, 20);
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("Performing replacement... (2 of 5 files done)")));
+        assert!(lines.iter().any(|line| line.contains("./src/main.rs")));
+    }
+
+    #[tokio::test]
+    async fn test_render_file_confirmation_byte_delta() {
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            false,
+            events.app_event_sender,
+        );
+        let result = |line: &str, replacement: &str| SearchResult {
+            path: PathBuf::from("/dir/file.txt"),
+            line_number: 1,
+            line: line.to_owned(),
+            replacement: replacement.to_owned(),
+            num_matches: 1,
+            included: true,
+            ..Default::default()
+        };
+        app.current_screen = Screen::ConfirmingFiles(FileConfirmationState::new(SearchState {
+            results: vec![result("foo", "foobar"), result("foo bar", "baz")],
+            ..Default::default()
+        }));
+        let lines = render_to_lines(&app, 80, 20);
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("File 1 of 1: ./file.txt (2 results, -1 byte)")));
+    }
+
+    #[test]
+    fn test_format_byte_delta() {
+        assert_eq!(format_byte_delta(12), "+12 bytes");
+        assert_eq!(format_byte_delta(1), "+1 byte");
+        assert_eq!(format_byte_delta(0), "0 bytes");
+        assert_eq!(format_byte_delta(-1), "-1 byte");
+        assert_eq!(format_byte_delta(-30), "-30 bytes");
+    }
+
+    #[test]
+    fn test_parse_content_width() {
+        assert_eq!("80%".parse(), Ok(ContentWidth::Percentage(80)));
+        assert_eq!("100%".parse(), Ok(ContentWidth::Percentage(100)));
+        assert_eq!("120".parse(), Ok(ContentWidth::Columns(120)));
+        for invalid in ["0%", "101%", "0", "-5", "wide", "%", ""] {
+            assert!(
+                invalid.parse::<ContentWidth>().is_err(),
+                "{:?} should be invalid",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_content_area() {
+        let rect = Rect::new(0, 0, 200, 10);
+        assert_eq!(content_area(rect, ContentWidth::default()).width, 160);
+        assert_eq!(content_area(rect, ContentWidth::Percentage(50)).width, 100);
+        let area = content_area(rect, ContentWidth::Columns(120));
+        assert_eq!((area.x, area.width), (40, 120));
+        // Never wider than the terminal
+        assert_eq!(content_area(rect, ContentWidth::Columns(300)).width, 200);
+    }
+
+    #[tokio::test]
+    async fn test_render_with_content_width() {
+        let events = EventHandler::new();
+        let app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            false,
+            events.app_event_sender,
+        )
+        .with_content_width(ContentWidth::Columns(40));
+
+        let lines = render_to_lines(&app, 100, 30);
+        let search_field_top = lines
+            .iter()
+            .find(|line| line.contains("Search text"))
+            .unwrap()
+            .trim();
+        assert_eq!(search_field_top.chars().count(), 40);
+    }
+
+    #[test]
+    fn test_plain_text_diff() {
+        let result = SearchResult {
+            path: PathBuf::from("/dir/src/main.rs"),
+            line_number: 12,
+            line: "let x = foo;".to_owned(),
+            replacement: "let x = bar;".to_owned(),
+            num_matches: 1,
+            included: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            plain_text_diff(&result, "./src/main.rs", &[]),
+            "./src/main.rs:12\n- let x = foo;\n+ let x = bar;\n"
+        );
+
+        let context = [
+            (11, "fn main() {".to_owned()),
+            (12, "let x = foo;".to_owned()),
+            (13, "}".to_owned()),
+        ];
+        assert_eq!(
+            plain_text_diff(&result, "./src/main.rs", &context),
+            "./src/main.rs:12\n  fn main() {\n- let x = foo;\n+ let x = bar;\n  }\n"
+        );
+
+        // Lines are copied as they are, rather than being simplified as when shown
+        let result = SearchResult {
+            line: "a".repeat(1000),
+            replacement: "b".repeat(1000),
+            ..result
+        };
+        assert_eq!(
+            plain_text_diff(&result, "./src/main.rs", &[]),
+            format!(
+                "./src/main.rs:12\n- {}\n+ {}\n",
+                "a".repeat(1000),
+                "b".repeat(1000)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_render_successes_with_context_after_replacement() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.txt");
+        // Neighbouring lines were also replaced, so differ from when they were searched
+        std::fs::write(&path, "start\nbar 1\nbar 2\nbar 3\nend\n").unwrap();
+        let result = |line_number: usize, replace_result| SearchResult {
+            path: path.clone(),
+            line_number,
+            line: format!("foo {}", line_number - 1),
+            replacement: format!("bar {}", line_number - 1),
+            num_matches: 1,
+            included: true,
+            replace_result: Some(replace_result),
+            ..Default::default()
+        };
+
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![temp_dir.path().to_path_buf()],
+            false,
+            false,
+            events.app_event_sender,
+        )
+        .with_results_context(1);
+        let mut replace_state = ReplaceState {
+            num_successes: 1,
+            num_ignored: 0,
+            errors: vec![result(4, ReplaceResult::Error("error".to_owned()))],
+            replacement_errors_pos: 0,
+            successes: vec![result(3, ReplaceResult::Success)],
+            successes_pos: 0,
+            show_successes: true,
+            num_remaining_matches: None,
+            expanded_context: HashMap::new(),
+            success_context: HashMap::new(),
+        };
+        replace_state.read_success_context(1);
+        // The context is read once, so later changes to the file aren't shown
+        std::fs::write(&path, "changed\n").unwrap();
+        app.current_screen = Screen::Results(replace_state);
+
+        let lines = render_to_lines(&app, 100, 40)
+            .into_iter()
+            .map(|line| line.trim().to_owned())
+            .collect::<Vec<_>>();
+        let start = lines
+            .iter()
+            .position(|line| line.ends_with("file.txt:3"))
+            .unwrap();
+        assert_eq!(
+            lines[start + 1..start + 4],
+            ["2 bar 1", "3 bar 2", "4 bar 3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expand_context_of_selected_success() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.txt");
+        let contents = (1..=25).map(|i| format!("line {i}\n")).collect::<String>();
+        std::fs::write(&path, contents).unwrap();
+        let result = |line_number: usize, replace_result| SearchResult {
+            path: path.clone(),
+            line_number,
+            line: format!("line {line_number}"),
+            replacement: format!("line {line_number}"),
+            num_matches: 1,
+            included: true,
+            replace_result: Some(replace_result),
+            ..Default::default()
+        };
+
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![temp_dir.path().to_path_buf()],
+            false,
+            false,
+            events.app_event_sender,
+        );
+        app.current_screen = Screen::Results(ReplaceState {
+            num_successes: 2,
+            num_ignored: 0,
+            errors: vec![result(1, ReplaceResult::Error("error".to_owned()))],
+            replacement_errors_pos: 0,
+            successes: vec![
+                result(10, ReplaceResult::Success),
+                result(20, ReplaceResult::Success),
+            ],
+            successes_pos: 0,
+            show_successes: true,
+            num_remaining_matches: None,
+            expanded_context: HashMap::new(),
+            success_context: HashMap::new(),
+        });
+        let rendered = |app: &App| {
+            render_to_lines(app, 100, 60)
+                .into_iter()
+                .map(|line| line.trim().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert!(!rendered(&app).iter().any(|line| line == "9 line 9"));
+
+        app.handle_key_events(&KeyEvent::new(KeyCode::Char('+'), KeyModifiers::NONE))
+            .unwrap();
+        let Screen::Results(replace_state) = &app.current_screen else {
+            panic!("Expected Results, found {:?}", app.current_screen);
+        };
+        assert_eq!(replace_state.context_for(0, 0), 5);
+        assert_eq!(replace_state.context_for(1, 0), 0);
+
+        let lines = rendered(&app);
+        let start = lines.iter().position(|line| line == "5 line 5").unwrap();
+        let expected = (5..=15)
+            .map(|i| format!("{i} line {i}"))
+            .collect::<Vec<_>>();
+        assert_eq!(lines[start..start + 11], expected);
+        // Only the selected result has more context
+        assert!(!lines.iter().any(|line| line == "19 line 19"));
+        assert!(!lines.iter().any(|line| line == "21 line 21"));
+    }
+
+    #[tokio::test]
+    async fn test_render_context_when_all_replacements_succeed() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("file.txt");
+        std::fs::write(&path, "start\nbar\nend\n").unwrap();
+
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![temp_dir.path().to_path_buf()],
+            false,
+            false,
+            events.app_event_sender,
+        )
+        .with_results_context(1);
+        let mut replace_state = ReplaceState {
+            num_successes: 1,
+            num_ignored: 0,
+            errors: vec![],
+            replacement_errors_pos: 0,
+            successes: vec![SearchResult {
+                path: path.clone(),
+                line_number: 2,
+                line: "foo".to_owned(),
+                replacement: "bar".to_owned(),
+                num_matches: 1,
+                included: true,
+                replace_result: Some(ReplaceResult::Success),
+                ..Default::default()
+            }],
+            successes_pos: 0,
+            show_successes: false,
+            num_remaining_matches: None,
+            expanded_context: HashMap::new(),
+            success_context: HashMap::new(),
+        };
+        replace_state.read_success_context(1);
+        app.current_screen = Screen::Results(replace_state);
+
+        let lines = render_to_lines(&app, 100, 40)
+            .into_iter()
+            .map(|line| line.trim().to_owned())
+            .collect::<Vec<_>>();
+        let start = lines
+            .iter()
+            .position(|line| line.ends_with("file.txt:2"))
+            .unwrap();
+        assert_eq!(lines[start + 1..start + 4], ["1 start", "2 bar", "3 end"]);
+    }
+
+    #[tokio::test]
+    async fn test_render_results_errors_with_relative_paths() {
+        let result = |path: &str, replace_result| SearchResult {
+            path: PathBuf::from(path),
+            line_number: 3,
+            line: "foo".to_owned(),
+            replacement: "bar".to_owned(),
+            num_matches: 1,
+            included: true,
+            replace_result: Some(replace_result),
+            ..Default::default()
+        };
+
+        let events = EventHandler::new();
+        let mut app = App::new(
+            vec![PathBuf::from("/dir")],
+            false,
+            false,
+            events.app_event_sender,
+        );
+        app.current_screen = Screen::Results(ReplaceState {
+            num_successes: 1,
+            num_ignored: 0,
+            errors: vec![result(
+                "/dir/src/file.txt",
+                ReplaceResult::Error("File changed since last search".to_owned()),
+            )],
+            replacement_errors_pos: 0,
+            successes: vec![result("/dir/other.txt", ReplaceResult::Success)],
+            successes_pos: 0,
+            show_successes: false,
+            num_remaining_matches: None,
+            expanded_context: HashMap::new(),
+            success_context: HashMap::new(),
+        });
+
+        let lines = render_to_lines(&app, 100, 40);
+        assert!(lines.iter().any(|line| line.trim() == "./src/file.txt:3"));
+        assert!(!lines.iter().any(|line| line.contains("/dir/")));
+
+        if let Screen::Results(replace_state) = &mut app.current_screen {
+            replace_state.show_successes = true;
+        }
+        let lines = render_to_lines(&app, 100, 40);
+        assert!(lines.iter().any(|line| line.trim() == "./other.txt:3"));
+    }
 }